@@ -1,6 +1,17 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use serde::Deserialize;
 
+/// Per-type queue limits that `JOB_QUEUE_TYPE_CONCURRENCY` can override. External
+/// integrations are kept low to stay under provider rate limits, and the GPS sweep's heavy
+/// deletes and partition DDL run for one team at a time.
+const DEFAULT_TYPE_CONCURRENCY: &str = "send_email=10,send_sms=10,send_push_notification=10,\
+    send_password_reset=10,send_verification_code=10,send_tracking_link=10,deliver_webhook=10,\
+    process_photo=4,record_payment_fees=4,generate_invoice_pdf=2,generate_estimate_pdf=2,\
+    appointment_reminder_sweep=2,license_expiry_sweep=2,overdue_invoice_sweep=2,recurring_job_sweep=2,\
+    sync_quickbooks=1,recurring_job_generation=1,gps_retention_sweep=1";
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub server: ServerSettings,
@@ -10,9 +21,13 @@ pub struct Settings {
     pub stripe: StripeSettings,
     pub twilio: TwilioSettings,
     pub sendgrid: SendGridSettings,
+    pub messaging: MessagingSettings,
     pub storage: StorageSettings,
     pub meilisearch: MeilisearchSettings,
     pub ai: AiSettings,
+    pub queue: QueueSettings,
+    pub webhooks: WebhookSettings,
+    pub tracking: TrackingSettings,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -20,6 +35,10 @@ pub struct ServerSettings {
     pub host: String,
     pub port: u16,
     pub env: String,
+    /// Base URL of the web app, used for links in emails
+    pub app_url: String,
+    /// Reverse proxies whose `X-Forwarded-For` is believed when finding the client address
+    pub trusted_proxies: Vec<std::net::IpAddr>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -37,15 +56,70 @@ pub struct RedisSettings {
 #[derive(Debug, Clone, Deserialize)]
 pub struct AuthSettings {
     pub jwt_secret: String,
+    /// AES-256 key sealing TOTP secrets at rest, base64 in `MFA_ENCRYPTION_KEY`
+    pub mfa_encryption_key: [u8; 32],
     pub jwt_expiry_hours: i64,
     pub refresh_token_expiry_days: i64,
+    /// Days after login that its refresh tokens stop rotating and the user must log in again
+    pub refresh_token_family_max_days: i64,
+    pub invitation_expiry_days: i64,
+    pub password_reset_expiry_minutes: i64,
+    pub verification_code_expiry_minutes: i64,
+    /// Code (and reset) requests allowed per user and channel in a rolling hour
+    pub verification_max_requests_per_hour: i64,
+    pub verification_resend_cooldown_secs: i64,
+    pub login_verification: LoginVerificationPolicy,
+}
+
+/// Which verified contact, if any, a user needs before they may log in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginVerificationPolicy {
+    /// Unverified users may log in
+    Off,
+    Email,
+    Phone,
+    /// Either a verified email or a verified phone
+    Any,
+}
+
+impl LoginVerificationPolicy {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "off" => Ok(Self::Off),
+            "email" => Ok(Self::Email),
+            "phone" => Ok(Self::Phone),
+            "any" => Ok(Self::Any),
+            other => Err(anyhow::anyhow!(
+                "Unknown LOGIN_VERIFICATION_POLICY '{}'; expected off, email, phone or any",
+                other
+            )),
+        }
+    }
+
+    pub fn allows(&self, email_verified: bool, phone_verified: bool) -> bool {
+        match self {
+            Self::Off => true,
+            Self::Email => email_verified,
+            Self::Phone => phone_verified,
+            Self::Any => email_verified || phone_verified,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct StripeSettings {
+    /// `stripe` or `fake`; see `services::payment_provider::from_settings`
+    pub provider: String,
+    /// ISO code charged for invoices, lowercase as Stripe expects
+    pub currency: String,
     pub secret_key: String,
     pub publishable_key: String,
     pub webhook_secret: String,
+    /// How old a signed webhook timestamp may be before it is rejected as a replay
+    pub webhook_tolerance_secs: i64,
+    /// How long a portal payment may stay pending before it is given up as abandoned
+    pub pending_payment_expiry_minutes: i64,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -61,8 +135,16 @@ pub struct SendGridSettings {
     pub from_email: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagingSettings {
+    /// `provider`, `capture` or `log`; see `services::messaging::from_settings`
+    pub sender: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct StorageSettings {
+    /// `s3` or `memory`; see `services::storage::from_settings`
+    pub backend: String,
     pub bucket: String,
     pub region: String,
     pub endpoint: String,
@@ -81,6 +163,51 @@ pub struct AiSettings {
     pub service_url: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueueSettings {
+    pub poll_interval_ms: u64,
+    pub worker_concurrency: usize,
+    pub max_attempts: i32,
+    pub backoff_base_secs: i64,
+    pub backoff_max_secs: i64,
+    pub heartbeat_timeout_secs: i64,
+    /// Heartbeats in a row that can fail before a worker gives up its job, since by then
+    /// another worker may have requeued it
+    pub heartbeat_max_failures: u32,
+    /// Most jobs of a type running at once across all replicas, by job type
+    pub type_concurrency: HashMap<String, i64>,
+    /// Limit for job types not in `type_concurrency`
+    pub default_type_concurrency: i64,
+    pub scheduler_tick_secs: u64,
+    /// Days succeeded jobs are kept; long enough to outlast every dedup window
+    pub retention_days: i32,
+    /// Days dead jobs are kept for inspection and manual retry
+    pub dead_retention_days: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSettings {
+    /// How long a subscriber has to answer one delivery
+    pub timeout_secs: u64,
+    /// Attempts per event before the delivery is given up (retried with the queue's backoff)
+    pub max_attempts: i32,
+    /// Consecutive failed attempts after which a subscription is switched off
+    pub disable_after_failures: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrackingSettings {
+    /// `straight_line` or `google`; see `services::routing::from_settings`
+    pub routing_provider: String,
+    pub google_maps_api_key: String,
+    /// Speed assumed by the straight-line ETA, allowing for roads and stops
+    pub average_speed_kmh: f64,
+    /// How long a customer's "on the way" link works if the job never starts
+    pub link_expiry_hours: i64,
+    /// Fixes older than this aren't shown to customers or used for an ETA
+    pub location_max_age_secs: i64,
+}
+
 impl Settings {
     pub fn from_env() -> Result<Self> {
         let settings = Self {
@@ -90,6 +217,14 @@ impl Settings {
                     .unwrap_or_else(|_| "8080".into())
                     .parse()?,
                 env: std::env::var("API_ENV").unwrap_or_else(|_| "development".into()),
+                app_url: std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:5173".into()),
+                trusted_proxies: std::env::var("TRUSTED_PROXIES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?,
             },
             database: DatabaseSettings {
                 url: std::env::var("DATABASE_URL")?,
@@ -105,17 +240,47 @@ impl Settings {
             },
             auth: AuthSettings {
                 jwt_secret: std::env::var("JWT_SECRET")?,
+                mfa_encryption_key: parse_key("MFA_ENCRYPTION_KEY", &std::env::var("MFA_ENCRYPTION_KEY")?)?,
                 jwt_expiry_hours: std::env::var("JWT_EXPIRY_HOURS")
                     .unwrap_or_else(|_| "24".into())
                     .parse()?,
                 refresh_token_expiry_days: std::env::var("REFRESH_TOKEN_EXPIRY_DAYS")
                     .unwrap_or_else(|_| "30".into())
                     .parse()?,
+                refresh_token_family_max_days: std::env::var("REFRESH_TOKEN_FAMILY_MAX_DAYS")
+                    .unwrap_or_else(|_| "90".into())
+                    .parse()?,
+                invitation_expiry_days: std::env::var("INVITATION_EXPIRY_DAYS")
+                    .unwrap_or_else(|_| "7".into())
+                    .parse()?,
+                password_reset_expiry_minutes: std::env::var("PASSWORD_RESET_EXPIRY_MINUTES")
+                    .unwrap_or_else(|_| "60".into())
+                    .parse()?,
+                verification_code_expiry_minutes: std::env::var("VERIFICATION_CODE_EXPIRY_MINUTES")
+                    .unwrap_or_else(|_| "15".into())
+                    .parse()?,
+                verification_max_requests_per_hour: std::env::var("VERIFICATION_MAX_REQUESTS_PER_HOUR")
+                    .unwrap_or_else(|_| "5".into())
+                    .parse()?,
+                verification_resend_cooldown_secs: std::env::var("VERIFICATION_RESEND_COOLDOWN_SECS")
+                    .unwrap_or_else(|_| "60".into())
+                    .parse()?,
+                login_verification: LoginVerificationPolicy::parse(
+                    &std::env::var("LOGIN_VERIFICATION_POLICY").unwrap_or_else(|_| "off".into()),
+                )?,
             },
             stripe: StripeSettings {
+                provider: std::env::var("PAYMENT_PROVIDER").unwrap_or_else(|_| "stripe".into()),
+                currency: std::env::var("STRIPE_CURRENCY").unwrap_or_else(|_| "usd".into()).to_lowercase(),
                 secret_key: std::env::var("STRIPE_SECRET_KEY").unwrap_or_default(),
                 publishable_key: std::env::var("STRIPE_PUBLISHABLE_KEY").unwrap_or_default(),
                 webhook_secret: std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default(),
+                webhook_tolerance_secs: std::env::var("STRIPE_WEBHOOK_TOLERANCE_SECS")
+                    .unwrap_or_else(|_| "300".into())
+                    .parse()?,
+                pending_payment_expiry_minutes: std::env::var("STRIPE_PENDING_PAYMENT_EXPIRY_MINUTES")
+                    .unwrap_or_else(|_| "60".into())
+                    .parse()?,
             },
             twilio: TwilioSettings {
                 account_sid: std::env::var("TWILIO_ACCOUNT_SID").unwrap_or_default(),
@@ -127,7 +292,11 @@ impl Settings {
                 from_email: std::env::var("SENDGRID_FROM_EMAIL")
                     .unwrap_or_else(|_| "noreply@fieldforge.com".into()),
             },
+            messaging: MessagingSettings {
+                sender: std::env::var("MESSAGE_SENDER").unwrap_or_else(|_| "log".into()),
+            },
             storage: StorageSettings {
+                backend: std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "s3".into()),
                 bucket: std::env::var("S3_BUCKET").unwrap_or_else(|_| "fieldforge-uploads".into()),
                 region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".into()),
                 endpoint: std::env::var("S3_ENDPOINT")
@@ -145,6 +314,69 @@ impl Settings {
                 service_url: std::env::var("AI_SERVICE_URL")
                     .unwrap_or_else(|_| "http://localhost:8000".into()),
             },
+            queue: QueueSettings {
+                poll_interval_ms: std::env::var("JOB_QUEUE_POLL_INTERVAL_MS")
+                    .unwrap_or_else(|_| "1000".into())
+                    .parse()?,
+                worker_concurrency: std::env::var("JOB_QUEUE_WORKER_CONCURRENCY")
+                    .unwrap_or_else(|_| "8".into())
+                    .parse()?,
+                max_attempts: std::env::var("JOB_QUEUE_MAX_ATTEMPTS")
+                    .unwrap_or_else(|_| "5".into())
+                    .parse()?,
+                backoff_base_secs: std::env::var("JOB_QUEUE_BACKOFF_BASE_SECS")
+                    .unwrap_or_else(|_| "10".into())
+                    .parse()?,
+                backoff_max_secs: std::env::var("JOB_QUEUE_BACKOFF_MAX_SECS")
+                    .unwrap_or_else(|_| "3600".into())
+                    .parse()?,
+                heartbeat_timeout_secs: std::env::var("JOB_QUEUE_HEARTBEAT_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "60".into())
+                    .parse()?,
+                heartbeat_max_failures: std::env::var("JOB_QUEUE_HEARTBEAT_MAX_FAILURES")
+                    .unwrap_or_else(|_| "3".into())
+                    .parse()?,
+                type_concurrency: parse_limits(
+                    DEFAULT_TYPE_CONCURRENCY,
+                    &std::env::var("JOB_QUEUE_TYPE_CONCURRENCY").unwrap_or_default(),
+                )?,
+                default_type_concurrency: std::env::var("JOB_QUEUE_DEFAULT_TYPE_CONCURRENCY")
+                    .unwrap_or_else(|_| "5".into())
+                    .parse()?,
+                scheduler_tick_secs: std::env::var("SCHEDULER_TICK_SECS")
+                    .unwrap_or_else(|_| "15".into())
+                    .parse()?,
+                retention_days: std::env::var("JOB_QUEUE_RETENTION_DAYS")
+                    .unwrap_or_else(|_| "7".into())
+                    .parse()?,
+                dead_retention_days: std::env::var("JOB_QUEUE_DEAD_RETENTION_DAYS")
+                    .unwrap_or_else(|_| "30".into())
+                    .parse()?,
+            },
+            webhooks: WebhookSettings {
+                timeout_secs: std::env::var("WEBHOOK_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "10".into())
+                    .parse()?,
+                max_attempts: std::env::var("WEBHOOK_MAX_ATTEMPTS")
+                    .unwrap_or_else(|_| "8".into())
+                    .parse()?,
+                disable_after_failures: std::env::var("WEBHOOK_DISABLE_AFTER_FAILURES")
+                    .unwrap_or_else(|_| "20".into())
+                    .parse()?,
+            },
+            tracking: TrackingSettings {
+                routing_provider: std::env::var("ROUTING_PROVIDER").unwrap_or_else(|_| "straight_line".into()),
+                google_maps_api_key: std::env::var("GOOGLE_MAPS_API_KEY").unwrap_or_default(),
+                average_speed_kmh: std::env::var("ETA_AVERAGE_SPEED_KMH")
+                    .unwrap_or_else(|_| "40".into())
+                    .parse()?,
+                link_expiry_hours: std::env::var("TRACKING_LINK_EXPIRY_HOURS")
+                    .unwrap_or_else(|_| "12".into())
+                    .parse()?,
+                location_max_age_secs: std::env::var("TRACKING_LOCATION_MAX_AGE_SECS")
+                    .unwrap_or_else(|_| "600".into())
+                    .parse()?,
+            },
         };
 
         Ok(settings)
@@ -154,3 +386,26 @@ impl Settings {
         self.server.env == "production"
     }
 }
+
+/// A 32-byte key given as base64, e.g. from `openssl rand -base64 32`
+fn parse_key(name: &str, value: &str) -> Result<[u8; 32]> {
+    use base64::Engine;
+
+    base64::engine::general_purpose::STANDARD
+        .decode(value.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("{} must be 32 bytes of base64 (openssl rand -base64 32)", name))
+}
+
+/// `name=limit` pairs separated by commas, with `overrides` replacing or adding to `defaults`
+fn parse_limits(defaults: &str, overrides: &str) -> Result<HashMap<String, i64>> {
+    let mut limits = HashMap::new();
+    for pair in defaults.split(',').chain(overrides.split(',')).map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (name, limit) = pair
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("JOB_QUEUE_TYPE_CONCURRENCY entries look like send_email=10, not {}", pair))?;
+        limits.insert(name.trim().to_string(), limit.trim().parse()?);
+    }
+    Ok(limits)
+}
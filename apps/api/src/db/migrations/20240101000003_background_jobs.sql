@@ -0,0 +1,30 @@
+-- Durable background job queue
+-- Jobs are claimed with FOR UPDATE SKIP LOCKED; a running job keeps a heartbeat so
+-- rows orphaned by a crashed replica are re-queued instead of lost.
+CREATE TABLE background_jobs (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id         UUID REFERENCES teams(id) ON DELETE CASCADE,
+    job_type        TEXT NOT NULL,
+    payload         JSONB NOT NULL,
+    status          TEXT NOT NULL DEFAULT 'queued' CHECK (status IN ('queued', 'running', 'succeeded', 'dead')),
+    attempts        INT NOT NULL DEFAULT 0,
+    max_attempts    INT NOT NULL DEFAULT 5,
+    run_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_by       TEXT,
+    locked_at       TIMESTAMPTZ,
+    heartbeat_at    TIMESTAMPTZ,
+    last_error      TEXT,
+    completed_at    TIMESTAMPTZ,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_background_jobs_ready ON background_jobs(job_type, run_at) WHERE status = 'queued';
+CREATE INDEX idx_background_jobs_running ON background_jobs(job_type, heartbeat_at) WHERE status = 'running';
+CREATE INDEX idx_background_jobs_team ON background_jobs(team_id, created_at DESC) WHERE team_id IS NOT NULL;
+CREATE INDEX idx_background_jobs_dead ON background_jobs(updated_at DESC) WHERE status = 'dead';
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON background_jobs
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,30 @@
+-- Cron-style scheduled tasks, evaluated in each team's timezone.
+-- The scheduler claims due rows with SKIP LOCKED and enqueues their payload
+-- into background_jobs in the same transaction.
+CREATE TABLE scheduled_tasks (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id         UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    name            TEXT NOT NULL,
+    cron            TEXT NOT NULL,
+    payload         JSONB NOT NULL,
+    is_active       BOOLEAN NOT NULL DEFAULT true,
+    last_run_at     TIMESTAMPTZ,
+    next_run_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (team_id, name)
+);
+
+CREATE INDEX idx_scheduled_tasks_due ON scheduled_tasks(next_run_at) WHERE is_active = true;
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON scheduled_tasks
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Lets producers enqueue idempotently (sweeps run repeatedly over the same rows)
+ALTER TABLE background_jobs ADD COLUMN dedup_key TEXT;
+CREATE UNIQUE INDEX idx_background_jobs_dedup ON background_jobs(dedup_key);
+
+-- Finished jobs are purged after a retention period, which also frees their dedup keys
+CREATE INDEX idx_background_jobs_succeeded ON background_jobs(completed_at) WHERE status = 'succeeded';
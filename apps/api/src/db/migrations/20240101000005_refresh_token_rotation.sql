@@ -0,0 +1,11 @@
+-- Rotating refresh tokens. Every token issued from one login shares a family_id;
+-- presenting a token that was already rotated (replaced_by set) revokes the family.
+ALTER TABLE refresh_tokens
+    ADD COLUMN family_id UUID NOT NULL DEFAULT gen_random_uuid(),
+    ADD COLUMN replaced_by UUID REFERENCES refresh_tokens(id) ON DELETE SET NULL,
+    ADD COLUMN revoked_reason TEXT,
+    ADD COLUMN user_agent TEXT,
+    ADD COLUMN last_used_at TIMESTAMPTZ;
+
+CREATE INDEX idx_refresh_tokens_family ON refresh_tokens(family_id) WHERE revoked_at IS NULL;
+CREATE INDEX idx_refresh_tokens_expired ON refresh_tokens(expires_at);
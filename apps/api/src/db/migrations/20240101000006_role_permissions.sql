@@ -0,0 +1,18 @@
+-- Per-team overrides on top of the built-in role permission sets. A row with
+-- granted = true adds the permission to the role, granted = false removes it.
+CREATE TABLE team_role_permissions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('admin', 'technician', 'apprentice', 'office_manager')),
+    permission TEXT NOT NULL,
+    granted BOOLEAN NOT NULL,
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (team_id, role, permission)
+);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON team_role_permissions
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,26 @@
+-- Users can belong to several teams, with a role per team. users.team_id stays as
+-- the default team used when a request doesn't pick one with X-Team-Id.
+CREATE TABLE team_memberships (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL DEFAULT 'technician'
+        CHECK (role IN ('owner', 'admin', 'technician', 'apprentice', 'office_manager')),
+    hourly_rate NUMERIC(10,2),
+    is_active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (team_id, user_id)
+);
+
+CREATE INDEX idx_team_memberships_user ON team_memberships(user_id) WHERE is_active = true;
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON team_memberships
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+INSERT INTO team_memberships (team_id, user_id, role, hourly_rate, is_active)
+SELECT team_id, id, role::text, hourly_rate, is_active
+FROM users
+WHERE team_id IS NOT NULL;
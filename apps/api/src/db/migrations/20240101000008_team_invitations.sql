@@ -0,0 +1,32 @@
+-- Emailed invitations to join a team. Only the SHA-256 of the token is stored; resending
+-- rotates the token and extends the expiry.
+CREATE TABLE team_invitations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    role TEXT NOT NULL
+        CHECK (role IN ('owner', 'admin', 'technician', 'apprentice', 'office_manager')),
+    hourly_rate NUMERIC(10,2),
+    token_hash TEXT NOT NULL UNIQUE,
+    invited_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    sent_count INT NOT NULL DEFAULT 1,
+    last_sent_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    accepted_at TIMESTAMPTZ,
+    accepted_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- One open invitation per email per team
+CREATE UNIQUE INDEX idx_team_invitations_open
+    ON team_invitations(team_id, lower(email))
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON team_invitations
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
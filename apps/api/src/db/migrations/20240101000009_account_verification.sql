@@ -0,0 +1,30 @@
+-- Single-use password reset tokens. Only the SHA-256 of the token is stored, and only
+-- once the queued message carrying it is sent; the token is minted at send time.
+CREATE TABLE password_reset_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT UNIQUE,
+    channel TEXT NOT NULL CHECK (channel IN ('email', 'phone')),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_password_reset_tokens_user ON password_reset_tokens(user_id, created_at DESC);
+
+-- Short numeric codes proving control of an email address or phone number. Like reset
+-- tokens, a code is minted and hashed when it is sent. Requests are rate limited per
+-- destination in Redis.
+CREATE TABLE verification_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel TEXT NOT NULL CHECK (channel IN ('email', 'phone')),
+    destination TEXT NOT NULL,
+    code_hash TEXT,
+    attempts INT NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    consumed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_verification_codes_user ON verification_codes(user_id, channel, created_at DESC);
@@ -0,0 +1,48 @@
+-- TOTP (RFC 6238) second factor. The base32 secret has to be readable to check codes,
+-- so it is sealed with MFA_ENCRYPTION_KEY (AES-256-GCM) rather than hashed;
+-- last_used_step stops a code being replayed within its window. failed_attempts counts
+-- wrong codes across every login challenge and locks the factor until locked_until.
+CREATE TABLE user_totp (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    secret_ciphertext TEXT NOT NULL,
+    confirmed_at TIMESTAMPTZ,
+    last_used_step BIGINT,
+    failed_attempts INT NOT NULL DEFAULT 0,
+    locked_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON user_totp
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Single-use recovery codes, stored as SHA-256 hashes
+CREATE TABLE user_recovery_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_user_recovery_codes_user ON user_recovery_codes(user_id) WHERE used_at IS NULL;
+
+-- Issued by a password login for users with a second factor; exchanged for a session
+-- once the code checks out
+CREATE TABLE mfa_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    attempts INT NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    consumed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_mfa_challenges_expired ON mfa_challenges(expires_at);
+
+-- Teams can make TOTP mandatory for members who can refund payments or void invoices
+ALTER TABLE teams ADD COLUMN require_mfa BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,30 @@
+-- Team-scoped API keys for integrations. Only the SHA-256 of the key is stored;
+-- `prefix` is the first few characters, kept so people can tell keys apart.
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_api_keys_team ON api_keys(team_id, created_at DESC);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON api_keys
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Requests made with a key are attributed to it. Key requests on collection routes have
+-- no single resource, so resource_id becomes optional.
+ALTER TABLE audit_log ADD COLUMN api_key_id UUID REFERENCES api_keys(id) ON DELETE SET NULL;
+ALTER TABLE audit_log ALTER COLUMN resource_id DROP NOT NULL;
+
+CREATE INDEX idx_audit_log_api_key ON audit_log(api_key_id, created_at DESC) WHERE api_key_id IS NOT NULL;
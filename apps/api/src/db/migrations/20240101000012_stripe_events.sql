@@ -0,0 +1,12 @@
+-- Stripe delivers webhooks at least once. An event is recorded in the same transaction
+-- that applies it, so a redelivery of an already-applied event is skipped.
+CREATE TABLE stripe_events (
+    id TEXT PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_stripe_events_processed ON stripe_events(processed_at);
+
+CREATE INDEX idx_payments_stripe_intent ON payments(stripe_payment_intent_id) WHERE stripe_payment_intent_id IS NOT NULL;
+CREATE INDEX idx_payments_stripe_charge ON payments(stripe_charge_id) WHERE stripe_charge_id IS NOT NULL;
@@ -0,0 +1,26 @@
+-- Outbound webhook delivery. `routes::webhooks` already selected last_status_code;
+-- disabled_at records when repeated failures switched a subscription off.
+ALTER TABLE webhooks
+    ADD COLUMN last_status_code INT,
+    ADD COLUMN disabled_at TIMESTAMPTZ;
+
+-- One row per HTTP attempt. Retries and replays of the same event share event_id.
+CREATE TABLE webhook_deliveries (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    webhook_id      UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    team_id         UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    event_id        UUID NOT NULL,
+    event_type      TEXT NOT NULL,
+    payload         JSONB NOT NULL,
+    attempt         INT NOT NULL,
+    replay_of       UUID REFERENCES webhook_deliveries(id) ON DELETE SET NULL,
+    status          TEXT NOT NULL CHECK (status IN ('succeeded', 'failed')),
+    response_status INT,
+    response_body   TEXT,
+    error           TEXT,
+    duration_ms     INT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at DESC);
+CREATE INDEX idx_webhook_deliveries_event ON webhook_deliveries(webhook_id, event_id);
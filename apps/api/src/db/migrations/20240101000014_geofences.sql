@@ -0,0 +1,33 @@
+-- Geofence check-in: a circle around each job's property. The team sets the default
+-- radius and whether crossing it moves the job; a property can override the radius.
+ALTER TABLE teams ADD COLUMN geofence_radius_meters INT NOT NULL DEFAULT 150;
+ALTER TABLE teams ADD COLUMN geofence_auto_transition BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE properties ADD COLUMN geofence_radius_meters INT;
+
+-- Arrivals and departures detected from technician GPS fixes. Rows are never updated:
+-- with the raw fix they point at, they are the record of when a technician was on site.
+CREATE TABLE job_geofence_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    job_id UUID NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event_type TEXT NOT NULL CHECK (event_type IN ('arrived', 'departed')),
+    -- 'gps' when detected from a fix, 'transition' when a manual status change at the site recorded it
+    source TEXT NOT NULL DEFAULT 'gps' CHECK (source IN ('gps', 'transition')),
+    gps_location_id UUID REFERENCES gps_locations(id) ON DELETE SET NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    accuracy DOUBLE PRECISION,
+    property_latitude DOUBLE PRECISION NOT NULL,
+    property_longitude DOUBLE PRECISION NOT NULL,
+    distance_meters DOUBLE PRECISION NOT NULL,
+    radius_meters INT NOT NULL,
+    -- Device clock, and when the server received the fix
+    recorded_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_job_geofence_events_job ON job_geofence_events(job_id, user_id, recorded_at DESC);
+
+-- Status changes made by a geofence point at the crossing that caused them
+ALTER TABLE job_status_history ADD COLUMN geofence_event_id UUID REFERENCES job_geofence_events(id) ON DELETE SET NULL;
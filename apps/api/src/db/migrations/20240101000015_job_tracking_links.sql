@@ -0,0 +1,18 @@
+-- "Technician on the way" links sent to customers when a job goes en route, one per
+-- channel. The token is minted when the message is sent and only its SHA-256 is stored;
+-- token_hash is NULL until then. A link stops working at expires_at, or as soon as the
+-- job is no longer scheduled or en route.
+CREATE TABLE job_tracking_links (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    job_id UUID NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+    channel TEXT NOT NULL CHECK (channel IN ('email', 'sms')),
+    token_hash TEXT UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    view_count INT NOT NULL DEFAULT 0,
+    last_viewed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_job_tracking_links_job ON job_tracking_links(job_id) WHERE revoked_at IS NULL;
@@ -0,0 +1,9 @@
+-- Per-mile reimbursement rate used when mileage expenses are created from GPS trips
+ALTER TABLE teams ADD COLUMN mileage_rate NUMERIC(6,4);
+
+-- Marks mileage expenses generated from a day's GPS trips; one per technician per day
+ALTER TABLE expenses ADD COLUMN gps_mileage BOOLEAN NOT NULL DEFAULT false;
+
+CREATE UNIQUE INDEX idx_expenses_gps_mileage_day
+    ON expenses(team_id, user_id, expense_date)
+    WHERE gps_mileage;
@@ -0,0 +1,64 @@
+-- Per-team GPS retention. Fixes older than the retention period are deleted; with
+-- downsampling on, fixes older than gps_downsample_after_days are thinned to one per
+-- technician per interval. gps_downsampled_before is how far the sweep has got.
+ALTER TABLE teams ADD COLUMN gps_retention_days INT NOT NULL DEFAULT 90;
+ALTER TABLE teams ADD COLUMN gps_downsample_after_days INT;
+ALTER TABLE teams ADD COLUMN gps_downsample_interval_secs INT NOT NULL DEFAULT 60;
+ALTER TABLE teams ADD COLUMN gps_downsampled_before TIMESTAMPTZ;
+
+-- A foreign key into a partitioned table has to include the partition key. Geofence
+-- events copy the fix's coordinates, so they keep their evidence once the fix is gone.
+ALTER TABLE job_geofence_events DROP CONSTRAINT job_geofence_events_gps_location_id_fkey;
+
+ALTER TABLE gps_locations RENAME TO gps_locations_unpartitioned;
+DROP INDEX idx_gps_locations_team_user;
+DROP INDEX idx_gps_locations_recent;
+
+-- GPS fixes, partitioned by month of the device clock so expired months can be dropped whole
+CREATE TABLE gps_locations (
+    id              UUID NOT NULL DEFAULT gen_random_uuid(),
+    team_id         UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    latitude        DOUBLE PRECISION NOT NULL,
+    longitude       DOUBLE PRECISION NOT NULL,
+    accuracy        DOUBLE PRECISION,
+    speed           DOUBLE PRECISION,
+    heading         DOUBLE PRECISION,
+    recorded_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (id, recorded_at),
+    -- A device re-uploading a buffered fix sends the same timestamp; it's stored once
+    CONSTRAINT gps_locations_fix_key UNIQUE (team_id, user_id, recorded_at)
+) PARTITION BY RANGE (recorded_at);
+
+CREATE INDEX idx_gps_locations_recent ON gps_locations(team_id, recorded_at DESC);
+
+-- Create the partition holding the given day's month (UTC) if it doesn't exist yet
+CREATE FUNCTION gps_locations_ensure_partition(day DATE) RETURNS VOID AS $$
+DECLARE
+    month_start DATE := date_trunc('month', day)::date;
+BEGIN
+    EXECUTE format(
+        'CREATE TABLE IF NOT EXISTS %I PARTITION OF gps_locations FOR VALUES FROM (%L) TO (%L)',
+        'gps_locations_' || to_char(month_start, 'YYYYMM'),
+        month_start::timestamp AT TIME ZONE 'UTC',
+        (month_start + INTERVAL '1 month')::timestamp AT TIME ZONE 'UTC'
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+-- Partitions for everything already stored, through two months ahead
+SELECT gps_locations_ensure_partition(month::date)
+FROM generate_series(
+    date_trunc('month', LEAST(COALESCE((SELECT min(recorded_at) FROM gps_locations_unpartitioned), now()), now()) AT TIME ZONE 'UTC'),
+    date_trunc('month', GREATEST(COALESCE((SELECT max(recorded_at) FROM gps_locations_unpartitioned), now()), now()) AT TIME ZONE 'UTC')
+        + INTERVAL '2 months',
+    INTERVAL '1 month'
+) AS month;
+
+INSERT INTO gps_locations (id, team_id, user_id, latitude, longitude, accuracy, speed, heading, recorded_at, created_at)
+SELECT id, team_id, user_id, latitude, longitude, accuracy, speed, heading, recorded_at, created_at
+FROM gps_locations_unpartitioned
+ON CONFLICT DO NOTHING;
+
+DROP TABLE gps_locations_unpartitioned;
@@ -0,0 +1,7 @@
+-- Minimum gap the dispatch board keeps between a technician's consecutive jobs. A longer
+-- drive between the two properties, when both are geocoded, takes precedence.
+ALTER TABLE teams ADD COLUMN schedule_travel_buffer_minutes INT NOT NULL DEFAULT 15;
+
+-- A technician's day, for lanes and double-booking checks
+CREATE INDEX idx_jobs_assignee_schedule ON jobs(team_id, assigned_to, scheduled_date)
+    WHERE scheduled_date IS NOT NULL AND deleted_at IS NULL;
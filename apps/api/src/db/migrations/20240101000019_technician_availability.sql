@@ -0,0 +1,65 @@
+-- When each technician works. A technician with no shifts works the team's hours and
+-- days. Times are wall-clock times in the team's time zone, like `jobs.scheduled_*`.
+CREATE TABLE technician_shifts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- 0 is Sunday, as in teams.working_days
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 0 AND 6),
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    -- A seasonal pattern; open-ended when NULL
+    effective_from DATE,
+    effective_until DATE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (end_time > start_time),
+    CHECK (effective_until IS NULL OR effective_from IS NULL OR effective_until >= effective_from)
+);
+
+CREATE INDEX idx_technician_shifts_user ON technician_shifts(team_id, user_id, weekday);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON technician_shifts
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Time off a technician asks for and a manager approves. Only approved time off blocks
+-- scheduling; pending requests are shown on the board so dispatch can plan around them.
+CREATE TABLE time_off_requests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Team-local wall-clock times; a whole day runs from its midnight to the next
+    starts_at TIMESTAMP NOT NULL,
+    ends_at TIMESTAMP NOT NULL,
+    kind TEXT NOT NULL DEFAULT 'vacation'
+        CHECK (kind IN ('vacation', 'sick', 'personal', 'training', 'other')),
+    reason TEXT,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'denied', 'cancelled')),
+    reviewed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    reviewed_at TIMESTAMPTZ,
+    review_note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (ends_at > starts_at)
+);
+
+CREATE INDEX idx_time_off_requests_user ON time_off_requests(team_id, user_id, starts_at);
+CREATE INDEX idx_time_off_requests_pending ON time_off_requests(team_id, starts_at) WHERE status = 'pending';
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE ON time_off_requests
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- Days the whole team is off
+CREATE TABLE team_holidays (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    holiday_date DATE NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (team_id, holiday_date)
+);
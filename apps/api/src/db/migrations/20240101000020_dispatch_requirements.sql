@@ -0,0 +1,6 @@
+-- What a technician needs to take a job. Skills match `users.skills` or
+-- `users.certifications`; license types need an active, unexpired row in `licenses`.
+ALTER TABLE jobs ADD COLUMN required_skills TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE jobs ADD COLUMN required_license_types TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX idx_licenses_user ON licenses(user_id, license_type) WHERE status = 'active';
@@ -0,0 +1,21 @@
+-- Bring recurring_rules in line with the RecurringRule model, which recurring job
+-- generation builds jobs from
+ALTER TABLE recurring_rules
+    ALTER COLUMN frequency TYPE TEXT USING frequency::text,
+    ADD CONSTRAINT recurring_rules_frequency_check
+        CHECK (frequency IN ('daily', 'weekly', 'biweekly', 'monthly', 'quarterly', 'semi_annual', 'annual', 'custom')),
+    ALTER COLUMN job_template SET DEFAULT '{}',
+    ADD COLUMN property_id UUID REFERENCES properties(id) ON DELETE SET NULL,
+    ADD COLUMN title TEXT NOT NULL DEFAULT '',
+    ADD COLUMN description TEXT,
+    ADD COLUMN next_occurrence DATE,
+    ADD COLUMN estimated_duration_minutes INT,
+    ADD COLUMN job_type TEXT,
+    ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal'
+        CHECK (priority IN ('emergency', 'high', 'normal', 'low')),
+    ADD COLUMN auto_schedule BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN advance_days INT NOT NULL DEFAULT 7;
+
+UPDATE recurring_rules SET next_occurrence = start_date WHERE next_occurrence IS NULL;
+
+CREATE INDEX idx_recurring_rules_next ON recurring_rules(team_id, next_occurrence) WHERE is_active = true;
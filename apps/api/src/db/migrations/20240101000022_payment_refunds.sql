@@ -0,0 +1,23 @@
+-- Every refund issued through the API. A processor refund is recorded as pending and
+-- committed before the processor is called, so money returned to the card is never
+-- missing from the books even if finalizing the payment fails afterwards.
+CREATE TABLE payment_refunds (
+    id                  UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id             UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    payment_id          UUID NOT NULL REFERENCES payments(id) ON DELETE CASCADE,
+    amount              NUMERIC(12,2) NOT NULL CHECK (amount > 0),
+    reason              TEXT,
+    status              TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'succeeded', 'failed')),
+    -- NULL for manual refunds of cash, checks and cards taken elsewhere
+    processor_refund_id TEXT,
+    processor_status    TEXT,
+    error               TEXT,
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at        TIMESTAMPTZ
+);
+
+CREATE INDEX idx_payment_refunds_payment ON payment_refunds(payment_id, created_at DESC);
+
+-- One processor refund in flight per payment; the next waits until it settles
+CREATE UNIQUE INDEX idx_payment_refunds_pending ON payment_refunds(payment_id) WHERE status = 'pending';
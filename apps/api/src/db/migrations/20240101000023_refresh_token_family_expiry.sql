@@ -0,0 +1,6 @@
+-- A refresh token family ends a fixed time after its login, however often it rotates.
+-- Families already in use get the default lifetime from now.
+ALTER TABLE refresh_tokens
+    ADD COLUMN family_expires_at TIMESTAMPTZ NOT NULL DEFAULT now() + interval '90 days';
+
+ALTER TABLE refresh_tokens ALTER COLUMN family_expires_at DROP DEFAULT;
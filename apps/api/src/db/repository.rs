@@ -1,35 +1,43 @@
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
 use crate::models::customer::{CreateCustomerRequest, Customer, CustomerListItem, UpdateCustomerRequest};
-use crate::models::job::{CreateJobRequest, Job, JobFilters, JobListItem};
+use crate::models::job::{CreateJobRequest, Job, JobFilters, JobListItem, JobStatusTransition};
 use crate::models::user::{CreateUserRequest, User, UserResponse};
 
 // ── Users ──
 
-pub async fn create_user(pool: &PgPool, req: &CreateUserRequest, password_hash: &str) -> ApiResult<User> {
-    let user = sqlx::query_as::<_, User>(
+/// `User` columns, with the role read back as text to match the model
+pub const USER_COLUMNS: &str = r#"
+    id, email, phone, password_hash, first_name, last_name, avatar_url, role::text AS role,
+    team_id, trade, hourly_rate, is_active, email_verified, phone_verified, last_login_at,
+    created_at, updated_at
+"#;
+
+pub async fn create_user(conn: &mut PgConnection, req: &CreateUserRequest, password_hash: &str) -> ApiResult<User> {
+    let user = sqlx::query_as::<_, User>(&format!(
         r#"
         INSERT INTO users (email, phone, password_hash, first_name, last_name, trade)
         VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING *
+        RETURNING {}
         "#,
-    )
+        USER_COLUMNS
+    ))
     .bind(&req.email)
     .bind(&req.phone)
     .bind(password_hash)
     .bind(&req.first_name)
     .bind(&req.last_name)
     .bind(&req.trade)
-    .fetch_one(pool)
+    .fetch_one(conn)
     .await?;
 
     Ok(user)
 }
 
 pub async fn find_user_by_email(pool: &PgPool, email: &str) -> ApiResult<Option<User>> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE email = $1", USER_COLUMNS))
         .bind(email)
         .fetch_optional(pool)
         .await?;
@@ -37,7 +45,7 @@ pub async fn find_user_by_email(pool: &PgPool, email: &str) -> ApiResult<Option<
 }
 
 pub async fn find_user_by_phone(pool: &PgPool, phone: &str) -> ApiResult<Option<User>> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE phone = $1")
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE phone = $1", USER_COLUMNS))
         .bind(phone)
         .fetch_optional(pool)
         .await?;
@@ -186,17 +194,30 @@ pub async fn delete_customer(pool: &PgPool, team_id: Uuid, id: Uuid) -> ApiResul
 
 // ── Jobs ──
 
+/// `Job` columns, with the enum columns read back as text to match the model
+pub const JOB_COLUMNS: &str = r#"
+    id, team_id, customer_id, property_id, assigned_to, parent_job_id, title, description,
+    status::text AS status, priority::text AS priority, job_type, trade, source,
+    scheduled_date, scheduled_start_time, scheduled_end_time, arrival_window_start, arrival_window_end,
+    estimated_duration_minutes, actual_duration_minutes, started_at, completed_at,
+    budget_amount, total_amount, internal_notes, access_instructions, permit_required, warranty_job,
+    recurring_rule_id, po_number, tags, required_skills, required_license_types,
+    version, created_at, updated_at, deleted_at
+"#;
+
 pub async fn create_job(pool: &PgPool, team_id: Uuid, req: &CreateJobRequest) -> ApiResult<Job> {
     let tags = req.tags.clone().unwrap_or_default();
 
-    let job = sqlx::query_as::<_, Job>(
+    let job = sqlx::query_as::<_, Job>(&format!(
         r#"
         INSERT INTO jobs (team_id, customer_id, property_id, assigned_to, title, description, priority, job_type, trade,
-                          scheduled_date, scheduled_start_time, estimated_duration_minutes, access_instructions, internal_notes, tags)
-        VALUES ($1, $2, $3, $4, $5, $6, COALESCE($7, 'normal')::job_priority, $8, $9, $10, $11, $12, $13, $14, $15)
-        RETURNING *
+                          scheduled_date, scheduled_start_time, estimated_duration_minutes, access_instructions, internal_notes, tags,
+                          required_skills, required_license_types)
+        VALUES ($1, $2, $3, $4, $5, $6, COALESCE($7, 'normal')::job_priority, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+        RETURNING {}
         "#,
-    )
+        JOB_COLUMNS
+    ))
     .bind(team_id)
     .bind(req.customer_id)
     .bind(req.property_id)
@@ -212,6 +233,8 @@ pub async fn create_job(pool: &PgPool, team_id: Uuid, req: &CreateJobRequest) ->
     .bind(&req.access_instructions)
     .bind(&req.internal_notes)
     .bind(&tags)
+    .bind(req.required_skills.clone().unwrap_or_default())
+    .bind(req.required_license_types.clone().unwrap_or_default())
     .fetch_one(pool)
     .await?;
 
@@ -219,9 +242,10 @@ pub async fn create_job(pool: &PgPool, team_id: Uuid, req: &CreateJobRequest) ->
 }
 
 pub async fn get_job(pool: &PgPool, team_id: Uuid, id: Uuid) -> ApiResult<Job> {
-    sqlx::query_as::<_, Job>(
-        "SELECT * FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
-    )
+    sqlx::query_as::<_, Job>(&format!(
+        "SELECT {} FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
+        JOB_COLUMNS
+    ))
     .bind(id)
     .bind(team_id)
     .fetch_optional(pool)
@@ -264,28 +288,29 @@ pub async fn list_jobs(pool: &PgPool, team_id: Uuid, filters: &JobFilters, limit
     Ok(jobs)
 }
 
-pub async fn update_job_status(pool: &PgPool, team_id: Uuid, job_id: Uuid, new_status: &str, user_id: Uuid, lat: Option<f64>, lng: Option<f64>, note: Option<&str>) -> ApiResult<Job> {
-    let job = get_job(pool, team_id, job_id).await?;
-
+/// Record a status change and apply it. The caller validates the transition and holds
+/// the job row lock, so `from_status` is the status it read under that lock.
+pub async fn update_job_status(conn: &mut PgConnection, team_id: Uuid, job_id: Uuid, from_status: &str, user_id: Uuid, transition: &JobStatusTransition) -> ApiResult<Job> {
     // Record status history
     sqlx::query(
         r#"
-        INSERT INTO job_status_history (job_id, from_status, to_status, changed_by, latitude, longitude, note)
-        VALUES ($1, $2::job_status, $3::job_status, $4, $5, $6, $7)
+        INSERT INTO job_status_history (job_id, from_status, to_status, changed_by, latitude, longitude, note, geofence_event_id)
+        VALUES ($1, $2::job_status, $3::job_status, $4, $5, $6, $7, $8)
         "#,
     )
     .bind(job_id)
-    .bind(&job.status)
-    .bind(new_status)
+    .bind(from_status)
+    .bind(&transition.status)
     .bind(user_id)
-    .bind(lat)
-    .bind(lng)
-    .bind(note)
-    .execute(pool)
+    .bind(transition.latitude)
+    .bind(transition.longitude)
+    .bind(&transition.note)
+    .bind(transition.geofence_event_id)
+    .execute(&mut *conn)
     .await?;
 
     // Update job status
-    let updated = sqlx::query_as::<_, Job>(
+    let updated = sqlx::query_as::<_, Job>(&format!(
         r#"
         UPDATE jobs SET
             status = $3::job_status,
@@ -293,13 +318,14 @@ pub async fn update_job_status(pool: &PgPool, team_id: Uuid, job_id: Uuid, new_s
             completed_at = CASE WHEN $3 = 'completed' THEN now() ELSE completed_at END,
             version = version + 1
         WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL
-        RETURNING *
+        RETURNING {}
         "#,
-    )
+        JOB_COLUMNS
+    ))
     .bind(job_id)
     .bind(team_id)
-    .bind(new_status)
-    .fetch_one(pool)
+    .bind(&transition.status)
+    .fetch_one(&mut *conn)
     .await?;
 
     Ok(updated)
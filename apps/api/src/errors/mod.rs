@@ -11,6 +11,15 @@ pub enum ApiError {
     #[error("Insufficient permissions")]
     Forbidden,
 
+    #[error("Create or join a team to continue")]
+    TeamRequired,
+
+    #[error("Verify your email or phone to continue")]
+    VerificationRequired,
+
+    #[error("Set up two-factor authentication to continue")]
+    MfaEnrollmentRequired,
+
     #[error("{0} not found")]
     NotFound(String),
 
@@ -41,6 +50,9 @@ impl IntoResponse for ApiError {
         let (status, error_code, message) = match &self {
             ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "UNAUTHORIZED", self.to_string()),
             ApiError::Forbidden => (StatusCode::FORBIDDEN, "FORBIDDEN", self.to_string()),
+            ApiError::TeamRequired => (StatusCode::FORBIDDEN, "TEAM_REQUIRED", self.to_string()),
+            ApiError::VerificationRequired => (StatusCode::FORBIDDEN, "VERIFICATION_REQUIRED", self.to_string()),
+            ApiError::MfaEnrollmentRequired => (StatusCode::FORBIDDEN, "MFA_ENROLLMENT_REQUIRED", self.to_string()),
             ApiError::NotFound(resource) => (
                 StatusCode::NOT_FOUND,
                 "NOT_FOUND",
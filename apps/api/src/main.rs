@@ -15,11 +15,19 @@ mod middleware;
 mod models;
 mod routes;
 mod services;
+#[cfg(test)]
+mod test_support;
 
 pub struct AppState {
     pub db: sqlx::PgPool,
     pub config: config::Settings,
     pub redis: redis::aio::ConnectionManager,
+    pub jobs: services::job_queue::JobQueue,
+    pub messenger: Arc<dyn services::messaging::MessageSender>,
+    pub payments: Arc<dyn services::payment_provider::PaymentProvider>,
+    pub storage: Arc<dyn services::storage::ObjectStorage>,
+    pub realtime: services::realtime::RealtimeHub,
+    pub routing: Arc<dyn services::routing::RoutingProvider>,
 }
 
 #[tokio::main]
@@ -48,22 +56,46 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Database connected and migrations applied");
 
+    // Fixes arriving before the first retention sweep still need this month's partition
+    services::gps_store::ensure_partitions(&db_pool).await?;
+
     let redis_client = redis::Client::open(settings.redis.url.as_str())?;
-    let redis_conn = redis::aio::ConnectionManager::new(redis_client).await?;
+    let redis_conn = redis::aio::ConnectionManager::new(redis_client.clone()).await?;
 
     tracing::info!("Redis connected");
 
+    let jobs = services::job_queue::JobQueue::new(db_pool.clone(), &settings.queue);
+    let messenger = services::messaging::from_settings(&settings)?;
+    let payments = services::payment_provider::from_settings(&settings)?;
+    let storage = services::storage::from_settings(&settings)?;
+    let realtime = services::realtime::RealtimeHub::new();
+    let routing = services::routing::from_settings(&settings)?;
+
     let state = Arc::new(AppState {
         db: db_pool,
         config: settings.clone(),
         redis: redis_conn,
+        jobs,
+        messenger,
+        payments,
+        storage,
+        realtime: realtime.clone(),
+        routing,
     });
 
+    services::scheduler::ensure_all_team_defaults(&state.db).await?;
+    services::job_queue::spawn_worker(state.clone());
+    services::scheduler::spawn_scheduler(state.clone());
+    services::realtime::spawn_listener(redis_client, realtime);
+
     let app = Router::new()
         .nest("/api/v1", routes::api_router(state.clone()))
         .layer(axum::middleware::from_fn(middleware::rate_limit::rate_limit_middleware))
         .layer(axum::middleware::from_fn(middleware::request_id::request_id_middleware))
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+            // Like the default span without the query string, which can hold `?access_token=`
+            tracing::debug_span!("request", method = %request.method(), path = %request.uri().path(), version = ?request.version())
+        }))
         .layer(middleware::cors::cors_layer(&settings))
         .with_state(state);
 
@@ -71,7 +103,7 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("FieldForge API listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
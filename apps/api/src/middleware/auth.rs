@@ -1,15 +1,20 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use axum::body::Body;
-use axum::extract::State;
+use axum::extract::{ConnectInfo, MatchedPath, State};
 use axum::http::Request;
 use axum::middleware::Next;
 use axum::response::Response;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::ApiError;
+use crate::middleware::permissions::{self, Permission};
+use crate::services::auth_service;
 use crate::AppState;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +33,50 @@ pub struct AuthUser {
     pub email: String,
     pub role: String,
     pub team_id: Option<Uuid>,
+    pub permissions: HashSet<Permission>,
+    /// The active team makes TOTP mandatory for money-moving permissions
+    pub team_requires_mfa: bool,
+    pub mfa_enrolled: bool,
+    /// Set when the request authenticated with an API key rather than a user session
+    pub api_key_id: Option<Uuid>,
+}
+
+/// Selects which of the user's teams a request acts on; defaults to `users.team_id`
+pub const TEAM_HEADER: &str = "X-Team-Id";
+
+#[derive(sqlx::FromRow)]
+struct RoleGrants {
+    role: String,
+    is_active: bool,
+    team_id: Option<Uuid>,
+    granted: Vec<String>,
+    revoked: Vec<String>,
+    team_requires_mfa: bool,
+    mfa_enrolled: bool,
+}
+
+/// Integrations can send a key here instead of `Authorization: Bearer`
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Every API key starts with this, which is how a Bearer value is told apart from a JWT
+pub const API_KEY_PREFIX: &str = "ffk_";
+
+#[derive(sqlx::FromRow)]
+struct ApiKeyGrants {
+    key_id: Uuid,
+    team_id: Uuid,
+    scopes: Vec<String>,
+    created_by: Uuid,
+    email: String,
+    role: String,
+    granted: Vec<String>,
+    revoked: Vec<String>,
+    team_requires_mfa: bool,
+    mfa_enrolled: bool,
+}
+
+fn header_str<'a>(request: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    request.headers().get(name).and_then(|v| v.to_str().ok())
 }
 
 pub async fn require_auth(
@@ -35,13 +84,56 @@ pub async fn require_auth(
     mut request: Request<Body>,
     next: Next,
 ) -> Result<Response, ApiError> {
-    let token = request
+    let bearer = header_str(&request, "Authorization").and_then(|v| v.strip_prefix("Bearer "));
+    let api_key = header_str(&request, API_KEY_HEADER)
+        .or(bearer.filter(|v| v.starts_with(API_KEY_PREFIX)))
+        .map(str::to_string);
+    let bearer = bearer.map(str::to_string);
+
+    let requested_team = request
         .headers()
-        .get("Authorization")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|v| v.strip_prefix("Bearer "))
-        .ok_or(ApiError::Unauthorized)?;
+        .get(TEAM_HEADER)
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(|v| v.parse::<Uuid>().ok())
+                .ok_or_else(|| ApiError::BadRequest(format!("{} must be a team id", TEAM_HEADER)))
+        })
+        .transpose()?;
 
+    let auth_user = match api_key {
+        Some(key) => authenticate_api_key(&state, &key, requested_team).await?,
+        None => authenticate_jwt(&state, bearer.as_deref().ok_or(ApiError::Unauthorized)?, requested_team).await?,
+    };
+
+    let Some(api_key_id) = auth_user.api_key_id else {
+        request.extensions_mut().insert(auth_user);
+        return Ok(next.run(request).await);
+    };
+
+    let audit = ApiKeyAudit::capture(&request, &auth_user, &state.config.server.trusted_proxies);
+    request.extensions_mut().insert(auth_user);
+    let response = next.run(request).await;
+
+    if let Some(audit) = audit {
+        if let Err(e) = audit.record(&state, api_key_id, response.status().as_u16()).await {
+            tracing::error!(api_key_id = %api_key_id, error = ?e, "Failed to record API key audit entry");
+        }
+    }
+    Ok(response)
+}
+
+/// Resolve a bearer value, either a JWT or an API key, the same way [`require_auth`] does.
+/// For connections that can't send headers, such as WebSockets.
+pub async fn authenticate(state: &AppState, token: &str, requested_team: Option<Uuid>) -> Result<AuthUser, ApiError> {
+    if token.starts_with(API_KEY_PREFIX) {
+        authenticate_api_key(state, token, requested_team).await
+    } else {
+        authenticate_jwt(state, token, requested_team).await
+    }
+}
+
+async fn authenticate_jwt(state: &AppState, token: &str, requested_team: Option<Uuid>) -> Result<AuthUser, ApiError> {
     let claims = decode::<Claims>(
         token,
         &DecodingKey::from_secret(state.config.auth.jwt_secret.as_bytes()),
@@ -50,16 +142,208 @@ pub async fn require_auth(
     .map_err(|_| ApiError::Unauthorized)?
     .claims;
 
-    let auth_user = AuthUser {
+    // Membership, role and team overrides are read per request so demotions and
+    // deactivations take effect before the access token expires
+    let grants = sqlx::query_as::<_, RoleGrants>(
+        r#"
+        SELECT COALESCE(m.role, u.role::text) AS role, u.is_active, m.team_id,
+               COALESCE(array_agg(p.permission) FILTER (WHERE p.granted), '{}') AS granted,
+               COALESCE(array_agg(p.permission) FILTER (WHERE NOT p.granted), '{}') AS revoked,
+               COALESCE(t.require_mfa, false) AS team_requires_mfa,
+               EXISTS (SELECT 1 FROM user_totp ut WHERE ut.user_id = u.id AND ut.confirmed_at IS NOT NULL) AS mfa_enrolled
+        FROM users u
+        LEFT JOIN LATERAL (
+            SELECT tm.team_id, tm.role FROM team_memberships tm
+            WHERE tm.user_id = u.id AND tm.is_active = true
+              AND ($2::uuid IS NULL OR tm.team_id = $2)
+            ORDER BY (tm.team_id = u.team_id) IS TRUE DESC, tm.created_at
+            LIMIT 1
+        ) m ON true
+        LEFT JOIN teams t ON t.id = m.team_id
+        LEFT JOIN team_role_permissions p ON p.team_id = m.team_id AND p.role = m.role
+        WHERE u.id = $1
+        GROUP BY u.id, m.team_id, m.role, t.require_mfa
+        "#,
+    )
+    .bind(claims.sub)
+    .bind(requested_team)
+    .fetch_optional(&state.db)
+    .await?
+    .filter(|grants| grants.is_active)
+    .ok_or(ApiError::Unauthorized)?;
+
+    if requested_team.is_some() && grants.team_id.is_none() {
+        return Err(ApiError::Forbidden);
+    }
+
+    // Without a team there is nothing to grant permissions on
+    let permissions = match grants.team_id {
+        Some(_) => permissions::resolve(&grants.role, &grants.granted, &grants.revoked),
+        None => HashSet::new(),
+    };
+
+    Ok(AuthUser {
         id: claims.sub,
         email: claims.email,
-        role: claims.role,
-        team_id: claims.team_id,
-    };
+        role: grants.role,
+        team_id: grants.team_id,
+        permissions,
+        team_requires_mfa: grants.team_requires_mfa,
+        mfa_enrolled: grants.mfa_enrolled,
+        api_key_id: None,
+    })
+}
 
-    request.extensions_mut().insert(auth_user);
+/// Resolve an API key to its team and the user who created it. The key gets its scopes
+/// limited to what that user can currently do, so demoting or removing the creator
+/// narrows or disables their keys.
+async fn authenticate_api_key(state: &AppState, key: &str, requested_team: Option<Uuid>) -> Result<AuthUser, ApiError> {
+    let grants = sqlx::query_as::<_, ApiKeyGrants>(
+        r#"
+        SELECT k.id AS key_id, k.team_id, k.scopes, k.created_by, COALESCE(u.email, '') AS email, m.role,
+               COALESCE(array_agg(p.permission) FILTER (WHERE p.granted), '{}') AS granted,
+               COALESCE(array_agg(p.permission) FILTER (WHERE NOT p.granted), '{}') AS revoked,
+               t.require_mfa AS team_requires_mfa,
+               EXISTS (SELECT 1 FROM user_totp ut WHERE ut.user_id = k.created_by AND ut.confirmed_at IS NOT NULL) AS mfa_enrolled
+        FROM api_keys k
+        JOIN teams t ON t.id = k.team_id
+        JOIN users u ON u.id = k.created_by AND u.is_active = true
+        JOIN team_memberships m ON m.team_id = k.team_id AND m.user_id = k.created_by AND m.is_active = true
+        LEFT JOIN team_role_permissions p ON p.team_id = k.team_id AND p.role = m.role
+        WHERE k.key_hash = $1 AND k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > now())
+        GROUP BY k.id, t.require_mfa, u.email, m.role
+        "#,
+    )
+    .bind(auth_service::hash_token(key))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ApiError::Unauthorized)?;
+
+    if requested_team.is_some_and(|team| team != grants.team_id) {
+        return Err(ApiError::Forbidden);
+    }
+
+    let held = permissions::resolve(&grants.role, &grants.granted, &grants.revoked);
+    let permissions = grants
+        .scopes
+        .iter()
+        .filter_map(|s| Permission::parse(s))
+        .filter(|p| held.contains(p))
+        .collect();
+
+    // Coarse enough that a busy integration doesn't write on every request
+    sqlx::query(
+        "UPDATE api_keys SET last_used_at = now() WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')",
+    )
+    .bind(grants.key_id)
+    .execute(&state.db)
+    .await?;
+
+    Ok(AuthUser {
+        id: grants.created_by,
+        email: grants.email,
+        role: grants.role,
+        team_id: Some(grants.team_id),
+        permissions,
+        team_requires_mfa: grants.team_requires_mfa,
+        mfa_enrolled: grants.mfa_enrolled,
+        api_key_id: Some(grants.key_id),
+    })
+}
+
+/// What an API key request did, captured before the handler consumes the request
+struct ApiKeyAudit {
+    team_id: Uuid,
+    user_id: Uuid,
+    action: String,
+    resource_type: String,
+    resource_id: Option<Uuid>,
+    path: String,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl ApiKeyAudit {
+    /// Every request made with a key is recorded, reads included
+    fn capture(request: &Request<Body>, auth: &AuthUser, trusted_proxies: &[IpAddr]) -> Option<Self> {
+        let method = request.method();
+        let path = request.uri().path().to_string();
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|m| m.as_str().to_string())
+            .unwrap_or_else(|| path.clone());
+        let route = route.strip_prefix("/api/v1").unwrap_or(&route).to_string();
+        let resource_type = route
+            .split('/')
+            .find(|s| !s.is_empty() && !s.starts_with('{'))
+            .unwrap_or("api")
+            .replace('-', "_");
+
+        Some(Self {
+            team_id: auth.team_id?,
+            user_id: auth.id,
+            action: format!("{} {}", method, route),
+            resource_type,
+            resource_id: path.split('/').find_map(|s| s.parse::<Uuid>().ok()),
+            path,
+            ip_address: client_ip(request, trusted_proxies).map(|ip| ip.to_string()),
+            user_agent: header_str(request, "user-agent").map(str::to_string),
+        })
+    }
+
+    async fn record(self, state: &AppState, api_key_id: Uuid, status: u16) -> Result<(), ApiError> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log (team_id, user_id, action, resource_type, resource_id, changes, ip_address, user_agent, api_key_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(self.team_id)
+        .bind(self.user_id)
+        .bind(&self.action)
+        .bind(&self.resource_type)
+        .bind(self.resource_id)
+        .bind(json!({ "path": self.path, "status": status }))
+        .bind(&self.ip_address)
+        .bind(&self.user_agent)
+        .bind(api_key_id)
+        .execute(&state.db)
+        .await?;
+        Ok(())
+    }
+}
+
+/// The connecting address, or when that is a trusted proxy, the nearest address in
+/// `X-Forwarded-For` that isn't one. Entries a client wrote itself sit further left and
+/// are never reached.
+fn client_ip(request: &Request<Body>, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>()?.0.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded = header_str(request, "x-forwarded-for").unwrap_or_default();
+    let mut nearest = peer;
+    for hop in forwarded.rsplit(',') {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        nearest = ip;
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+    }
+    Some(nearest)
+}
 
-    Ok(next.run(request).await)
+/// For account-level routes (sessions, second factor, personal notifications) that act
+/// as the signed-in person. API keys act for a team, so they are turned away here.
+pub async fn deny_api_keys(request: Request<Body>, next: Next) -> Result<Response, ApiError> {
+    match request.extensions().get::<AuthUser>() {
+        Some(auth) if auth.api_key_id.is_some() => Err(ApiError::Forbidden),
+        _ => Ok(next.run(request).await),
+    }
 }
 
 pub fn verify_token(token: &str, secret: &str) -> Result<Claims, ApiError> {
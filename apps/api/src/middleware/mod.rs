@@ -1,4 +1,6 @@
 pub mod auth;
 pub mod cors;
+pub mod permissions;
 pub mod rate_limit;
 pub mod request_id;
+pub mod team;
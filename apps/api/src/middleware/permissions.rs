@@ -0,0 +1,396 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::auth::AuthUser;
+
+/// Everything a protected route can require. Stored and exchanged as `resource.action`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    #[serde(rename = "customers.read")]
+    CustomersRead,
+    #[serde(rename = "customers.write")]
+    CustomersWrite,
+    #[serde(rename = "customers.delete")]
+    CustomersDelete,
+    #[serde(rename = "jobs.read")]
+    JobsRead,
+    /// See every job on the team rather than only jobs assigned to you
+    #[serde(rename = "jobs.read_all")]
+    JobsReadAll,
+    #[serde(rename = "jobs.write")]
+    JobsWrite,
+    #[serde(rename = "jobs.update_status")]
+    JobsUpdateStatus,
+    /// Field work on a job: notes, photos and checklists
+    #[serde(rename = "jobs.work")]
+    JobsWork,
+    #[serde(rename = "estimates.read")]
+    EstimatesRead,
+    #[serde(rename = "estimates.write")]
+    EstimatesWrite,
+    #[serde(rename = "estimates.send")]
+    EstimatesSend,
+    /// Record a customer's approval or decline and convert to an invoice
+    #[serde(rename = "estimates.approve")]
+    EstimatesApprove,
+    #[serde(rename = "invoices.read")]
+    InvoicesRead,
+    #[serde(rename = "invoices.write")]
+    InvoicesWrite,
+    #[serde(rename = "invoices.send")]
+    InvoicesSend,
+    #[serde(rename = "invoices.void")]
+    InvoicesVoid,
+    #[serde(rename = "payments.read")]
+    PaymentsRead,
+    #[serde(rename = "payments.record")]
+    PaymentsRecord,
+    #[serde(rename = "payments.refund")]
+    PaymentsRefund,
+    #[serde(rename = "time.track")]
+    TimeTrack,
+    #[serde(rename = "inventory.read")]
+    InventoryRead,
+    #[serde(rename = "inventory.write")]
+    InventoryWrite,
+    #[serde(rename = "purchasing.manage")]
+    PurchasingManage,
+    #[serde(rename = "fleet.read")]
+    FleetRead,
+    #[serde(rename = "fleet.write")]
+    FleetWrite,
+    #[serde(rename = "expenses.read")]
+    ExpensesRead,
+    #[serde(rename = "expenses.write")]
+    ExpensesWrite,
+    #[serde(rename = "messages.read")]
+    MessagesRead,
+    #[serde(rename = "messages.send")]
+    MessagesSend,
+    #[serde(rename = "marketing.read")]
+    MarketingRead,
+    #[serde(rename = "marketing.write")]
+    MarketingWrite,
+    #[serde(rename = "documents.read")]
+    DocumentsRead,
+    #[serde(rename = "documents.write")]
+    DocumentsWrite,
+    #[serde(rename = "compliance.read")]
+    ComplianceRead,
+    #[serde(rename = "compliance.write")]
+    ComplianceWrite,
+    #[serde(rename = "team.read")]
+    TeamRead,
+    #[serde(rename = "team.manage")]
+    TeamManage,
+    #[serde(rename = "team.permissions")]
+    TeamPermissions,
+    /// Team settings, automation, tags, scheduled tasks and the job queue
+    #[serde(rename = "settings.manage")]
+    SettingsManage,
+    #[serde(rename = "webhooks.manage")]
+    WebhooksManage,
+    #[serde(rename = "api_keys.manage")]
+    ApiKeysManage,
+    #[serde(rename = "audit.read")]
+    AuditRead,
+    #[serde(rename = "gps.track")]
+    GpsTrack,
+    #[serde(rename = "gps.view")]
+    GpsView,
+    /// Technician shifts, team holidays and reviewing time off
+    #[serde(rename = "schedule.manage")]
+    ScheduleManage,
+}
+
+use Permission::*;
+
+impl Permission {
+    pub const ALL: &'static [Permission] = &[
+        CustomersRead, CustomersWrite, CustomersDelete,
+        JobsRead, JobsReadAll, JobsWrite, JobsUpdateStatus, JobsWork,
+        EstimatesRead, EstimatesWrite, EstimatesSend, EstimatesApprove,
+        InvoicesRead, InvoicesWrite, InvoicesSend, InvoicesVoid,
+        PaymentsRead, PaymentsRecord, PaymentsRefund,
+        TimeTrack,
+        InventoryRead, InventoryWrite, PurchasingManage,
+        FleetRead, FleetWrite,
+        ExpensesRead, ExpensesWrite,
+        MessagesRead, MessagesSend,
+        MarketingRead, MarketingWrite,
+        DocumentsRead, DocumentsWrite,
+        ComplianceRead, ComplianceWrite,
+        TeamRead, TeamManage, TeamPermissions,
+        SettingsManage, WebhooksManage, ApiKeysManage, AuditRead,
+        GpsTrack, GpsView,
+        ScheduleManage,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CustomersRead => "customers.read",
+            CustomersWrite => "customers.write",
+            CustomersDelete => "customers.delete",
+            JobsRead => "jobs.read",
+            JobsReadAll => "jobs.read_all",
+            JobsWrite => "jobs.write",
+            JobsUpdateStatus => "jobs.update_status",
+            JobsWork => "jobs.work",
+            EstimatesRead => "estimates.read",
+            EstimatesWrite => "estimates.write",
+            EstimatesSend => "estimates.send",
+            EstimatesApprove => "estimates.approve",
+            InvoicesRead => "invoices.read",
+            InvoicesWrite => "invoices.write",
+            InvoicesSend => "invoices.send",
+            InvoicesVoid => "invoices.void",
+            PaymentsRead => "payments.read",
+            PaymentsRecord => "payments.record",
+            PaymentsRefund => "payments.refund",
+            TimeTrack => "time.track",
+            InventoryRead => "inventory.read",
+            InventoryWrite => "inventory.write",
+            PurchasingManage => "purchasing.manage",
+            FleetRead => "fleet.read",
+            FleetWrite => "fleet.write",
+            ExpensesRead => "expenses.read",
+            ExpensesWrite => "expenses.write",
+            MessagesRead => "messages.read",
+            MessagesSend => "messages.send",
+            MarketingRead => "marketing.read",
+            MarketingWrite => "marketing.write",
+            DocumentsRead => "documents.read",
+            DocumentsWrite => "documents.write",
+            ComplianceRead => "compliance.read",
+            ComplianceWrite => "compliance.write",
+            TeamRead => "team.read",
+            TeamManage => "team.manage",
+            TeamPermissions => "team.permissions",
+            SettingsManage => "settings.manage",
+            WebhooksManage => "webhooks.manage",
+            ApiKeysManage => "api_keys.manage",
+            AuditRead => "audit.read",
+            GpsTrack => "gps.track",
+            GpsView => "gps.view",
+            ScheduleManage => "schedule.manage",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Permission> {
+        Self::ALL.iter().copied().find(|p| p.as_str() == value)
+    }
+
+    /// Permissions that move money. Teams with `require_mfa` only allow them to
+    /// members who have enrolled a second factor.
+    pub fn requires_mfa(&self) -> bool {
+        matches!(self, PaymentsRefund | InvoicesVoid)
+    }
+}
+
+/// Every role a team member can hold
+pub const ROLES: &[&str] = &["owner", "admin", "office_manager", "technician", "apprentice"];
+
+/// Roles whose permission sets a team may override. The owner always has everything.
+pub const OVERRIDABLE_ROLES: &[&str] = &["admin", "office_manager", "technician", "apprentice"];
+
+const OFFICE_MANAGER: &[Permission] = &[
+    CustomersRead, CustomersWrite, CustomersDelete,
+    JobsRead, JobsReadAll, JobsWrite, JobsUpdateStatus, JobsWork,
+    EstimatesRead, EstimatesWrite, EstimatesSend, EstimatesApprove,
+    InvoicesRead, InvoicesWrite, InvoicesSend,
+    PaymentsRead, PaymentsRecord,
+    TimeTrack,
+    InventoryRead, InventoryWrite, PurchasingManage,
+    FleetRead, FleetWrite,
+    ExpensesRead, ExpensesWrite,
+    MessagesRead, MessagesSend,
+    MarketingRead, MarketingWrite,
+    DocumentsRead, DocumentsWrite,
+    ComplianceRead, ComplianceWrite,
+    TeamRead,
+    GpsTrack, GpsView,
+    ScheduleManage,
+];
+
+const TECHNICIAN: &[Permission] = &[
+    CustomersRead,
+    JobsRead, JobsUpdateStatus, JobsWork,
+    EstimatesRead, EstimatesWrite,
+    InvoicesRead,
+    PaymentsRecord,
+    TimeTrack,
+    InventoryRead,
+    FleetRead,
+    ExpensesRead, ExpensesWrite,
+    MessagesRead, MessagesSend,
+    DocumentsRead, DocumentsWrite,
+    ComplianceRead,
+    TeamRead,
+    GpsTrack,
+];
+
+const APPRENTICE: &[Permission] = &[
+    CustomersRead,
+    JobsRead, JobsUpdateStatus, JobsWork,
+    TimeTrack,
+    InventoryRead,
+    FleetRead,
+    MessagesRead,
+    DocumentsRead,
+    ComplianceRead,
+    TeamRead,
+    GpsTrack,
+];
+
+/// Built-in permissions for a role, before team overrides. Unknown roles get nothing.
+pub fn role_defaults(role: &str) -> HashSet<Permission> {
+    match role {
+        "owner" => Permission::ALL.iter().copied().collect(),
+        "admin" => Permission::ALL.iter().copied().filter(|p| *p != TeamPermissions).collect(),
+        "office_manager" => OFFICE_MANAGER.iter().copied().collect(),
+        "technician" => TECHNICIAN.iter().copied().collect(),
+        "apprentice" => APPRENTICE.iter().copied().collect(),
+        _ => HashSet::new(),
+    }
+}
+
+/// Apply a team's overrides to the role defaults. Overrides naming a permission this
+/// build doesn't know are ignored.
+pub fn resolve(role: &str, granted: &[String], revoked: &[String]) -> HashSet<Permission> {
+    let mut permissions = role_defaults(role);
+    if role == "owner" {
+        return permissions;
+    }
+    permissions.extend(granted.iter().filter_map(|p| Permission::parse(p)));
+    for permission in revoked.iter().filter_map(|p| Permission::parse(p)) {
+        permissions.remove(&permission);
+    }
+    permissions
+}
+
+impl AuthUser {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    pub fn require(&self, permission: Permission) -> ApiResult<()> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden)
+        }
+    }
+
+    /// The team requires a second factor this user hasn't set up yet
+    pub fn needs_mfa_enrollment(&self) -> bool {
+        self.team_requires_mfa && !self.mfa_enrolled
+    }
+
+    /// The assignee to restrict job queries to, or `None` when the user can see every job
+    pub fn job_scope(&self) -> Option<Uuid> {
+        if self.has(JobsReadAll) {
+            None
+        } else {
+            Some(self.id)
+        }
+    }
+}
+
+/// Check the job belongs to the team and, for users limited to their own jobs, that it
+/// is assigned to them. Reported as not found so restricted users can't probe for ids.
+pub async fn ensure_job_access(pool: &PgPool, auth: &AuthUser, team_id: Uuid, job_id: Uuid) -> ApiResult<()> {
+    let visible = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM jobs
+            WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL
+              AND ($3::uuid IS NULL OR assigned_to = $3)
+        )
+        "#,
+    )
+    .bind(job_id)
+    .bind(team_id)
+    .bind(auth.job_scope())
+    .fetch_one(pool)
+    .await?;
+
+    if visible {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound("Job".into()))
+    }
+}
+
+/// Route layer that rejects requests whose user lacks `permission`:
+///
+/// ```ignore
+/// .route("/invoices/:id/void", post(void_invoice).route_layer(require(Permission::InvoicesVoid)))
+/// ```
+///
+/// Must sit inside `require_auth`, which puts the `AuthUser` in the request extensions.
+pub fn require(permission: Permission) -> RequirePermission {
+    RequirePermission { permission }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequirePermission {
+    permission: Permission,
+}
+
+impl<S> Layer<S> for RequirePermission {
+    type Service = RequirePermissionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequirePermissionService { inner, permission: self.permission }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequirePermissionService<S> {
+    inner: S,
+    permission: Permission,
+}
+
+impl<S> Service<Request<Body>> for RequirePermissionService<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let denied = match request.extensions().get::<AuthUser>() {
+            Some(user) if user.team_id.is_none() => Some(ApiError::TeamRequired),
+            Some(user) if self.permission.requires_mfa() && user.needs_mfa_enrollment() => {
+                Some(ApiError::MfaEnrollmentRequired)
+            }
+            Some(user) if user.has(self.permission) => None,
+            _ => Some(ApiError::Forbidden),
+        };
+
+        if let Some(error) = denied {
+            tracing::debug!(permission = self.permission.as_str(), path = %request.uri().path(), "Permission denied");
+            return Box::pin(async { Ok(error.into_response()) });
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
@@ -14,10 +14,11 @@ pub async fn request_id_middleware(mut req: Request, next: Next) -> Response {
         request_id.parse().unwrap(),
     );
 
+    // The path only: the WebSocket handshake carries its access token in the query string
     let span = tracing::info_span!(
         "request",
         method = %req.method(),
-        uri = %req.uri(),
+        path = %req.uri().path(),
         request_id = %request_id,
     );
 
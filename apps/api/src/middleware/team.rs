@@ -0,0 +1,27 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+use crate::middleware::auth::AuthUser;
+
+/// The team a request acts on, with the authenticated user. `require_auth` resolves the
+/// team once per request from the `X-Team-Id` header or the user's default team and
+/// checks membership; this extractor rejects users who don't have a team yet.
+#[derive(Debug, Clone)]
+pub struct TeamContext {
+    pub team_id: Uuid,
+    pub auth: AuthUser,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TeamContext {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let auth = parts.extensions.get::<AuthUser>().cloned().ok_or(ApiError::Unauthorized)?;
+        let team_id = auth.team_id.ok_or(ApiError::TeamRequired)?;
+        Ok(TeamContext { team_id, auth })
+    }
+}
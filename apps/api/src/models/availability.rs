@@ -0,0 +1,84 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A weekly block of work for one technician
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TechnicianShift {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub user_id: Uuid,
+    /// 0 is Sunday
+    pub weekday: i16,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub effective_from: Option<NaiveDate>,
+    pub effective_until: Option<NaiveDate>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShiftInput {
+    pub weekday: i16,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub effective_from: Option<NaiveDate>,
+    pub effective_until: Option<NaiveDate>,
+}
+
+/// A technician's whole weekly pattern, replacing the current one
+#[derive(Debug, Deserialize)]
+pub struct ReplaceShiftsRequest {
+    pub shifts: Vec<ShiftInput>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TimeOffRequest {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub user_id: Uuid,
+    /// Team-local wall-clock time
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+    /// `vacation`, `sick`, `personal`, `training` or `other`
+    pub kind: String,
+    pub reason: Option<String>,
+    /// `pending`, `approved`, `denied` or `cancelled`
+    pub status: String,
+    pub reviewed_by: Option<Uuid>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_note: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTimeOffRequest {
+    /// Someone else's request, for managers entering it on their behalf
+    pub user_id: Option<Uuid>,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+    pub kind: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewTimeOffRequest {
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TeamHoliday {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub holiday_date: NaiveDate,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateHolidayRequest {
+    pub holiday_date: NaiveDate,
+    pub name: String,
+}
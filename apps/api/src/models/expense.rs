@@ -23,6 +23,8 @@ pub struct Expense {
     pub mileage: Option<Decimal>,
     pub mileage_rate: Option<Decimal>,
     pub notes: Option<String>,
+    /// Created from the technician's GPS trips for the day
+    pub gps_mileage: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
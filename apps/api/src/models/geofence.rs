@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A technician entering or leaving a job's geofence
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct GeofenceEvent {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub job_id: Uuid,
+    pub user_id: Uuid,
+    /// `arrived` or `departed`
+    pub event_type: String,
+    /// `gps` when detected from a fix, `transition` when recorded by a manual check-in
+    pub source: String,
+    pub gps_location_id: Option<Uuid>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy: Option<f64>,
+    pub property_latitude: f64,
+    pub property_longitude: f64,
+    pub distance_meters: f64,
+    pub radius_meters: i32,
+    /// Time on the device when the fix was taken
+    pub recorded_at: DateTime<Utc>,
+    /// Time the server received it
+    pub created_at: DateTime<Utc>,
+}
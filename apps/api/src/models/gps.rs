@@ -0,0 +1,32 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A stored GPS fix
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct GpsLocation {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub user_id: Uuid,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy: Option<f64>,
+    /// Meters per second, as reported by the device
+    pub speed: Option<f64>,
+    pub heading: Option<f64>,
+    /// Time on the device when the fix was taken
+    pub recorded_at: DateTime<Utc>,
+    /// Time the server received it
+    pub created_at: DateTime<Utc>,
+}
+
+/// A fix as posted by a device
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewGpsLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy: Option<f64>,
+    pub speed: Option<f64>,
+    pub heading: Option<f64>,
+    pub recorded_at: Option<DateTime<Utc>>,
+}
@@ -61,6 +61,10 @@ pub struct Job {
     pub recurring_rule_id: Option<Uuid>,
     pub po_number: Option<String>,
     pub tags: Vec<String>,
+    /// Matched against technicians' skills and certifications when dispatching
+    pub required_skills: Vec<String>,
+    /// Technicians need an active license of each type
+    pub required_license_types: Vec<String>,
     pub version: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -83,6 +87,8 @@ pub struct CreateJobRequest {
     pub access_instructions: Option<String>,
     pub internal_notes: Option<String>,
     pub tags: Option<Vec<String>>,
+    pub required_skills: Option<Vec<String>>,
+    pub required_license_types: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -98,6 +104,11 @@ pub struct UpdateJobRequest {
     pub estimated_duration_minutes: Option<i32>,
     pub internal_notes: Option<String>,
     pub tags: Option<Vec<String>>,
+    pub required_skills: Option<Vec<String>>,
+    pub required_license_types: Option<Vec<String>>,
+    /// Apply a new assignee or schedule even if it conflicts
+    #[serde(default)]
+    pub force: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -106,6 +117,9 @@ pub struct JobStatusTransition {
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
     pub note: Option<String>,
+    /// Set when a geofence crossing made the change, never by clients
+    #[serde(skip)]
+    pub geofence_event_id: Option<Uuid>,
 }
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
@@ -23,4 +23,12 @@ pub mod automation_rule;
 pub mod document;
 pub mod license;
 pub mod recurring_rule;
+pub mod background_job;
+pub mod scheduled_task;
+pub mod role_permission;
+pub mod api_key;
+pub mod webhook;
+pub mod geofence;
+pub mod gps;
+pub mod availability;
 pub mod common;
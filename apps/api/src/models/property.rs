@@ -25,6 +25,8 @@ pub struct Property {
     pub alarm_code_encrypted: Option<String>,
     pub pet_info: Option<String>,
     pub is_primary: bool,
+    /// Overrides the team's check-in radius for this property
+    pub geofence_radius_meters: Option<i32>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
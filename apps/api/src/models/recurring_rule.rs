@@ -18,6 +18,8 @@ pub struct RecurringRule {
     pub start_date: NaiveDate,
     pub end_date: Option<NaiveDate>,
     pub next_occurrence: Option<NaiveDate>,
+    pub max_occurrences: Option<i32>,
+    pub occurrences_created: i32,
     pub estimated_duration_minutes: Option<i32>,
     pub assigned_to: Option<Uuid>,
     pub job_type: Option<String>,
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RolePermissionOverride {
+    pub permission: String,
+    pub granted: bool,
+}
+
+/// Replaces every override for one role
+#[derive(Debug, Deserialize)]
+pub struct UpdateRolePermissionsRequest {
+    pub overrides: Vec<RolePermissionOverride>,
+}
@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ScheduledTask {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub name: String,
+    pub cron: String,
+    pub payload: serde_json::Value,
+    pub is_active: bool,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub next_run_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateScheduledTaskRequest {
+    pub cron: Option<String>,
+    pub is_active: Option<bool>,
+}
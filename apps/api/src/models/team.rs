@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -27,6 +27,24 @@ pub struct Team {
     pub plan_tier: String,
     pub stripe_customer_id: Option<String>,
     pub stripe_subscription_id: Option<String>,
+    pub require_mfa: bool,
+    /// Default check-in radius around a job's property; properties may override it
+    pub geofence_radius_meters: i32,
+    /// Whether geofence arrivals and departures start and pause jobs
+    pub geofence_auto_transition: bool,
+    /// Per-mile rate for mileage expenses generated from GPS trips
+    pub mileage_rate: Option<rust_decimal::Decimal>,
+    /// GPS fixes older than this are deleted
+    pub gps_retention_days: i32,
+    /// Fixes older than this are thinned to one per `gps_downsample_interval_secs`; `None` keeps every fix
+    pub gps_downsample_after_days: Option<i32>,
+    pub gps_downsample_interval_secs: i32,
+    pub working_hours_start: Option<NaiveTime>,
+    pub working_hours_end: Option<NaiveTime>,
+    /// Days the team works, 0 (Sunday) to 6
+    pub working_days: Option<Vec<i32>>,
+    /// Minimum gap the schedule keeps between a technician's jobs for travel
+    pub schedule_travel_buffer_minutes: i32,
     pub is_active: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -39,3 +57,62 @@ pub struct CreateTeamRequest {
     pub phone: Option<String>,
     pub timezone: Option<String>,
 }
+
+/// A user's membership of a team, as listed in the team's member roster
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TeamMember {
+    pub user_id: Uuid,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub first_name: String,
+    pub last_name: String,
+    pub avatar_url: Option<String>,
+    pub role: String,
+    pub hourly_rate: Option<rust_decimal::Decimal>,
+    pub is_active: bool,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// One of the teams the current user can act on via `X-Team-Id`
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TeamMembership {
+    pub team_id: Uuid,
+    pub team_name: String,
+    pub role: String,
+    pub is_default: bool,
+}
+
+/// A pending or settled invitation. The token itself is only ever returned by email.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TeamInvitation {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub role: String,
+    pub hourly_rate: Option<rust_decimal::Decimal>,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub sent_count: i32,
+    pub last_sent_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitationRequest {
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub role: String,
+    pub hourly_rate: Option<rust_decimal::Decimal>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationRequest {
+    pub token: String,
+    /// Required when the invitee doesn't have an account yet
+    pub password: Option<String>,
+}
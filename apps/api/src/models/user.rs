@@ -31,6 +31,8 @@ pub struct CreateUserRequest {
     pub first_name: String,
     pub last_name: String,
     pub trade: Option<String>,
+    /// When set, a team with this name is created with the new user as owner
+    pub company_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,6 +42,18 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    /// A current TOTP code or an unused recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     pub token: String,
@@ -61,3 +75,46 @@ pub struct UserResponse {
     pub is_active: bool,
     pub created_at: DateTime<Utc>,
 }
+
+/// Where a verification code or reset link is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContactChannel {
+    Email,
+    Phone,
+}
+
+impl ContactChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContactChannel::Email => "email",
+            ContactChannel::Phone => "phone",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendVerificationRequest {
+    pub channel: ContactChannel,
+    /// The email address or phone number to verify
+    pub destination: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmVerificationRequest {
+    pub channel: ContactChannel,
+    pub destination: String,
+    pub code: String,
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::auth::API_KEY_PREFIX;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::api_key::{ApiKey, CreateApiKeyRequest};
+use crate::services::auth_service;
+use crate::AppState;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/team/api-keys", get(list_api_keys).route_layer(require(Permission::ApiKeysManage)))
+        .route("/team/api-keys", post(create_api_key).route_layer(require(Permission::ApiKeysManage)))
+        .route("/team/api-keys/:id", delete(revoke_api_key).route_layer(require(Permission::ApiKeysManage)))
+}
+
+const API_KEY_COLUMNS: &str =
+    "id, team_id, name, prefix, scopes, created_by, expires_at, last_used_at, revoked_at, created_at";
+
+/// Characters of the key kept in the clear for display
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+async fn list_api_keys(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+) -> ApiResult<Json<serde_json::Value>> {
+    let keys = sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE team_id = $1 ORDER BY revoked_at IS NOT NULL, created_at DESC",
+        API_KEY_COLUMNS
+    ))
+    .bind(team_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({
+        "data": keys,
+        "meta": { "total": keys.len() },
+        "errors": null,
+    })))
+}
+
+/// Issue a key. Scopes are permission names and can't exceed what the creator holds;
+/// the key keeps acting within the creator's permissions from then on.
+async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if auth.api_key_id.is_some() {
+        return Err(ApiError::Forbidden);
+    }
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::Validation("Name is required".into()));
+    }
+    if req.scopes.is_empty() {
+        return Err(ApiError::Validation("At least one scope is required".into()));
+    }
+    if req.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(ApiError::Validation("expires_at must be in the future".into()));
+    }
+
+    let mut scopes = Vec::new();
+    for scope in &req.scopes {
+        let permission = Permission::parse(scope)
+            .ok_or_else(|| ApiError::Validation(format!("Unknown scope '{}'", scope)))?;
+        if matches!(permission, Permission::ApiKeysManage | Permission::TeamPermissions) {
+            return Err(ApiError::Validation(format!("'{}' can't be granted to an API key", scope)));
+        }
+        if !auth.has(permission) {
+            return Err(ApiError::Validation(format!("You don't have '{}' yourself", scope)));
+        }
+        if !scopes.contains(&permission.as_str()) {
+            scopes.push(permission.as_str());
+        }
+    }
+
+    let key = format!("{}{}", API_KEY_PREFIX, auth_service::generate_token());
+    let prefix: String = key.chars().take(DISPLAY_PREFIX_LEN).collect();
+
+    let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+        r#"
+        INSERT INTO api_keys (team_id, name, prefix, key_hash, scopes, created_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING {}
+        "#,
+        API_KEY_COLUMNS
+    ))
+    .bind(team_id)
+    .bind(name)
+    .bind(&prefix)
+    .bind(auth_service::hash_token(&key))
+    .bind(&scopes)
+    .bind(auth.id)
+    .bind(req.expires_at)
+    .fetch_one(&state.db)
+    .await?;
+
+    tracing::info!(api_key_id = %api_key.id, team_id = %team_id, user_id = %auth.id, "API key created");
+
+    Ok(Json(json!({
+        "data": { "api_key": api_key, "key": key },
+        "meta": { "message": "Copy this key now; it won't be shown again" },
+        "errors": null,
+    })))
+}
+
+async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let revoked = sqlx::query(
+        "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND team_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .bind(team_id)
+    .execute(&state.db)
+    .await?;
+
+    if revoked.rows_affected() == 0 {
+        return Err(ApiError::NotFound("API key".into()));
+    }
+
+    tracing::info!(api_key_id = %id, team_id = %team_id, user_id = %auth.id, "API key revoked");
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "message": "API key revoked" },
+        "errors": null,
+    })))
+}
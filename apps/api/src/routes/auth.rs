@@ -1,27 +1,84 @@
 use std::sync::Arc;
 
 use axum::extract::State;
+use axum::http::{header, HeaderMap};
 use axum::routing::post;
-use axum::{Json, Router};
+use axum::{Extension, Json, Router};
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::db::repository;
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::create_token;
-use crate::models::user::{CreateUserRequest, LoginRequest};
-use crate::services::auth_service;
+use crate::middleware::auth::{create_token, deny_api_keys, AuthUser};
+use crate::middleware::permissions::Permission;
+use crate::models::team::CreateTeamRequest;
+use crate::models::user::{
+    ConfirmVerificationRequest, ContactChannel, CreateUserRequest, ForgotPasswordRequest, LoginRequest,
+    MfaLoginRequest, RefreshTokenRequest, ResetPasswordRequest, SendVerificationRequest, User,
+};
+use crate::services::{auth_service, mfa_service, team_service, verification_service};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/auth/register", post(register))
         .route("/auth/login", post(login))
+        .route("/auth/login/mfa", post(login_mfa))
         .route("/auth/me", axum::routing::get(me))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout))
+        .route("/auth/password/forgot", post(forgot_password))
+        .route("/auth/password/reset", post(reset_password))
+        .route("/auth/verification/send", post(send_verification))
+        .route("/auth/verification/confirm", post(confirm_verification))
+}
+
+/// Auth routes that need a valid access token
+pub fn protected_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/auth/logout-all", post(logout_all))
+        .route("/auth/permissions", axum::routing::get(my_permissions))
+        .route_layer(axum::middleware::from_fn(deny_api_keys))
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok())
+}
+
+/// Mint an access token plus a refresh token that starts a new token family
+pub(crate) async fn issue_session(
+    state: &AppState,
+    user_id: Uuid,
+    email: Option<&str>,
+    role: &str,
+    team_id: Option<Uuid>,
+    headers: &HeaderMap,
+) -> ApiResult<(String, String)> {
+    let token = create_token(
+        user_id,
+        email.unwrap_or(""),
+        role,
+        team_id,
+        &state.config.auth.jwt_secret,
+        state.config.auth.jwt_expiry_hours,
+    )?;
+
+    let mut conn = state.db.acquire().await?;
+    let (refresh_token, _) = auth_service::issue_refresh_token(
+        &mut conn,
+        user_id,
+        auth_service::TokenFamily::start(state.config.auth.refresh_token_family_max_days),
+        state.config.auth.refresh_token_expiry_days,
+        user_agent(headers),
+    )
+    .await?;
+
+    Ok((token, refresh_token))
 }
 
 async fn register(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<CreateUserRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
     // Validate at least one identifier
@@ -42,31 +99,49 @@ async fn register(
     }
 
     let password_hash = auth_service::hash_password(&req.password)?;
-    let user = repository::create_user(&state.db, &req, &password_hash).await?;
 
-    let token = create_token(
-        user.id,
-        user.email.as_deref().unwrap_or(""),
-        &format!("{:?}", user.role),
-        user.team_id,
-        &state.config.auth.jwt_secret,
-        state.config.auth.jwt_expiry_hours,
-    )?;
+    let mut tx = state.db.begin().await?;
+    let user = repository::create_user(&mut tx, &req, &password_hash).await?;
+
+    let team_id = match req.company_name.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+        Some(name) => {
+            let team_req = CreateTeamRequest {
+                name: name.to_string(),
+                primary_trade: req.trade.clone(),
+                phone: req.phone.clone(),
+                timezone: None,
+            };
+            Some(team_service::create_team(&mut tx, user.id, &team_req).await?.id)
+        }
+        None => user.team_id,
+    };
+
+    let channel = if user.email.is_some() { ContactChannel::Email } else { ContactChannel::Phone };
+    verification_service::send_verification_code(&mut tx, &state, user.id, channel).await?;
+    tx.commit().await?;
 
-    let refresh_token = Uuid::new_v4().to_string();
+    // Under a verification policy the account exists but can't sign in until verified
+    let session = if state.config.auth.login_verification.allows(user.email_verified, user.phone_verified) {
+        Some(issue_session(&state, user.id, user.email.as_deref(), &user.role, team_id, &headers).await?)
+    } else {
+        None
+    };
+    let (token, refresh_token) = session.unzip();
 
     Ok(Json(json!({
         "data": {
             "token": token,
             "refresh_token": refresh_token,
+            "verification_required": token.is_none(),
+            "verification_channel": channel,
             "user": {
                 "id": user.id,
                 "email": user.email,
                 "phone": user.phone,
                 "first_name": user.first_name,
                 "last_name": user.last_name,
-                "role": format!("{:?}", user.role),
-                "team_id": user.team_id,
+                "role": user.role,
+                "team_id": team_id,
             }
         },
         "meta": null,
@@ -76,6 +151,7 @@ async fn register(
 
 async fn login(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<LoginRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let user = if let Some(ref email) = req.email {
@@ -92,16 +168,50 @@ async fn login(
         return Err(ApiError::Unauthorized);
     }
 
-    let token = create_token(
-        user.id,
-        user.email.as_deref().unwrap_or(""),
-        &format!("{:?}", user.role),
-        user.team_id,
-        &state.config.auth.jwt_secret,
-        state.config.auth.jwt_expiry_hours,
-    )?;
+    if !state.config.auth.login_verification.allows(user.email_verified, user.phone_verified) {
+        return Err(ApiError::VerificationRequired);
+    }
+
+    // With a second factor enrolled, the password only earns a short-lived challenge
+    if mfa_service::is_enrolled(&state.db, user.id).await? {
+        let (mfa_token, expires_at) = mfa_service::create_challenge(&state.db, user.id).await?;
+        return Ok(Json(json!({
+            "data": {
+                "mfa_required": true,
+                "mfa_token": mfa_token,
+                "expires_at": expires_at,
+            },
+            "meta": { "message": "Enter a code from your authenticator app or a recovery code" },
+            "errors": null,
+        })));
+    }
+
+    login_session(&state, &user, &headers).await
+}
+
+/// Second step of a login for users with TOTP: trade the challenge token and a code
+/// for a session
+async fn login_mfa(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<MfaLoginRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let user_id =
+        mfa_service::complete_challenge(&state.db, &state.config.auth.mfa_encryption_key, &req.mfa_token, &req.code).await?;
+
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", repository::USER_COLUMNS))
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?
+        .filter(|u| u.is_active)
+        .ok_or(ApiError::Unauthorized)?;
+
+    login_session(&state, &user, &headers).await
+}
 
-    let refresh_token = Uuid::new_v4().to_string();
+async fn login_session(state: &AppState, user: &User, headers: &HeaderMap) -> ApiResult<Json<serde_json::Value>> {
+    let (token, refresh_token) =
+        issue_session(state, user.id, user.email.as_deref(), &user.role, user.team_id, headers).await?;
 
     // Update last login
     sqlx::query("UPDATE users SET last_login_at = now() WHERE id = $1")
@@ -119,7 +229,7 @@ async fn login(
                 "phone": user.phone,
                 "first_name": user.first_name,
                 "last_name": user.last_name,
-                "role": format!("{:?}", user.role),
+                "role": user.role,
                 "team_id": user.team_id,
             }
         },
@@ -144,9 +254,10 @@ async fn me(
     let claims = crate::middleware::auth::verify_token(token, &state.config.auth.jwt_secret)
         .map_err(|_| ApiError::Unauthorized)?;
 
-    let user = sqlx::query_as::<_, crate::models::user::User>(
-        "SELECT * FROM users WHERE id = $1",
-    )
+    let user = sqlx::query_as::<_, crate::models::user::User>(&format!(
+        "SELECT {} FROM users WHERE id = $1",
+        repository::USER_COLUMNS
+    ))
     .bind(claims.sub)
     .fetch_optional(&state.db)
     .await?
@@ -159,7 +270,7 @@ async fn me(
             "phone": user.phone,
             "first_name": user.first_name,
             "last_name": user.last_name,
-            "role": format!("{:?}", user.role),
+            "role": user.role,
             "team_id": user.team_id,
             "avatar_url": user.avatar_url,
         },
@@ -167,3 +278,269 @@ async fn me(
         "errors": null,
     })))
 }
+
+/// Exchange a refresh token for a new access token and a rotated refresh token
+async fn refresh(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<RefreshTokenRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let (user_id, refresh_token) = auth_service::rotate_refresh_token(
+        &state.db,
+        &req.refresh_token,
+        state.config.auth.refresh_token_expiry_days,
+        user_agent(&headers),
+    )
+    .await?;
+
+    let user = repository::find_user_by_id(&state.db, user_id)
+        .await?
+        .filter(|u| u.is_active)
+        .ok_or(ApiError::Unauthorized)?;
+
+    let token = create_token(
+        user.id,
+        user.email.as_deref().unwrap_or(""),
+        &user.role,
+        user.team_id,
+        &state.config.auth.jwt_secret,
+        state.config.auth.jwt_expiry_hours,
+    )?;
+
+    Ok(Json(json!({
+        "data": {
+            "token": token,
+            "refresh_token": refresh_token,
+        },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Revoke the presented refresh token and the rest of its family (this device)
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RefreshTokenRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    auth_service::revoke_refresh_token(&state.db, &req.refresh_token).await?;
+
+    Ok(Json(json!({ "data": null, "meta": null, "errors": null })))
+}
+
+/// Revoke every refresh token for the current user (all devices). Access tokens
+/// already issued stay valid until they expire.
+async fn logout_all(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let revoked = auth_service::revoke_all_refresh_tokens(&state.db, auth.id).await?;
+
+    tracing::info!(user_id = %auth.id, revoked, "Logged out of all devices");
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "revoked_sessions": revoked },
+        "errors": null,
+    })))
+}
+
+fn contact_from(email: Option<&str>, phone: Option<&str>) -> ApiResult<(ContactChannel, String)> {
+    match (email, phone) {
+        (Some(email), _) if !email.trim().is_empty() => Ok((ContactChannel::Email, email.to_string())),
+        (_, Some(phone)) if !phone.trim().is_empty() => Ok((ContactChannel::Phone, phone.to_string())),
+        _ => Err(ApiError::Validation("Email or phone is required".into())),
+    }
+}
+
+/// Send a reset link by email or SMS. Always answers the same way whether or not an
+/// account exists.
+async fn forgot_password(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ForgotPasswordRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let (channel, destination) = contact_from(req.email.as_deref(), req.phone.as_deref())?;
+    verification_service::request_password_reset(&state, channel, &destination).await?;
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "message": "If an account matches, a reset link is on its way" },
+        "errors": null,
+    })))
+}
+
+/// Choose a new password with a reset token. Signs the user out everywhere.
+async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    verification_service::reset_password(&state, &req.token, &req.password).await?;
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "message": "Password updated; sign in with your new password" },
+        "errors": null,
+    })))
+}
+
+/// Send a verification code. Public so users held back by the login verification
+/// policy can still verify; the code itself is the proof.
+async fn send_verification(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SendVerificationRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    verification_service::send_verification_code_to(&state, req.channel, &req.destination).await?;
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "message": "If an account matches, a code is on its way" },
+        "errors": null,
+    })))
+}
+
+async fn confirm_verification(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ConfirmVerificationRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    verification_service::confirm_verification_code(&state, req.channel, &req.destination, &req.code).await?;
+
+    Ok(Json(json!({
+        "data": { "channel": req.channel, "verified": true },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// The caller's role and effective permissions, for clients deciding what to show
+async fn my_permissions(Extension(auth): Extension<AuthUser>) -> ApiResult<Json<serde_json::Value>> {
+    let permissions: Vec<&str> = Permission::ALL
+        .iter()
+        .filter(|p| auth.has(**p))
+        .map(|p| p.as_str())
+        .collect();
+
+    Ok(Json(json!({
+        "data": {
+            "role": auth.role,
+            "permissions": permissions,
+            "restricted_to_assigned_jobs": auth.job_scope().is_some(),
+        },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use chrono::{DateTime, Utc};
+    use serde_json::Value;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    async fn set_password(db: &PgPool, user_id: Uuid) {
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(auth_service::hash_password(PASSWORD).unwrap())
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    /// Log a new owner in and return their refresh token
+    async fn logged_in(db: &PgPool, app: &Router) -> String {
+        let team_id = test_support::team(db, "acme").await;
+        let user_id = test_support::member(db, team_id, "owner@example.com", "owner").await;
+        set_password(db, user_id).await;
+        let login = json!({ "email": "owner@example.com", "password": PASSWORD });
+        let (status, body) = test_support::call(app, "POST", "/api/v1/auth/login", None, Some(login)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["data"]["refresh_token"].as_str().unwrap().to_string()
+    }
+
+    async fn refresh_with(app: &Router, token: &str) -> (StatusCode, Value) {
+        test_support::call(app, "POST", "/api/v1/auth/refresh", None, Some(json!({ "refresh_token": token }))).await
+    }
+
+    async fn revoked_reasons(db: &PgPool) -> Vec<Option<String>> {
+        sqlx::query_scalar("SELECT revoked_reason FROM refresh_tokens ORDER BY created_at, revoked_reason NULLS LAST")
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn replaying_a_rotated_refresh_token_revokes_its_family(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let app = test_support::app(state);
+        let first = logged_in(&db, &app).await;
+
+        let (status, body) = refresh_with(&app, &first).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let second = body["data"]["refresh_token"].as_str().unwrap().to_string();
+
+        let (status, _) = refresh_with(&app, &first).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // The token it was rotated into went with the family
+        let (status, _) = refresh_with(&app, &second).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(revoked_reasons(&db).await, [Some("rotated".to_string()), Some("reuse_detected".to_string())]);
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn refresh_tokens_stop_rotating_when_their_family_ends(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let app = test_support::app(state.clone());
+        let first = logged_in(&db, &app).await;
+
+        let (status, body) = refresh_with(&app, &first).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let second = body["data"]["refresh_token"].as_str().unwrap().to_string();
+        let family_ends: Vec<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT DISTINCT family_expires_at FROM refresh_tokens").fetch_all(&db).await.unwrap();
+        assert_eq!(family_ends.len(), 1, "rotation keeps the login's lifetime");
+        let max_days = state.config.auth.refresh_token_family_max_days;
+        assert!(family_ends[0] > Utc::now() + chrono::Duration::days(max_days - 1));
+
+        sqlx::query("UPDATE refresh_tokens SET family_expires_at = now() - interval '1 minute'").execute(&db).await.unwrap();
+        let (status, _) = refresh_with(&app, &second).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(revoked_reasons(&db).await, [Some("rotated".to_string()), Some("family_expired".to_string())]);
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn two_factor_login_issues_a_session(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let team_id = test_support::team(&db, "acme").await;
+        let user_id = test_support::member(&db, team_id, "owner@example.com", "owner").await;
+        set_password(&db, user_id).await;
+        mfa_service::begin_enrollment(&db, &state.config.auth.mfa_encryption_key, user_id).await.unwrap();
+        sqlx::query("UPDATE user_totp SET confirmed_at = now() WHERE user_id = $1").bind(user_id).execute(&db).await.unwrap();
+        sqlx::query("INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(auth_service::hash_token("abcdefghij"))
+            .execute(&db)
+            .await
+            .unwrap();
+        let app = test_support::app(state);
+
+        let login = json!({ "email": "owner@example.com", "password": PASSWORD });
+        let (status, body) = test_support::call(&app, "POST", "/api/v1/auth/login", None, Some(login)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["mfa_required"], true);
+        assert!(body["data"]["token"].is_null());
+
+        let second = json!({ "mfa_token": body["data"]["mfa_token"], "code": "abcde-fghij" });
+        let (status, body) = test_support::call(&app, "POST", "/api/v1/auth/login/mfa", None, Some(second)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body["data"]["token"].is_string());
+        assert_eq!(body["data"]["user"]["role"], "owner");
+    }
+}
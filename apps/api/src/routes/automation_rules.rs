@@ -1,33 +1,31 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::automation_rule::{AutomationRule, CreateAutomationRuleRequest};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/automation-rules", get(list_rules).post(create_rule))
+        .route("/automation-rules", get(list_rules).post(create_rule).route_layer(require(Permission::SettingsManage)))
         .route(
-            "/automation-rules/{id}",
-            get(get_rule).patch(update_rule).delete(delete_rule),
+            "/automation-rules/:id",
+            get(get_rule).patch(update_rule).delete(delete_rule).route_layer(require(Permission::SettingsManage)),
         )
-        .route("/automation-rules/{id}/toggle", axum::routing::post(toggle_rule))
+        .route("/automation-rules/:id/toggle", post(toggle_rule).route_layer(require(Permission::SettingsManage)))
 }
 
 async fn list_rules(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rules = sqlx::query_as::<_, AutomationRule>(
         "SELECT * FROM automation_rules WHERE team_id = $1 ORDER BY created_at DESC",
     )
@@ -40,11 +38,9 @@ async fn list_rules(
 
 async fn create_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateAutomationRuleRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, AutomationRule>(
         r#"INSERT INTO automation_rules (team_id, name, trigger_event, conditions, actions, delay_minutes)
            VALUES ($1, $2, $3, $4, $5, $6)
@@ -64,11 +60,9 @@ async fn create_rule(
 
 async fn get_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, AutomationRule>(
         "SELECT * FROM automation_rules WHERE id = $1 AND team_id = $2",
     )
@@ -83,12 +77,10 @@ async fn get_rule(
 
 async fn update_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<serde_json::Value>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, AutomationRule>(
         r#"UPDATE automation_rules
            SET name = COALESCE($3, name),
@@ -113,11 +105,9 @@ async fn update_rule(
 
 async fn delete_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM automation_rules WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -129,11 +119,9 @@ async fn delete_rule(
 
 async fn toggle_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, AutomationRule>(
         r#"UPDATE automation_rules
            SET is_active = NOT is_active, updated_at = NOW()
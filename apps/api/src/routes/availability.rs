@@ -0,0 +1,498 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use chrono::{Duration, NaiveDate};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::availability::{
+    CreateHolidayRequest, CreateTimeOffRequest, ReplaceShiftsRequest, ReviewTimeOffRequest, TeamHoliday,
+    TechnicianShift, TimeOffRequest,
+};
+use crate::services::availability::{self, MAX_RANGE_DAYS};
+use crate::services::job_queue::BackgroundJob;
+use crate::services::schedule;
+use crate::AppState;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/availability", get(get_availability).route_layer(require(Permission::JobsRead)))
+        .route("/team/members/:id/shifts", get(list_shifts).route_layer(require(Permission::TeamRead)))
+        .route(
+            "/team/members/:id/shifts",
+            put(replace_shifts).route_layer(require(Permission::ScheduleManage)),
+        )
+        .route("/team/holidays", get(list_holidays).route_layer(require(Permission::TeamRead)))
+        .route("/team/holidays", post(create_holiday).route_layer(require(Permission::ScheduleManage)))
+        .route("/team/holidays/:id", delete(delete_holiday).route_layer(require(Permission::ScheduleManage)))
+        .route("/time-off", get(list_time_off).post(request_time_off).route_layer(require(Permission::TeamRead)))
+        .route("/time-off/:id/approve", post(approve_time_off).route_layer(require(Permission::ScheduleManage)))
+        .route("/time-off/:id/deny", post(deny_time_off).route_layer(require(Permission::ScheduleManage)))
+        .route("/time-off/:id/cancel", post(cancel_time_off).route_layer(require(Permission::TeamRead)))
+}
+
+#[derive(Debug, Deserialize)]
+struct AvailabilityQuery {
+    from: NaiveDate,
+    /// Defaults to `from`
+    to: Option<NaiveDate>,
+    technician_id: Option<Uuid>,
+    /// Leave out free gaps shorter than this, e.g. the length of the visit being booked
+    min_minutes: Option<i64>,
+}
+
+/// Free time per technician and day, after shifts, holidays, time off, jobs and running
+/// timers. Technicians who only see their own jobs get only their own availability.
+async fn get_availability(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Query(query): Query<AvailabilityQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let to = query.to.unwrap_or(query.from);
+    if to < query.from {
+        return Err(ApiError::Validation("to must not be before from".into()));
+    }
+    if (to - query.from).num_days() >= MAX_RANGE_DAYS {
+        return Err(ApiError::Validation(format!("A query covers at most {} days", MAX_RANGE_DAYS)));
+    }
+    if query.min_minutes.is_some_and(|minutes| minutes <= 0) {
+        return Err(ApiError::Validation("min_minutes must be positive".into()));
+    }
+    let only = auth.job_scope().or(query.technician_id);
+
+    let technicians = availability::availability(&state, team_id, query.from, to, only, query.min_minutes).await?;
+
+    Ok(Json(json!({
+        "data": technicians,
+        "meta": { "from": query.from, "to": to },
+        "errors": null,
+    })))
+}
+
+async fn ensure_member(state: &AppState, team_id: Uuid, user_id: Uuid) -> ApiResult<()> {
+    let is_member = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM team_memberships WHERE team_id = $1 AND user_id = $2 AND is_active)",
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+    if is_member {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound("Team member".into()))
+    }
+}
+
+async fn list_shifts(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let shifts = sqlx::query_as::<_, TechnicianShift>(
+        r#"
+        SELECT * FROM technician_shifts
+        WHERE team_id = $1 AND user_id = $2
+        ORDER BY weekday, start_time, effective_from NULLS FIRST
+        "#,
+    )
+    .bind(team_id)
+    .bind(id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({
+        "data": shifts,
+        "meta": { "total": shifts.len() },
+        "errors": null,
+    })))
+}
+
+/// Replace a technician's weekly pattern. An empty list puts them back on team hours.
+async fn replace_shifts(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+    Json(req): Json<ReplaceShiftsRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    availability::validate_shifts(&req.shifts)?;
+    ensure_member(&state, team_id, id).await?;
+
+    let mut tx = state.db.begin().await?;
+    sqlx::query("DELETE FROM technician_shifts WHERE team_id = $1 AND user_id = $2")
+        .bind(team_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut shifts = Vec::with_capacity(req.shifts.len());
+    for shift in &req.shifts {
+        let created = sqlx::query_as::<_, TechnicianShift>(
+            r#"
+            INSERT INTO technician_shifts (team_id, user_id, weekday, start_time, end_time, effective_from, effective_until)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(team_id)
+        .bind(id)
+        .bind(shift.weekday)
+        .bind(shift.start_time)
+        .bind(shift.end_time)
+        .bind(shift.effective_from)
+        .bind(shift.effective_until)
+        .fetch_one(&mut *tx)
+        .await?;
+        shifts.push(created);
+    }
+    tx.commit().await?;
+
+    tracing::info!(user_id = %id, shifts = shifts.len(), "Technician shifts replaced");
+
+    Ok(Json(json!({
+        "data": shifts,
+        "meta": { "total": shifts.len() },
+        "errors": null,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct HolidayQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+async fn list_holidays(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Query(query): Query<HolidayQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let holidays = sqlx::query_as::<_, TeamHoliday>(
+        r#"
+        SELECT * FROM team_holidays
+        WHERE team_id = $1
+          AND ($2::date IS NULL OR holiday_date >= $2)
+          AND ($3::date IS NULL OR holiday_date <= $3)
+        ORDER BY holiday_date
+        "#,
+    )
+    .bind(team_id)
+    .bind(query.from)
+    .bind(query.to)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({
+        "data": holidays,
+        "meta": { "total": holidays.len() },
+        "errors": null,
+    })))
+}
+
+async fn create_holiday(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Json(req): Json<CreateHolidayRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::Validation("Holiday name is required".into()));
+    }
+
+    let holiday = sqlx::query_as::<_, TeamHoliday>(
+        r#"
+        INSERT INTO team_holidays (team_id, holiday_date, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (team_id, holiday_date) DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(team_id)
+    .bind(req.holiday_date)
+    .bind(name)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::Conflict(format!("{} is already a holiday", req.holiday_date)))?;
+
+    Ok(Json(json!({
+        "data": holiday,
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+async fn delete_holiday(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let result = sqlx::query("DELETE FROM team_holidays WHERE id = $1 AND team_id = $2")
+        .bind(id)
+        .bind(team_id)
+        .execute(&state.db)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Holiday".into()));
+    }
+
+    Ok(Json(json!({
+        "data": { "deleted": true },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct TimeOffQuery {
+    status: Option<String>,
+    user_id: Option<Uuid>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// Members without `schedule.manage` only see their own requests
+fn time_off_scope(auth: &AuthUser) -> Option<Uuid> {
+    if auth.has(Permission::ScheduleManage) {
+        None
+    } else {
+        Some(auth.id)
+    }
+}
+
+async fn list_time_off(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Query(query): Query<TimeOffQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let requests = sqlx::query_as::<_, TimeOffRequest>(
+        r#"
+        SELECT * FROM time_off_requests
+        WHERE team_id = $1
+          AND ($2::uuid IS NULL OR user_id = $2)
+          AND ($3::uuid IS NULL OR user_id = $3)
+          AND ($4::text IS NULL OR status = $4)
+          AND ($5::date IS NULL OR ends_at > $5::date::timestamp)
+          AND ($6::date IS NULL OR starts_at < ($6::date + 1)::timestamp)
+        ORDER BY starts_at
+        "#,
+    )
+    .bind(team_id)
+    .bind(time_off_scope(&auth))
+    .bind(query.user_id)
+    .bind(&query.status)
+    .bind(query.from)
+    .bind(query.to)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({
+        "data": requests,
+        "meta": { "total": requests.len() },
+        "errors": null,
+    })))
+}
+
+/// Ask for time off. A manager entering time off for someone else approves it as they go.
+async fn request_time_off(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Json(req): Json<CreateTimeOffRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let kind = req.kind.as_deref().unwrap_or("vacation");
+    availability::validate_time_off(req.starts_at, req.ends_at, kind)?;
+
+    let user_id = req.user_id.unwrap_or(auth.id);
+    let on_behalf = user_id != auth.id;
+    if on_behalf {
+        auth.require(Permission::ScheduleManage)?;
+        ensure_member(&state, team_id, user_id).await?;
+    }
+
+    let request = sqlx::query_as::<_, TimeOffRequest>(
+        r#"
+        INSERT INTO time_off_requests (team_id, user_id, starts_at, ends_at, kind, reason, status, reviewed_by, reviewed_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CASE WHEN $8::uuid IS NULL THEN NULL ELSE now() END)
+        RETURNING *
+        "#,
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .bind(req.starts_at)
+    .bind(req.ends_at)
+    .bind(kind)
+    .bind(&req.reason)
+    .bind(if on_behalf { "approved" } else { "pending" })
+    .bind(on_behalf.then_some(auth.id))
+    .fetch_one(&state.db)
+    .await?;
+
+    tracing::info!(time_off_id = %request.id, user_id = %user_id, status = %request.status, "Time off requested");
+
+    let conflicting_jobs = if on_behalf { conflicting_jobs(&state, &request).await? } else { Vec::new() };
+
+    Ok(Json(json!({
+        "data": request,
+        "meta": { "conflicting_jobs": conflicting_jobs },
+        "errors": null,
+    })))
+}
+
+/// Jobs assigned to the requester during the time off, for dispatch to move
+async fn conflicting_jobs(state: &AppState, request: &TimeOffRequest) -> ApiResult<Vec<Uuid>> {
+    let from = request.starts_at.date();
+    let to = (request.ends_at - Duration::microseconds(1)).date();
+    let mut conn = state.db.acquire().await?;
+    let jobs = schedule::jobs_between(&mut conn, request.team_id, from, to, Some(request.user_id)).await?;
+
+    Ok(jobs
+        .iter()
+        .filter(|job| match job.slot() {
+            Some(slot) => {
+                let interval = availability::Interval::on(job.scheduled_date, slot.start, slot.end);
+                request.starts_at < interval.end && interval.start < request.ends_at
+            }
+            None => true,
+        })
+        .map(|job| job.id)
+        .collect())
+}
+
+async fn review(
+    state: &AppState,
+    team_id: Uuid,
+    auth: &AuthUser,
+    id: Uuid,
+    status: &str,
+    note: Option<&str>,
+) -> ApiResult<TimeOffRequest> {
+    let request = sqlx::query_as::<_, TimeOffRequest>(
+        r#"
+        UPDATE time_off_requests SET status = $3, reviewed_by = $4, reviewed_at = now(), review_note = $5
+        WHERE id = $1 AND team_id = $2 AND status = 'pending'
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .bind(status)
+    .bind(auth.id)
+    .bind(note)
+    .fetch_optional(&state.db)
+    .await?;
+
+    let Some(request) = request else {
+        let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM time_off_requests WHERE id = $1 AND team_id = $2)")
+            .bind(id)
+            .bind(team_id)
+            .fetch_one(&state.db)
+            .await?;
+        return Err(if exists {
+            ApiError::Conflict("Only pending time off can be reviewed".into())
+        } else {
+            ApiError::NotFound("Time off request".into())
+        });
+    };
+
+    tracing::info!(time_off_id = %request.id, status, "Time off reviewed");
+
+    let job = BackgroundJob::SendPushNotification {
+        team_id: request.team_id,
+        user_id: request.user_id,
+        title: format!("Time off {}", status),
+        body: format!(
+            "Your time off from {} to {} was {}",
+            request.starts_at.format("%b %-d %H:%M"),
+            request.ends_at.format("%b %-d %H:%M"),
+            status
+        ),
+    };
+    state.jobs.enqueue(job).await?;
+
+    Ok(request)
+}
+
+async fn approve_time_off(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+    req: Option<Json<ReviewTimeOffRequest>>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let note = req.and_then(|Json(req)| req.note);
+    let request = review(&state, team_id, &auth, id, "approved", note.as_deref()).await?;
+    let conflicting_jobs = conflicting_jobs(&state, &request).await?;
+
+    Ok(Json(json!({
+        "data": request,
+        "meta": { "conflicting_jobs": conflicting_jobs },
+        "errors": null,
+    })))
+}
+
+async fn deny_time_off(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+    req: Option<Json<ReviewTimeOffRequest>>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let note = req.and_then(|Json(req)| req.note);
+    let request = review(&state, team_id, &auth, id, "denied", note.as_deref()).await?;
+
+    Ok(Json(json!({
+        "data": request,
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Withdraw a request, or give back approved time off that hasn't ended yet
+async fn cancel_time_off(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let current = sqlx::query_as::<_, TimeOffRequest>(
+        "SELECT * FROM time_off_requests WHERE id = $1 AND team_id = $2 AND ($3::uuid IS NULL OR user_id = $3)",
+    )
+    .bind(id)
+    .bind(team_id)
+    .bind(time_off_scope(&auth))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Time off request".into()))?;
+
+    if !matches!(current.status.as_str(), "pending" | "approved") {
+        return Err(ApiError::Conflict(format!("Time off that was {} can't be cancelled", current.status)));
+    }
+    let over = sqlx::query_scalar::<_, bool>("SELECT $2 <= (now() AT TIME ZONE timezone) FROM teams WHERE id = $1")
+        .bind(team_id)
+        .bind(current.ends_at)
+        .fetch_one(&state.db)
+        .await?;
+    if over {
+        return Err(ApiError::Conflict("Time off that's already over can't be cancelled".into()));
+    }
+
+    let request = sqlx::query_as::<_, TimeOffRequest>(
+        "UPDATE time_off_requests SET status = 'cancelled' WHERE id = $1 AND status = $2 RETURNING *",
+    )
+    .bind(id)
+    .bind(&current.status)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::Conflict("The request was reviewed at the same time; reload and try again".into()))?;
+
+    tracing::info!(time_off_id = %request.id, "Time off cancelled");
+
+    Ok(Json(json!({
+        "data": request,
+        "meta": null,
+        "errors": null,
+    })))
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::background_job::{BackgroundJobFilters, BackgroundJobRecord};
+use crate::models::common::PaginationParams;
+use crate::services::job_queue;
+use crate::AppState;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/background-jobs", get(list_background_jobs).route_layer(require(Permission::SettingsManage)))
+        .route(
+            "/background-jobs/:id/retry",
+            post(retry_background_job).route_layer(require(Permission::SettingsManage)),
+        )
+}
+
+async fn list_background_jobs(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Query(pagination): Query<PaginationParams>,
+    Query(filters): Query<BackgroundJobFilters>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let jobs = sqlx::query_as::<_, BackgroundJobRecord>(
+        r#"
+        SELECT * FROM background_jobs
+        WHERE team_id = $1
+          AND ($2::text IS NULL OR status = $2)
+          AND ($3::text IS NULL OR job_type = $3)
+        ORDER BY created_at DESC
+        LIMIT $4
+        "#,
+    )
+    .bind(team_id)
+    .bind(&filters.status)
+    .bind(&filters.job_type)
+    .bind(pagination.limit())
+    .fetch_all(&state.db)
+    .await?;
+
+    let counts = job_queue::status_counts(&state.db, team_id).await?;
+
+    Ok(Json(json!({
+        "data": jobs,
+        "meta": { "counts": counts },
+        "errors": null,
+    })))
+}
+
+/// Move a dead-lettered job back onto the queue with a fresh attempt budget
+async fn retry_background_job(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let job = sqlx::query_as::<_, BackgroundJobRecord>(
+        r#"
+        UPDATE background_jobs SET status = 'queued', attempts = 0, run_at = now(), completed_at = NULL, last_error = NULL
+        WHERE id = $1 AND team_id = $2 AND status = 'dead'
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Dead background job".into()))?;
+
+    tracing::info!(job_id = %job.id, job_type = %job.job_type, "Dead background job requeued");
+
+    Ok(Json(json!({ "data": job, "meta": null, "errors": null })))
+}
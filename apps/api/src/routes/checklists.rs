@@ -1,24 +1,30 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
 use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::checklist::{Checklist, ChecklistItem};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/jobs/{job_id}/checklists", get(list_job_checklists).post(create_checklist))
-        .route("/checklists/{id}", get(get_checklist).delete(delete_checklist))
-        .route("/checklists/{id}/items", axum::routing::post(add_item))
-        .route("/checklists/{checklist_id}/items/{item_id}/toggle", axum::routing::post(toggle_item))
+        .route("/jobs/:job_id/checklists", get(list_job_checklists).route_layer(require(Permission::JobsRead)))
+        .route("/jobs/:job_id/checklists", post(create_checklist).route_layer(require(Permission::JobsWork)))
+        .route("/checklists/:id", get(get_checklist).route_layer(require(Permission::JobsRead)))
+        .route("/checklists/:id", delete(delete_checklist).route_layer(require(Permission::JobsWork)))
+        .route("/checklists/:id/items", post(add_item).route_layer(require(Permission::JobsWork)))
+        .route(
+            "/checklists/:checklist_id/items/:item_id/toggle",
+            post(toggle_item).route_layer(require(Permission::JobsWork)),
+        )
 }
 
 #[derive(Deserialize)]
@@ -37,11 +43,11 @@ struct CreateChecklistItemInput {
 
 async fn create_checklist(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
     Json(req): Json<CreateChecklistRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
     let checklist_type = req.checklist_type.as_deref().unwrap_or("custom");
     let is_required = req.is_required.unwrap_or(false);
 
@@ -92,8 +98,11 @@ async fn create_checklist(
 
 async fn list_job_checklists(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
+
     let checklists = sqlx::query_as::<_, Checklist>(
         "SELECT * FROM checklists WHERE job_id = $1 ORDER BY created_at",
     )
@@ -127,12 +136,15 @@ async fn list_job_checklists(
 
 async fn get_checklist(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let checklist = sqlx::query_as::<_, Checklist>(
-        "SELECT * FROM checklists WHERE id = $1",
+        "SELECT * FROM checklists WHERE id = $1 AND team_id = $2 AND (job_id IS NULL OR $3::uuid IS NULL OR job_id IN (SELECT id FROM jobs WHERE assigned_to = $3))",
     )
     .bind(id)
+    .bind(team_id)
+    .bind(auth.job_scope())
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Checklist".into()))?;
@@ -161,10 +173,14 @@ async fn get_checklist(
 
 async fn delete_checklist(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    sqlx::query("DELETE FROM checklists WHERE id = $1")
+    ensure_checklist_access(&state, team_id, &auth, id).await?;
+
+    sqlx::query("DELETE FROM checklists WHERE id = $1 AND team_id = $2")
         .bind(id)
+        .bind(team_id)
         .execute(&state.db)
         .await?;
 
@@ -183,9 +199,12 @@ struct AddItemRequest {
 
 async fn add_item(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(checklist_id): Path<Uuid>,
     Json(req): Json<AddItemRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    ensure_checklist_access(&state, team_id, &auth, checklist_id).await?;
+
     let order = match req.sort_order {
         Some(o) => o,
         None => {
@@ -221,10 +240,11 @@ async fn add_item(
 
 async fn toggle_item(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path((checklist_id, item_id)): Path<(Uuid, Uuid)>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let user_id = auth.id;
+    ensure_checklist_access(&state, team_id, &auth, checklist_id).await?;
 
     let item = sqlx::query_as::<_, ChecklistItem>(
         r#"
@@ -275,3 +295,20 @@ async fn toggle_item(
         "errors": null,
     })))
 }
+
+/// Checklists are reached by id, so check team and job visibility before touching items
+async fn ensure_checklist_access(state: &AppState, team_id: Uuid, auth: &AuthUser, checklist_id: Uuid) -> ApiResult<()> {
+    let job_id = sqlx::query_scalar::<_, Option<Uuid>>(
+        "SELECT job_id FROM checklists WHERE id = $1 AND team_id = $2",
+    )
+    .bind(checklist_id)
+    .bind(team_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Checklist".into()))?;
+
+    if let Some(job_id) = job_id {
+        permissions::ensure_job_access(&state.db, auth, team_id, job_id).await?;
+    }
+    Ok(())
+}
@@ -1,36 +1,49 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
-use axum::routing::get;
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::db::repository;
 use crate::errors::ApiResult;
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::common::PaginationParams;
 use crate::models::customer::{CreateCustomerRequest, UpdateCustomerRequest};
+use crate::services::events;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/customers", get(list_customers).post(create_customer))
-        .route(
-            "/customers/{id}",
-            get(get_customer).patch(update_customer).delete(delete_customer),
-        )
+        .route("/customers", get(list_customers).route_layer(require(Permission::CustomersRead)))
+        .route("/customers", post(create_customer).route_layer(require(Permission::CustomersWrite)))
+        .route("/customers/:id", get(get_customer).route_layer(require(Permission::CustomersRead)))
+        .route("/customers/:id", patch(update_customer).route_layer(require(Permission::CustomersWrite)))
+        .route("/customers/:id", delete(delete_customer).route_layer(require(Permission::CustomersDelete)))
 }
 
 async fn create_customer(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateCustomerRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let customer = repository::create_customer(&state.db, team_id, &req).await?;
 
+    // The portal token grants access to the customer's portal, so it stays out of the payload
+    let data = json!({
+        "customer_id": customer.id,
+        "first_name": customer.first_name,
+        "last_name": customer.last_name,
+        "email": customer.email,
+        "phone": customer.phone,
+        "company_name": customer.company_name,
+        "referral_source": customer.referral_source,
+        "tags": customer.tags,
+    });
+    events::publish(&state.db, &state, team_id, events::CUSTOMER_CREATED, data).await?;
+
     Ok(Json(json!({
         "data": customer,
         "meta": null,
@@ -40,11 +53,10 @@ async fn create_customer(
 
 async fn list_customers(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(pagination): Query<PaginationParams>,
     Query(params): Query<std::collections::HashMap<String, String>>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let search = params.get("search").map(|s| s.as_str());
     let cursor = pagination.cursor.as_ref().and_then(|c| c.parse::<Uuid>().ok());
 
@@ -63,10 +75,9 @@ async fn list_customers(
 
 async fn get_customer(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let customer = repository::get_customer(&state.db, team_id, id).await?;
 
     Ok(Json(json!({
@@ -78,11 +89,10 @@ async fn get_customer(
 
 async fn update_customer(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateCustomerRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let customer = repository::update_customer(&state.db, team_id, id, &req).await?;
 
     Ok(Json(json!({
@@ -94,10 +104,9 @@ async fn update_customer(
 
 async fn delete_customer(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     repository::delete_customer(&state.db, team_id, id).await?;
 
     Ok(Json(json!({
@@ -1,22 +1,25 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::document::{Document, Signature};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/documents", get(list_documents).post(create_document))
-        .route("/documents/{id}", get(get_document).delete(delete_document))
-        .route("/signatures", get(list_signatures).post(create_signature))
+        .route("/documents", get(list_documents).route_layer(require(Permission::DocumentsRead)))
+        .route("/documents", post(create_document).route_layer(require(Permission::DocumentsWrite)))
+        .route("/documents/:id", get(get_document).route_layer(require(Permission::DocumentsRead)))
+        .route("/documents/:id", delete(delete_document).route_layer(require(Permission::DocumentsWrite)))
+        .route("/signatures", get(list_signatures).route_layer(require(Permission::DocumentsRead)))
+        .route("/signatures", post(create_signature).route_layer(require(Permission::DocumentsWrite)))
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -27,11 +30,9 @@ struct DocumentFilter {
 
 async fn list_documents(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(filter): Query<DocumentFilter>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let docs = if let (Some(entity_type), Some(entity_id)) = (&filter.entity_type, filter.entity_id) {
         sqlx::query_as::<_, Document>(
             "SELECT * FROM documents WHERE team_id = $1 AND entity_type = $2 AND entity_id = $3 ORDER BY created_at DESC",
@@ -55,11 +56,9 @@ async fn list_documents(
 
 async fn create_document(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateDocumentRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let doc = sqlx::query_as::<_, Document>(
         r#"INSERT INTO documents (team_id, entity_type, entity_id, file_name, file_url, file_size, mime_type, uploaded_by, description)
            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
@@ -82,11 +81,9 @@ async fn create_document(
 
 async fn get_document(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let doc = sqlx::query_as::<_, Document>(
         "SELECT * FROM documents WHERE id = $1 AND team_id = $2",
     )
@@ -101,11 +98,9 @@ async fn get_document(
 
 async fn delete_document(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM documents WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -117,11 +112,9 @@ async fn delete_document(
 
 async fn list_signatures(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(filter): Query<DocumentFilter>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let sigs = if let (Some(entity_type), Some(entity_id)) = (&filter.entity_type, filter.entity_id) {
         sqlx::query_as::<_, Signature>(
             "SELECT * FROM signatures WHERE team_id = $1 AND entity_type = $2 AND entity_id = $3 ORDER BY signed_at DESC",
@@ -145,11 +138,9 @@ async fn list_signatures(
 
 async fn create_signature(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateSignatureRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let sig = sqlx::query_as::<_, Signature>(
         r#"INSERT INTO signatures (team_id, entity_type, entity_id, signer_name, signer_email, signature_url, ip_address, signed_at)
            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
@@ -1,30 +1,33 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::equipment::{CreateEquipmentRequest, Equipment};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/equipment", get(list_equipment).post(create_equipment))
-        .route("/equipment/{id}", get(get_equipment).patch(update_equipment).delete(delete_equipment))
+        .route("/equipment", get(list_equipment).route_layer(require(Permission::FleetRead)))
+        .route("/equipment", post(create_equipment).route_layer(require(Permission::FleetWrite)))
+        .route("/equipment/:id", get(get_equipment).route_layer(require(Permission::FleetRead)))
+        .route(
+            "/equipment/:id",
+            patch(update_equipment).delete(delete_equipment).route_layer(require(Permission::FleetWrite)),
+        )
 }
 
 async fn list_equipment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let items = sqlx::query_as::<_, Equipment>(
         "SELECT * FROM equipment WHERE team_id = $1 AND is_active = true ORDER BY name",
     )
@@ -41,10 +44,9 @@ async fn list_equipment(
 
 async fn create_equipment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateEquipmentRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let condition = req.condition.as_deref().unwrap_or("good");
 
     let item = sqlx::query_as::<_, Equipment>(
@@ -82,11 +84,9 @@ async fn create_equipment(
 
 async fn get_equipment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let item = sqlx::query_as::<_, Equipment>(
         "SELECT * FROM equipment WHERE id = $1 AND team_id = $2",
     )
@@ -117,12 +117,10 @@ struct UpdateEquipmentRequest {
 
 async fn update_equipment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateEquipmentRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let item = sqlx::query_as::<_, Equipment>(
         r#"
         UPDATE equipment SET
@@ -162,11 +160,9 @@ async fn update_equipment(
 
 async fn delete_equipment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query(
         "UPDATE equipment SET is_active = false, updated_at = now() WHERE id = $1 AND team_id = $2",
     )
@@ -1,36 +1,42 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
-use axum::routing::{get, post};
+use axum::response::Response;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::common::PaginationParams;
 use crate::models::estimate::CreateEstimateRequest;
+use crate::services::document_pdf::{self, DocumentKind};
+use crate::services::events;
+use crate::services::job_queue::BackgroundJob;
+use crate::services::realtime::{self, Audience, WsEvent};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/estimates", get(list_estimates).post(create_estimate))
-        .route("/estimates/{id}", get(get_estimate).patch(update_estimate))
-        .route("/estimates/{id}/send", post(send_estimate))
-        .route("/estimates/{id}/approve", post(approve_estimate))
-        .route("/estimates/{id}/decline", post(decline_estimate))
-        .route("/estimates/{id}/convert", post(convert_to_invoice))
-        .route("/estimates/{id}/duplicate", post(duplicate_estimate))
+        .route("/estimates", get(list_estimates).route_layer(require(Permission::EstimatesRead)))
+        .route("/estimates", post(create_estimate).route_layer(require(Permission::EstimatesWrite)))
+        .route("/estimates/:id", get(get_estimate).route_layer(require(Permission::EstimatesRead)))
+        .route("/estimates/:id", patch(update_estimate).route_layer(require(Permission::EstimatesWrite)))
+        .route("/estimates/:id/pdf", get(download_pdf).route_layer(require(Permission::EstimatesRead)))
+        .route("/estimates/:id/send", post(send_estimate).route_layer(require(Permission::EstimatesSend)))
+        .route("/estimates/:id/approve", post(approve_estimate).route_layer(require(Permission::EstimatesApprove)))
+        .route("/estimates/:id/decline", post(decline_estimate).route_layer(require(Permission::EstimatesApprove)))
+        .route("/estimates/:id/convert", post(convert_to_invoice).route_layer(require(Permission::EstimatesApprove)))
+        .route("/estimates/:id/duplicate", post(duplicate_estimate).route_layer(require(Permission::EstimatesWrite)))
 }
 
 async fn create_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateEstimateRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let mut tx = state.db.begin().await?;
 
     // Generate estimate number
@@ -145,11 +151,10 @@ async fn create_estimate(
 
 async fn list_estimates(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(pagination): Query<PaginationParams>,
     Query(params): Query<std::collections::HashMap<String, String>>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let status = params.get("status").map(|s| s.as_str());
     let cursor = pagination.cursor.as_ref().and_then(|c| c.parse::<Uuid>().ok());
 
@@ -195,11 +200,9 @@ async fn list_estimates(
 
 async fn get_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let estimate = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         "SELECT * FROM estimates WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
     )
@@ -228,12 +231,10 @@ async fn get_estimate(
 
 async fn update_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(body): Json<serde_json::Value>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     // Verify estimate exists and belongs to team
     let _existing = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         "SELECT * FROM estimates WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
@@ -277,11 +278,9 @@ async fn update_estimate(
 
 async fn send_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let estimate = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         r#"
         UPDATE estimates SET status = 'sent'::estimate_status, sent_at = now()
@@ -297,6 +296,7 @@ async fn send_estimate(
 
     // TODO: Send email/SMS to customer with portal link
     tracing::info!(estimate_id = %id, "Estimate sent to customer");
+    state.jobs.enqueue(BackgroundJob::GenerateEstimatePdf { team_id, estimate_id: id }).await?;
 
     Ok(Json(json!({
         "data": estimate,
@@ -305,6 +305,14 @@ async fn send_estimate(
     })))
 }
 
+async fn download_pdf(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Response> {
+    document_pdf::download(&state, DocumentKind::Estimate, team_id, id).await
+}
+
 #[derive(serde::Deserialize)]
 struct ApproveRequest {
     signature: Option<String>,
@@ -312,12 +320,10 @@ struct ApproveRequest {
 
 async fn approve_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<ApproveRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let estimate = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         r#"
         UPDATE estimates SET
@@ -337,24 +343,44 @@ async fn approve_estimate(
 
     // Auto-update job status if linked (only if transition is valid)
     if let Some(job_id) = estimate.job_id {
-        let job = sqlx::query_as::<_, crate::models::job::Job>(
-            "SELECT * FROM jobs WHERE id = $1 AND team_id = $2",
-        )
+        let job = sqlx::query_as::<_, crate::models::job::Job>(&format!(
+            "SELECT {} FROM jobs WHERE id = $1 AND team_id = $2",
+            crate::db::repository::JOB_COLUMNS
+        ))
         .bind(job_id)
         .bind(team_id)
         .fetch_optional(&state.db)
         .await?;
 
         if let Some(job) = job {
-            if crate::services::job_service::is_valid_transition(&job.status, &"approved".to_string()) {
+            if crate::services::job_service::is_valid_transition(&job.status, "approved") {
                 sqlx::query("UPDATE jobs SET status = 'approved'::job_status WHERE id = $1")
                     .bind(job_id)
                     .execute(&state.db)
                     .await?;
+                let event = WsEvent::JobStatusChanged {
+                    job_id: job_id.to_string(),
+                    old_status: job.status.clone(),
+                    new_status: "approved".into(),
+                };
+                realtime::publish(&state, Audience::team(team_id).job(Some(job_id)).user(job.assigned_to), event).await;
             }
         }
     }
 
+    let data = json!({
+        "estimate_id": estimate.id,
+        "estimate_number": estimate.estimate_number,
+        "customer_id": estimate.customer_id,
+        "job_id": estimate.job_id,
+        "total": estimate.total,
+        "approved_via": "staff",
+    });
+    events::publish(&state.db, &state, team_id, events::ESTIMATE_APPROVED, data).await?;
+
+    // Re-render so the stored copy carries the signature
+    state.jobs.enqueue(BackgroundJob::GenerateEstimatePdf { team_id, estimate_id: id }).await?;
+
     Ok(Json(json!({
         "data": estimate,
         "meta": { "message": "Estimate approved" },
@@ -369,12 +395,10 @@ struct DeclineRequest {
 
 async fn decline_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<DeclineRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let estimate = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         r#"
         UPDATE estimates SET
@@ -401,11 +425,9 @@ async fn decline_estimate(
 
 async fn convert_to_invoice(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let mut tx = state.db.begin().await?;
 
     // Get approved estimate
@@ -488,11 +510,9 @@ async fn convert_to_invoice(
 
 async fn duplicate_estimate(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     // Fetch original estimate
     let original = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         "SELECT * FROM estimates WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
@@ -1,23 +1,28 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::expense::{CreateExpenseRequest, Expense};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/expenses", get(list_expenses).post(create_expense))
-        .route("/expenses/{id}", get(get_expense).patch(update_expense).delete(delete_expense))
-        .route("/jobs/{job_id}/expenses", get(list_job_expenses))
+        .route("/expenses", get(list_expenses).route_layer(require(Permission::ExpensesRead)))
+        .route("/expenses", post(create_expense).route_layer(require(Permission::ExpensesWrite)))
+        .route("/expenses/:id", get(get_expense).route_layer(require(Permission::ExpensesRead)))
+        .route(
+            "/expenses/:id",
+            patch(update_expense).delete(delete_expense).route_layer(require(Permission::ExpensesWrite)),
+        )
+        .route("/jobs/:job_id/expenses", get(list_job_expenses).route_layer(require(Permission::ExpensesRead)))
 }
 
 #[derive(Deserialize)]
@@ -30,11 +35,9 @@ struct ExpenseFilters {
 
 async fn list_expenses(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(_filters): Query<ExpenseFilters>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let expenses = sqlx::query_as::<_, Expense>(
         "SELECT * FROM expenses WHERE team_id = $1 ORDER BY expense_date DESC",
     )
@@ -53,10 +56,9 @@ async fn list_expenses(
 
 async fn create_expense(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Json(req): Json<CreateExpenseRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let user_id = auth.id;
 
     let is_billable = req.is_billable.unwrap_or(false);
@@ -100,11 +102,9 @@ async fn create_expense(
 
 async fn get_expense(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let expense = sqlx::query_as::<_, Expense>(
         "SELECT * FROM expenses WHERE id = $1 AND team_id = $2",
     )
@@ -134,12 +134,10 @@ struct UpdateExpenseRequest {
 
 async fn update_expense(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateExpenseRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let expense = sqlx::query_as::<_, Expense>(
         r#"
         UPDATE expenses SET
@@ -177,11 +175,9 @@ async fn update_expense(
 
 async fn delete_expense(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM expenses WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -197,8 +193,11 @@ async fn delete_expense(
 
 async fn list_job_expenses(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
+
     let expenses = sqlx::query_as::<_, Expense>(
         "SELECT * FROM expenses WHERE job_id = $1 ORDER BY expense_date DESC",
     )
@@ -1,30 +1,30 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::document::FuelLog;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/vehicles/{vehicle_id}/fuel-logs", get(list_fuel_logs).post(create_fuel_log))
-        .route("/fuel-logs/{id}", get(get_fuel_log).delete(delete_fuel_log))
+        .route("/vehicles/:vehicle_id/fuel-logs", get(list_fuel_logs).route_layer(require(Permission::FleetRead)))
+        .route("/vehicles/:vehicle_id/fuel-logs", post(create_fuel_log).route_layer(require(Permission::FleetWrite)))
+        .route("/fuel-logs/:id", get(get_fuel_log).route_layer(require(Permission::FleetRead)))
+        .route("/fuel-logs/:id", delete(delete_fuel_log).route_layer(require(Permission::FleetWrite)))
 }
 
 async fn list_fuel_logs(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(vehicle_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let logs = sqlx::query_as::<_, FuelLog>(
         "SELECT * FROM fuel_logs WHERE vehicle_id = $1 AND team_id = $2 ORDER BY filled_at DESC",
     )
@@ -38,12 +38,10 @@ async fn list_fuel_logs(
 
 async fn create_fuel_log(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(vehicle_id): Path<Uuid>,
     Json(req): Json<CreateFuelLogRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let log = sqlx::query_as::<_, FuelLog>(
         r#"INSERT INTO fuel_logs (vehicle_id, team_id, gallons, cost_per_gallon, total_cost, odometer, fuel_type, station, filled_by, filled_at)
            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, COALESCE($10, NOW()))
@@ -67,11 +65,9 @@ async fn create_fuel_log(
 
 async fn get_fuel_log(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let log = sqlx::query_as::<_, FuelLog>(
         "SELECT * FROM fuel_logs WHERE id = $1 AND team_id = $2",
     )
@@ -86,11 +82,9 @@ async fn get_fuel_log(
 
 async fn delete_fuel_log(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM fuel_logs WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
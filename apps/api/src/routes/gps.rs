@@ -1,94 +1,104 @@
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::routing::{get, post};
 use axum::{Json, Router};
-use axum::Extension;
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
-use crate::errors::ApiResult;
-use crate::middleware::auth::AuthUser;
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::expense::Expense;
+use crate::models::gps::{GpsLocation, NewGpsLocation};
+use crate::services::{gps_store, trips};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/gps/location", post(update_location))
-        .route("/gps/technicians", get(list_technician_locations))
-        .route("/gps/technicians/{user_id}/history", get(location_history))
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-struct GpsLocation {
-    id: Uuid,
-    team_id: Uuid,
-    user_id: Uuid,
-    latitude: f64,
-    longitude: f64,
-    accuracy: Option<f64>,
-    speed: Option<f64>,
-    heading: Option<f64>,
-    recorded_at: DateTime<Utc>,
-    created_at: DateTime<Utc>,
+        .route("/gps/location", post(update_location).route_layer(require(Permission::GpsTrack)))
+        .route("/gps/locations/batch", post(upload_locations).route_layer(require(Permission::GpsTrack)))
+        .route("/gps/technicians", get(list_technician_locations).route_layer(require(Permission::GpsView)))
+        .route("/gps/technicians/:user_id/history", get(location_history).route_layer(require(Permission::GpsView)))
+        .route("/gps/technicians/:user_id/trips", get(list_trips).route_layer(require(Permission::GpsView)))
+        .route(
+            "/gps/technicians/:user_id/mileage-expense",
+            post(create_mileage_expense).route_layer(require(Permission::ExpensesWrite)),
+        )
 }
 
 async fn update_location(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
-    Json(req): Json<UpdateLocationRequest>,
+    TeamContext { team_id, auth }: TeamContext,
+    Json(mut req): Json<NewGpsLocation>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let user_id = auth.id;
+    let recorded_at = *req.recorded_at.get_or_insert_with(Utc::now);
 
-    // Upsert into a GPS tracking table (or use Redis for real-time)
-    let loc = sqlx::query_as::<_, GpsLocation>(
-        r#"INSERT INTO gps_locations (team_id, user_id, latitude, longitude, accuracy, speed, heading, recorded_at)
-           VALUES ($1, $2, $3, $4, $5, $6, $7, COALESCE($8, NOW()))
-           RETURNING *"#,
-    )
-    .bind(team_id)
-    .bind(user_id)
-    .bind(req.latitude)
-    .bind(req.longitude)
-    .bind(req.accuracy)
-    .bind(req.speed)
-    .bind(req.heading)
-    .bind(req.recorded_at)
-    .fetch_one(&state.db)
-    .await?;
+    let ingested = gps_store::ingest(&state, team_id, user_id, vec![req]).await?;
+    if let Some(rejected) = ingested.rejected.into_iter().next() {
+        return Err(ApiError::Validation(rejected.error));
+    }
+
+    // A re-sent fix gets the stored one back rather than an error
+    let loc = match ingested.stored.into_iter().next() {
+        Some(loc) => loc,
+        None => gps_store::find(&state.db, team_id, user_id, recorded_at)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("GPS location".into()))?,
+    };
+
+    Ok(Json(json!({ "data": loc, "meta": { "geofence_events": ingested.geofence_events }, "errors": null })))
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchLocationsRequest {
+    locations: Vec<NewGpsLocation>,
+}
+
+/// Upload fixes buffered on the device, each with its own `recorded_at`. Fixes already
+/// stored are skipped, so a device can safely retry an upload it isn't sure went through.
+async fn upload_locations(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Json(req): Json<BatchLocationsRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if req.locations.is_empty() || req.locations.len() > gps_store::MAX_BATCH {
+        return Err(ApiError::Validation(format!("Send between 1 and {} locations", gps_store::MAX_BATCH)));
+    }
+
+    let ingested = gps_store::ingest(&state, team_id, auth.id, req.locations).await?;
 
-    Ok(Json(json!({ "data": loc, "meta": null, "errors": null })))
+    Ok(Json(json!({
+        "data": {
+            "accepted": ingested.stored.len(),
+            "duplicates": ingested.duplicates,
+            "rejected": ingested.rejected,
+            "latest": ingested.stored.last(),
+        },
+        "meta": { "geofence_events": ingested.geofence_events },
+        "errors": null,
+    })))
 }
 
 async fn list_technician_locations(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    // Get the latest location for each technician
-    let locations = sqlx::query_as::<_, GpsLocation>(
-        r#"SELECT DISTINCT ON (user_id) *
-           FROM gps_locations
-           WHERE team_id = $1 AND recorded_at > NOW() - INTERVAL '1 hour'
-           ORDER BY user_id, recorded_at DESC"#,
-    )
-    .bind(team_id)
-    .fetch_all(&state.db)
-    .await?;
+    // Latest location for each technician active in the last hour
+    let locations = gps_store::latest_positions(&state, team_id, Duration::hours(1)).await?;
 
     Ok(Json(json!({ "data": locations, "meta": null, "errors": null })))
 }
 
 async fn location_history(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(user_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let history = sqlx::query_as::<_, GpsLocation>(
         r#"SELECT * FROM gps_locations
            WHERE team_id = $1 AND user_id = $2 AND recorded_at > NOW() - INTERVAL '24 hours'
@@ -104,11 +114,116 @@ async fn location_history(
 }
 
 #[derive(Debug, Deserialize)]
-struct UpdateLocationRequest {
-    latitude: f64,
-    longitude: f64,
-    accuracy: Option<f64>,
-    speed: Option<f64>,
-    heading: Option<f64>,
-    recorded_at: Option<DateTime<Utc>>,
+struct DayQuery {
+    date: NaiveDate,
+}
+
+/// A day's driving split into trips and stops, with mileage and simplified replay paths
+async fn list_trips(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<DayQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let report = trips::analyze_day(&state, team_id, user_id, query.date).await?;
+
+    Ok(Json(json!({ "data": report, "meta": null, "errors": null })))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateMileageExpenseRequest {
+    date: NaiveDate,
+    /// Overrides the team's mileage rate; only honoured for members who can set that rate
+    mileage_rate: Option<Decimal>,
+    vehicle_id: Option<Uuid>,
+    job_id: Option<Uuid>,
+    is_billable: Option<bool>,
+}
+
+/// Turn a technician's GPS mileage for a day into a reimbursable mileage expense. Anyone
+/// may claim their own; claiming for someone else needs `gps.view`. The team's rate applies
+/// unless the caller has `settings.manage`, which is what it takes to change that rate.
+async fn create_mileage_expense(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<CreateMileageExpenseRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if user_id != auth.id {
+        auth.require(Permission::GpsView)?;
+    }
+
+    let team_rate = sqlx::query_scalar::<_, Option<Decimal>>("SELECT mileage_rate FROM teams WHERE id = $1")
+        .bind(team_id)
+        .fetch_one(&state.db)
+        .await?;
+    let requested_rate = req.mileage_rate.filter(|_| auth.has(Permission::SettingsManage));
+    let rate = requested_rate
+        .or(team_rate)
+        .ok_or_else(|| ApiError::Validation("The team has no mileage rate set".into()))?;
+    if rate <= Decimal::ZERO {
+        return Err(ApiError::Validation("Mileage rate must be greater than zero".into()));
+    }
+
+    if let Some(job_id) = req.job_id {
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL)",
+        )
+        .bind(job_id)
+        .bind(team_id)
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(ApiError::NotFound("Job".into()));
+        }
+    }
+    if let Some(vehicle_id) = req.vehicle_id {
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM vehicles WHERE id = $1 AND team_id = $2)",
+        )
+        .bind(vehicle_id)
+        .bind(team_id)
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(ApiError::NotFound("Vehicle".into()));
+        }
+    }
+
+    let report = trips::analyze_day(&state, team_id, user_id, req.date).await?;
+    let miles = report.miles();
+    if miles <= Decimal::ZERO {
+        return Err(ApiError::Validation(format!("No trips recorded on {}", req.date)));
+    }
+
+    let expense = sqlx::query_as::<_, Expense>(
+        r#"
+        INSERT INTO expenses (team_id, user_id, job_id, vehicle_id, category, description, amount,
+                              expense_date, is_billable, is_reimbursable, mileage, mileage_rate, gps_mileage)
+        VALUES ($1, $2, $3, $4, 'mileage', $5, $6, $7, $8, true, $9, $10, true)
+        ON CONFLICT (team_id, user_id, expense_date) WHERE gps_mileage DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .bind(req.job_id)
+    .bind(req.vehicle_id)
+    .bind(format!("Mileage for {}: {} mi over {} trip(s)", req.date, miles, report.trips.len()))
+    .bind((miles * rate).round_dp(2))
+    .bind(req.date)
+    .bind(req.is_billable.unwrap_or(false))
+    .bind(miles)
+    .bind(rate)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::Conflict(format!("GPS mileage for {} has already been claimed", req.date)))?;
+
+    tracing::info!(expense_id = %expense.id, user_id = %user_id, date = %req.date, miles = %miles, "Mileage expense created from GPS trips");
+
+    Ok(Json(json!({
+        "data": expense,
+        "meta": { "trips": report.trips.len(), "total_distance_miles": report.total_distance_miles },
+        "errors": null,
+    })))
 }
@@ -1,33 +1,39 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
+use rust_decimal::prelude::ToPrimitive;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::inventory::{CreateInventoryItemRequest, InventoryItem, InventoryLocation, InventoryStock};
+use crate::services::realtime::{self, Audience, WsEvent};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/inventory/items", get(list_items).post(create_item))
-        .route("/inventory/items/{id}", get(get_item).patch(update_item).delete(delete_item))
-        .route("/inventory/locations", get(list_locations).post(create_location))
-        .route("/inventory/items/{id}/stock", get(get_item_stock))
-        .route("/inventory/items/{id}/adjust", axum::routing::post(adjust_stock))
+        .route("/inventory/items", get(list_items).route_layer(require(Permission::InventoryRead)))
+        .route("/inventory/items", post(create_item).route_layer(require(Permission::InventoryWrite)))
+        .route("/inventory/items/:id", get(get_item).route_layer(require(Permission::InventoryRead)))
+        .route(
+            "/inventory/items/:id",
+            patch(update_item).delete(delete_item).route_layer(require(Permission::InventoryWrite)),
+        )
+        .route("/inventory/locations", get(list_locations).route_layer(require(Permission::InventoryRead)))
+        .route("/inventory/locations", post(create_location).route_layer(require(Permission::InventoryWrite)))
+        .route("/inventory/items/:id/stock", get(get_item_stock).route_layer(require(Permission::InventoryRead)))
+        .route("/inventory/items/:id/adjust", post(adjust_stock).route_layer(require(Permission::InventoryWrite)))
 }
 
 async fn list_items(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let items = sqlx::query_as::<_, InventoryItem>(
         "SELECT * FROM inventory_items WHERE team_id = $1 AND is_active = true ORDER BY name",
     )
@@ -44,10 +50,9 @@ async fn list_items(
 
 async fn create_item(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateInventoryItemRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let unit = req.unit_of_measure.as_deref().unwrap_or("each");
 
     let item = sqlx::query_as::<_, InventoryItem>(
@@ -82,11 +87,9 @@ async fn create_item(
 
 async fn get_item(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let item = sqlx::query_as::<_, InventoryItem>(
         "SELECT * FROM inventory_items WHERE id = $1 AND team_id = $2",
     )
@@ -127,12 +130,10 @@ struct UpdateInventoryItemRequest {
 
 async fn update_item(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateInventoryItemRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let item = sqlx::query_as::<_, InventoryItem>(
         r#"
         UPDATE inventory_items SET
@@ -172,11 +173,9 @@ async fn update_item(
 
 async fn delete_item(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query(
         "UPDATE inventory_items SET is_active = false, updated_at = now() WHERE id = $1 AND team_id = $2",
     )
@@ -194,10 +193,8 @@ async fn delete_item(
 
 async fn list_locations(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let locations = sqlx::query_as::<_, InventoryLocation>(
         "SELECT * FROM inventory_locations WHERE team_id = $1 AND is_active = true ORDER BY name",
     )
@@ -222,10 +219,9 @@ struct CreateLocationRequest {
 
 async fn create_location(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateLocationRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let loc_type = req.location_type.as_deref().unwrap_or("warehouse");
 
     let location = sqlx::query_as::<_, InventoryLocation>(
@@ -252,12 +248,14 @@ async fn create_location(
 
 async fn get_item_stock(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let stock = sqlx::query_as::<_, InventoryStock>(
-        "SELECT * FROM inventory_stock WHERE item_id = $1",
+        "SELECT * FROM inventory_stock WHERE item_id = $1 AND item_id IN (SELECT id FROM inventory_items WHERE team_id = $2)",
     )
     .bind(id)
+    .bind(team_id)
     .fetch_all(&state.db)
     .await?;
 
@@ -281,11 +279,10 @@ struct AdjustStockRequest {
 
 async fn adjust_stock(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<AdjustStockRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let user_id = auth.id;
 
     let mut tx = state.db.begin().await?;
@@ -323,10 +320,40 @@ async fn adjust_stock(
     .execute(&mut *tx)
     .await?;
 
+    // Alert when a draw-down leaves the item at or under its minimum across all locations
+    let low_stock = if req.quantity < rust_decimal::Decimal::ZERO {
+        sqlx::query_as::<_, (String, rust_decimal::Decimal, rust_decimal::Decimal)>(
+            r#"
+            SELECT i.name, COALESCE(SUM(s.quantity), 0), i.min_stock_level
+            FROM inventory_items i
+            LEFT JOIN inventory_stock s ON s.item_id = i.id
+            WHERE i.id = $1 AND i.team_id = $2 AND i.min_stock_level > 0
+            GROUP BY i.id
+            HAVING COALESCE(SUM(s.quantity), 0) <= i.min_stock_level
+            "#,
+        )
+        .bind(id)
+        .bind(team_id)
+        .fetch_optional(&mut *tx)
+        .await?
+    } else {
+        None
+    };
+
     tx.commit().await?;
 
     tracing::info!(item_id = %id, qty = %req.quantity, txn_type = %req.txn_type, "Stock adjusted");
 
+    if let Some((item_name, current, minimum)) = low_stock {
+        let event = WsEvent::InventoryAlert {
+            item_id: id.to_string(),
+            item_name,
+            current_quantity: current.floor().to_i32().unwrap_or_default(),
+            min_quantity: minimum.ceil().to_i32().unwrap_or_default(),
+        };
+        realtime::publish(&state, Audience::team(team_id), event).await;
+    }
+
     Ok(Json(json!({
         "data": null,
         "meta": { "message": "Stock adjusted" },
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::json;
+
+use crate::errors::ApiResult;
+use crate::models::team::AcceptInvitationRequest;
+use crate::routes::auth::issue_session;
+use crate::services::team_service;
+use crate::AppState;
+
+/// Invitation links are opened before the invitee has an account, so these are public;
+/// the token in the link is the credential
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/invitations/accept", post(accept_invitation))
+        .route("/invitations/:token", get(preview_invitation))
+}
+
+/// What the accept page needs: who invited them, as what, and whether to ask for a password
+async fn preview_invitation(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let mut conn = state.db.acquire().await?;
+    let invitation = team_service::find_open_invitation(&mut conn, &token, false).await?;
+
+    let team_name = sqlx::query_scalar::<_, String>("SELECT name FROM teams WHERE id = $1")
+        .bind(invitation.team_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let has_account = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = $1)")
+        .bind(&invitation.email)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(Json(json!({
+        "data": {
+            "team_name": team_name,
+            "email": invitation.email,
+            "first_name": invitation.first_name,
+            "last_name": invitation.last_name,
+            "role": invitation.role,
+            "expires_at": invitation.expires_at,
+            "has_account": has_account,
+        },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Accept an invitation. A new account is signed in straight away; an existing account
+/// keeps its password and is asked to log in, so the link alone never grants a session
+/// to someone else's account.
+async fn accept_invitation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<AcceptInvitationRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let accepted = team_service::accept_invitation(&state, &req.token, req.password.as_deref()).await?;
+    let invitation = &accepted.invitation;
+
+    // Invitees verified their email by following the link; other policies still apply
+    let may_sign_in = state.config.auth.login_verification.allows(true, false);
+    if !accepted.created_user || !may_sign_in {
+        return Ok(Json(json!({
+            "data": {
+                "user_id": accepted.user_id,
+                "team_id": invitation.team_id,
+            },
+            "meta": {
+                "message": "Joined the team; sign in to continue",
+                "verification_required": !may_sign_in,
+            },
+            "errors": null,
+        })));
+    }
+
+    let (token, refresh_token) = issue_session(
+        &state,
+        accepted.user_id,
+        Some(&invitation.email),
+        &invitation.role,
+        Some(invitation.team_id),
+        &headers,
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "data": {
+            "token": token,
+            "refresh_token": refresh_token,
+            "user": {
+                "id": accepted.user_id,
+                "email": invitation.email,
+                "first_name": invitation.first_name,
+                "last_name": invitation.last_name,
+                "role": invitation.role,
+                "team_id": invitation.team_id,
+            }
+        },
+        "meta": null,
+        "errors": null,
+    })))
+}
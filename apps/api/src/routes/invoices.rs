@@ -1,35 +1,41 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
+use axum::response::Response;
 use axum::routing::{get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::common::PaginationParams;
 use crate::models::invoice::CreateInvoiceRequest;
 use crate::models::payment::RecordPaymentRequest;
+use crate::services::document_pdf::{self, DocumentKind};
+use crate::services::events;
+use crate::services::job_queue::BackgroundJob;
+use crate::services::realtime::{self, Audience, WsEvent};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/invoices", get(list_invoices).post(create_invoice))
-        .route("/invoices/{id}", get(get_invoice))
-        .route("/invoices/{id}/send", post(send_invoice))
-        .route("/invoices/{id}/void", post(void_invoice))
-        .route("/invoices/{id}/payments", get(list_payments).post(record_payment))
+        .route("/invoices", get(list_invoices).route_layer(require(Permission::InvoicesRead)))
+        .route("/invoices", post(create_invoice).route_layer(require(Permission::InvoicesWrite)))
+        .route("/invoices/:id", get(get_invoice).route_layer(require(Permission::InvoicesRead)))
+        .route("/invoices/:id/pdf", get(download_pdf).route_layer(require(Permission::InvoicesRead)))
+        .route("/invoices/:id/send", post(send_invoice).route_layer(require(Permission::InvoicesSend)))
+        .route("/invoices/:id/void", post(void_invoice).route_layer(require(Permission::InvoicesVoid)))
+        .route("/invoices/:id/payments", get(list_payments).route_layer(require(Permission::PaymentsRead)))
+        .route("/invoices/:id/payments", post(record_payment).route_layer(require(Permission::PaymentsRecord)))
 }
 
 async fn create_invoice(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateInvoiceRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let mut tx = state.db.begin().await?;
 
     let invoice_number = sqlx::query_scalar::<_, String>(
@@ -137,11 +143,10 @@ async fn create_invoice(
 
 async fn list_invoices(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(pagination): Query<PaginationParams>,
     Query(params): Query<std::collections::HashMap<String, String>>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let status = params.get("status").map(|s| s.as_str());
     let cursor = pagination.cursor.as_ref().and_then(|c| c.parse::<Uuid>().ok());
 
@@ -187,11 +192,9 @@ async fn list_invoices(
 
 async fn get_invoice(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let invoice = sqlx::query_as::<_, crate::models::invoice::Invoice>(
         "SELECT * FROM invoices WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
     )
@@ -228,11 +231,9 @@ async fn get_invoice(
 
 async fn send_invoice(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let invoice = sqlx::query_as::<_, crate::models::invoice::Invoice>(
         r#"
         UPDATE invoices SET status = 'sent'::invoice_status, sent_at = now()
@@ -248,6 +249,7 @@ async fn send_invoice(
 
     // TODO: Send email/SMS with portal link
     tracing::info!(invoice_id = %id, "Invoice sent to customer");
+    state.jobs.enqueue(BackgroundJob::GenerateInvoicePdf { team_id, invoice_id: id }).await?;
 
     Ok(Json(json!({
         "data": invoice,
@@ -256,13 +258,19 @@ async fn send_invoice(
     })))
 }
 
+async fn download_pdf(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Response> {
+    document_pdf::download(&state, DocumentKind::Invoice, team_id, id).await
+}
+
 async fn void_invoice(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let invoice = sqlx::query_as::<_, crate::models::invoice::Invoice>(
         r#"
         UPDATE invoices SET status = 'void'::invoice_status, voided_at = now()
@@ -285,11 +293,10 @@ async fn void_invoice(
 
 async fn record_payment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(invoice_id): Path<Uuid>,
     Json(req): Json<RecordPaymentRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let user_id = auth.id;
 
     // Validate payment amount
@@ -387,10 +394,31 @@ async fn record_payment(
     .execute(&mut *tx)
     .await?;
 
+    let data = json!({
+        "payment_id": payment.id,
+        "invoice_id": invoice_id,
+        "customer_id": invoice.customer_id,
+        "amount": payment.amount,
+        "tip_amount": payment.tip_amount,
+        "payment_method": payment.payment_method,
+    });
+    events::publish(&mut *tx, &state, team_id, events::PAYMENT_RECEIVED, data).await?;
+    if new_status == "paid" {
+        let data = json!({ "invoice_id": invoice_id, "customer_id": invoice.customer_id });
+        events::publish(&mut *tx, &state, team_id, events::INVOICE_PAID, data).await?;
+    }
+
     tx.commit().await?;
 
     tracing::info!(invoice_id = %invoice_id, amount = %req.amount, "Payment recorded");
 
+    let event = WsEvent::PaymentReceived {
+        payment_id: payment.id.to_string(),
+        invoice_id: invoice_id.to_string(),
+        amount: payment.amount.to_string(),
+    };
+    realtime::publish(&state, Audience::team(team_id).job(invoice.job_id), event).await;
+
     Ok(Json(json!({
         "data": payment,
         "meta": {
@@ -403,12 +431,14 @@ async fn record_payment(
 
 async fn list_payments(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(invoice_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let payments = sqlx::query_as::<_, crate::models::payment::Payment>(
-        "SELECT * FROM payments WHERE invoice_id = $1 ORDER BY collected_at DESC",
+        "SELECT * FROM payments WHERE invoice_id = $1 AND team_id = $2 ORDER BY collected_at DESC",
     )
     .bind(invoice_id)
+    .bind(team_id)
     .fetch_all(&state.db)
     .await?;
 
@@ -1,51 +1,64 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
-use axum::routing::{get, patch};
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::db::repository;
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::common::PaginationParams;
+use crate::models::geofence::GeofenceEvent;
 use crate::models::job::{CreateJobRequest, JobFilters, JobStatusTransition, UpdateJobRequest};
+use crate::services::schedule::{self, MoveRequest};
 use crate::services::job_service;
+use crate::services::realtime::{self, Audience, WsEvent};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/jobs", get(list_jobs).post(create_job))
-        .route("/jobs/{id}", get(get_job).patch(update_job))
-        .route("/jobs/{id}/status", patch(transition_status))
+        .route("/jobs", get(list_jobs).route_layer(require(Permission::JobsRead)))
+        .route("/jobs", post(create_job).route_layer(require(Permission::JobsWrite)))
+        .route("/jobs/:id", get(get_job).route_layer(require(Permission::JobsRead)))
+        .route("/jobs/:id", patch(update_job).route_layer(require(Permission::JobsWrite)))
+        .route("/jobs/:id/status", patch(transition_status).route_layer(require(Permission::JobsUpdateStatus)))
+        .route("/jobs/:id/geofence-events", get(list_geofence_events).route_layer(require(Permission::JobsRead)))
 }
 
 async fn create_job(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateJobRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let job = repository::create_job(&state.db, team_id, &req).await?;
 
     tracing::info!(job_id = %job.id, "Job created: {}", job.title);
 
+    if req.scheduled_date.is_some() {
+        let event = WsEvent::ScheduleChanged { job_id: job.id.to_string(), change_type: "created".into() };
+        realtime::publish(&state, Audience::team(team_id).job(Some(job.id)).user(job.assigned_to), event).await;
+    }
+    let conflicts = schedule::job_conflicts(&state, team_id, &job).await?;
+
     Ok(Json(json!({
         "data": job,
-        "meta": null,
+        "meta": { "schedule_conflicts": conflicts },
         "errors": null,
     })))
 }
 
 async fn list_jobs(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Query(pagination): Query<PaginationParams>,
-    Query(filters): Query<JobFilters>,
+    Query(mut filters): Query<JobFilters>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    if let Some(assignee) = auth.job_scope() {
+        filters.assigned_to = Some(assignee);
+    }
     let cursor = pagination.cursor.as_ref().and_then(|c| c.parse::<Uuid>().ok());
 
     let jobs = repository::list_jobs(&state.db, team_id, &filters, pagination.limit(), cursor).await?;
@@ -63,10 +76,10 @@ async fn list_jobs(
 
 async fn get_job(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    permissions::ensure_job_access(&state.db, &auth, team_id, id).await?;
     let job = repository::get_job(&state.db, team_id, id).await?;
 
     Ok(Json(json!({
@@ -76,91 +89,99 @@ async fn get_job(
     })))
 }
 
+/// Edit a job. A new assignee or schedule goes through the same checks as a move on the
+/// board: the technician must be an active member, only jobs not yet under way can be
+/// moved, and conflicts reject the change unless `force` is set.
 async fn update_job(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateJobRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-    // Verify job exists and belongs to team
-    let _existing = repository::get_job(&state.db, team_id, id).await?;
-
-    let updated = sqlx::query_as::<_, crate::models::job::Job>(
+    permissions::ensure_job_access(&state.db, &auth, team_id, id).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    let reschedules = req.assigned_to.is_some()
+        || req.scheduled_date.is_some()
+        || req.scheduled_start_time.is_some()
+        || req.scheduled_end_time.is_some();
+    let moved = if reschedules {
+        let request = MoveRequest {
+            assigned_to: req.assigned_to,
+            scheduled_date: req.scheduled_date,
+            scheduled_start_time: req.scheduled_start_time,
+            scheduled_end_time: req.scheduled_end_time,
+            force: req.force,
+            version: None,
+        };
+        Some(schedule::apply_move(&state, &mut tx, team_id, id, &request).await?)
+    } else {
+        None
+    };
+
+    let updated = sqlx::query_as::<_, crate::models::job::Job>(&format!(
         r#"
         UPDATE jobs SET
             title = COALESCE($3, title),
             description = COALESCE($4, description),
             priority = COALESCE($5::job_priority, priority),
-            assigned_to = COALESCE($6, assigned_to),
-            scheduled_date = COALESCE($7, scheduled_date),
-            scheduled_start_time = COALESCE($8, scheduled_start_time),
-            scheduled_end_time = COALESCE($9, scheduled_end_time),
-            estimated_duration_minutes = COALESCE($10, estimated_duration_minutes),
-            internal_notes = COALESCE($11, internal_notes),
-            version = version + 1
+            estimated_duration_minutes = COALESCE($6, estimated_duration_minutes),
+            internal_notes = COALESCE($7, internal_notes),
+            required_skills = COALESCE($8, required_skills),
+            required_license_types = COALESCE($9, required_license_types),
+            -- A move has already bumped it
+            version = version + CASE WHEN $10 THEN 0 ELSE 1 END
         WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL
-        RETURNING *
+        RETURNING {}
         "#,
-    )
+        repository::JOB_COLUMNS
+    ))
     .bind(id)
     .bind(team_id)
     .bind(&req.title)
     .bind(&req.description)
     .bind(&req.priority)
-    .bind(req.assigned_to)
-    .bind(req.scheduled_date)
-    .bind(req.scheduled_start_time)
-    .bind(req.scheduled_end_time)
     .bind(req.estimated_duration_minutes)
     .bind(&req.internal_notes)
-    .fetch_one(&state.db)
-    .await?;
+    .bind(&req.required_skills)
+    .bind(&req.required_license_types)
+    .bind(reschedules)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Job".into()))?;
+
+    tx.commit().await?;
+
+    let mut conflicts = Vec::new();
+    if let Some((existing, moved)) = moved {
+        let change_type = if updated.assigned_to != existing.assigned_to { "reassigned" } else { "rescheduled" };
+        let event = WsEvent::ScheduleChanged { job_id: id.to_string(), change_type: change_type.into() };
+        realtime::publish(&state, Audience::team(team_id).job(Some(id)).user(updated.assigned_to), event.clone()).await;
+        // The previous assignee's board needs to drop the job
+        if existing.assigned_to.is_some() && existing.assigned_to != updated.assigned_to {
+            realtime::publish(&state, Audience::team(team_id).user(existing.assigned_to), event).await;
+        }
+        conflicts = moved.conflicts;
+    }
 
     Ok(Json(json!({
         "data": updated,
-        "meta": null,
+        "meta": { "schedule_conflicts": conflicts },
         "errors": null,
     })))
 }
 
 async fn transition_status(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<JobStatusTransition>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let user_id = auth.id;
+    permissions::ensure_job_access(&state.db, &auth, team_id, id).await?;
 
-    let job = repository::get_job(&state.db, team_id, id).await?;
-
-    // Validate FSM transition
-    if !job_service::is_valid_transition(&job.status, &req.status) {
-        return Err(ApiError::BadRequest(format!(
-            "Invalid status transition: {} → {}",
-            job.status, req.status
-        )));
-    }
-
-    let updated = repository::update_job_status(
-        &state.db,
-        team_id,
-        id,
-        &req.status,
-        user_id,
-        req.latitude,
-        req.longitude,
-        req.note.as_deref(),
-    )
-    .await?;
-
-    // Trigger side effects
-    let side_effects = job_service::get_transition_side_effects(&job.status, &req.status);
-    for effect in &side_effects {
-        tracing::info!(job_id = %id, effect = %effect, "Triggering side effect");
-        // TODO: dispatch to background job queue
-    }
+    let (updated, side_effects) = job_service::transition_job(&state, team_id, id, user_id, &req).await?;
 
     Ok(Json(json!({
         "data": updated,
@@ -170,3 +191,27 @@ async fn transition_status(
         "errors": null,
     })))
 }
+
+/// Arrivals and departures at the job's property, oldest first, for checking when a
+/// technician was on site
+async fn list_geofence_events(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, id).await?;
+
+    let events = sqlx::query_as::<_, GeofenceEvent>(
+        "SELECT * FROM job_geofence_events WHERE job_id = $1 AND team_id = $2 ORDER BY recorded_at",
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({
+        "data": events,
+        "meta": { "total": events.len() },
+        "errors": null,
+    })))
+}
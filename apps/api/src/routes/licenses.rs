@@ -1,14 +1,14 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::license::{
     CreateInsurancePolicyRequest, CreateLicenseRequest, InsurancePolicy, License,
 };
@@ -16,24 +16,20 @@ use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/licenses", get(list_licenses).post(create_license))
-        .route("/licenses/{id}", get(get_license).delete(delete_license))
-        .route(
-            "/insurance-policies",
-            get(list_policies).post(create_policy),
-        )
-        .route(
-            "/insurance-policies/{id}",
-            get(get_policy).delete(delete_policy),
-        )
+        .route("/licenses", get(list_licenses).route_layer(require(Permission::ComplianceRead)))
+        .route("/licenses", post(create_license).route_layer(require(Permission::ComplianceWrite)))
+        .route("/licenses/:id", get(get_license).route_layer(require(Permission::ComplianceRead)))
+        .route("/licenses/:id", delete(delete_license).route_layer(require(Permission::ComplianceWrite)))
+        .route("/insurance-policies", get(list_policies).route_layer(require(Permission::ComplianceRead)))
+        .route("/insurance-policies", post(create_policy).route_layer(require(Permission::ComplianceWrite)))
+        .route("/insurance-policies/:id", get(get_policy).route_layer(require(Permission::ComplianceRead)))
+        .route("/insurance-policies/:id", delete(delete_policy).route_layer(require(Permission::ComplianceWrite)))
 }
 
 async fn list_licenses(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let licenses = sqlx::query_as::<_, License>(
         "SELECT * FROM licenses WHERE team_id = $1 ORDER BY expiry_date ASC",
     )
@@ -46,11 +42,9 @@ async fn list_licenses(
 
 async fn create_license(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateLicenseRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let license = sqlx::query_as::<_, License>(
         r#"INSERT INTO licenses (team_id, user_id, license_type, license_number, issuing_state, issuing_authority, issued_date, expiry_date)
            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
@@ -72,11 +66,9 @@ async fn create_license(
 
 async fn get_license(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let license = sqlx::query_as::<_, License>(
         "SELECT * FROM licenses WHERE id = $1 AND team_id = $2",
     )
@@ -91,11 +83,9 @@ async fn get_license(
 
 async fn delete_license(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM licenses WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -107,10 +97,8 @@ async fn delete_license(
 
 async fn list_policies(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let policies = sqlx::query_as::<_, InsurancePolicy>(
         "SELECT * FROM insurance_policies WHERE team_id = $1 ORDER BY expiry_date ASC",
     )
@@ -123,11 +111,9 @@ async fn list_policies(
 
 async fn create_policy(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateInsurancePolicyRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let policy = sqlx::query_as::<_, InsurancePolicy>(
         r#"INSERT INTO insurance_policies (team_id, policy_type, provider, policy_number, coverage_amount, premium_amount, effective_date, expiry_date, auto_renew, notes)
            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
@@ -151,11 +137,9 @@ async fn create_policy(
 
 async fn get_policy(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let policy = sqlx::query_as::<_, InsurancePolicy>(
         "SELECT * FROM insurance_policies WHERE id = $1 AND team_id = $2",
     )
@@ -170,11 +154,9 @@ async fn get_policy(
 
 async fn delete_policy(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM insurance_policies WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -1,31 +1,34 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::message::{Message, SendMessageRequest};
+use crate::services::realtime::{self, Audience, WsEvent};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/messages", get(list_messages).post(send_message))
-        .route("/customers/{customer_id}/messages", get(list_customer_messages))
-        .route("/jobs/{job_id}/messages", get(list_job_messages))
-        .route("/messages/{id}", get(get_message))
+        .route("/messages", get(list_messages).route_layer(require(Permission::MessagesRead)))
+        .route("/messages", post(send_message).route_layer(require(Permission::MessagesSend)))
+        .route(
+            "/customers/:customer_id/messages",
+            get(list_customer_messages).route_layer(require(Permission::MessagesRead)),
+        )
+        .route("/jobs/:job_id/messages", get(list_job_messages).route_layer(require(Permission::MessagesRead)))
+        .route("/messages/:id", get(get_message).route_layer(require(Permission::MessagesRead)))
 }
 
 async fn list_messages(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let messages = sqlx::query_as::<_, Message>(
         "SELECT * FROM messages WHERE team_id = $1 ORDER BY created_at DESC LIMIT 100",
     )
@@ -42,11 +45,9 @@ async fn list_messages(
 
 async fn send_message(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<SendMessageRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     // Look up customer contact info
     let customer = sqlx::query_as::<_, crate::models::customer::Customer>(
         "SELECT * FROM customers WHERE id = $1 AND team_id = $2",
@@ -85,6 +86,13 @@ async fn send_message(
     // TODO: Actually send via Twilio/SendGrid and update status
     tracing::info!(message_id = %message.id, channel = %req.channel, "Message queued");
 
+    let event = WsEvent::NewMessage {
+        message_id: message.id.to_string(),
+        customer_id: message.customer_id.to_string(),
+        direction: message.direction.clone(),
+    };
+    realtime::publish(&state, Audience::team(team_id).job(message.job_id), event).await;
+
     Ok(Json(json!({
         "data": message,
         "meta": null,
@@ -94,12 +102,14 @@ async fn send_message(
 
 async fn list_customer_messages(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(customer_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let messages = sqlx::query_as::<_, Message>(
-        "SELECT * FROM messages WHERE customer_id = $1 ORDER BY created_at DESC",
+        "SELECT * FROM messages WHERE customer_id = $1 AND team_id = $2 ORDER BY created_at DESC",
     )
     .bind(customer_id)
+    .bind(team_id)
     .fetch_all(&state.db)
     .await?;
 
@@ -112,8 +122,11 @@ async fn list_customer_messages(
 
 async fn list_job_messages(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
+
     let messages = sqlx::query_as::<_, Message>(
         "SELECT * FROM messages WHERE job_id = $1 ORDER BY created_at DESC",
     )
@@ -130,12 +143,14 @@ async fn list_job_messages(
 
 async fn get_message(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let message = sqlx::query_as::<_, Message>(
-        "SELECT * FROM messages WHERE id = $1",
+        "SELECT * FROM messages WHERE id = $1 AND team_id = $2",
     )
     .bind(id)
+    .bind(team_id)
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Message".into()))?;
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::auth::{deny_api_keys, AuthUser};
+use crate::services::{auth_service, mfa_service};
+use crate::AppState;
+
+/// Self-service two-factor settings. Not team-scoped: the second factor belongs to the
+/// account, whichever team the request acts on.
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/auth/mfa", get(mfa_status).delete(disable_mfa))
+        .route("/auth/mfa/totp/setup", post(setup_totp))
+        .route("/auth/mfa/totp/confirm", post(confirm_totp))
+        .route("/auth/mfa/recovery-codes", post(regenerate_recovery_codes))
+        .route_layer(axum::middleware::from_fn(deny_api_keys))
+}
+
+#[derive(Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct DisableMfaRequest {
+    password: String,
+    code: String,
+}
+
+async fn mfa_status(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let mut conn = state.db.acquire().await?;
+    let enrollment = mfa_service::get_enrollment(&mut conn, auth.id).await?;
+    let remaining = mfa_service::remaining_recovery_codes(&state.db, auth.id).await?;
+
+    Ok(Json(json!({
+        "data": {
+            "enabled": enrollment.as_ref().is_some_and(|e| e.confirmed_at.is_some()),
+            "pending_setup": enrollment.as_ref().is_some_and(|e| e.confirmed_at.is_none()),
+            "enabled_at": enrollment.and_then(|e| e.confirmed_at),
+            "recovery_codes_remaining": remaining,
+            "required_by_team": auth.team_requires_mfa,
+        },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Generate a secret and the `otpauth://` URI to show as a QR code. Nothing is enforced
+/// until the first code is confirmed.
+async fn setup_totp(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let secret = mfa_service::begin_enrollment(&state.db, &state.config.auth.mfa_encryption_key, auth.id).await?;
+
+    Ok(Json(json!({
+        "data": {
+            "secret": secret,
+            "otpauth_uri": mfa_service::provisioning_uri(&secret, &auth.email),
+        },
+        "meta": { "message": "Scan the code, then confirm with the 6-digit code it shows" },
+        "errors": null,
+    })))
+}
+
+/// Turn TOTP on. The recovery codes are only ever shown in this response.
+async fn confirm_totp(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+    Json(req): Json<CodeRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let recovery_codes = mfa_service::confirm_enrollment(&state.db, &state.config.auth.mfa_encryption_key, auth.id, &req.code).await?;
+
+    Ok(Json(json!({
+        "data": { "enabled": true, "recovery_codes": recovery_codes },
+        "meta": { "message": "Store these recovery codes somewhere safe; each works once" },
+        "errors": null,
+    })))
+}
+
+async fn regenerate_recovery_codes(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+    Json(req): Json<CodeRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let recovery_codes = mfa_service::regenerate_recovery_codes(&state.db, &state.config.auth.mfa_encryption_key, auth.id, &req.code).await?;
+
+    Ok(Json(json!({
+        "data": { "recovery_codes": recovery_codes },
+        "meta": { "message": "Previous recovery codes no longer work" },
+        "errors": null,
+    })))
+}
+
+/// Turn TOTP off with the password and a current code. Not allowed while any of the
+/// user's teams requires it.
+async fn disable_mfa(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+    Json(req): Json<DisableMfaRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let required = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM team_memberships m
+            JOIN teams t ON t.id = m.team_id
+            WHERE m.user_id = $1 AND m.is_active = true AND t.require_mfa = true
+        )
+        "#,
+    )
+    .bind(auth.id)
+    .fetch_one(&state.db)
+    .await?;
+    if required {
+        return Err(ApiError::BadRequest("A team you belong to requires two-factor authentication".into()));
+    }
+
+    let password_hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE id = $1")
+        .bind(auth.id)
+        .fetch_one(&state.db)
+        .await?;
+    if !auth_service::verify_password(&req.password, &password_hash)? {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let mut tx = state.db.begin().await?;
+    if !mfa_service::verify_second_factor(&mut tx, &state.config.auth.mfa_encryption_key, auth.id, &req.code).await? {
+        tx.commit().await?;
+        return Err(ApiError::Validation("Incorrect authentication code".into()));
+    }
+    mfa_service::disable(&mut tx, auth.id).await?;
+    tx.commit().await?;
+
+    tracing::info!(user_id = %auth.id, "Two-factor authentication disabled");
+
+    Ok(Json(json!({
+        "data": { "enabled": false },
+        "meta": null,
+        "errors": null,
+    })))
+}
@@ -31,8 +31,16 @@ pub mod tags;
 pub mod fuel_logs;
 pub mod purchase_orders;
 pub mod gps;
+pub mod schedule;
+pub mod availability;
 pub mod portal;
 pub mod stripe;
+pub mod background_jobs;
+pub mod scheduled_tasks;
+pub mod role_permissions;
+pub mod invitations;
+pub mod mfa;
+pub mod api_keys;
 
 use std::sync::Arc;
 use axum::Router;
@@ -44,12 +52,16 @@ pub fn api_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
     let public_routes = Router::new()
         .merge(health::router())
         .merge(auth::router())
+        .merge(invitations::router())
         .merge(portal::router())
         .merge(stripe::router())
-        .merge(webhooks::router());
+        // Authenticates from a query token or its first message
+        .merge(ws::router());
 
     // Protected routes — require valid JWT
     let protected_routes = Router::new()
+        .merge(auth::protected_router())
+        .merge(mfa::router())
         .merge(customers::router())
         .merge(jobs::router())
         .merge(estimates::router())
@@ -71,7 +83,6 @@ pub fn api_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
         .merge(audit::router())
         .merge(notifications::router())
         .merge(payments::router())
-        .merge(ws::router())
         .merge(automation_rules::router())
         .merge(documents::router())
         .merge(licenses::router())
@@ -80,6 +91,13 @@ pub fn api_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
         .merge(fuel_logs::router())
         .merge(purchase_orders::router())
         .merge(gps::router())
+        .merge(schedule::router())
+        .merge(availability::router())
+        .merge(background_jobs::router())
+        .merge(scheduled_tasks::router())
+        .merge(webhooks::router())
+        .merge(role_permissions::router())
+        .merge(api_keys::router())
         .layer(axum::middleware::from_fn_with_state(state.clone(), require_auth));
 
     Router::new()
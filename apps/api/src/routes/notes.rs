@@ -1,31 +1,36 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::note::CreateNoteRequest;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/jobs/{job_id}/notes", get(list_job_notes).post(create_note))
-        .route("/customers/{customer_id}/notes", get(list_customer_notes))
-        .route("/notes/{id}", get(get_note).delete(delete_note))
+        .route("/jobs/:job_id/notes", get(list_job_notes).route_layer(require(Permission::JobsRead)))
+        .route("/jobs/:job_id/notes", post(create_note).route_layer(require(Permission::JobsWork)))
+        .route(
+            "/customers/:customer_id/notes",
+            get(list_customer_notes).route_layer(require(Permission::CustomersRead)),
+        )
+        .route("/notes/:id", get(get_note).route_layer(require(Permission::JobsRead)))
+        .route("/notes/:id", delete(delete_note).route_layer(require(Permission::JobsWork)))
 }
 
 async fn create_note(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
     Json(req): Json<CreateNoteRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
     let user_id = auth.id;
 
     let note_type = req.note_type.as_deref().unwrap_or("text");
@@ -59,8 +64,11 @@ async fn create_note(
 
 async fn list_job_notes(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
+
     let notes = sqlx::query_as::<_, crate::models::note::Note>(
         "SELECT * FROM notes WHERE job_id = $1 ORDER BY created_at DESC",
     )
@@ -77,12 +85,15 @@ async fn list_job_notes(
 
 async fn list_customer_notes(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(customer_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let notes = sqlx::query_as::<_, crate::models::note::Note>(
-        "SELECT * FROM notes WHERE customer_id = $1 ORDER BY created_at DESC",
+        "SELECT * FROM notes WHERE customer_id = $1 AND team_id = $2 AND (job_id IS NULL OR $3::uuid IS NULL OR job_id IN (SELECT id FROM jobs WHERE assigned_to = $3)) ORDER BY created_at DESC",
     )
     .bind(customer_id)
+    .bind(team_id)
+    .bind(auth.job_scope())
     .fetch_all(&state.db)
     .await?;
 
@@ -95,12 +106,15 @@ async fn list_customer_notes(
 
 async fn get_note(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let note = sqlx::query_as::<_, crate::models::note::Note>(
-        "SELECT * FROM notes WHERE id = $1",
+        "SELECT * FROM notes WHERE id = $1 AND team_id = $2 AND (job_id IS NULL OR $3::uuid IS NULL OR job_id IN (SELECT id FROM jobs WHERE assigned_to = $3))",
     )
     .bind(id)
+    .bind(team_id)
+    .bind(auth.job_scope())
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Note".into()))?;
@@ -114,10 +128,13 @@ async fn get_note(
 
 async fn delete_note(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    sqlx::query("DELETE FROM notes WHERE id = $1")
+    sqlx::query("DELETE FROM notes WHERE id = $1 AND team_id = $2 AND (job_id IS NULL OR $3::uuid IS NULL OR job_id IN (SELECT id FROM jobs WHERE assigned_to = $3))")
         .bind(id)
+        .bind(team_id)
+        .bind(auth.job_scope())
         .execute(&state.db)
         .await?;
 
@@ -9,16 +9,17 @@ use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::ApiResult;
-use crate::middleware::auth::AuthUser;
+use crate::middleware::auth::{deny_api_keys, AuthUser};
 use crate::models::common::PaginationParams;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/notifications", get(list_notifications))
-        .route("/notifications/{id}/read", post(mark_read))
+        .route("/notifications/:id/read", post(mark_read))
         .route("/notifications/read-all", post(mark_all_read))
         .route("/notifications/unread-count", get(unread_count))
+        .route_layer(axum::middleware::from_fn(deny_api_keys))
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
@@ -1,35 +1,36 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, Query, State};
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::ApiResult;
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::common::PaginationParams;
+use crate::services::payment_service;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/payments", get(list_payments))
-        .route("/payments/{id}", get(get_payment))
-        .route("/payments/{id}/refund", axum::routing::post(refund_payment))
+        .route("/payments", get(list_payments).route_layer(require(Permission::PaymentsRead)))
+        .route("/payments/:id", get(get_payment).route_layer(require(Permission::PaymentsRead)))
+        .route("/payments/:id/refund", post(refund_payment).route_layer(require(Permission::PaymentsRefund)))
 }
 
 async fn list_payments(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Query(pagination): Query<PaginationParams>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let limit = pagination.limit();
 
-    let payments = sqlx::query_as::<_, crate::models::payment::Payment>(
-        r#"SELECT * FROM payments WHERE team_id = $1 ORDER BY collected_at DESC LIMIT $2"#,
-    )
+    let payments = sqlx::query_as::<_, crate::models::payment::Payment>(&format!(
+        "SELECT {} FROM payments WHERE team_id = $1 ORDER BY collected_at DESC LIMIT $2",
+        payment_service::PAYMENT_COLUMNS
+    ))
     .bind(team_id)
     .bind(limit)
     .fetch_all(&state.db)
@@ -49,14 +50,13 @@ async fn list_payments(
 
 async fn get_payment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    let payment = sqlx::query_as::<_, crate::models::payment::Payment>(
-        r#"SELECT * FROM payments WHERE id = $1 AND team_id = $2"#,
-    )
+    let payment = sqlx::query_as::<_, crate::models::payment::Payment>(&format!(
+        "SELECT {} FROM payments WHERE id = $1 AND team_id = $2",
+        payment_service::PAYMENT_COLUMNS
+    ))
     .bind(id)
     .bind(team_id)
     .fetch_optional(&state.db)
@@ -72,45 +72,19 @@ async fn get_payment(
 
 async fn refund_payment(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<RefundRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    let payment = sqlx::query_as::<_, crate::models::payment::Payment>(
-        r#"SELECT * FROM payments WHERE id = $1 AND team_id = $2"#,
-    )
-    .bind(id)
-    .bind(team_id)
-    .fetch_optional(&state.db)
-    .await?
-    .ok_or_else(|| crate::errors::ApiError::NotFound("Payment".into()))?;
-
-    if payment.status == "refunded" {
-        return Err(crate::errors::ApiError::Validation("Payment already refunded".into()));
-    }
-
-    let refund_amount = req.amount.unwrap_or(payment.amount);
-
-    let updated = sqlx::query_as::<_, crate::models::payment::Payment>(
-        r#"UPDATE payments
-           SET status = 'refunded', refunded_amount = $3, refund_reason = $4, updated_at = NOW()
-           WHERE id = $1 AND team_id = $2
-           RETURNING *"#,
-    )
-    .bind(id)
-    .bind(team_id)
-    .bind(refund_amount)
-    .bind(&req.reason)
-    .fetch_one(&state.db)
-    .await?;
-
-    tracing::info!(payment_id = %id, amount = %refund_amount, "Payment refunded");
+    let (payment, processor_refund) =
+        payment_service::refund_payment(&state, team_id, id, req.amount, req.reason.as_deref()).await?;
 
     Ok(Json(json!({
-        "data": updated,
-        "meta": null,
+        "data": payment,
+        "meta": {
+            "refund_id": processor_refund.as_ref().map(|r| r.id.clone()),
+            "refund_status": processor_refund.map(|r| r.status).unwrap_or_else(|| "manual".into()),
+        },
         "errors": null,
     })))
 }
@@ -1,22 +1,24 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/jobs/{job_id}/photos", get(list_job_photos).post(create_photo))
-        .route("/photos/{id}", get(get_photo).delete(delete_photo))
-        .route("/photos/presigned-url", post(get_presigned_upload_url))
+        .route("/jobs/:job_id/photos", get(list_job_photos).route_layer(require(Permission::JobsRead)))
+        .route("/jobs/:job_id/photos", post(create_photo).route_layer(require(Permission::JobsWork)))
+        .route("/photos/:id", get(get_photo).route_layer(require(Permission::JobsRead)))
+        .route("/photos/:id", delete(delete_photo).route_layer(require(Permission::JobsWork)))
+        .route("/photos/presigned-url", post(get_presigned_upload_url).route_layer(require(Permission::JobsWork)))
 }
 
 #[derive(Deserialize)]
@@ -36,10 +38,9 @@ struct PresignedUrlResponse {
 
 async fn get_presigned_upload_url(
     State(_state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<PresignedUrlRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let file_id = Uuid::new_v4();
 
     // Generate S3 key: team_id/photos/year/month/file_id.ext
@@ -98,11 +99,11 @@ struct CreatePhotoRequest {
 
 async fn create_photo(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
     Json(req): Json<CreatePhotoRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
     let user_id = auth.id;
 
     let category = req.category.as_deref().unwrap_or("general");
@@ -140,8 +141,11 @@ async fn create_photo(
 
 async fn list_job_photos(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
+
     let photos = sqlx::query_as::<_, crate::models::photo::Photo>(
         "SELECT * FROM photos WHERE job_id = $1 AND deleted_at IS NULL ORDER BY created_at DESC",
     )
@@ -158,12 +162,15 @@ async fn list_job_photos(
 
 async fn get_photo(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let photo = sqlx::query_as::<_, crate::models::photo::Photo>(
-        "SELECT * FROM photos WHERE id = $1 AND deleted_at IS NULL",
+        "SELECT * FROM photos WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL AND (job_id IS NULL OR $3::uuid IS NULL OR job_id IN (SELECT id FROM jobs WHERE assigned_to = $3))",
     )
     .bind(id)
+    .bind(team_id)
+    .bind(auth.job_scope())
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Photo".into()))?;
@@ -188,12 +195,15 @@ async fn get_photo(
 
 async fn delete_photo(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     sqlx::query(
-        "UPDATE photos SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL",
+        "UPDATE photos SET deleted_at = now() WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL AND (job_id IS NULL OR $3::uuid IS NULL OR job_id IN (SELECT id FROM jobs WHERE assigned_to = $3))",
     )
     .bind(id)
+    .bind(team_id)
+    .bind(auth.job_scope())
     .execute(&state.db)
     .await?;
 
@@ -1,21 +1,30 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use rust_decimal::Decimal;
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
+use crate::services::events;
+use crate::services::job_queue::BackgroundJob;
+use crate::services::payment_provider::NewPaymentIntent;
+use crate::services::payment_service;
+use crate::services::tracking;
 use crate::AppState;
 
 /// Public customer portal endpoints — no auth required, token-based access
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/portal/estimates/{token}", get(get_estimate_by_token))
-        .route("/portal/estimates/{token}/approve", post(approve_estimate))
-        .route("/portal/estimates/{token}/decline", post(decline_estimate))
-        .route("/portal/invoices/{token}", get(get_invoice_by_token))
-        .route("/portal/invoices/{token}/pay", post(initiate_payment))
+        .route("/portal/estimates/:token", get(get_estimate_by_token))
+        .route("/portal/estimates/:token/approve", post(approve_estimate))
+        .route("/portal/estimates/:token/decline", post(decline_estimate))
+        .route("/portal/invoices/:token", get(get_invoice_by_token))
+        .route("/portal/invoices/:token/pay", post(initiate_payment))
+        .route("/portal/tracking/:token", get(get_tracking))
 }
 
 async fn get_estimate_by_token(
@@ -54,22 +63,63 @@ async fn approve_estimate(
     Path(token): Path<String>,
     Json(req): Json<ApproveEstimateRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    let signature = req.signature_data.as_deref().filter(|s| !s.trim().is_empty());
+    let mut tx = state.db.begin().await?;
+
     let estimate = sqlx::query_as::<_, crate::models::estimate::Estimate>(
         r#"UPDATE estimates
            SET status = 'approved'::estimate_status,
                approved_at = NOW(),
-               approved_by_name = $2,
+               customer_signature = COALESCE($2, customer_signature),
+               signed_at = CASE WHEN $2 IS NOT NULL THEN NOW() ELSE signed_at END,
                updated_at = NOW()
            WHERE portal_token = $1 AND status IN ('sent'::estimate_status, 'viewed'::estimate_status)
            RETURNING *"#,
     )
     .bind(&token)
-    .bind(&req.signer_name)
-    .fetch_optional(&state.db)
+    .bind(signature)
+    .fetch_optional(&mut *tx)
     .await?
     .ok_or_else(|| ApiError::NotFound("Estimate".into()))?;
 
+    // Keep who signed alongside the image so the PDF can caption it
+    if let Some(signature) = signature {
+        let signer_name = req
+            .signer_name
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .unwrap_or("Customer");
+        sqlx::query(
+            r#"INSERT INTO signatures (team_id, job_id, estimate_id, signer_name, signer_role, signature_data)
+               VALUES ($1, $2, $3, $4, 'customer', $5)"#,
+        )
+        .bind(estimate.team_id)
+        .bind(estimate.job_id)
+        .bind(estimate.id)
+        .bind(signer_name)
+        .bind(signature)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let data = json!({
+        "estimate_id": estimate.id,
+        "estimate_number": estimate.estimate_number,
+        "customer_id": estimate.customer_id,
+        "job_id": estimate.job_id,
+        "total": estimate.total,
+        "approved_via": "portal",
+    });
+    events::publish(&mut *tx, &state, estimate.team_id, events::ESTIMATE_APPROVED, data).await?;
+
+    tx.commit().await?;
+
     tracing::info!(estimate_id = %estimate.id, "Estimate approved via portal");
+    state
+        .jobs
+        .enqueue(BackgroundJob::GenerateEstimatePdf { team_id: estimate.team_id, estimate_id: estimate.id })
+        .await?;
 
     Ok(Json(json!({ "data": estimate, "meta": null, "errors": null })))
 }
@@ -117,9 +167,10 @@ async fn get_invoice_by_token(
     .fetch_all(&state.db)
     .await?;
 
-    let payments = sqlx::query_as::<_, crate::models::payment::Payment>(
-        "SELECT * FROM payments WHERE invoice_id = $1 ORDER BY collected_at DESC",
-    )
+    let payments = sqlx::query_as::<_, crate::models::payment::Payment>(&format!(
+        "SELECT {} FROM payments WHERE invoice_id = $1 ORDER BY collected_at DESC",
+        payment_service::PAYMENT_COLUMNS
+    ))
     .bind(invoice.id)
     .fetch_all(&state.db)
     .await?;
@@ -135,44 +186,190 @@ async fn get_invoice_by_token(
     })))
 }
 
+#[derive(sqlx::FromRow)]
+struct PayableInvoice {
+    id: Uuid,
+    team_id: Uuid,
+    customer_id: Uuid,
+    invoice_number: String,
+    amount_due: Decimal,
+    team_name: String,
+    customer_email: Option<String>,
+}
+
+/// Methods a customer can pay with online; cash and checks are recorded by the team
+const ONLINE_METHODS: &[&str] = &["card", "ach", "apple_pay", "google_pay"];
+
+/// Start a card payment: record a pending payment, create the PaymentIntent for amount + tip,
+/// and hand its client secret to Stripe.js. The payment only counts towards the invoice once
+/// Stripe's `payment_intent.succeeded` webhook arrives.
+///
+/// The pending payment is committed before Stripe is called so the invoice lock isn't held
+/// across the network; Stripe can't report on the intent until Stripe.js confirms it with
+/// the client secret, which only leaves here after the intent id is stored.
 async fn initiate_payment(
     State(state): State<Arc<AppState>>,
     Path(token): Path<String>,
     Json(req): Json<InitiatePaymentRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let invoice = sqlx::query_as::<_, crate::models::invoice::Invoice>(
-        "SELECT * FROM invoices WHERE portal_token = $1 AND status NOT IN ('void'::invoice_status, 'paid'::invoice_status)",
+    let method = req.method.as_deref().unwrap_or("card");
+    if !ONLINE_METHODS.contains(&method) {
+        return Err(ApiError::Validation(format!("Payment method '{}' can't be used online", method)));
+    }
+
+    let tip = req.tip_amount.unwrap_or_default();
+    if req.amount <= Decimal::ZERO {
+        return Err(ApiError::Validation("Payment amount must be greater than zero".into()));
+    }
+    if tip < Decimal::ZERO {
+        return Err(ApiError::Validation("Tip can't be negative".into()));
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let invoice = sqlx::query_as::<_, PayableInvoice>(
+        r#"
+        SELECT i.id, i.team_id, i.customer_id, i.invoice_number, i.amount_due,
+               t.name AS team_name, c.email AS customer_email
+        FROM invoices i
+        JOIN teams t ON t.id = i.team_id
+        JOIN customers c ON c.id = i.customer_id
+        WHERE i.portal_token = $1 AND i.deleted_at IS NULL
+          AND i.status NOT IN ('void'::invoice_status, 'paid'::invoice_status, 'draft'::invoice_status)
+        FOR UPDATE OF i
+        "#,
     )
     .bind(&token)
-    .fetch_optional(&state.db)
+    .fetch_optional(&mut *tx)
     .await?
     .ok_or_else(|| ApiError::NotFound("Invoice".into()))?;
 
-    // Record the payment
-    let payment = sqlx::query_as::<_, crate::models::payment::Payment>(
-        r#"INSERT INTO payments (team_id, invoice_id, amount, method, status, collected_at)
-           VALUES ($1, $2, $3, $4::payment_method, 'pending'::payment_status, NOW())
-           RETURNING *"#,
+    let expiry_minutes = state.config.stripe.pending_payment_expiry_minutes;
+
+    // A reload or a second click picks up the payment already started for the same amount,
+    // and the idempotency key below hands back the same PaymentIntent
+    let open = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT id FROM payments
+        WHERE invoice_id = $1 AND status = 'pending'::payment_status
+          AND amount = $2 AND tip_amount = $3 AND payment_method = $4::payment_method
+          AND created_at > NOW() - make_interval(mins => $5::int)
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
     )
-    .bind(invoice.team_id)
     .bind(invoice.id)
     .bind(req.amount)
-    .bind(req.method.as_deref().unwrap_or("card"))
+    .bind(tip)
+    .bind(method)
+    .bind(expiry_minutes)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let payment_id = match open {
+        Some(payment_id) => payment_id,
+        None => {
+            // Payments still awaiting Stripe hold their share of the balance until they
+            // settle, fail or are expired by the scheduler
+            let pending = sqlx::query_scalar::<_, Decimal>(
+                r#"
+                SELECT COALESCE(SUM(amount), 0) FROM payments
+                WHERE invoice_id = $1 AND status IN ('pending'::payment_status, 'processing'::payment_status)
+                "#,
+            )
+            .bind(invoice.id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            let payable = (invoice.amount_due - pending).max(Decimal::ZERO);
+            if req.amount > payable {
+                return Err(ApiError::Validation(if pending > Decimal::ZERO {
+                    format!(
+                        "Payment amount {} exceeds amount due {} ({} is already awaiting confirmation)",
+                        req.amount, payable, pending
+                    )
+                } else {
+                    format!("Payment amount {} exceeds amount due {}", req.amount, invoice.amount_due)
+                }));
+            }
+
+            sqlx::query_scalar::<_, Uuid>(
+                r#"
+                INSERT INTO payments (team_id, invoice_id, customer_id, amount, tip_amount, payment_method, status)
+                VALUES ($1, $2, $3, $4, $5, $6::payment_method, 'pending'::payment_status)
+                RETURNING id
+                "#,
+            )
+            .bind(invoice.team_id)
+            .bind(invoice.id)
+            .bind(invoice.customer_id)
+            .bind(req.amount)
+            .bind(tip)
+            .bind(method)
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+
+    tx.commit().await?;
+
+    let intent = NewPaymentIntent {
+        amount_cents: payment_service::to_cents(req.amount + tip)?,
+        currency: state.config.stripe.currency.clone(),
+        description: format!("{} — invoice {}", invoice.team_name, invoice.invoice_number),
+        receipt_email: invoice.customer_email.clone(),
+        metadata: HashMap::from([
+            ("payment_id".to_string(), payment_id.to_string()),
+            ("invoice_id".to_string(), invoice.id.to_string()),
+            ("team_id".to_string(), invoice.team_id.to_string()),
+        ]),
+        idempotency_key: format!("payment-{}", payment_id),
+    };
+    let created = match state.payments.create_payment_intent(&intent).await {
+        Ok(created) => created,
+        Err(e) => {
+            // Nothing was charged; don't leave a pending payment nobody can complete
+            sqlx::query("UPDATE payments SET status = 'failed'::payment_status WHERE id = $1")
+                .bind(payment_id)
+                .execute(&state.db)
+                .await?;
+            return Err(ApiError::Internal(e.context("Failed to create PaymentIntent")));
+        }
+    };
+
+    let payment = sqlx::query_as::<_, crate::models::payment::Payment>(&format!(
+        "UPDATE payments SET stripe_payment_intent_id = $2 WHERE id = $1 RETURNING {}",
+        payment_service::PAYMENT_COLUMNS
+    ))
+    .bind(payment_id)
+    .bind(&created.id)
     .fetch_one(&state.db)
     .await?;
 
-    tracing::info!(invoice_id = %invoice.id, payment_id = %payment.id, amount = %req.amount, "Payment initiated via portal");
+    tracing::info!(invoice_id = %invoice.id, payment_id = %payment.id, amount = %req.amount, tip = %tip, "Payment initiated via portal");
 
     Ok(Json(json!({
         "data": {
             "payment": payment,
-            "stripe_client_secret": null,
+            "stripe_client_secret": created.client_secret,
+            "stripe_publishable_key": state.config.stripe.publishable_key,
         },
         "meta": null,
         "errors": null,
     })))
 }
 
+/// Live "technician on the way" page: latest location, ETA and arrival window. The link
+/// stops working once the job starts.
+async fn get_tracking(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let view = tracking::view(&state, &token).await?;
+
+    Ok(Json(json!({ "data": view, "meta": null, "errors": null })))
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct ApproveEstimateRequest {
     signer_name: Option<String>,
@@ -186,6 +383,147 @@ struct DeclineEstimateRequest {
 
 #[derive(Debug, serde::Deserialize)]
 struct InitiatePaymentRequest {
-    amount: rust_decimal::Decimal,
+    amount: Decimal,
+    tip_amount: Option<Decimal>,
     method: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use chrono::Utc;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::services::stripe_webhook;
+    use crate::test_support::{self, WEBHOOK_SECRET};
+
+    async fn send(app: &Router, request: Request<Body>) -> StatusCode {
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    /// A sent invoice for $150 and its portal token
+    async fn sent_invoice(db: &PgPool) -> (Uuid, String) {
+        let team_id = test_support::team(db, "acme").await;
+        let customer_id = test_support::customer(db, team_id).await;
+        sqlx::query_as(
+            r#"
+            INSERT INTO invoices (team_id, customer_id, invoice_number, status, total, amount_due)
+            VALUES ($1, $2, 'INV-0001', 'sent'::invoice_status, 150, 150)
+            RETURNING id, portal_token
+            "#,
+        )
+        .bind(team_id)
+        .bind(customer_id)
+        .fetch_one(db)
+        .await
+        .unwrap()
+    }
+
+    async fn pay(app: &Router, token: &str, amount: i64, tip: i64) -> (StatusCode, serde_json::Value) {
+        let body = json!({ "amount": amount, "tip_amount": tip });
+        test_support::call(app, "POST", &format!("/api/v1/portal/invoices/{}/pay", token), None, Some(body)).await
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn portal_payment_settles_from_signed_webhook(db: PgPool) {
+        let (invoice_id, token) = sent_invoice(&db).await;
+        let provider = FakeProvider::default();
+        let state = test_support::state(db.clone(), provider.clone()).await;
+        let app = test_support::app(state);
+
+        let (status, body) = pay(&app, &token, 150, 10).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let intents = provider.intents();
+        assert_eq!(intents.len(), 1);
+        let intent = &intents[0];
+        assert_eq!(intent.request.amount_cents, 16_000);
+        let payment_id = Uuid::from_str(&intent.request.metadata["payment_id"]).unwrap();
+        assert_eq!(body["data"]["payment"]["id"], json!(payment_id));
+        assert_eq!(body["data"]["payment"]["status"], "pending");
+        assert_eq!(body["data"]["payment"]["stripe_payment_intent_id"], json!(intent.id));
+
+        let event = json!({
+            "id": "evt_test_succeeded",
+            "type": "payment_intent.succeeded",
+            "data": { "object": { "id": intent.id, "latest_charge": intent.charge_id } },
+        })
+        .to_string();
+        let signature = stripe_webhook::sign_payload(&event, WEBHOOK_SECRET, Utc::now().timestamp());
+        let webhook = || {
+            Request::post("/api/v1/webhooks/stripe")
+                .header("stripe-signature", signature.as_str())
+                .body(Body::from(event.clone()))
+                .unwrap()
+        };
+        assert_eq!(send(&app, webhook()).await, StatusCode::OK);
+        // Stripe redelivers; the second copy must not count the payment twice
+        assert_eq!(send(&app, webhook()).await, StatusCode::OK);
+
+        let (payment_status, charge_id): (String, Option<String>) =
+            sqlx::query_as("SELECT status::text, stripe_charge_id FROM payments WHERE id = $1")
+                .bind(payment_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(payment_status, "succeeded");
+        assert_eq!(charge_id.as_deref(), Some(intent.charge_id.as_str()));
+
+        let (invoice_status, amount_paid, amount_due): (String, Decimal, Decimal) =
+            sqlx::query_as("SELECT status::text, amount_paid, amount_due FROM invoices WHERE id = $1")
+                .bind(invoice_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(invoice_status, "paid");
+        assert_eq!(amount_paid, Decimal::from(150));
+        assert_eq!(amount_due, Decimal::ZERO);
+
+        // Nothing is left to pay once the invoice is settled
+        let (status, _) = pay(&app, &token, 150, 10).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn pending_portal_payments_hold_their_share_until_they_expire(db: PgPool) {
+        let (invoice_id, token) = sent_invoice(&db).await;
+        let provider = FakeProvider::default();
+        let state = test_support::state(db.clone(), provider.clone()).await;
+        let app = test_support::app(state);
+
+        let (status, first) = pay(&app, &token, 100, 0).await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+
+        // Paying the same amount again resumes the open payment and its intent
+        let (status, again) = pay(&app, &token, 100, 0).await;
+        assert_eq!(status, StatusCode::OK, "{again}");
+        assert_eq!(again["data"]["payment"]["id"], first["data"]["payment"]["id"]);
+        assert_eq!(again["data"]["stripe_client_secret"], first["data"]["stripe_client_secret"]);
+        assert_eq!(provider.intents().len(), 1);
+
+        // Only $50 is left while the $100 is awaiting Stripe
+        let (status, _) = pay(&app, &token, 150, 0).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = pay(&app, &token, 50, 0).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Abandoned checkouts are given up and free the balance again
+        sqlx::query("UPDATE payments SET created_at = NOW() - INTERVAL '2 hours' WHERE invoice_id = $1")
+            .bind(invoice_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(payment_service::expire_abandoned(&db, 60).await.unwrap(), 2);
+        let (status, body) = pay(&app, &token, 150, 0).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_ne!(body["data"]["payment"]["id"], first["data"]["payment"]["id"]);
+    }
+}
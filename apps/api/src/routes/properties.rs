@@ -1,31 +1,39 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::property::CreatePropertyRequest;
+use crate::services::geofence;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/customers/{customer_id}/properties", get(list_customer_properties).post(create_property))
-        .route("/properties/{id}", get(get_property).patch(update_property).delete(delete_property))
+        .route(
+            "/customers/:customer_id/properties",
+            get(list_customer_properties).route_layer(require(Permission::CustomersRead)),
+        )
+        .route(
+            "/customers/:customer_id/properties",
+            post(create_property).route_layer(require(Permission::CustomersWrite)),
+        )
+        .route("/properties/:id", get(get_property).route_layer(require(Permission::CustomersRead)))
+        .route("/properties/:id", patch(update_property).route_layer(require(Permission::CustomersWrite)))
+        .route("/properties/:id", delete(delete_property).route_layer(require(Permission::CustomersDelete)))
 }
 
 async fn create_property(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(customer_id): Path<Uuid>,
     Json(req): Json<CreatePropertyRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let property_type = req.property_type.as_deref().unwrap_or("residential");
 
     let property = sqlx::query_as::<_, crate::models::property::Property>(
@@ -59,11 +67,9 @@ async fn create_property(
 
 async fn list_customer_properties(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(customer_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let properties = sqlx::query_as::<_, crate::models::property::Property>(
         "SELECT * FROM properties WHERE customer_id = $1 AND team_id = $2 ORDER BY is_primary DESC, created_at",
     )
@@ -81,11 +87,9 @@ async fn list_customer_properties(
 
 async fn get_property(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let property = sqlx::query_as::<_, crate::models::property::Property>(
         "SELECT * FROM properties WHERE id = $1 AND team_id = $2",
     )
@@ -116,15 +120,25 @@ struct UpdatePropertyRequest {
     access_notes: Option<String>,
     pet_info: Option<String>,
     is_primary: Option<bool>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    geofence_radius_meters: Option<i32>,
 }
 
 async fn update_property(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdatePropertyRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    if req.latitude.is_some_and(|lat| !(-90.0..=90.0).contains(&lat))
+        || req.longitude.is_some_and(|lng| !(-180.0..=180.0).contains(&lng))
+    {
+        return Err(ApiError::Validation("Coordinates are out of range".into()));
+    }
+    if let Some(radius) = req.geofence_radius_meters {
+        geofence::validate_radius(radius)?;
+    }
 
     let property = sqlx::query_as::<_, crate::models::property::Property>(
         r#"
@@ -141,6 +155,9 @@ async fn update_property(
             access_notes = COALESCE($12, access_notes),
             pet_info = COALESCE($13, pet_info),
             is_primary = COALESCE($14, is_primary),
+            latitude = COALESCE($15, latitude),
+            longitude = COALESCE($16, longitude),
+            geofence_radius_meters = COALESCE($17, geofence_radius_meters),
             updated_at = now()
         WHERE id = $1 AND team_id = $2
         RETURNING *
@@ -160,6 +177,9 @@ async fn update_property(
     .bind(&req.access_notes)
     .bind(&req.pet_info)
     .bind(req.is_primary)
+    .bind(req.latitude)
+    .bind(req.longitude)
+    .bind(req.geofence_radius_meters)
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Property".into()))?;
@@ -173,11 +193,9 @@ async fn update_property(
 
 async fn delete_property(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM properties WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
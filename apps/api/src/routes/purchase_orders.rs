@@ -1,33 +1,33 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::document::PurchaseOrder;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/purchase-orders", get(list_orders).post(create_order))
+        .route("/purchase-orders", get(list_orders).route_layer(require(Permission::InventoryRead)))
+        .route("/purchase-orders", post(create_order).route_layer(require(Permission::PurchasingManage)))
+        .route("/purchase-orders/:id", get(get_order).route_layer(require(Permission::InventoryRead)))
         .route(
-            "/purchase-orders/{id}",
-            get(get_order).patch(update_order).delete(delete_order),
+            "/purchase-orders/:id",
+            patch(update_order).delete(delete_order).route_layer(require(Permission::PurchasingManage)),
         )
-        .route("/purchase-orders/{id}/receive", axum::routing::post(receive_order))
+        .route("/purchase-orders/:id/receive", post(receive_order).route_layer(require(Permission::PurchasingManage)))
 }
 
 async fn list_orders(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let orders = sqlx::query_as::<_, PurchaseOrder>(
         "SELECT * FROM purchase_orders WHERE team_id = $1 ORDER BY created_at DESC",
     )
@@ -40,11 +40,9 @@ async fn list_orders(
 
 async fn create_order(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreatePurchaseOrderRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let order = sqlx::query_as::<_, PurchaseOrder>(
         r#"INSERT INTO purchase_orders (team_id, job_id, vendor, po_number, status, subtotal, tax, total, notes, expected_date, created_by)
            VALUES ($1, $2, $3, $4, 'draft'::po_status, $5, $6, $7, $8, $9, $10)
@@ -68,11 +66,9 @@ async fn create_order(
 
 async fn get_order(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let order = sqlx::query_as::<_, PurchaseOrder>(
         "SELECT * FROM purchase_orders WHERE id = $1 AND team_id = $2",
     )
@@ -87,12 +83,10 @@ async fn get_order(
 
 async fn update_order(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<serde_json::Value>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let order = sqlx::query_as::<_, PurchaseOrder>(
         r#"UPDATE purchase_orders
            SET vendor = COALESCE($3, vendor),
@@ -115,11 +109,9 @@ async fn update_order(
 
 async fn delete_order(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM purchase_orders WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -131,11 +123,9 @@ async fn delete_order(
 
 async fn receive_order(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let order = sqlx::query_as::<_, PurchaseOrder>(
         r#"UPDATE purchase_orders
            SET status = 'received'::po_status, received_date = CURRENT_DATE, updated_at = NOW()
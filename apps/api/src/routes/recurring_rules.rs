@@ -1,34 +1,33 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
+use crate::db::repository;
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::recurring_rule::{CreateRecurringRuleRequest, RecurringRule};
+use crate::services::recurring_service;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/recurring-rules", get(list_rules).post(create_rule))
-        .route(
-            "/recurring-rules/{id}",
-            get(get_rule).delete(delete_rule),
-        )
-        .route("/recurring-rules/{id}/toggle", axum::routing::post(toggle_rule))
-        .route("/recurring-rules/{id}/generate", axum::routing::post(generate_next))
+        .route("/recurring-rules", get(list_rules).route_layer(require(Permission::JobsReadAll)))
+        .route("/recurring-rules", post(create_rule).route_layer(require(Permission::JobsWrite)))
+        .route("/recurring-rules/:id", get(get_rule).route_layer(require(Permission::JobsReadAll)))
+        .route("/recurring-rules/:id", delete(delete_rule).route_layer(require(Permission::JobsWrite)))
+        .route("/recurring-rules/:id/toggle", post(toggle_rule).route_layer(require(Permission::JobsWrite)))
+        .route("/recurring-rules/:id/generate", post(generate_next).route_layer(require(Permission::JobsWrite)))
 }
 
 async fn list_rules(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rules = sqlx::query_as::<_, RecurringRule>(
         "SELECT * FROM recurring_rules WHERE team_id = $1 ORDER BY next_occurrence ASC NULLS LAST",
     )
@@ -41,14 +40,12 @@ async fn list_rules(
 
 async fn create_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateRecurringRuleRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, RecurringRule>(
         r#"INSERT INTO recurring_rules (team_id, customer_id, property_id, title, description, frequency, interval_value, day_of_week, day_of_month, start_date, end_date, estimated_duration_minutes, assigned_to, job_type, priority, auto_schedule, advance_days, next_occurrence)
-           VALUES ($1, $2, $3, $4, $5, $6::recurring_frequency, $7, $8, $9, $10, $11, $12, $13, $14, $15::job_priority, $16, $17, $10)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $10)
            RETURNING *"#,
     )
     .bind(team_id)
@@ -76,11 +73,9 @@ async fn create_rule(
 
 async fn get_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, RecurringRule>(
         "SELECT * FROM recurring_rules WHERE id = $1 AND team_id = $2",
     )
@@ -95,11 +90,9 @@ async fn get_rule(
 
 async fn delete_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM recurring_rules WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -111,11 +104,9 @@ async fn delete_rule(
 
 async fn toggle_rule(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let rule = sqlx::query_as::<_, RecurringRule>(
         r#"UPDATE recurring_rules SET is_active = NOT is_active, updated_at = NOW()
            WHERE id = $1 AND team_id = $2 RETURNING *"#,
@@ -130,40 +121,14 @@ async fn toggle_rule(
 
 async fn generate_next(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    let rule = sqlx::query_as::<_, RecurringRule>(
-        "SELECT * FROM recurring_rules WHERE id = $1 AND team_id = $2 AND is_active = true",
-    )
-    .bind(id)
-    .bind(team_id)
-    .fetch_optional(&state.db)
-    .await?
-    .ok_or_else(|| ApiError::NotFound("Recurring rule".into()))?;
-
-    // Create a job from the recurring rule
-    let job = sqlx::query_as::<_, crate::models::job::Job>(
-        r#"INSERT INTO jobs (team_id, customer_id, property_id, title, description, status, priority, job_type, estimated_duration_minutes, assigned_to, scheduled_start)
-           VALUES ($1, $2, $3, $4, $5, 'scheduled'::job_status, $6::job_priority, $7, $8, $9, $10::date)
-           RETURNING *"#,
-    )
-    .bind(team_id)
-    .bind(rule.customer_id)
-    .bind(rule.property_id)
-    .bind(&rule.title)
-    .bind(&rule.description)
-    .bind(&rule.priority)
-    .bind(&rule.job_type)
-    .bind(rule.estimated_duration_minutes)
-    .bind(rule.assigned_to)
-    .bind(rule.next_occurrence)
-    .fetch_one(&state.db)
-    .await?;
-
-    tracing::info!(rule_id = %id, job_id = %job.id, "Generated job from recurring rule");
+    let generated = recurring_service::generate_next_occurrence(&state.db, id, Some(team_id)).await?;
+    let job_id = generated
+        .job_id
+        .ok_or_else(|| ApiError::Conflict("Recurring rule is inactive or has no remaining occurrences".into()))?;
+    let job = repository::get_job(&state.db, team_id, job_id).await?;
 
     Ok(Json(json!({ "data": job, "meta": null, "errors": null })))
 }
@@ -1,31 +1,31 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::review::{CreateReviewRequest, Review};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/reviews", get(list_reviews).post(create_review))
-        .route("/reviews/{id}", get(get_review).patch(update_review))
-        .route("/reviews/{id}/respond", axum::routing::post(respond_to_review))
+        .route("/reviews", get(list_reviews).route_layer(require(Permission::MarketingRead)))
+        .route("/reviews", post(create_review).route_layer(require(Permission::MarketingWrite)))
+        .route("/reviews/:id", get(get_review).route_layer(require(Permission::MarketingRead)))
+        .route("/reviews/:id", patch(update_review).route_layer(require(Permission::MarketingWrite)))
+        .route("/reviews/:id/respond", post(respond_to_review).route_layer(require(Permission::MarketingWrite)))
 }
 
 async fn list_reviews(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let reviews = sqlx::query_as::<_, Review>(
         "SELECT * FROM reviews WHERE team_id = $1 ORDER BY created_at DESC",
     )
@@ -51,11 +51,9 @@ async fn list_reviews(
 
 async fn create_review(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateReviewRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let review = sqlx::query_as::<_, Review>(
         r#"
         INSERT INTO reviews (team_id, customer_id, job_id, platform, rating, content, reviewer_name)
@@ -84,12 +82,14 @@ async fn create_review(
 
 async fn get_review(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let review = sqlx::query_as::<_, Review>(
-        "SELECT * FROM reviews WHERE id = $1",
+        "SELECT * FROM reviews WHERE id = $1 AND team_id = $2",
     )
     .bind(id)
+    .bind(team_id)
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Review".into()))?;
@@ -109,6 +109,7 @@ struct UpdateReviewRequest {
 
 async fn update_review(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateReviewRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
@@ -118,13 +119,14 @@ async fn update_review(
             rating = COALESCE($2, rating),
             content = COALESCE($3, content),
             updated_at = now()
-        WHERE id = $1
+        WHERE id = $1 AND team_id = $4
         RETURNING *
         "#,
     )
     .bind(id)
     .bind(req.rating)
     .bind(&req.content)
+    .bind(team_id)
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Review".into()))?;
@@ -143,6 +145,7 @@ struct RespondRequest {
 
 async fn respond_to_review(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<RespondRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
@@ -152,12 +155,13 @@ async fn respond_to_review(
             response = $2,
             responded_at = now(),
             updated_at = now()
-        WHERE id = $1
+        WHERE id = $1 AND team_id = $3
         RETURNING *
         "#,
     )
     .bind(id)
     .bind(&req.response)
+    .bind(team_id)
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Review".into()))?;
@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, put};
+use axum::{Json, Router};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::permissions::{self, require, Permission, OVERRIDABLE_ROLES};
+use crate::middleware::team::TeamContext;
+use crate::models::role_permission::{RolePermissionOverride, UpdateRolePermissionsRequest};
+use crate::AppState;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/team/permissions", get(list_role_permissions).route_layer(require(Permission::TeamPermissions)))
+        .route(
+            "/team/permissions/:role",
+            put(update_role_permissions).route_layer(require(Permission::TeamPermissions)),
+        )
+        .route(
+            "/team/permissions/:role",
+            delete(reset_role_permissions).route_layer(require(Permission::TeamPermissions)),
+        )
+}
+
+fn permission_names(set: &HashSet<Permission>) -> Vec<&'static str> {
+    Permission::ALL.iter().filter(|p| set.contains(p)).map(|p| p.as_str()).collect()
+}
+
+async fn role_overrides(state: &AppState, team_id: Uuid, role: &str) -> ApiResult<Vec<RolePermissionOverride>> {
+    let overrides = sqlx::query_as::<_, RolePermissionOverride>(
+        "SELECT permission, granted FROM team_role_permissions WHERE team_id = $1 AND role = $2 ORDER BY permission",
+    )
+    .bind(team_id)
+    .bind(role)
+    .fetch_all(&state.db)
+    .await?;
+    Ok(overrides)
+}
+
+async fn role_summary(state: &AppState, team_id: Uuid, role: &str) -> ApiResult<serde_json::Value> {
+    let overrides = role_overrides(state, team_id, role).await?;
+    let (granted, revoked): (Vec<_>, Vec<_>) = overrides.iter().partition(|o| o.granted);
+    let granted: Vec<String> = granted.into_iter().map(|o| o.permission.clone()).collect();
+    let revoked: Vec<String> = revoked.into_iter().map(|o| o.permission.clone()).collect();
+    let effective = permissions::resolve(role, &granted, &revoked);
+
+    Ok(json!({
+        "role": role,
+        "permissions": permission_names(&effective),
+        "overrides": overrides,
+    }))
+}
+
+fn validate_role(role: &str) -> ApiResult<()> {
+    if OVERRIDABLE_ROLES.contains(&role) {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(format!(
+            "Role must be one of: {}",
+            OVERRIDABLE_ROLES.join(", ")
+        )))
+    }
+}
+
+/// Effective permissions for every role on the team, with the overrides that produced them
+async fn list_role_permissions(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+) -> ApiResult<Json<serde_json::Value>> {
+    let mut roles = vec![json!({
+        "role": "owner",
+        "permissions": permission_names(&permissions::role_defaults("owner")),
+        "overrides": [],
+    })];
+    for role in OVERRIDABLE_ROLES {
+        roles.push(role_summary(&state, team_id, role).await?);
+    }
+
+    let available: Vec<&str> = Permission::ALL.iter().map(|p| p.as_str()).collect();
+
+    Ok(Json(json!({
+        "data": roles,
+        "meta": { "available_permissions": available },
+        "errors": null,
+    })))
+}
+
+/// Replace a role's overrides. Entries that match the role's default are dropped so
+/// only real differences are stored.
+async fn update_role_permissions(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(role): Path<String>,
+    Json(req): Json<UpdateRolePermissionsRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    validate_role(&role)?;
+
+    let defaults = permissions::role_defaults(&role);
+    let mut changes = Vec::new();
+    for entry in &req.overrides {
+        let permission = Permission::parse(&entry.permission)
+            .ok_or_else(|| ApiError::Validation(format!("Unknown permission '{}'", entry.permission)))?;
+        if permission == Permission::TeamPermissions && entry.granted {
+            return Err(ApiError::Validation("team.permissions can only be held by the owner".into()));
+        }
+        if defaults.contains(&permission) != entry.granted {
+            changes.push((permission, entry.granted));
+        }
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    sqlx::query("DELETE FROM team_role_permissions WHERE team_id = $1 AND role = $2")
+        .bind(team_id)
+        .bind(&role)
+        .execute(&mut *tx)
+        .await?;
+
+    for (permission, granted) in changes {
+        sqlx::query(
+            r#"
+            INSERT INTO team_role_permissions (team_id, role, permission, granted, updated_by)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (team_id, role, permission) DO UPDATE SET granted = EXCLUDED.granted, updated_by = EXCLUDED.updated_by
+            "#,
+        )
+        .bind(team_id)
+        .bind(&role)
+        .bind(permission.as_str())
+        .bind(granted)
+        .bind(auth.id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    tracing::info!(team_id = %team_id, role = %role, user_id = %auth.id, "Role permissions updated");
+
+    let summary = role_summary(&state, team_id, &role).await?;
+
+    Ok(Json(json!({
+        "data": summary,
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Drop every override for a role, restoring its defaults
+async fn reset_role_permissions(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(role): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    validate_role(&role)?;
+
+    sqlx::query("DELETE FROM team_role_permissions WHERE team_id = $1 AND role = $2")
+        .bind(team_id)
+        .bind(&role)
+        .execute(&state.db)
+        .await?;
+
+    let summary = role_summary(&state, team_id, &role).await?;
+
+    Ok(Json(json!({
+        "data": summary,
+        "meta": null,
+        "errors": null,
+    })))
+}
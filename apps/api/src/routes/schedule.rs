@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, patch, post};
+use axum::{Json, Router};
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::services::dispatch;
+use crate::services::route_plan;
+use crate::services::schedule::{self, MoveRequest, Placement, Slot};
+use crate::AppState;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/schedule", get(get_board).route_layer(require(Permission::JobsRead)))
+        .route("/schedule/check", post(check_placement).route_layer(require(Permission::JobsWrite)))
+        .route("/schedule/jobs/:id", patch(move_job).route_layer(require(Permission::JobsWrite)))
+        .route("/jobs/:id/dispatch-suggestions", get(dispatch_suggestions).route_layer(require(Permission::JobsWrite)))
+        .route("/schedule/routes", get(plan_team_routes).post(apply_team_routes).route_layer(require(Permission::JobsWrite)))
+        .route("/schedule/routes/:technician_id", get(plan_route).route_layer(require(Permission::JobsRead)))
+        .route("/schedule/routes/:technician_id", post(apply_route).route_layer(require(Permission::JobsWrite)))
+}
+
+#[derive(Debug, Deserialize)]
+struct BoardQuery {
+    date: NaiveDate,
+    /// `day` (the default) or `week`, Monday to Sunday around `date`
+    view: Option<String>,
+    technician_id: Option<Uuid>,
+}
+
+/// Per-technician lanes for a day or week, with each job's conflicts. Technicians who only
+/// see their own jobs get only their own lane.
+async fn get_board(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Query(query): Query<BoardQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let (from, to) = match query.view.as_deref().unwrap_or("day") {
+        "day" => (query.date, query.date),
+        "week" => {
+            let monday = query.date - Duration::days(query.date.weekday().num_days_from_monday().into());
+            (monday, monday + Duration::days(6))
+        }
+        other => return Err(ApiError::Validation(format!("Unknown view '{}'; expected day or week", other))),
+    };
+    let only = auth.job_scope().or(query.technician_id);
+
+    let board = schedule::board(&state, team_id, from, to, only).await?;
+
+    Ok(Json(json!({ "data": board, "meta": null, "errors": null })))
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRequest {
+    /// An existing job being moved; its duration and property fill in what's omitted
+    job_id: Option<Uuid>,
+    assigned_to: Uuid,
+    scheduled_date: NaiveDate,
+    scheduled_start_time: Option<NaiveTime>,
+    scheduled_end_time: Option<NaiveTime>,
+    estimated_duration_minutes: Option<i32>,
+    property_id: Option<Uuid>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct JobDefaults {
+    property_id: Option<Uuid>,
+    arrival_window_start: Option<NaiveTime>,
+    estimated_duration_minutes: Option<i32>,
+}
+
+/// Conflicts a placement would have, for highlighting a drop target before the drop
+async fn check_placement(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Json(req): Json<CheckRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let mut conn = state.db.acquire().await?;
+
+    let defaults = match req.job_id {
+        Some(job_id) => Some(
+            sqlx::query_as::<_, JobDefaults>(
+                r#"
+                SELECT property_id, arrival_window_start, estimated_duration_minutes
+                FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL
+                "#,
+            )
+            .bind(job_id)
+            .bind(team_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("Job".into()))?,
+        ),
+        None => None,
+    };
+
+    let property_id = req.property_id.or(defaults.as_ref().and_then(|job| job.property_id));
+    let location = match property_id {
+        Some(property_id) => schedule::property_location(&mut conn, team_id, property_id).await?,
+        None => None,
+    };
+    let placement = Placement {
+        job_id: req.job_id,
+        date: req.scheduled_date,
+        slot: Slot::of(
+            req.scheduled_start_time,
+            req.scheduled_end_time,
+            defaults.as_ref().and_then(|job| job.arrival_window_start),
+            req.estimated_duration_minutes.or(defaults.as_ref().and_then(|job| job.estimated_duration_minutes)),
+        ),
+        location,
+    };
+
+    let conflicts = schedule::conflicts_for(&state, &mut conn, team_id, req.assigned_to, &placement).await?;
+
+    Ok(Json(json!({
+        "data": { "slot": placement.slot, "conflicts": conflicts },
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Drag-and-drop reschedule and reassign. Conflicts reject the move unless `force` is set.
+async fn move_job(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+    Json(req): Json<MoveRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let moved = schedule::move_job(&state, team_id, id, &req).await?;
+
+    Ok(Json(json!({
+        "data": moved.job,
+        "meta": { "conflicts": moved.conflicts },
+        "errors": null,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct SuggestionQuery {
+    /// Rank for this date instead of the job's scheduled date
+    date: Option<NaiveDate>,
+    /// Rank for this start instead of the job's scheduled time
+    start_time: Option<NaiveTime>,
+}
+
+/// Technicians ranked for a job, each score broken down into its factors
+async fn dispatch_suggestions(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+    Query(query): Query<SuggestionQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let suggestions = dispatch::suggest(&state, team_id, id, query.date, query.start_time).await?;
+
+    Ok(Json(json!({
+        "data": suggestions,
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Query for planning, body for applying
+#[derive(Debug, Deserialize)]
+struct RouteRequest {
+    date: NaiveDate,
+    /// Let jobs move between technicians and hand out unassigned ones; team-wide only
+    #[serde(default)]
+    reassign: bool,
+}
+
+/// A technician's jobs for the day in the order that drives least, without changing them.
+/// Technicians who only see their own jobs can only plan their own route.
+async fn plan_route(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(technician_id): Path<Uuid>,
+    Query(query): Query<RouteRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if auth.job_scope().is_some_and(|own| own != technician_id) {
+        return Err(ApiError::NotFound("Technician".into()));
+    }
+
+    let plan = route_plan::plan(&state, team_id, query.date, Some(technician_id), false).await?;
+
+    Ok(Json(json!({ "data": plan, "meta": null, "errors": null })))
+}
+
+/// Plan a technician's route and rewrite their jobs' start times to match
+async fn apply_route(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(technician_id): Path<Uuid>,
+    Json(req): Json<RouteRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let plan = route_plan::plan(&state, team_id, req.date, Some(technician_id), false).await?;
+    let updated = route_plan::apply(&state, team_id, &plan).await?;
+
+    Ok(Json(json!({
+        "data": plan,
+        "meta": { "updated_jobs": updated },
+        "errors": null,
+    })))
+}
+
+/// Every working technician's route for the day, for dispatchers
+async fn plan_team_routes(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Query(query): Query<RouteRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let plan = route_plan::plan(&state, team_id, query.date, None, query.reassign).await?;
+
+    Ok(Json(json!({ "data": plan, "meta": null, "errors": null })))
+}
+
+/// Plan every technician's route and write the start times, and assignees with `reassign`
+async fn apply_team_routes(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Json(req): Json<RouteRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let plan = route_plan::plan(&state, team_id, req.date, None, req.reassign).await?;
+    let updated = route_plan::apply(&state, team_id, &plan).await?;
+
+    Ok(Json(json!({
+        "data": plan,
+        "meta": { "updated_jobs": updated },
+        "errors": null,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn applying_team_routes_hands_out_and_times_the_days_jobs(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let app = test_support::app(state.clone());
+        let team_id = test_support::team(&db, "acme").await;
+        let owner = test_support::member(&db, team_id, "owner@example.com", "owner").await;
+        let alex = test_support::member(&db, team_id, "alex@example.com", "technician").await;
+        let customer_id = test_support::customer(&db, team_id).await;
+        // A Wednesday, inside default working hours
+        let date = NaiveDate::from_ymd_opt(2030, 6, 5).unwrap();
+
+        let mut jobs = Vec::new();
+        for (assigned_to, latitude) in [(Some(alex), 39.78), (None, 39.80)] {
+            let job_id = test_support::job(&db, team_id, customer_id, "scheduled", assigned_to).await;
+            let property_id = test_support::property(&db, team_id, customer_id, latitude, -89.65).await;
+            sqlx::query("UPDATE jobs SET property_id = $2, scheduled_date = $3, estimated_duration_minutes = 60 WHERE id = $1")
+                .bind(job_id)
+                .bind(property_id)
+                .bind(date)
+                .execute(&db)
+                .await
+                .unwrap();
+            jobs.push(job_id);
+        }
+
+        let bearer = test_support::bearer(&state, owner, team_id);
+        let (status, body) = test_support::call(
+            &app,
+            "POST",
+            "/api/v1/schedule/routes",
+            Some(&bearer),
+            Some(json!({ "date": date, "reassign": true })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let updated = body["meta"]["updated_jobs"].as_array().unwrap();
+        assert_eq!(updated.len(), 2);
+        for job in updated {
+            assert_eq!(job["assigned_to"], json!(alex));
+            assert_eq!(job["status"], "scheduled");
+            assert!(job["scheduled_start_time"].is_string());
+        }
+
+        let stored: Vec<(Option<Uuid>, Option<NaiveTime>, i32)> =
+            sqlx::query_as("SELECT assigned_to, scheduled_start_time, version FROM jobs WHERE id = ANY($1)")
+                .bind(&jobs)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert!(stored.iter().all(|(assigned_to, start, version)| *assigned_to == Some(alex) && start.is_some() && *version == 2));
+    }
+}
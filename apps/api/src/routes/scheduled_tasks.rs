@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::routing::{get, patch, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::scheduled_task::{ScheduledTask, UpdateScheduledTaskRequest};
+use crate::services::scheduler;
+use crate::AppState;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/scheduled-tasks", get(list_tasks).route_layer(require(Permission::SettingsManage)))
+        .route("/scheduled-tasks/:id", patch(update_task).route_layer(require(Permission::SettingsManage)))
+        .route("/scheduled-tasks/:id/run", post(run_task_now).route_layer(require(Permission::SettingsManage)))
+}
+
+async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+) -> ApiResult<Json<serde_json::Value>> {
+    let tasks = sqlx::query_as::<_, ScheduledTask>(
+        "SELECT * FROM scheduled_tasks WHERE team_id = $1 ORDER BY name",
+    )
+    .bind(team_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({ "data": tasks, "meta": null, "errors": null })))
+}
+
+/// Change a task's cron expression or pause/resume it. The next run is recomputed in
+/// the team's timezone.
+async fn update_task(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateScheduledTaskRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let task = sqlx::query_as::<_, ScheduledTask>(
+        "SELECT * FROM scheduled_tasks WHERE id = $1 AND team_id = $2",
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Scheduled task".into()))?;
+
+    let cron = req.cron.unwrap_or(task.cron);
+    let schedule = scheduler::parse_cron(&cron).map_err(ApiError::Validation)?;
+    let tz = scheduler::team_timezone(&state.db, team_id).await?;
+    let next_run_at = scheduler::next_run_after(&schedule, tz, Utc::now())
+        .ok_or_else(|| ApiError::Validation("Cron expression never fires again".into()))?;
+
+    let task = sqlx::query_as::<_, ScheduledTask>(
+        r#"UPDATE scheduled_tasks SET cron = $3, is_active = $4, next_run_at = $5
+           WHERE id = $1 AND team_id = $2 RETURNING *"#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .bind(cron.trim())
+    .bind(req.is_active.unwrap_or(task.is_active))
+    .bind(next_run_at)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(Json(json!({ "data": task, "meta": null, "errors": null })))
+}
+
+/// Make the task due immediately; the scheduler picks it up on its next tick
+async fn run_task_now(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let task = sqlx::query_as::<_, ScheduledTask>(
+        r#"UPDATE scheduled_tasks SET next_run_at = now()
+           WHERE id = $1 AND team_id = $2 AND is_active = true RETURNING *"#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Active scheduled task".into()))?;
+
+    Ok(Json(json!({ "data": task, "meta": null, "errors": null })))
+}
@@ -3,17 +3,17 @@ use std::sync::Arc;
 use axum::extract::{Query, State};
 use axum::routing::get;
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 
 use crate::errors::ApiResult;
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/search", get(global_search))
+        .route("/search", get(global_search).route_layer(require(Permission::CustomersRead)))
 }
 
 #[derive(Deserialize)]
@@ -26,10 +26,9 @@ struct SearchParams {
 
 async fn global_search(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Query(params): Query<SearchParams>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let limit = params.limit.unwrap_or(10).min(50);
     let query = format!("%{}%", params.q.to_lowercase());
 
@@ -40,17 +39,20 @@ async fn global_search(
     let mut results = serde_json::Map::new();
 
     if search_type == "all" || search_type == "jobs" {
-        let jobs = sqlx::query_as::<_, crate::models::job::Job>(
+        let jobs = sqlx::query_as::<_, crate::models::job::Job>(&format!(
             r#"
-            SELECT * FROM jobs
+            SELECT {} FROM jobs
             WHERE team_id = $1 AND deleted_at IS NULL
               AND (LOWER(title) LIKE $2 OR LOWER(description) LIKE $2 OR po_number ILIKE $2)
+              AND ($4::uuid IS NULL OR assigned_to = $4)
             ORDER BY created_at DESC LIMIT $3
             "#,
-        )
+            crate::db::repository::JOB_COLUMNS
+        ))
         .bind(team_id)
         .bind(&query)
         .bind(limit)
+        .bind(auth.job_scope())
         .fetch_all(&state.db)
         .await?;
 
@@ -1,32 +1,38 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::service_plan::{CustomerServicePlan, ServicePlan};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/service-plans", get(list_plans).post(create_plan))
-        .route("/service-plans/{id}", get(get_plan).patch(update_plan).delete(delete_plan))
-        .route("/service-plans/{id}/enroll", axum::routing::post(enroll_customer))
-        .route("/customers/{customer_id}/service-plans", get(list_customer_plans))
+        .route("/service-plans", get(list_plans).route_layer(require(Permission::MarketingRead)))
+        .route("/service-plans", post(create_plan).route_layer(require(Permission::MarketingWrite)))
+        .route("/service-plans/:id", get(get_plan).route_layer(require(Permission::MarketingRead)))
+        .route(
+            "/service-plans/:id",
+            patch(update_plan).delete(delete_plan).route_layer(require(Permission::MarketingWrite)),
+        )
+        .route("/service-plans/:id/enroll", post(enroll_customer).route_layer(require(Permission::MarketingWrite)))
+        .route(
+            "/customers/:customer_id/service-plans",
+            get(list_customer_plans).route_layer(require(Permission::MarketingRead)),
+        )
 }
 
 async fn list_plans(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let plans = sqlx::query_as::<_, ServicePlan>(
         "SELECT * FROM service_plans WHERE team_id = $1 AND is_active = true ORDER BY name",
     )
@@ -56,10 +62,9 @@ struct CreatePlanRequest {
 
 async fn create_plan(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreatePlanRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let visits = req.visits_per_year.unwrap_or(1);
     let priority = req.priority_scheduling.unwrap_or(true);
 
@@ -95,11 +100,9 @@ async fn create_plan(
 
 async fn get_plan(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let plan = sqlx::query_as::<_, ServicePlan>(
         "SELECT * FROM service_plans WHERE id = $1 AND team_id = $2",
     )
@@ -138,12 +141,10 @@ struct UpdatePlanRequest {
 
 async fn update_plan(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdatePlanRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let plan = sqlx::query_as::<_, ServicePlan>(
         r#"
         UPDATE service_plans SET
@@ -179,11 +180,9 @@ async fn update_plan(
 
 async fn delete_plan(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query(
         "UPDATE service_plans SET is_active = false, updated_at = now() WHERE id = $1 AND team_id = $2",
     )
@@ -208,11 +207,10 @@ struct EnrollCustomerRequest {
 
 async fn enroll_customer(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(plan_id): Path<Uuid>,
     Json(req): Json<EnrollCustomerRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let frequency = req.billing_frequency.as_deref().unwrap_or("monthly");
 
     let enrollment = sqlx::query_as::<_, CustomerServicePlan>(
@@ -241,12 +239,14 @@ async fn enroll_customer(
 
 async fn list_customer_plans(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(customer_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     let enrollments = sqlx::query_as::<_, CustomerServicePlan>(
-        "SELECT * FROM customer_service_plans WHERE customer_id = $1 ORDER BY start_date DESC",
+        "SELECT * FROM customer_service_plans WHERE customer_id = $1 AND team_id = $2 ORDER BY start_date DESC",
     )
     .bind(customer_id)
+    .bind(team_id)
     .fetch_all(&state.db)
     .await?;
 
@@ -4,7 +4,10 @@ use axum::extract::State;
 use axum::http::{HeaderMap, StatusCode};
 use axum::routing::post;
 use axum::Router;
+use chrono::Utc;
 
+use crate::errors::ApiError;
+use crate::services::{realtime, stripe_webhook};
 use crate::AppState;
 
 /// Stripe webhook handler — receives events from Stripe
@@ -12,84 +15,75 @@ pub fn router() -> Router<Arc<AppState>> {
     Router::new().route("/webhooks/stripe", post(handle_stripe_webhook))
 }
 
+/// Verify, de-duplicate and apply one event. Anything other than a 2xx makes Stripe retry,
+/// so signature and payload problems get a 400 and only processing failures a 500.
 async fn handle_stripe_webhook(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     body: String,
 ) -> Result<StatusCode, StatusCode> {
-    let _signature = headers
+    let signature = headers
         .get("stripe-signature")
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
-    // TODO: Verify webhook signature with Stripe secret
-    // let event = stripe::Webhook::construct_event(&body, signature, &webhook_secret)?;
+    let settings = &state.config.stripe;
+    if let Err(e) = stripe_webhook::verify_signature(
+        &body,
+        signature,
+        &settings.webhook_secret,
+        settings.webhook_tolerance_secs,
+        Utc::now(),
+    ) {
+        tracing::warn!(error = %e, "Rejected Stripe webhook");
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
     let event: serde_json::Value = serde_json::from_str(&body)
         .map_err(|_| StatusCode::BAD_REQUEST)?;
 
+    let event_id = event.get("id").and_then(|v| v.as_str()).ok_or(StatusCode::BAD_REQUEST)?;
     let event_type = event
         .get("type")
         .and_then(|v| v.as_str())
         .unwrap_or("unknown");
+    let object = event
+        .get("data")
+        .and_then(|d| d.get("object"))
+        .ok_or(StatusCode::BAD_REQUEST)?;
 
-    tracing::info!(event_type = %event_type, "Received Stripe webhook");
-
-    match event_type {
-        "payment_intent.succeeded" => {
-            if let Some(data) = event.get("data").and_then(|d| d.get("object")) {
-                let payment_intent_id = data.get("id").and_then(|v| v.as_str()).unwrap_or("");
-                let amount = data.get("amount").and_then(|v| v.as_i64()).unwrap_or(0);
-
-                tracing::info!(
-                    payment_intent = %payment_intent_id,
-                    amount_cents = %amount,
-                    "Payment succeeded"
-                );
+    tracing::info!(event_id = %event_id, event_type = %event_type, "Received Stripe webhook");
 
-                // Update payment status in DB
-                let _result = sqlx::query(
-                    r#"UPDATE payments SET status = 'succeeded'::payment_status, updated_at = NOW()
-                       WHERE stripe_payment_intent_id = $1"#,
-                )
-                .bind(payment_intent_id)
-                .execute(&state.db)
-                .await;
-            }
+    match process_event(&state, event_id, event_type, object).await {
+        Ok(()) => Ok(StatusCode::OK),
+        Err(ApiError::BadRequest(message)) => {
+            tracing::warn!(event_id = %event_id, error = %message, "Malformed Stripe event");
+            Err(StatusCode::BAD_REQUEST)
         }
-        "payment_intent.payment_failed" => {
-            if let Some(data) = event.get("data").and_then(|d| d.get("object")) {
-                let payment_intent_id = data.get("id").and_then(|v| v.as_str()).unwrap_or("");
+        Err(e) => {
+            tracing::error!(event_id = %event_id, error = %e, "Failed to apply Stripe event");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
 
-                tracing::warn!(payment_intent = %payment_intent_id, "Payment failed");
+async fn process_event(
+    state: &AppState,
+    event_id: &str,
+    event_type: &str,
+    object: &serde_json::Value,
+) -> Result<(), ApiError> {
+    let mut tx = state.db.begin().await?;
 
-                let _result = sqlx::query(
-                    r#"UPDATE payments SET status = 'failed'::payment_status, updated_at = NOW()
-                       WHERE stripe_payment_intent_id = $1"#,
-                )
-                .bind(payment_intent_id)
-                .execute(&state.db)
-                .await;
-            }
-        }
-        "charge.refunded" => {
-            if let Some(data) = event.get("data").and_then(|d| d.get("object")) {
-                let charge_id = data.get("id").and_then(|v| v.as_str()).unwrap_or("");
-                tracing::info!(charge = %charge_id, "Charge refunded");
-            }
-        }
-        "invoice.payment_succeeded" => {
-            // Subscription payment succeeded
-            tracing::info!("Subscription payment succeeded");
-        }
-        "customer.subscription.deleted" => {
-            // Subscription cancelled
-            tracing::info!("Subscription cancelled");
-        }
-        _ => {
-            tracing::debug!(event_type = %event_type, "Unhandled Stripe event");
-        }
+    if !stripe_webhook::claim_event(&mut tx, event_id, event_type).await? {
+        tracing::debug!(event_id = %event_id, "Stripe event already processed");
+        return Ok(());
     }
 
-    Ok(StatusCode::OK)
+    let mut realtime = realtime::Pending::default();
+    stripe_webhook::apply_event(&mut tx, state, &mut realtime, event_type, object).await?;
+    tx.commit().await?;
+
+    realtime.publish(state).await;
+    Ok(())
 }
@@ -1,29 +1,29 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::tag::Tag;
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/tags", get(list_tags).post(create_tag))
-        .route("/tags/{id}", get(get_tag).delete(delete_tag))
+        .route("/tags", get(list_tags).route_layer(require(Permission::CustomersRead)))
+        .route("/tags", post(create_tag).route_layer(require(Permission::SettingsManage)))
+        .route("/tags/:id", get(get_tag).route_layer(require(Permission::CustomersRead)))
+        .route("/tags/:id", delete(delete_tag).route_layer(require(Permission::SettingsManage)))
 }
 
 async fn list_tags(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let tags = sqlx::query_as::<_, Tag>(
         "SELECT * FROM tags WHERE team_id = $1 ORDER BY name ASC",
     )
@@ -36,11 +36,9 @@ async fn list_tags(
 
 async fn create_tag(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateTagRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let tag = sqlx::query_as::<_, Tag>(
         "INSERT INTO tags (team_id, name, color) VALUES ($1, $2, $3) RETURNING *",
     )
@@ -55,11 +53,9 @@ async fn create_tag(
 
 async fn get_tag(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let tag = sqlx::query_as::<_, Tag>(
         "SELECT * FROM tags WHERE id = $1 AND team_id = $2",
     )
@@ -74,11 +70,9 @@ async fn get_tag(
 
 async fn delete_tag(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM tags WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -1,34 +1,59 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::{get, patch, post};
-use axum::{Json, Router};
-use axum::Extension;
+use axum::middleware::from_fn;
+use axum::routing::{delete, get, patch, post};
+use axum::{Extension, Json, Router};
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::auth::{deny_api_keys, AuthUser};
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::team::{
+    CreateInvitationRequest, CreateTeamRequest, Team, TeamInvitation, TeamMember, TeamMembership,
+};
+use crate::services::{geofence, gps_store, mfa_service, schedule};
+use crate::services::team_service::{self, INVITATION_COLUMNS, TEAM_COLUMNS};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/team", get(get_team).patch(update_team))
-        .route("/team/members", get(list_members).post(invite_member))
-        .route("/team/members/{id}", patch(update_member))
-        .route("/team/members/{id}/deactivate", post(deactivate_member))
+        .route("/team", get(get_team).route_layer(require(Permission::TeamRead)))
+        .route("/team", patch(update_team).route_layer(require(Permission::SettingsManage)))
+        .route("/team/members", get(list_members).route_layer(require(Permission::TeamRead)))
+        .route("/team/members", post(invite_member).route_layer(require(Permission::TeamManage)))
+        .route("/team/members/:id", patch(update_member).route_layer(require(Permission::TeamManage)))
+        .route(
+            "/team/members/:id/deactivate",
+            post(deactivate_member).route_layer(require(Permission::TeamManage)),
+        )
+        .route(
+            "/team/members/:id/mfa",
+            delete(reset_member_mfa)
+                .route_layer(require(Permission::TeamManage))
+                .route_layer(from_fn(deny_api_keys)),
+        )
+        .route("/team/invitations", get(list_invitations).route_layer(require(Permission::TeamManage)))
+        .route(
+            "/team/invitations/:id/resend",
+            post(resend_invitation).route_layer(require(Permission::TeamManage)),
+        )
+        .route(
+            "/team/invitations/:id",
+            delete(revoke_invitation).route_layer(require(Permission::TeamManage)),
+        )
+        .route("/teams", post(create_team).route_layer(from_fn(deny_api_keys)))
+        .route("/me/teams", get(list_my_teams).route_layer(from_fn(deny_api_keys)))
 }
 
 async fn get_team(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    let team = sqlx::query_as::<_, crate::models::team::Team>(
-        "SELECT * FROM teams WHERE id = $1",
-    )
+    let team = sqlx::query_as::<_, Team>(&format!("SELECT {} FROM teams WHERE id = $1", TEAM_COLUMNS))
     .bind(team_id)
     .fetch_optional(&state.db)
     .await?
@@ -53,16 +78,37 @@ struct UpdateTeamRequest {
     zip_code: Option<String>,
     tax_rate: Option<rust_decimal::Decimal>,
     default_hourly_rate: Option<rust_decimal::Decimal>,
+    require_mfa: Option<bool>,
+    geofence_radius_meters: Option<i32>,
+    geofence_auto_transition: Option<bool>,
+    mileage_rate: Option<rust_decimal::Decimal>,
+    gps_retention_days: Option<i32>,
+    /// 0 turns downsampling off
+    gps_downsample_after_days: Option<i32>,
+    gps_downsample_interval_secs: Option<i32>,
+    working_hours_start: Option<chrono::NaiveTime>,
+    working_hours_end: Option<chrono::NaiveTime>,
+    working_days: Option<Vec<i32>>,
+    schedule_travel_buffer_minutes: Option<i32>,
 }
 
 async fn update_team(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<UpdateTeamRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    if let Some(radius) = req.geofence_radius_meters {
+        geofence::validate_radius(radius)?;
+    }
+    gps_store::validate_retention(req.gps_retention_days, req.gps_downsample_after_days, req.gps_downsample_interval_secs)?;
+    schedule::validate_team_hours(
+        req.working_hours_start,
+        req.working_hours_end,
+        req.working_days.as_deref(),
+        req.schedule_travel_buffer_minutes,
+    )?;
 
-    let team = sqlx::query_as::<_, crate::models::team::Team>(
+    let team = sqlx::query_as::<_, Team>(&format!(
         r#"
         UPDATE teams SET
             name = COALESCE($2, name),
@@ -75,11 +121,23 @@ async fn update_team(
             zip_code = COALESCE($9, zip_code),
             tax_rate = COALESCE($10, tax_rate),
             default_hourly_rate = COALESCE($11, default_hourly_rate),
+            require_mfa = COALESCE($12, require_mfa),
+            geofence_radius_meters = COALESCE($13, geofence_radius_meters),
+            geofence_auto_transition = COALESCE($14, geofence_auto_transition),
+            mileage_rate = COALESCE($15, mileage_rate),
+            gps_retention_days = COALESCE($16, gps_retention_days),
+            gps_downsample_after_days = NULLIF(COALESCE($17, gps_downsample_after_days), 0),
+            gps_downsample_interval_secs = COALESCE($18, gps_downsample_interval_secs),
+            working_hours_start = COALESCE($19, working_hours_start),
+            working_hours_end = COALESCE($20, working_hours_end),
+            working_days = COALESCE($21, working_days),
+            schedule_travel_buffer_minutes = COALESCE($22, schedule_travel_buffer_minutes),
             updated_at = now()
         WHERE id = $1
-        RETURNING *
+        RETURNING {}
         "#,
-    )
+        TEAM_COLUMNS
+    ))
     .bind(team_id)
     .bind(&req.name)
     .bind(&req.phone)
@@ -91,6 +149,17 @@ async fn update_team(
     .bind(&req.zip_code)
     .bind(req.tax_rate)
     .bind(req.default_hourly_rate)
+    .bind(req.require_mfa)
+    .bind(req.geofence_radius_meters)
+    .bind(req.geofence_auto_transition)
+    .bind(req.mileage_rate)
+    .bind(req.gps_retention_days)
+    .bind(req.gps_downsample_after_days)
+    .bind(req.gps_downsample_interval_secs)
+    .bind(req.working_hours_start)
+    .bind(req.working_hours_end)
+    .bind(&req.working_days)
+    .bind(req.schedule_travel_buffer_minutes)
     .fetch_one(&state.db)
     .await?;
 
@@ -101,15 +170,22 @@ async fn update_team(
     })))
 }
 
+/// Member roster joined from team memberships, so users on several teams show up in each
+const MEMBER_COLUMNS: &str = r#"
+    SELECT u.id AS user_id, u.email, u.phone, u.first_name, u.last_name, u.avatar_url,
+           m.role, m.hourly_rate, m.is_active, m.created_at AS joined_at
+    FROM team_memberships m
+    JOIN users u ON u.id = m.user_id
+"#;
+
 async fn list_members(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    let members = sqlx::query_as::<_, crate::models::user::User>(
-        "SELECT * FROM users WHERE team_id = $1 AND deleted_at IS NULL ORDER BY first_name",
-    )
+    let members = sqlx::query_as::<_, TeamMember>(&format!(
+        "{} WHERE m.team_id = $1 ORDER BY u.first_name",
+        MEMBER_COLUMNS
+    ))
     .bind(team_id)
     .fetch_all(&state.db)
     .await?;
@@ -121,60 +197,106 @@ async fn list_members(
     })))
 }
 
-#[derive(Deserialize)]
-struct InviteMemberRequest {
-    email: String,
-    first_name: String,
-    last_name: String,
-    role: String,
-    hourly_rate: Option<rust_decimal::Decimal>,
+async fn get_member(state: &AppState, team_id: Uuid, user_id: Uuid) -> ApiResult<TeamMember> {
+    sqlx::query_as::<_, TeamMember>(&format!("{} WHERE m.team_id = $1 AND m.user_id = $2", MEMBER_COLUMNS))
+        .bind(team_id)
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Team member".into()))
 }
 
+/// Invite someone by email. They join the team when they accept the emailed link;
+/// the roster only shows them from then on.
 async fn invite_member(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
-    Json(req): Json<InviteMemberRequest>,
+    TeamContext { team_id, auth }: TeamContext,
+    Json(req): Json<CreateInvitationRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    // Check if email already exists
-    let exists = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM users WHERE email = $1",
-    )
-    .bind(&req.email)
-    .fetch_one(&state.db)
-    .await?;
+    ensure_can_assign_role(&auth, None, Some(&req.role))?;
 
-    if exists > 0 {
-        return Err(ApiError::Conflict("A user with this email already exists".into()));
-    }
+    let invitation = team_service::create_invitation(&state, team_id, auth.id, &req).await?;
 
-    // Create user with temporary password (they'll set their own via invite link)
-    let temp_hash = crate::services::auth_service::hash_password("temp-invite-password")?;
+    Ok(Json(json!({
+        "data": invitation,
+        "meta": { "message": "Invitation sent" },
+        "errors": null,
+    })))
+}
 
-    let user = sqlx::query_as::<_, crate::models::user::User>(
+/// Open invitations first, then the most recently sent
+async fn list_invitations(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+) -> ApiResult<Json<serde_json::Value>> {
+    let invitations = sqlx::query_as::<_, TeamInvitation>(&format!(
         r#"
-        INSERT INTO users (team_id, email, password_hash, first_name, last_name, role, hourly_rate)
-        VALUES ($1, $2, $3, $4, $5, $6::user_role, $7)
-        RETURNING *
+        SELECT {} FROM team_invitations
+        WHERE team_id = $1
+        ORDER BY (accepted_at IS NULL AND revoked_at IS NULL) DESC, last_sent_at DESC
         "#,
-    )
+        INVITATION_COLUMNS
+    ))
     .bind(team_id)
-    .bind(&req.email)
-    .bind(&temp_hash)
-    .bind(&req.first_name)
-    .bind(&req.last_name)
-    .bind(&req.role)
-    .bind(req.hourly_rate)
-    .fetch_one(&state.db)
+    .fetch_all(&state.db)
     .await?;
 
-    // TODO: Send invite email with password reset link
-    tracing::info!(user_id = %user.id, email = %req.email, "Team member invited");
+    Ok(Json(json!({
+        "data": invitations,
+        "meta": { "total": invitations.len() },
+        "errors": null,
+    })))
+}
+
+async fn get_open_invitation(state: &AppState, team_id: Uuid, id: Uuid) -> ApiResult<TeamInvitation> {
+    sqlx::query_as::<_, TeamInvitation>(&format!(
+        "SELECT {} FROM team_invitations WHERE id = $1 AND team_id = $2 AND accepted_at IS NULL AND revoked_at IS NULL",
+        INVITATION_COLUMNS
+    ))
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Invitation".into()))
+}
+
+/// Email a fresh link; the previous one stops working
+async fn resend_invitation(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let current = get_open_invitation(&state, team_id, id).await?;
+    ensure_can_assign_role(&auth, None, Some(&current.role))?;
+
+    let invitation = team_service::resend_invitation(&state, team_id, id).await?;
 
     Ok(Json(json!({
-        "data": user,
-        "meta": { "message": "Invitation sent" },
+        "data": invitation,
+        "meta": { "message": "Invitation resent" },
+        "errors": null,
+    })))
+}
+
+async fn revoke_invitation(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let current = get_open_invitation(&state, team_id, id).await?;
+    ensure_can_assign_role(&auth, None, Some(&current.role))?;
+
+    sqlx::query("UPDATE team_invitations SET revoked_at = now() WHERE id = $1 AND team_id = $2")
+        .bind(id)
+        .bind(team_id)
+        .execute(&state.db)
+        .await?;
+
+    tracing::info!(invitation_id = %id, team_id = %team_id, user_id = %auth.id, "Team invitation revoked");
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "message": "Invitation revoked" },
         "errors": null,
     })))
 }
@@ -187,55 +309,201 @@ struct UpdateMemberRequest {
 
 async fn update_member(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateMemberRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    let current = get_member(&state, team_id, id).await?;
+    ensure_can_assign_role(&auth, Some(&current.role), req.role.as_deref())?;
 
-    let user = sqlx::query_as::<_, crate::models::user::User>(
+    sqlx::query(
         r#"
-        UPDATE users SET
-            role = COALESCE($3::user_role, role),
-            hourly_rate = COALESCE($4, hourly_rate),
-            updated_at = now()
-        WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL
-        RETURNING *
+        UPDATE team_memberships SET
+            role = COALESCE($3, role),
+            hourly_rate = COALESCE($4, hourly_rate)
+        WHERE team_id = $1 AND user_id = $2
         "#,
     )
-    .bind(id)
     .bind(team_id)
+    .bind(id)
     .bind(&req.role)
     .bind(req.hourly_rate)
-    .fetch_optional(&state.db)
-    .await?
-    .ok_or_else(|| ApiError::NotFound("Team member".into()))?;
+    .execute(&state.db)
+    .await?;
+
+    let member = get_member(&state, team_id, id).await?;
 
     Ok(Json(json!({
-        "data": user,
+        "data": member,
         "meta": null,
         "errors": null,
     })))
 }
 
+/// Remove a member's access to this team. Their account and other memberships are untouched.
 async fn deactivate_member(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if id == auth.id {
+        return Err(ApiError::BadRequest("You cannot deactivate yourself".into()));
+    }
+    let current = get_member(&state, team_id, id).await?;
+    ensure_can_assign_role(&auth, Some(&current.role), None)?;
+
+    sqlx::query("UPDATE team_memberships SET is_active = false WHERE team_id = $1 AND user_id = $2")
+        .bind(team_id)
+        .bind(id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(Json(json!({
+        "data": null,
+        "meta": { "message": "Team member deactivated" },
+        "errors": null,
+    })))
+}
+
+/// Start a new team with the caller as owner. Open to any signed-in user, including
+/// those without a team yet; it becomes their default team if they had none.
+async fn create_team(
     State(state): State<Arc<AppState>>,
     Extension(auth): Extension<AuthUser>,
+    Json(req): Json<CreateTeamRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let mut tx = state.db.begin().await?;
+    let team = team_service::create_team(&mut tx, auth.id, &req).await?;
+    tx.commit().await?;
+
+    Ok(Json(json!({
+        "data": team,
+        "meta": { "role": "owner" },
+        "errors": null,
+    })))
+}
+
+/// Clear a locked-out member's second factor so they can sign in with their password
+/// and enroll again. Owner only, and recorded in the audit log.
+async fn reset_member_mfa(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    if auth.role != "owner" {
+        return Err(ApiError::Forbidden);
+    }
+    if id == auth.id {
+        return Err(ApiError::BadRequest("Use your recovery codes to reset your own second factor".into()));
+    }
+    get_member(&state, team_id, id).await?;
+
+    let mut tx = state.db.begin().await?;
+    let removed = mfa_service::disable(&mut tx, id).await?;
 
     sqlx::query(
-        "UPDATE users SET is_active = false, updated_at = now() WHERE id = $1 AND team_id = $2",
+        r#"
+        INSERT INTO audit_log (team_id, user_id, action, resource_type, resource_id)
+        VALUES ($1, $2, 'mfa.reset', 'user', $3)
+        "#,
     )
-    .bind(id)
     .bind(team_id)
-    .execute(&state.db)
+    .bind(auth.id)
+    .bind(id)
+    .execute(&mut *tx)
     .await?;
+    tx.commit().await?;
+
+    tracing::info!(team_id = %team_id, user_id = %id, reset_by = %auth.id, removed, "Member second factor reset");
 
     Ok(Json(json!({
         "data": null,
-        "meta": { "message": "Team member deactivated" },
+        "meta": { "message": "Two-factor authentication reset; the member can enroll again after signing in" },
+        "errors": null,
+    })))
+}
+
+/// Teams the current user belongs to, for picking an `X-Team-Id`
+async fn list_my_teams(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthUser>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let teams = sqlx::query_as::<_, TeamMembership>(
+        r#"
+        SELECT t.id AS team_id, t.name AS team_name, m.role, (u.team_id = t.id) IS TRUE AS is_default
+        FROM team_memberships m
+        JOIN teams t ON t.id = m.team_id
+        JOIN users u ON u.id = m.user_id
+        WHERE m.user_id = $1 AND m.is_active = true
+        ORDER BY t.name
+        "#,
+    )
+    .bind(auth.id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(json!({
+        "data": teams,
+        "meta": { "active_team_id": auth.team_id },
         "errors": null,
     })))
 }
+
+/// Roles must be real ones. Only an owner may hand out the owner role or change an
+/// existing owner, so admins can't promote themselves or lock the owner out.
+fn ensure_can_assign_role(auth: &AuthUser, current: Option<&str>, requested: Option<&str>) -> ApiResult<()> {
+    if let Some(role) = requested.filter(|role| !permissions::ROLES.contains(role)) {
+        return Err(ApiError::Validation(format!(
+            "Unknown role '{}'; must be one of: {}",
+            role,
+            permissions::ROLES.join(", ")
+        )));
+    }
+    let touches_owner = current == Some("owner") || requested == Some("owner");
+    if touches_owner && auth.role != "owner" {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn owner_signs_up_and_invites_with_known_roles_only(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let app = test_support::app(state);
+
+        // Verification codes are rate-limited per address in Redis, which outlives the database
+        let email = format!("owner-{}@example.com", Uuid::new_v4());
+        let signup = json!({
+            "email": email,
+            "password": "correct horse battery staple",
+            "first_name": "Ana",
+            "last_name": "Silva",
+            "company_name": "Silva Electric",
+        });
+        let (status, body) = test_support::call(&app, "POST", "/api/v1/auth/register", None, Some(signup)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["user"]["role"], "owner");
+        assert!(body["data"]["user"]["team_id"].is_string());
+        let bearer = format!("Bearer {}", body["data"]["token"].as_str().unwrap());
+
+        let invite = |role: &str| {
+            json!({ "email": "tech@example.com", "first_name": "Lee", "last_name": "Park", "role": role })
+        };
+        let (status, body) = test_support::call(&app, "POST", "/api/v1/team/members", Some(&bearer), Some(invite("superuser"))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+
+        let (status, body) = test_support::call(&app, "POST", "/api/v1/team/members", Some(&bearer), Some(invite("technician"))).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["role"], "technician");
+    }
+}
@@ -9,24 +9,26 @@ use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
 use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{self, require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::time_entry::{StartTimerRequest, StopTimerRequest};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/time-entries/start", post(start_timer))
-        .route("/time-entries/{id}/stop", post(stop_timer))
-        .route("/jobs/{job_id}/time-entries", get(list_job_time_entries))
-        .route("/time-entries/active", get(get_active_timer))
+        .route("/time-entries/start", post(start_timer).route_layer(require(Permission::TimeTrack)))
+        .route("/time-entries/:id/stop", post(stop_timer).route_layer(require(Permission::TimeTrack)))
+        .route("/jobs/:job_id/time-entries", get(list_job_time_entries).route_layer(require(Permission::JobsRead)))
+        .route("/time-entries/active", get(get_active_timer).route_layer(require(Permission::TimeTrack)))
 }
 
 async fn start_timer(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, auth }: TeamContext,
     Json(req): Json<StartTimerRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
     let user_id = auth.id;
+    permissions::ensure_job_access(&state.db, &auth, team_id, req.job_id).await?;
 
     // Check for existing active timer
     let active = sqlx::query_scalar::<_, i64>(
@@ -153,8 +155,11 @@ async fn get_active_timer(
 
 async fn list_job_time_entries(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, auth }: TeamContext,
     Path(job_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    permissions::ensure_job_access(&state.db, &auth, team_id, job_id).await?;
+
     let entries = sqlx::query_as::<_, crate::models::time_entry::TimeEntry>(
         "SELECT * FROM time_entries WHERE job_id = $1 ORDER BY started_at DESC",
     )
@@ -1,31 +1,35 @@
 use std::sync::Arc;
 
 use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::routing::{get, patch, post};
 use axum::{Json, Router};
-use axum::Extension;
 use serde::Deserialize;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
 use crate::models::vehicle::{CreateVehicleRequest, Vehicle, VehicleMaintenance};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/vehicles", get(list_vehicles).post(create_vehicle))
-        .route("/vehicles/{id}", get(get_vehicle).patch(update_vehicle).delete(delete_vehicle))
-        .route("/vehicles/{id}/maintenance", get(list_maintenance).post(create_maintenance))
+        .route("/vehicles", get(list_vehicles).route_layer(require(Permission::FleetRead)))
+        .route("/vehicles", post(create_vehicle).route_layer(require(Permission::FleetWrite)))
+        .route("/vehicles/:id", get(get_vehicle).route_layer(require(Permission::FleetRead)))
+        .route(
+            "/vehicles/:id",
+            patch(update_vehicle).delete(delete_vehicle).route_layer(require(Permission::FleetWrite)),
+        )
+        .route("/vehicles/:id/maintenance", get(list_maintenance).route_layer(require(Permission::FleetRead)))
+        .route("/vehicles/:id/maintenance", post(create_maintenance).route_layer(require(Permission::FleetWrite)))
 }
 
 async fn list_vehicles(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let vehicles = sqlx::query_as::<_, Vehicle>(
         "SELECT * FROM vehicles WHERE team_id = $1 ORDER BY make, model",
     )
@@ -42,11 +46,9 @@ async fn list_vehicles(
 
 async fn create_vehicle(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateVehicleRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let vehicle = sqlx::query_as::<_, Vehicle>(
         r#"
         INSERT INTO vehicles (team_id, make, model, year, vin, license_plate, color,
@@ -82,11 +84,9 @@ async fn create_vehicle(
 
 async fn get_vehicle(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let vehicle = sqlx::query_as::<_, Vehicle>(
         "SELECT * FROM vehicles WHERE id = $1 AND team_id = $2",
     )
@@ -132,12 +132,10 @@ struct UpdateVehicleRequest {
 
 async fn update_vehicle(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateVehicleRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let vehicle = sqlx::query_as::<_, Vehicle>(
         r#"
         UPDATE vehicles SET
@@ -187,11 +185,9 @@ async fn update_vehicle(
 
 async fn delete_vehicle(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM vehicles WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -207,8 +203,11 @@ async fn delete_vehicle(
 
 async fn list_maintenance(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(vehicle_id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    ensure_team_vehicle(&state, team_id, vehicle_id).await?;
+
     let records = sqlx::query_as::<_, VehicleMaintenance>(
         "SELECT * FROM vehicle_maintenance WHERE vehicle_id = $1 ORDER BY performed_at DESC",
     )
@@ -238,9 +237,12 @@ struct CreateMaintenanceRequest {
 
 async fn create_maintenance(
     State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(vehicle_id): Path<Uuid>,
     Json(req): Json<CreateMaintenanceRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
+    ensure_team_vehicle(&state, team_id, vehicle_id).await?;
+
     let record = sqlx::query_as::<_, VehicleMaintenance>(
         r#"
         INSERT INTO vehicle_maintenance (vehicle_id, maintenance_type, description, provider, cost,
@@ -279,3 +281,20 @@ async fn create_maintenance(
         "errors": null,
     })))
 }
+
+/// Maintenance records have no team column of their own
+async fn ensure_team_vehicle(state: &AppState, team_id: Uuid, vehicle_id: Uuid) -> ApiResult<()> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM vehicles WHERE id = $1 AND team_id = $2)",
+    )
+    .bind(vehicle_id)
+    .bind(team_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    if exists {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound("Vehicle".into()))
+    }
+}
@@ -1,51 +1,67 @@
 use std::sync::Arc;
 
-use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
 use axum::{Json, Router};
-use axum::Extension;
-use serde::Deserialize;
+use chrono::Utc;
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
-use crate::middleware::auth::AuthUser;
+use crate::middleware::permissions::{require, Permission};
+use crate::middleware::team::TeamContext;
+use crate::models::common::PaginationParams;
+use crate::models::webhook::{
+    CreateWebhookRequest, UpdateWebhookRequest, Webhook, WebhookDelivery, WebhookDeliveryFilters,
+};
+use crate::services::events;
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::services::{outbound, webhook_service};
 use crate::AppState;
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/webhooks", get(list_webhooks).post(create_webhook))
-        .route("/webhooks/{id}", get(get_webhook).delete(delete_webhook))
-        .route("/webhooks/{id}/test", axum::routing::post(test_webhook))
+        .route("/webhooks", get(list_webhooks).post(create_webhook).route_layer(require(Permission::WebhooksManage)))
+        .route(
+            "/webhooks/:id",
+            get(get_webhook)
+                .patch(update_webhook)
+                .delete(delete_webhook)
+                .route_layer(require(Permission::WebhooksManage)),
+        )
+        .route("/webhooks/:id/test", post(test_webhook).route_layer(require(Permission::WebhooksManage)))
+        .route(
+            "/webhooks/:id/deliveries",
+            get(list_deliveries).route_layer(require(Permission::WebhooksManage)),
+        )
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/replay",
+            post(replay_delivery).route_layer(require(Permission::WebhooksManage)),
+        )
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
-struct Webhook {
-    id: Uuid,
-    team_id: Uuid,
-    url: String,
-    secret: String,
-    events: Vec<String>,
-    is_active: bool,
-    last_triggered_at: Option<chrono::DateTime<chrono::Utc>>,
-    last_status_code: Option<i32>,
-    failure_count: i32,
-    created_at: chrono::DateTime<chrono::Utc>,
-    updated_at: chrono::DateTime<chrono::Utc>,
+/// Deliveries are sent from the server and their responses shown in the delivery log, so
+/// the endpoint has to be https on a public address. Checked again before every delivery.
+async fn validate_url(url: &str) -> ApiResult<()> {
+    outbound::resolve_public(url, true)
+        .await
+        .map(|_| ())
+        .map_err(|e| ApiError::Validation(format!("Webhook URL {}", e)))
 }
 
-#[derive(Deserialize)]
-struct CreateWebhookRequest {
-    url: String,
-    events: Vec<String>,
+async fn find_webhook(state: &AppState, team_id: Uuid, id: Uuid) -> ApiResult<Webhook> {
+    sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE id = $1 AND team_id = $2")
+        .bind(id)
+        .bind(team_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Webhook".into()))
 }
 
 async fn list_webhooks(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     let webhooks = sqlx::query_as::<_, Webhook>(
         "SELECT * FROM webhooks WHERE team_id = $1 ORDER BY created_at DESC",
     )
@@ -55,20 +71,18 @@ async fn list_webhooks(
 
     Ok(Json(json!({
         "data": webhooks,
-        "meta": { "total": webhooks.len() },
+        "meta": { "total": webhooks.len(), "event_types": events::EVENT_TYPES },
         "errors": null,
     })))
 }
 
 async fn create_webhook(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Json(req): Json<CreateWebhookRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
-    // Generate a random secret for HMAC signing
-    let secret = format!("whsec_{}", Uuid::new_v4().to_string().replace('-', ""));
+    validate_url(&req.url).await?;
+    events::validate_subscription(&req.events)?;
 
     let webhook = sqlx::query_as::<_, Webhook>(
         r#"
@@ -79,7 +93,7 @@ async fn create_webhook(
     )
     .bind(team_id)
     .bind(&req.url)
-    .bind(&secret)
+    .bind(webhook_service::generate_secret())
     .bind(&req.events)
     .fetch_one(&state.db)
     .await?;
@@ -95,20 +109,55 @@ async fn create_webhook(
 
 async fn get_webhook(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let webhook = find_webhook(&state, team_id, id).await?;
+
+    Ok(Json(json!({
+        "data": webhook,
+        "meta": null,
+        "errors": null,
+    })))
+}
+
+/// Change the URL or events, or switch a subscription back on after it was disabled
+async fn update_webhook(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
+    Json(req): Json<UpdateWebhookRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
+    if let Some(url) = &req.url {
+        validate_url(url).await?;
+    }
+    if let Some(subscribed) = &req.events {
+        events::validate_subscription(subscribed)?;
+    }
 
     let webhook = sqlx::query_as::<_, Webhook>(
-        "SELECT * FROM webhooks WHERE id = $1 AND team_id = $2",
+        r#"
+        UPDATE webhooks SET
+            url = COALESCE($3, url),
+            events = COALESCE($4, events),
+            is_active = COALESCE($5, is_active),
+            failure_count = CASE WHEN $5 THEN 0 ELSE failure_count END,
+            disabled_at = CASE WHEN $5 THEN NULL ELSE disabled_at END
+        WHERE id = $1 AND team_id = $2
+        RETURNING *
+        "#,
     )
     .bind(id)
     .bind(team_id)
+    .bind(&req.url)
+    .bind(&req.events)
+    .bind(req.is_active)
     .fetch_optional(&state.db)
     .await?
     .ok_or_else(|| ApiError::NotFound("Webhook".into()))?;
 
+    tracing::info!(webhook_id = %webhook.id, is_active = webhook.is_active, "Webhook updated");
+
     Ok(Json(json!({
         "data": webhook,
         "meta": null,
@@ -118,11 +167,9 @@ async fn get_webhook(
 
 async fn delete_webhook(
     State(state): State<Arc<AppState>>,
-    Extension(auth): Extension<AuthUser>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let team_id = auth.team_id.unwrap_or_default();
-
     sqlx::query("DELETE FROM webhooks WHERE id = $1 AND team_id = $2")
         .bind(id)
         .bind(team_id)
@@ -136,16 +183,106 @@ async fn delete_webhook(
     })))
 }
 
+/// Send a `webhook.test` event right away and report how the endpoint answered
 async fn test_webhook(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    // TODO: Send a test payload to the webhook URL
-    tracing::info!(webhook_id = %id, "Webhook test triggered");
+    let webhook = find_webhook(&state, team_id, id).await?;
+    let delivery = webhook_service::send_test(&state, &webhook).await?;
 
+    tracing::info!(webhook_id = %id, status = %delivery.status, "Webhook test sent");
+
+    let message = if delivery.status == "succeeded" {
+        "Test webhook delivered"
+    } else {
+        "Test webhook failed"
+    };
     Ok(Json(json!({
-        "data": null,
-        "meta": { "message": "Test webhook sent" },
+        "data": delivery,
+        "meta": { "message": message },
+        "errors": null,
+    })))
+}
+
+/// Delivery attempts for one subscription, newest first
+async fn list_deliveries(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path(id): Path<Uuid>,
+    Query(pagination): Query<PaginationParams>,
+    Query(filters): Query<WebhookDeliveryFilters>,
+) -> ApiResult<Json<serde_json::Value>> {
+    find_webhook(&state, team_id, id).await?;
+    let cursor = pagination.cursor.as_ref().and_then(|c| c.parse::<Uuid>().ok());
+
+    let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+        r#"
+        SELECT * FROM webhook_deliveries
+        WHERE webhook_id = $1
+          AND ($2::text IS NULL OR status = $2)
+          AND ($3::uuid IS NULL OR event_id = $3)
+          AND ($4::uuid IS NULL OR created_at < (SELECT created_at FROM webhook_deliveries WHERE id = $4))
+        ORDER BY created_at DESC
+        LIMIT $5
+        "#,
+    )
+    .bind(id)
+    .bind(&filters.status)
+    .bind(filters.event_id)
+    .bind(cursor)
+    .bind(pagination.limit())
+    .fetch_all(&state.db)
+    .await?;
+    let has_more = deliveries.len() as i64 == pagination.limit();
+
+    Ok(Json(json!({
+        "data": deliveries,
+        "meta": {
+            "has_more": has_more,
+            "cursor": deliveries.last().map(|d| d.id.to_string()),
+        },
+        "errors": null,
+    })))
+}
+
+/// Queue a logged delivery to be sent again with its original payload and event id, so
+/// subscribers that dedupe on the id see it as the same event
+async fn replay_delivery(
+    State(state): State<Arc<AppState>>,
+    TeamContext { team_id, .. }: TeamContext,
+    Path((id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let webhook = find_webhook(&state, team_id, id).await?;
+    if !webhook.is_active {
+        return Err(ApiError::BadRequest("Re-enable the webhook before replaying deliveries".into()));
+    }
+
+    let delivery = sqlx::query_as::<_, WebhookDelivery>(
+        "SELECT * FROM webhook_deliveries WHERE id = $1 AND webhook_id = $2",
+    )
+    .bind(delivery_id)
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Webhook delivery".into()))?;
+
+    let job = BackgroundJob::DeliverWebhook {
+        team_id,
+        webhook_id: id,
+        event_id: delivery.event_id,
+        event_type: delivery.event_type.clone(),
+        payload: delivery.payload.clone(),
+        replay_of: Some(delivery.id),
+    };
+    let job_id = job_queue::enqueue_with(&state.db, &job, Utc::now(), state.config.webhooks.max_attempts, None).await?;
+
+    tracing::info!(webhook_id = %id, delivery_id = %delivery_id, event_id = %delivery.event_id, "Webhook delivery replay queued");
+
+    Ok(Json(json!({
+        "data": { "job_id": job_id, "replay_of": delivery.id, "event_id": delivery.event_id },
+        "meta": { "message": "Replay queued" },
         "errors": null,
     })))
 }
@@ -1,116 +1,370 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::State;
-use axum::response::IntoResponse;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
+use futures_util::stream::SplitStream;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use uuid::Uuid;
 
+use crate::errors::{ApiError, ApiResult};
+use crate::middleware::auth::{self, AuthUser};
+use crate::middleware::permissions::{self, Permission};
+use crate::services::realtime::{self, Channel, RealtimeMessage};
 use crate::AppState;
 
+/// How long a client that didn't pass `?access_token=` has to send its `auth` message
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a long-lived socket's token is checked again (expiry, revocation, removal from the team)
+const AUTH_RECHECK: Duration = Duration::from_secs(60);
+const PING_INTERVAL: Duration = Duration::from_secs(25);
+/// Nothing heard from the client for this long, not even a pong, closes the socket
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Frames queued for one client; a client that falls this far behind is disconnected
+const SEND_BUFFER: usize = 256;
+const MAX_SUBSCRIPTIONS: usize = 50;
+
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_POLICY: u16 = 1008;
+
+/// Mounted outside `require_auth`: browsers can't set headers on a WebSocket handshake, so
+/// the socket authenticates itself from `?access_token=` or its first message.
 pub fn router() -> Router<Arc<AppState>> {
     Router::new().route("/ws", get(ws_handler))
 }
 
+#[derive(Deserialize)]
+struct ConnectParams {
+    access_token: Option<String>,
+    team_id: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// First message when no token was given on the URL; later, to swap in a refreshed token
+    Auth { token: String },
+    Ping,
+    Subscribe { channel: String },
+    Unsubscribe { channel: String },
+}
+
+struct Session {
+    token: String,
+    requested_team: Option<Uuid>,
+    team_id: Uuid,
+    auth: AuthUser,
+}
+
+impl Session {
+    async fn start(state: &AppState, token: String, requested_team: Option<Uuid>) -> ApiResult<Self> {
+        let auth = auth::authenticate(state, &token, requested_team).await?;
+        let team_id = auth.team_id.ok_or(ApiError::TeamRequired)?;
+        Ok(Self { token, requested_team, team_id, auth })
+    }
+
+    /// Resolve the token again so expiry, revocation and role changes reach open sockets.
+    /// The socket stays on the team it connected to.
+    async fn refresh(&mut self, state: &AppState, token: Option<String>) -> ApiResult<()> {
+        let token = token.unwrap_or_else(|| self.token.clone());
+        let auth = auth::authenticate(state, &token, Some(self.team_id)).await?;
+        if auth.team_id != Some(self.team_id) {
+            return Err(ApiError::Forbidden);
+        }
+        self.token = token;
+        self.auth = auth;
+        Ok(())
+    }
+
+    /// Events of a topic the user may not see are dropped whatever channel carried them
+    fn may_receive(&self, event: &RealtimeMessage) -> bool {
+        event.team_id == self.team_id
+            && realtime::topic_permission(&event.topic).is_some_and(|permission| self.auth.has(permission))
+    }
+}
+
+/// Outbound frames go through a bounded queue drained by a writer task, so one slow client
+/// can't hold up the event loop
+struct Outbox {
+    tx: mpsc::Sender<Message>,
+}
+
+impl Outbox {
+    /// `false` when the socket should be closed: the client is gone or has stopped reading
+    fn send(&self, message: Message) -> bool {
+        match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("WebSocket send buffer full; disconnecting slow client");
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    fn json(&self, value: serde_json::Value) -> bool {
+        self.send(Message::Text(value.to_string()))
+    }
+
+    fn close(&self, code: u16, reason: &'static str) {
+        let _ = self.tx.try_send(Message::Close(Some(CloseFrame { code, reason: Cow::Borrowed(reason) })));
+    }
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+    Query(params): Query<ConnectParams>,
+) -> ApiResult<Response> {
+    // A token on the URL is checked before upgrading, so a bad one fails with a plain 401
+    let session = match params.access_token {
+        Some(token) => Some(Session::start(&state, token, params.team_id).await?),
+        None => None,
+    };
+    let requested_team = params.team_id;
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, session, requested_team)))
 }
 
-async fn handle_socket(mut socket: WebSocket, _state: Arc<AppState>) {
-    // Send welcome message
-    if socket
-        .send(Message::Text(
-            serde_json::json!({
-                "type": "connected",
-                "message": "Connected to FieldForge real-time events"
-            })
-            .to_string(),
-        ))
-        .await
-        .is_err()
-    {
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>, session: Option<Session>, requested_team: Option<Uuid>) {
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::channel::<Message>(SEND_BUFFER);
+    let mut writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let closing = matches!(message, Message::Close(_));
+            if sink.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+    });
+    let outbox = Outbox { tx };
+
+    let session = match session {
+        Some(session) => Some(session),
+        None => authenticate_first_message(&state, &mut stream, &outbox, requested_team).await,
+    };
+    if let Some(session) = session {
+        run(&state, session, &mut stream, &outbox).await;
+    }
+
+    // Let queued frames (including any close frame) go out, but don't wait on a stalled client
+    drop(outbox);
+    if tokio::time::timeout(Duration::from_secs(5), &mut writer).await.is_err() {
+        writer.abort();
+    }
+}
+
+/// Without `?access_token=`, the first message must be `{"type": "auth", "token": "..."}`
+async fn authenticate_first_message(
+    state: &AppState,
+    stream: &mut SplitStream<WebSocket>,
+    outbox: &Outbox,
+    requested_team: Option<Uuid>,
+) -> Option<Session> {
+    let token = match tokio::time::timeout(AUTH_TIMEOUT, stream.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Auth { token }) => token,
+            _ => {
+                outbox.json(json!({ "type": "error", "message": "The first message must be an auth message" }));
+                outbox.close(CLOSE_POLICY, "Authentication required");
+                return None;
+            }
+        },
+        Ok(_) => return None,
+        Err(_) => {
+            outbox.close(CLOSE_POLICY, "Authentication timed out");
+            return None;
+        }
+    };
+
+    match Session::start(state, token, requested_team).await {
+        Ok(session) => Some(session),
+        Err(e) => {
+            tracing::debug!(error = %e, "WebSocket authentication failed");
+            outbox.json(json!({ "type": "error", "message": "Authentication failed" }));
+            outbox.close(CLOSE_POLICY, "Authentication failed");
+            None
+        }
+    }
+}
+
+/// Check the user may follow a channel. Technicians without `jobs.read_all` can follow
+/// their own user channel and the jobs assigned to them, but not team-wide job feeds.
+async fn authorize(state: &AppState, session: &Session, channel: &Channel) -> ApiResult<()> {
+    let auth = &session.auth;
+    match channel {
+        Channel::Team { team_id, topic } => {
+            if *team_id != session.team_id {
+                return Err(ApiError::Forbidden);
+            }
+            auth.require(realtime::topic_permission(topic).ok_or(ApiError::Forbidden)?)?;
+            if matches!(*topic, "jobs" | "schedule") {
+                auth.require(Permission::JobsReadAll)?;
+            }
+            Ok(())
+        }
+        Channel::Job(job_id) => {
+            auth.require(Permission::JobsRead)?;
+            permissions::ensure_job_access(&state.db, auth, session.team_id, *job_id).await
+        }
+        Channel::User(user_id) if *user_id == auth.id => Ok(()),
+        Channel::User(_) => auth.require(Permission::JobsReadAll),
+    }
+}
+
+fn connected_message(session: &Session) -> serde_json::Value {
+    let topics: Vec<&str> = realtime::TOPICS
+        .iter()
+        .filter(|(_, permission)| session.auth.has(*permission))
+        .map(|(name, _)| *name)
+        .collect();
+    json!({
+        "type": "connected",
+        "message": "Connected to FieldForge real-time events",
+        "user_id": session.auth.id,
+        "team_id": session.team_id,
+        "topics": topics,
+    })
+}
+
+/// Stream events for the subscribed channels until the client leaves, stops answering
+/// pings, falls behind, or its token stops being valid.
+async fn run(state: &AppState, mut session: Session, stream: &mut SplitStream<WebSocket>, outbox: &Outbox) {
+    let mut events = state.realtime.subscribe();
+    let mut channels: HashSet<Channel> = HashSet::new();
+    let mut last_seen = Instant::now();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut recheck = tokio::time::interval(AUTH_RECHECK);
+    ping.tick().await;
+    recheck.tick().await;
+
+    if !outbox.json(connected_message(&session)) {
         return;
     }
 
-    // Main event loop
     loop {
         tokio::select! {
-            msg = socket.recv() => {
-                match msg {
+            frame = stream.next() => {
+                match frame {
                     Some(Ok(Message::Text(text))) => {
-                        // Handle client messages (subscribe to channels, ping, etc.)
-                        if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) {
-                            match parsed.get("type").and_then(|t| t.as_str()) {
-                                Some("ping") => {
-                                    let _ = socket.send(Message::Text(
-                                        serde_json::json!({"type": "pong"}).to_string()
-                                    )).await;
-                                }
-                                Some("subscribe") => {
-                                    let channel = parsed.get("channel").and_then(|c| c.as_str()).unwrap_or("default");
-                                    tracing::debug!("Client subscribed to channel: {}", channel);
-                                    let _ = socket.send(Message::Text(
-                                        serde_json::json!({
-                                            "type": "subscribed",
-                                            "channel": channel
-                                        }).to_string()
-                                    )).await;
-                                }
-                                _ => {}
-                            }
+                        last_seen = Instant::now();
+                        if !handle_client_message(state, &mut session, &mut channels, outbox, &text).await {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => last_seen = Instant::now(),
+                }
+            }
+            event = events.recv() => {
+                match event {
+                    Ok(event) if session.may_receive(&event) && channels.iter().any(|c| c.matches(&event)) => {
+                        if !outbox.send(Message::Text(event.payload.to_string())) {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(user_id = %session.auth.id, missed, "WebSocket client missed real-time events");
+                        if !outbox.json(json!({ "type": "lagged", "missed": missed })) {
+                            break;
                         }
                     }
-                    Some(Ok(Message::Close(_))) | None => break,
-                    _ => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    outbox.close(CLOSE_GOING_AWAY, "Heartbeat timed out");
+                    break;
+                }
+                if !outbox.send(Message::Ping(Vec::new())) {
+                    break;
+                }
+            }
+            _ = recheck.tick() => {
+                if session.refresh(state, None).await.is_err() {
+                    outbox.json(json!({ "type": "error", "message": "Session expired; reconnect with a fresh token" }));
+                    outbox.close(CLOSE_POLICY, "Session expired");
+                    break;
+                }
+                if !prune_channels(state, &session, &mut channels, outbox).await {
+                    break;
                 }
             }
         }
     }
 
-    tracing::debug!("WebSocket connection closed");
+    tracing::debug!(user_id = %session.auth.id, team_id = %session.team_id, "WebSocket connection closed");
 }
 
-/// Event types that can be broadcast to connected clients
-#[derive(Debug, Clone, serde::Serialize)]
-#[serde(tag = "type")]
-pub enum WsEvent {
-    #[serde(rename = "job_status_changed")]
-    JobStatusChanged {
-        job_id: String,
-        old_status: String,
-        new_status: String,
-    },
-    #[serde(rename = "payment_received")]
-    PaymentReceived {
-        payment_id: String,
-        invoice_id: String,
-        amount: String,
-    },
-    #[serde(rename = "new_message")]
-    NewMessage {
-        message_id: String,
-        customer_id: String,
-        direction: String,
-    },
-    #[serde(rename = "schedule_changed")]
-    ScheduleChanged {
-        job_id: String,
-        change_type: String,
-    },
-    #[serde(rename = "inventory_alert")]
-    InventoryAlert {
-        item_id: String,
-        item_name: String,
-        current_quantity: i32,
-        min_quantity: i32,
-    },
-    #[serde(rename = "technician_location")]
-    TechnicianLocation {
-        user_id: String,
-        latitude: f64,
-        longitude: f64,
-    },
+/// Drop subscriptions the user lost access to since subscribing
+async fn prune_channels(state: &AppState, session: &Session, channels: &mut HashSet<Channel>, outbox: &Outbox) -> bool {
+    let mut revoked = Vec::new();
+    for channel in channels.iter() {
+        if authorize(state, session, channel).await.is_err() {
+            revoked.push(channel.clone());
+        }
+    }
+    for channel in revoked {
+        channels.remove(&channel);
+        if !outbox.json(json!({ "type": "unsubscribed", "channel": channel.name(), "reason": "access revoked" })) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Returns `false` when the socket should close
+async fn handle_client_message(
+    state: &AppState,
+    session: &mut Session,
+    channels: &mut HashSet<Channel>,
+    outbox: &Outbox,
+    text: &str,
+) -> bool {
+    let reply = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Ping) => json!({ "type": "pong" }),
+        Ok(ClientMessage::Auth { token }) => {
+            if session.refresh(state, Some(token)).await.is_err() {
+                outbox.json(json!({ "type": "error", "message": "Authentication failed" }));
+                outbox.close(CLOSE_POLICY, "Authentication failed");
+                return false;
+            }
+            if !prune_channels(state, session, channels, outbox).await {
+                return false;
+            }
+            json!({ "type": "authenticated", "user_id": session.auth.id })
+        }
+        Ok(ClientMessage::Subscribe { channel: name }) => match Channel::parse(&name) {
+            None => json!({ "type": "error", "channel": name, "message": "Unknown channel" }),
+            Some(_) if channels.len() >= MAX_SUBSCRIPTIONS => {
+                json!({ "type": "error", "channel": name, "message": "Too many subscriptions" })
+            }
+            Some(channel) => match authorize(state, session, &channel).await {
+                Ok(()) => {
+                    let reply = json!({ "type": "subscribed", "channel": channel.name() });
+                    channels.insert(channel);
+                    reply
+                }
+                Err(_) => json!({ "type": "error", "channel": name, "message": "Not allowed to subscribe to this channel" }),
+            },
+        },
+        Ok(ClientMessage::Unsubscribe { channel: name }) => {
+            if let Some(channel) = Channel::parse(&name) {
+                channels.remove(&channel);
+            }
+            json!({ "type": "unsubscribed", "channel": name })
+        }
+        Err(_) => json!({ "type": "error", "message": "Unrecognized message" }),
+    };
+    outbox.json(reply)
 }
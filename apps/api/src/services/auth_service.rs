@@ -1,6 +1,13 @@
 use argon2::{self, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
 
 use crate::errors::{ApiError, ApiResult};
 
@@ -20,3 +27,167 @@ pub fn verify_password(password: &str, hash: &str) -> ApiResult<bool> {
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok())
 }
+
+/// Random 256-bit token, URL-safe. Only its hash is ever stored.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// SHA-256 hex digest used to look up opaque tokens without storing them
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct StoredRefreshToken {
+    id: Uuid,
+    user_id: Uuid,
+    family_id: Uuid,
+    family_expires_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+    replaced_by: Option<Uuid>,
+}
+
+/// The refresh tokens issued from one login. Rotation carries the family on, but never past
+/// `expires_at`.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenFamily {
+    pub id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl TokenFamily {
+    /// A family for a fresh login
+    pub fn start(max_days: i64) -> Self {
+        TokenFamily { id: Uuid::new_v4(), expires_at: Utc::now() + Duration::days(max_days) }
+    }
+}
+
+/// Store a new refresh token in `family`: the rotated token's, or a new one for a fresh
+/// login. It expires after `expiry_days` or with its family, whichever is sooner. Returns
+/// the raw token and its row id.
+pub async fn issue_refresh_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    family: TokenFamily,
+    expiry_days: i64,
+    user_agent: Option<&str>,
+) -> ApiResult<(String, Uuid)> {
+    let token = generate_token();
+
+    let id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO refresh_tokens (user_id, token_hash, expires_at, family_id, family_expires_at, user_agent)
+        VALUES ($1, $2, LEAST(now() + make_interval(days => $3), $5), $4, $5, $6)
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(hash_token(&token))
+    .bind(expiry_days as i32)
+    .bind(family.id)
+    .bind(family.expires_at)
+    .bind(user_agent)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok((token, id))
+}
+
+/// Exchange a refresh token for a new one (single use). Presenting a token that was
+/// already rotated means it leaked or was replayed, so the whole family is revoked, and so
+/// is a family past its lifetime. Returns the user id and the replacement token.
+pub async fn rotate_refresh_token(pool: &PgPool, token: &str, expiry_days: i64, user_agent: Option<&str>) -> ApiResult<(Uuid, String)> {
+    let mut tx = pool.begin().await?;
+
+    let stored = sqlx::query_as::<_, StoredRefreshToken>(
+        r#"
+        SELECT id, user_id, family_id, family_expires_at, expires_at, revoked_at, replaced_by
+        FROM refresh_tokens WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ApiError::Unauthorized)?;
+
+    if stored.revoked_at.is_some() {
+        if stored.replaced_by.is_some() {
+            let revoked = revoke_family(&mut tx, stored.family_id, "reuse_detected").await?;
+            tx.commit().await?;
+            tracing::warn!(user_id = %stored.user_id, family_id = %stored.family_id, revoked, "Refresh token reuse detected; family revoked");
+        }
+        return Err(ApiError::Unauthorized);
+    }
+
+    if stored.family_expires_at <= Utc::now() {
+        revoke_family(&mut tx, stored.family_id, "family_expired").await?;
+        tx.commit().await?;
+        return Err(ApiError::Unauthorized);
+    }
+
+    if stored.expires_at <= Utc::now() {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let family = TokenFamily { id: stored.family_id, expires_at: stored.family_expires_at };
+    let (new_token, new_id) = issue_refresh_token(&mut tx, stored.user_id, family, expiry_days, user_agent).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens SET revoked_at = now(), revoked_reason = 'rotated', replaced_by = $2, last_used_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(stored.id)
+    .bind(new_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok((stored.user_id, new_token))
+}
+
+async fn revoke_family(conn: &mut PgConnection, family_id: Uuid, reason: &str) -> ApiResult<u64> {
+    let result = sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = now(), revoked_reason = $2 WHERE family_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(family_id)
+    .bind(reason)
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Log out one device: revoke every live token in the presented token's family.
+/// Unknown tokens are ignored so logout is idempotent.
+pub async fn revoke_refresh_token(pool: &PgPool, token: &str) -> ApiResult<()> {
+    let mut conn = pool.acquire().await?;
+    let family_id = sqlx::query_scalar::<_, Uuid>("SELECT family_id FROM refresh_tokens WHERE token_hash = $1")
+        .bind(hash_token(token))
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    if let Some(family_id) = family_id {
+        revoke_family(&mut conn, family_id, "logout").await?;
+    }
+    Ok(())
+}
+
+/// Log out everywhere: revoke all of the user's live refresh tokens
+pub async fn revoke_all_refresh_tokens(pool: &PgPool, user_id: Uuid) -> ApiResult<u64> {
+    let result = sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = now(), revoked_reason = 'logout_all' WHERE user_id = $1 AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
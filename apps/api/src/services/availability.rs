@@ -0,0 +1,466 @@
+//! When technicians can take work.
+//!
+//! A technician works their weekly shifts, or the team's working hours and days if no
+//! shift pattern is in effect on the date. Team holidays take the whole day and approved
+//! time off takes its span; pending time off is reported but doesn't block anything. On
+//! top of that, [`availability`] takes out scheduled jobs, padded by the team's travel
+//! buffer, and running timers, leaving the free time dispatch and online booking can fill.
+//! Times are wall-clock times in the team's time zone, like `jobs.scheduled_*`.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::availability::ShiftInput;
+use crate::services::schedule::{self, TeamHours, Technician};
+use crate::AppState;
+
+/// Longest range one availability query covers
+pub const MAX_RANGE_DAYS: i64 = 31;
+pub const TIME_OFF_KINDS: &[&str] = &["vacation", "sick", "personal", "training", "other"];
+/// How much longer a job with a running timer is assumed to take once it's past its slot
+const OVERRUN_MINUTES: i64 = 30;
+
+/// A span of local time within one day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Interval {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl Interval {
+    pub fn on(date: NaiveDate, start: NaiveTime, end: NaiveTime) -> Interval {
+        Interval { start: date.and_time(start), end: date.and_time(end) }
+    }
+
+    /// The whole of `date`, midnight to midnight
+    fn day(date: NaiveDate) -> Interval {
+        let start = date.and_time(NaiveTime::MIN);
+        Interval { start, end: start + Duration::days(1) }
+    }
+
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    pub fn contains(&self, other: &Interval) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    fn clip(&self, to: &Interval) -> Option<Interval> {
+        let clipped = Interval { start: self.start.max(to.start), end: self.end.min(to.end) };
+        (clipped.start < clipped.end).then_some(clipped)
+    }
+
+    pub fn minutes(&self) -> i64 {
+        (self.end - self.start).num_minutes()
+    }
+}
+
+/// Sorted, with overlapping and touching intervals joined
+fn merge(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.sort_by_key(|interval| interval.start);
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for interval in intervals {
+        match merged.last_mut() {
+            Some(last) if interval.start <= last.end => last.end = last.end.max(interval.end),
+            _ => merged.push(interval),
+        }
+    }
+    merged
+}
+
+/// What's left of `intervals` once everything in `remove` is taken out
+fn subtract(intervals: &[Interval], remove: &[Interval]) -> Vec<Interval> {
+    let remove = merge(remove.to_vec());
+    let mut left = Vec::new();
+    for interval in intervals {
+        let mut start = interval.start;
+        for cut in remove.iter().filter(|cut| cut.overlaps(interval)) {
+            if cut.start > start {
+                left.push(Interval { start, end: cut.start });
+            }
+            start = start.max(cut.end);
+        }
+        if start < interval.end {
+            left.push(Interval { start, end: interval.end });
+        }
+    }
+    left
+}
+
+/// Check a weekly shift pattern before it replaces a technician's current one
+pub fn validate_shifts(shifts: &[ShiftInput]) -> ApiResult<()> {
+    for (i, shift) in shifts.iter().enumerate() {
+        if !(0..=6).contains(&shift.weekday) {
+            return Err(ApiError::Validation(format!("shifts[{}]: weekday is 0 (Sunday) to 6 (Saturday)", i)));
+        }
+        if shift.end_time <= shift.start_time {
+            return Err(ApiError::Validation(format!("shifts[{}]: a shift must end after it starts", i)));
+        }
+        if let (Some(from), Some(until)) = (shift.effective_from, shift.effective_until) {
+            if until < from {
+                return Err(ApiError::Validation(format!("shifts[{}]: effective_until is before effective_from", i)));
+            }
+        }
+        let clash = shifts[..i].iter().position(|other| {
+            other.weekday == shift.weekday
+                && other.start_time < shift.end_time
+                && shift.start_time < other.end_time
+                && other.effective_from.map_or(true, |from| shift.effective_until.map_or(true, |until| from <= until))
+                && shift.effective_from.map_or(true, |from| other.effective_until.map_or(true, |until| from <= until))
+        });
+        if let Some(j) = clash {
+            return Err(ApiError::Validation(format!("shifts[{}] overlaps shifts[{}]", i, j)));
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_time_off(starts_at: NaiveDateTime, ends_at: NaiveDateTime, kind: &str) -> ApiResult<()> {
+    if ends_at <= starts_at {
+        return Err(ApiError::Validation("Time off must end after it starts".into()));
+    }
+    if (ends_at - starts_at).num_days() > 366 {
+        return Err(ApiError::Validation("Time off can't run longer than a year".into()));
+    }
+    if !TIME_OFF_KINDS.contains(&kind) {
+        return Err(ApiError::Validation(format!(
+            "Unknown kind '{}'; expected one of {}",
+            kind,
+            TIME_OFF_KINDS.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Shift {
+    user_id: Uuid,
+    weekday: i16,
+    start_time: NaiveTime,
+    end_time: NaiveTime,
+    effective_from: Option<NaiveDate>,
+    effective_until: Option<NaiveDate>,
+}
+
+impl Shift {
+    fn in_effect(&self, date: NaiveDate) -> bool {
+        self.effective_from.map_or(true, |from| from <= date) && self.effective_until.map_or(true, |until| date <= until)
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct TimeOffRow {
+    id: Uuid,
+    user_id: Uuid,
+    starts_at: NaiveDateTime,
+    ends_at: NaiveDateTime,
+    kind: String,
+    status: String,
+}
+
+/// Pending or approved time off, cut to the day it falls on
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeOff {
+    pub id: Uuid,
+    pub kind: String,
+    pub status: String,
+    #[serde(flatten)]
+    pub interval: Interval,
+}
+
+/// A technician's day before any jobs are put on it
+#[derive(Debug, Clone, Serialize)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub holiday: Option<String>,
+    /// When they're meant to work: their shifts, or the team's hours
+    pub shifts: Vec<Interval>,
+    pub time_off: Vec<TimeOff>,
+}
+
+impl Day {
+    pub fn is_working_day(&self) -> bool {
+        self.holiday.is_none() && !self.shifts.is_empty()
+    }
+
+    /// Approved time off
+    pub fn approved_time_off(&self) -> impl Iterator<Item = &TimeOff> {
+        self.time_off.iter().filter(|time_off| time_off.status == "approved")
+    }
+
+    /// Shift time that isn't a holiday or taken as approved time off
+    pub fn working(&self) -> Vec<Interval> {
+        if self.holiday.is_some() {
+            return Vec::new();
+        }
+        let off: Vec<Interval> = self.approved_time_off().map(|time_off| time_off.interval).collect();
+        subtract(&self.shifts, &off)
+    }
+}
+
+/// Team hours, holidays, shifts and time off for some technicians over a range of dates
+pub struct Calendar {
+    hours: TeamHours,
+    holidays: HashMap<NaiveDate, String>,
+    shifts: HashMap<Uuid, Vec<Shift>>,
+    time_off: HashMap<Uuid, Vec<TimeOffRow>>,
+}
+
+impl Calendar {
+    pub async fn load(conn: &mut PgConnection, team_id: Uuid, users: &[Uuid], from: NaiveDate, to: NaiveDate) -> ApiResult<Calendar> {
+        let hours = schedule::team_hours(conn, team_id).await?;
+
+        let holidays = sqlx::query_as::<_, (NaiveDate, String)>(
+            "SELECT holiday_date, name FROM team_holidays WHERE team_id = $1 AND holiday_date BETWEEN $2 AND $3",
+        )
+        .bind(team_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let shift_rows = sqlx::query_as::<_, Shift>(
+            r#"
+            SELECT user_id, weekday, start_time, end_time, effective_from, effective_until
+            FROM technician_shifts
+            WHERE team_id = $1 AND user_id = ANY($2)
+              AND (effective_from IS NULL OR effective_from <= $4)
+              AND (effective_until IS NULL OR effective_until >= $3)
+            "#,
+        )
+        .bind(team_id)
+        .bind(users)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let time_off_rows = sqlx::query_as::<_, TimeOffRow>(
+            r#"
+            SELECT id, user_id, starts_at, ends_at, kind, status
+            FROM time_off_requests
+            WHERE team_id = $1 AND user_id = ANY($2) AND status IN ('pending', 'approved')
+              AND starts_at < ($4::date + 1)::timestamp AND ends_at > $3::date::timestamp
+            ORDER BY starts_at
+            "#,
+        )
+        .bind(team_id)
+        .bind(users)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut shifts: HashMap<Uuid, Vec<Shift>> = HashMap::new();
+        for shift in shift_rows {
+            shifts.entry(shift.user_id).or_default().push(shift);
+        }
+        let mut time_off: HashMap<Uuid, Vec<TimeOffRow>> = HashMap::new();
+        for row in time_off_rows {
+            time_off.entry(row.user_id).or_default().push(row);
+        }
+
+        Ok(Calendar { hours, holidays: holidays.into_iter().collect(), shifts, time_off })
+    }
+
+    pub fn hours(&self) -> &TeamHours {
+        &self.hours
+    }
+
+    /// A technician's day, or with no technician the team's
+    pub fn day(&self, user_id: Option<Uuid>, date: NaiveDate) -> Day {
+        let whole_day = Interval::day(date);
+
+        // A seasonal pattern that doesn't cover the date leaves the team's hours
+        let pattern: Vec<&Shift> = user_id
+            .and_then(|user_id| self.shifts.get(&user_id))
+            .map(|shifts| shifts.iter().filter(|shift| shift.in_effect(date)).collect())
+            .unwrap_or_default();
+        let shifts = if pattern.is_empty() {
+            self.team_shift(date).into_iter().collect()
+        } else {
+            let weekday = date.weekday().num_days_from_sunday() as i16;
+            merge(
+                pattern
+                    .iter()
+                    .filter(|shift| shift.weekday == weekday)
+                    .map(|shift| Interval::on(date, shift.start_time, shift.end_time))
+                    .collect(),
+            )
+        };
+
+        let time_off = user_id
+            .and_then(|user_id| self.time_off.get(&user_id))
+            .into_iter()
+            .flatten()
+            .filter_map(|row| {
+                let interval = Interval { start: row.starts_at, end: row.ends_at }.clip(&whole_day)?;
+                Some(TimeOff { id: row.id, kind: row.kind.clone(), status: row.status.clone(), interval })
+            })
+            .collect();
+
+        Day { date, holiday: self.holidays.get(&date).cloned(), shifts, time_off }
+    }
+
+    fn team_shift(&self, date: NaiveDate) -> Option<Interval> {
+        if !self.hours.is_working_day(date) {
+            return None;
+        }
+        let whole_day = Interval::day(date);
+        let start = self.hours.working_hours_start.map_or(whole_day.start, |start| date.and_time(start));
+        let end = self.hours.working_hours_end.map_or(whole_day.end, |end| date.and_time(end));
+        (start < end).then_some(Interval { start, end })
+    }
+}
+
+/// Something that keeps a technician busy
+#[derive(Debug, Clone, Serialize)]
+pub struct Busy {
+    /// `job`, or `timer` for a running time entry
+    pub kind: &'static str,
+    pub job_id: Uuid,
+    pub title: String,
+    #[serde(flatten)]
+    pub interval: Interval,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AvailableDay {
+    #[serde(flatten)]
+    pub day: Day,
+    pub busy: Vec<Busy>,
+    /// Working time with nothing booked, from now on
+    pub free: Vec<Interval>,
+    pub free_minutes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TechnicianAvailability {
+    #[serde(flatten)]
+    pub technician: Technician,
+    pub days: Vec<AvailableDay>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct RunningTimer {
+    user_id: Uuid,
+    job_id: Uuid,
+    title: String,
+    started_at: DateTime<Utc>,
+}
+
+/// Free time for every technician over `from..=to`, or just `only`. Gaps shorter than
+/// `min_minutes` are left out, so a booking flow can ask for slots that fit a visit.
+pub async fn availability(
+    state: &AppState,
+    team_id: Uuid,
+    from: NaiveDate,
+    to: NaiveDate,
+    only: Option<Uuid>,
+    min_minutes: Option<i64>,
+) -> ApiResult<Vec<TechnicianAvailability>> {
+    let mut conn = state.db.acquire().await?;
+
+    let technicians = schedule::technicians(&mut conn, team_id, &[], only).await?;
+    let users: Vec<Uuid> = technicians.iter().map(|technician| technician.user_id).collect();
+    let calendar = Calendar::load(&mut conn, team_id, &users, from, to).await?;
+    let jobs = schedule::jobs_between(&mut conn, team_id, from, to, only).await?;
+
+    let timers = sqlx::query_as::<_, RunningTimer>(
+        r#"
+        SELECT t.user_id, t.job_id, j.title, t.started_at
+        FROM time_entries t
+        JOIN jobs j ON j.id = t.job_id
+        WHERE t.team_id = $1 AND t.user_id = ANY($2) AND t.ended_at IS NULL
+        "#,
+    )
+    .bind(team_id)
+    .bind(&users)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let tz: Tz = calendar.hours().timezone.parse().unwrap_or(Tz::UTC);
+    let now = Utc::now().with_timezone(&tz).naive_local();
+    let buffer = Duration::minutes(calendar.hours().schedule_travel_buffer_minutes.into());
+    let dates: Vec<NaiveDate> = from.iter_days().take_while(|date| *date <= to).collect();
+
+    let result = technicians
+        .into_iter()
+        .map(|technician| {
+            let user_id = technician.user_id;
+            let mut busy: Vec<Busy> = jobs
+                .iter()
+                .filter(|job| job.assigned_to == Some(user_id))
+                .filter_map(|job| {
+                    let slot = job.slot()?;
+                    Some(Busy {
+                        kind: "job",
+                        job_id: job.id,
+                        title: job.title.clone(),
+                        interval: Interval::on(job.scheduled_date, slot.start, slot.end),
+                    })
+                })
+                .collect();
+            // A job being worked keeps its technician until its slot ends, or a while
+            // longer when it's already running over
+            for timer in timers.iter().filter(|timer| timer.user_id == user_id) {
+                let started = timer.started_at.with_timezone(&tz).naive_local();
+                let booked_end = busy
+                    .iter()
+                    .find(|busy| busy.job_id == timer.job_id && busy.interval.end > now)
+                    .map(|busy| busy.interval.end);
+                let end = booked_end.unwrap_or(now + Duration::minutes(OVERRUN_MINUTES));
+                busy.push(Busy {
+                    kind: "timer",
+                    job_id: timer.job_id,
+                    title: timer.title.clone(),
+                    interval: Interval { start: started.min(now), end },
+                });
+            }
+            busy.sort_by_key(|busy| busy.interval.start);
+
+            let days = dates
+                .iter()
+                .map(|&date| {
+                    let day = calendar.day(Some(user_id), date);
+                    let whole_day = Interval::day(date);
+                    let mut taken: Vec<Interval> = busy
+                        .iter()
+                        .map(|busy| Interval { start: busy.interval.start - buffer, end: busy.interval.end + buffer })
+                        .collect();
+                    taken.push(Interval { start: whole_day.start, end: now.max(whole_day.start) });
+
+                    let free: Vec<Interval> = subtract(&day.working(), &taken)
+                        .into_iter()
+                        .filter(|interval| min_minutes.map_or(true, |min| interval.minutes() >= min))
+                        .collect();
+                    let day_busy = busy
+                        .iter()
+                        .filter_map(|busy| {
+                            let interval = busy.interval.clip(&whole_day)?;
+                            Some(Busy { interval, ..busy.clone() })
+                        })
+                        .collect();
+
+                    AvailableDay {
+                        free_minutes: free.iter().map(Interval::minutes).sum(),
+                        day,
+                        busy: day_busy,
+                        free,
+                    }
+                })
+                .collect();
+
+            TechnicianAvailability { technician, days }
+        })
+        .collect();
+
+    Ok(result)
+}
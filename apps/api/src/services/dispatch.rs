@@ -0,0 +1,683 @@
+//! Ranking technicians for a job.
+//!
+//! A technician missing one of the job's required skills (from their skills or
+//! certifications) or license types (an active license that hasn't expired by the job's
+//! date) can't take it, and neither can one who is off that day. Everyone else is scored
+//! out of 100:
+//!
+//! - proximity: straight-line distance to the property from where they'll be coming from,
+//!   their previous job that day, else their latest GPS fix, else their last known location
+//! - load: how much of their working time that day is still unbooked
+//! - preferred: the customer's preferred technician
+//! - trade: their trade or skills include the job's trade
+//!
+//! Emergencies weigh proximity over load. Conflicts with the job's slot (overlaps, travel
+//! buffers, outside shift hours) cost points rather than ruling anyone out, since dispatch
+//! can still move the other job.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::db::repository::JOB_COLUMNS;
+use crate::errors::{ApiError, ApiResult};
+use crate::models::job::Job;
+use crate::services::availability::{Calendar, Day};
+use crate::services::gps_store;
+use crate::services::routing::{Coordinate, StraightLine};
+use crate::services::schedule::{self, Conflict, Placement, ScheduledJob, Slot, TeamHours, Technician};
+use crate::AppState;
+
+/// Beyond this a technician gets no proximity points
+const MAX_DISTANCE_KM: f64 = 60.0;
+const CONFLICT_PENALTY: i32 = 15;
+/// Booked time assumed for a job with only a date
+const UNTIMED_JOB_MINUTES: i64 = 60;
+/// Conflicts that mean the technician isn't working when the job is
+const OFF_DUTY_CONFLICTS: &[&str] = &["holiday", "time_off", "non_working_day"];
+
+struct Weights {
+    proximity: f64,
+    load: f64,
+    preferred: f64,
+    trade: f64,
+}
+
+const NORMAL: Weights = Weights { proximity: 40.0, load: 30.0, preferred: 20.0, trade: 10.0 };
+const EMERGENCY: Weights = Weights { proximity: 60.0, load: 10.0, preferred: 20.0, trade: 10.0 };
+
+/// One part of a technician's score
+#[derive(Debug, Clone, Serialize)]
+pub struct Factor {
+    /// `proximity`, `load`, `preferred`, `trade` or `conflicts`
+    pub factor: &'static str,
+    pub points: i32,
+    /// Most this factor can add; 0 for penalties
+    pub max: i32,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    #[serde(flatten)]
+    pub technician: Technician,
+    pub score: i32,
+    pub distance_km: Option<f64>,
+    pub factors: Vec<Factor>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ineligible {
+    #[serde(flatten)]
+    pub technician: Technician,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suggestions {
+    pub job_id: Uuid,
+    pub date: Option<NaiveDate>,
+    pub slot: Option<Slot>,
+    pub emergency: bool,
+    /// Best first
+    pub suggestions: Vec<Suggestion>,
+    pub ineligible: Vec<Ineligible>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Profile {
+    id: Uuid,
+    trade: Option<String>,
+    skills: Option<Vec<String>>,
+    certifications: Option<Vec<String>>,
+    last_location_lat: Option<f64>,
+    last_location_lng: Option<f64>,
+}
+
+impl Profile {
+    fn has_skill(&self, skill: &str) -> bool {
+        self.skills.iter().chain(self.certifications.iter()).flatten().any(|have| have.eq_ignore_ascii_case(skill))
+    }
+
+    fn last_location(&self) -> Option<Coordinate> {
+        Some(Coordinate { latitude: self.last_location_lat?, longitude: self.last_location_lng? })
+    }
+}
+
+/// Technicians' skills, certifications and active licenses, for checking whether they can
+/// take a job
+pub struct Qualifications {
+    profiles: HashMap<Uuid, Profile>,
+    /// `(user_id, license_type)` with the type lowercased
+    licenses: HashSet<(Uuid, String)>,
+}
+
+impl Qualifications {
+    /// Licenses count if they haven't expired by `on`
+    pub async fn load(conn: &mut PgConnection, team_id: Uuid, users: &[Uuid], on: NaiveDate) -> ApiResult<Qualifications> {
+        let profiles = sqlx::query_as::<_, Profile>(
+            "SELECT id, trade, skills, certifications, last_location_lat, last_location_lng FROM users WHERE id = ANY($1)",
+        )
+        .bind(users)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|profile| (profile.id, profile))
+        .collect();
+
+        let licenses = sqlx::query_as::<_, (Uuid, String)>(
+            r#"
+            SELECT user_id, license_type FROM licenses
+            WHERE team_id = $1 AND user_id = ANY($2) AND status = 'active'
+              AND (expiry_date IS NULL OR expiry_date >= $3)
+            "#,
+        )
+        .bind(team_id)
+        .bind(users)
+        .bind(on)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|(user_id, license_type)| (user_id, license_type.to_lowercase()))
+        .collect();
+
+        Ok(Qualifications { profiles, licenses })
+    }
+
+    fn profile(&self, user_id: Uuid) -> Option<&Profile> {
+        self.profiles.get(&user_id)
+    }
+
+    /// Why a technician can't take a job with these requirements; empty if they can
+    pub fn missing(&self, user_id: Uuid, skills: &[String], license_types: &[String]) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let missing_skills: Vec<&str> = skills
+            .iter()
+            .filter(|skill| !self.profile(user_id).is_some_and(|profile| profile.has_skill(skill)))
+            .map(String::as_str)
+            .collect();
+        if !missing_skills.is_empty() {
+            reasons.push(format!("Missing skills: {}", missing_skills.join(", ")));
+        }
+        let missing_licenses: Vec<&str> = license_types
+            .iter()
+            .filter(|license_type| !self.licenses.contains(&(user_id, license_type.to_lowercase())))
+            .map(String::as_str)
+            .collect();
+        if !missing_licenses.is_empty() {
+            reasons.push(format!("No active license: {}", missing_licenses.join(", ")));
+        }
+        reasons
+    }
+}
+
+/// Where a technician will be setting off from, and how we know
+struct Origin {
+    location: Coordinate,
+    source: String,
+}
+
+/// Rank the team's technicians for a job, at its scheduled date and time or at `date` and
+/// `start` if given
+pub async fn suggest(
+    state: &AppState,
+    team_id: Uuid,
+    job_id: Uuid,
+    date: Option<NaiveDate>,
+    start: Option<NaiveTime>,
+) -> ApiResult<Suggestions> {
+    let mut conn = state.db.acquire().await?;
+
+    let job = sqlx::query_as::<_, Job>(&format!(
+        "SELECT {} FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL",
+        JOB_COLUMNS
+    ))
+    .bind(job_id)
+    .bind(team_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Job".into()))?;
+
+    let preferred = sqlx::query_scalar::<_, Option<Uuid>>("SELECT preferred_technician_id FROM customers WHERE id = $1")
+        .bind(job.customer_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let destination = match job.property_id {
+        Some(property_id) => schedule::property_location(&mut conn, team_id, property_id).await?,
+        None => None,
+    };
+
+    let date = date.or(job.scheduled_date);
+    // A new start drops the old end, so the job keeps its estimate rather than its old length
+    let slot = match start {
+        Some(start) => Slot::of(Some(start), None, None, job.estimated_duration_minutes),
+        None => Slot::of(
+            job.scheduled_start_time,
+            job.scheduled_end_time,
+            job.arrival_window_start,
+            job.estimated_duration_minutes,
+        ),
+    };
+
+    let also: Vec<Uuid> = job.assigned_to.into_iter().collect();
+    let technicians = schedule::technicians(&mut conn, team_id, &also, None).await?;
+    let users: Vec<Uuid> = technicians.iter().map(|technician| technician.user_id).collect();
+
+    let hours = schedule::team_hours(&mut conn, team_id).await?;
+    let tz: Tz = hours.timezone.parse().unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let on = date.unwrap_or(today);
+
+    let qualifications = Qualifications::load(&mut conn, team_id, &users, on).await?;
+    let calendar = Calendar::load(&mut conn, team_id, &users, on, on).await?;
+    let day_jobs = match date {
+        Some(date) => schedule::jobs_between(&mut conn, team_id, date, date, None).await?,
+        None => Vec::new(),
+    };
+    drop(conn);
+
+    // Live positions only mean something for work happening now
+    let fixes: HashMap<Uuid, Coordinate> = if on == today {
+        let max_age = Duration::seconds(state.config.tracking.location_max_age_secs);
+        gps_store::latest_positions(state, team_id, max_age)
+            .await?
+            .into_iter()
+            .map(|fix| (fix.user_id, Coordinate { latitude: fix.latitude, longitude: fix.longitude }))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let emergency = job.priority == "emergency";
+    let travel = StraightLine::new(&state.config.tracking);
+    let request = Request {
+        job_id: job.id,
+        required_skills: &job.required_skills,
+        required_license_types: &job.required_license_types,
+        trade: job.trade.as_deref(),
+        date,
+        slot,
+        destination,
+        preferred,
+        weights: if emergency { &EMERGENCY } else { &NORMAL },
+        hours: &hours,
+        day_jobs: &day_jobs,
+        fixes: &fixes,
+        travel: &travel,
+    };
+    let technicians = technicians
+        .into_iter()
+        .map(|technician| {
+            let day = calendar.day(Some(technician.user_id), on);
+            (technician, day)
+        })
+        .collect();
+    let (suggestions, ineligible) = rank(&request, technicians, &qualifications);
+
+    Ok(Suggestions { job_id: job.id, date, slot, emergency, suggestions, ineligible })
+}
+
+/// The job being placed and what's already known about its day
+struct Request<'a> {
+    job_id: Uuid,
+    required_skills: &'a [String],
+    required_license_types: &'a [String],
+    trade: Option<&'a str>,
+    /// `None` while the job isn't scheduled
+    date: Option<NaiveDate>,
+    slot: Option<Slot>,
+    destination: Option<Coordinate>,
+    preferred: Option<Uuid>,
+    weights: &'a Weights,
+    hours: &'a TeamHours,
+    /// Every job on `date`, whoever it's assigned to
+    day_jobs: &'a [ScheduledJob],
+    /// Live positions, for work happening today
+    fixes: &'a HashMap<Uuid, Coordinate>,
+    travel: &'a StraightLine,
+}
+
+/// Score each technician on their day, best first, and set aside those who can't take the job
+fn rank(
+    request: &Request,
+    technicians: Vec<(Technician, Day)>,
+    qualifications: &Qualifications,
+) -> (Vec<Suggestion>, Vec<Ineligible>) {
+    let weights = request.weights;
+    let placement =
+        request.date.map(|date| Placement { job_id: Some(request.job_id), date, slot: request.slot, location: request.destination });
+
+    let mut suggestions = Vec::new();
+    let mut ineligible = Vec::new();
+    for (technician, day) in technicians {
+        let user_id = technician.user_id;
+        let Some(profile) = qualifications.profile(user_id) else {
+            continue;
+        };
+
+        let mut reasons = qualifications.missing(user_id, request.required_skills, request.required_license_types);
+
+        let own_jobs: Vec<_> = request
+            .day_jobs
+            .iter()
+            .filter(|other| other.assigned_to == Some(user_id) && other.id != request.job_id)
+            .cloned()
+            .collect();
+        let conflicts = match &placement {
+            Some(placement) => schedule::check(placement, request.hours, &day, &own_jobs, request.travel),
+            None => Vec::new(),
+        };
+        for conflict in conflicts.iter().filter(|conflict| OFF_DUTY_CONFLICTS.contains(&conflict.kind)) {
+            reasons.push(conflict.message.clone());
+        }
+
+        if !reasons.is_empty() {
+            ineligible.push(Ineligible { technician, reasons });
+            continue;
+        }
+
+        let mut factors = Vec::new();
+
+        // Proximity
+        let origin = request
+            .slot
+            .and_then(|slot| {
+                own_jobs
+                    .iter()
+                    .filter_map(|other| Some((other.slot()?, other)))
+                    .filter(|(other_slot, _)| other_slot.end <= slot.start)
+                    .max_by_key(|(other_slot, _)| other_slot.end)
+                    .and_then(|(_, other)| {
+                        let location = Coordinate { latitude: other.latitude?, longitude: other.longitude? };
+                        Some(Origin { location, source: format!("previous job \"{}\"", other.title) })
+                    })
+            })
+            .or_else(|| request.fixes.get(&user_id).map(|&location| Origin { location, source: "current GPS position".into() }))
+            .or_else(|| profile.last_location().map(|location| Origin { location, source: "last known location".into() }));
+        let distance_km = match (origin.as_ref(), request.destination) {
+            (Some(origin), Some(destination)) => {
+                Some(request.travel.estimate_now(origin.location, destination).distance_meters / 1000.0)
+            }
+            _ => None,
+        };
+        let (points, detail) = match (distance_km, origin) {
+            (Some(km), Some(origin)) => {
+                let closeness = 1.0 - km.min(MAX_DISTANCE_KM) / MAX_DISTANCE_KM;
+                (weights.proximity * closeness, format!("{:.1} km from {}", km, origin.source))
+            }
+            _ if request.destination.is_none() => (0.0, "The job's property isn't geocoded".to_string()),
+            _ => (0.0, "No known location".to_string()),
+        };
+        factors.push(factor("proximity", points, weights.proximity, detail));
+
+        // Load
+        let working: i64 = day.working().iter().map(|interval| interval.minutes()).sum();
+        let booked: i64 = own_jobs
+            .iter()
+            .map(|other| other.slot().map_or(UNTIMED_JOB_MINUTES, |slot| (slot.end - slot.start).num_minutes()))
+            .sum();
+        let (points, detail) = if request.date.is_none() {
+            (weights.load / 2.0, "The job isn't scheduled yet".to_string())
+        } else if working == 0 {
+            (0.0, "No working time that day".to_string())
+        } else {
+            let free = 1.0 - (booked as f64 / working as f64).min(1.0);
+            (
+                weights.load * free,
+                format!("{} job{}, {} of {} min booked", own_jobs.len(), if own_jobs.len() == 1 { "" } else { "s" }, booked, working),
+            )
+        };
+        factors.push(factor("load", points, weights.load, detail));
+
+        // Customer preference
+        let is_preferred = request.preferred == Some(user_id);
+        factors.push(factor(
+            "preferred",
+            if is_preferred { weights.preferred } else { 0.0 },
+            weights.preferred,
+            if is_preferred { "The customer's preferred technician" } else { "Not the customer's preferred technician" }.into(),
+        ));
+
+        // Trade
+        let trade_match = request.trade.map(|trade| {
+            profile.trade.as_deref().is_some_and(|have| have.eq_ignore_ascii_case(trade)) || profile.has_skill(trade)
+        });
+        let (points, detail) = match (trade_match, request.trade) {
+            (Some(true), Some(trade)) => (weights.trade, format!("Works in {}", trade)),
+            (Some(false), Some(trade)) => (0.0, format!("Doesn't list {}", trade)),
+            _ => (weights.trade, "The job has no trade".to_string()),
+        };
+        factors.push(factor("trade", points, weights.trade, detail));
+
+        let penalised: Vec<&Conflict> = conflicts.iter().filter(|conflict| !OFF_DUTY_CONFLICTS.contains(&conflict.kind)).collect();
+        if !penalised.is_empty() {
+            let messages: Vec<&str> = penalised.iter().map(|conflict| conflict.message.as_str()).collect();
+            factors.push(Factor {
+                factor: "conflicts",
+                points: -CONFLICT_PENALTY * penalised.len() as i32,
+                max: 0,
+                detail: messages.join("; "),
+            });
+        }
+
+        let score = factors.iter().map(|factor| factor.points).sum::<i32>().max(0);
+        suggestions.push(Suggestion { technician, score, distance_km, factors, conflicts });
+    }
+
+    suggestions.sort_by(|a, b| {
+        b.score.cmp(&a.score).then_with(|| {
+            a.distance_km.unwrap_or(f64::MAX).total_cmp(&b.distance_km.unwrap_or(f64::MAX))
+        })
+    });
+
+    (suggestions, ineligible)
+}
+
+fn factor(name: &'static str, points: f64, max: f64, detail: String) -> Factor {
+    Factor { factor: name, points: points.round() as i32, max: max.round() as i32, detail }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::config::TrackingSettings;
+    use crate::services::availability::{Interval, TimeOff};
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    // A Wednesday
+    const DATE: (i32, u32, u32) = (2030, 6, 5);
+    const SITE: Coordinate = Coordinate { latitude: 39.7817, longitude: -89.6501 };
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(DATE.0, DATE.1, DATE.2).unwrap()
+    }
+
+    fn time(hour: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    /// About `km` kilometres north of the site
+    fn north_of_site(km: f64) -> Coordinate {
+        Coordinate { latitude: SITE.latitude + km / 111.2, longitude: SITE.longitude }
+    }
+
+    fn hours() -> TeamHours {
+        TeamHours {
+            timezone: "UTC".into(),
+            working_hours_start: Some(time(8)),
+            working_hours_end: Some(time(17)),
+            working_days: None,
+            schedule_travel_buffer_minutes: 0,
+        }
+    }
+
+    fn travel() -> StraightLine {
+        StraightLine::new(&TrackingSettings {
+            routing_provider: "straight_line".into(),
+            google_maps_api_key: String::new(),
+            average_speed_kmh: 40.0,
+            link_expiry_hours: 4,
+            location_max_age_secs: 300,
+        })
+    }
+
+    fn working_day() -> Day {
+        Day { date: date(), holiday: None, shifts: vec![Interval::on(date(), time(8), time(17))], time_off: Vec::new() }
+    }
+
+    struct Tech {
+        technician: Technician,
+        profile: Profile,
+        licenses: Vec<&'static str>,
+        day: Day,
+    }
+
+    fn tech(name: &str, location: Coordinate) -> Tech {
+        let id = Uuid::new_v4();
+        Tech {
+            technician: Technician {
+                user_id: id,
+                first_name: name.into(),
+                last_name: "Ortiz".into(),
+                avatar_url: None,
+                role: "technician".into(),
+            },
+            profile: Profile {
+                id,
+                trade: Some("plumbing".into()),
+                skills: None,
+                certifications: None,
+                last_location_lat: Some(location.latitude),
+                last_location_lng: Some(location.longitude),
+            },
+            licenses: Vec::new(),
+            day: working_day(),
+        }
+    }
+
+    fn booked(tech: &Tech, start: u32, end: u32) -> ScheduledJob {
+        ScheduledJob {
+            id: Uuid::new_v4(),
+            title: "Service call".into(),
+            status: "scheduled".into(),
+            priority: "normal".into(),
+            assigned_to: Some(tech.technician.user_id),
+            scheduled_date: date(),
+            scheduled_start_time: Some(time(start)),
+            scheduled_end_time: Some(time(end)),
+            arrival_window_start: None,
+            arrival_window_end: None,
+            estimated_duration_minutes: None,
+            customer_id: Uuid::new_v4(),
+            customer_name: "Dana Reyes".into(),
+            property_id: None,
+            property_address: None,
+            latitude: Some(SITE.latitude),
+            longitude: Some(SITE.longitude),
+            required_skills: Vec::new(),
+            required_license_types: Vec::new(),
+            version: 1,
+        }
+    }
+
+    /// Rank `techs` for a 9-10 job at the site, returning first names best first and
+    /// the ineligible with their reasons
+    fn rank_for(
+        techs: Vec<Tech>,
+        day_jobs: &[ScheduledJob],
+        skills: &[String],
+        licenses: &[String],
+    ) -> (Vec<String>, Vec<(String, Vec<String>)>) {
+        let hours = hours();
+        let travel = travel();
+        let fixes = HashMap::new();
+        let request = Request {
+            job_id: Uuid::new_v4(),
+            required_skills: skills,
+            required_license_types: licenses,
+            trade: Some("plumbing"),
+            date: Some(date()),
+            slot: Some(Slot { start: time(9), end: time(10) }),
+            destination: Some(SITE),
+            preferred: None,
+            weights: &NORMAL,
+            hours: &hours,
+            day_jobs,
+            fixes: &fixes,
+            travel: &travel,
+        };
+        let mut qualifications = Qualifications { profiles: HashMap::new(), licenses: HashSet::new() };
+        let mut technicians = Vec::new();
+        for tech in techs {
+            let user_id = tech.technician.user_id;
+            qualifications.licenses.extend(tech.licenses.iter().map(|license| (user_id, license.to_string())));
+            qualifications.profiles.insert(user_id, tech.profile);
+            technicians.push((tech.technician, tech.day));
+        }
+
+        let (suggestions, ineligible) = rank(&request, technicians, &qualifications);
+        (
+            suggestions.into_iter().map(|suggestion| suggestion.technician.first_name).collect(),
+            ineligible.into_iter().map(|ineligible| (ineligible.technician.first_name, ineligible.reasons)).collect(),
+        )
+    }
+
+    #[test]
+    fn nearer_technicians_rank_higher() {
+        let far = tech("Far", north_of_site(30.0));
+        let near = tech("Near", north_of_site(2.0));
+        let out_of_range = tech("Remote", north_of_site(90.0));
+
+        let (ranked, ineligible) = rank_for(vec![far, out_of_range, near], &[], &[], &[]);
+        assert_eq!(ranked, ["Near", "Far", "Remote"]);
+        assert!(ineligible.is_empty());
+    }
+
+    #[test]
+    fn missing_skills_and_licenses_rule_a_technician_out() {
+        let mut qualified = tech("Qualified", north_of_site(20.0));
+        qualified.profile.certifications = Some(vec!["Backflow Testing".into()]);
+        qualified.licenses = vec!["master plumber"];
+        let mut unlicensed = tech("Unlicensed", north_of_site(1.0));
+        unlicensed.profile.skills = Some(vec!["backflow testing".into()]);
+        let unskilled = tech("Unskilled", north_of_site(1.0));
+
+        let (ranked, ineligible) =
+            rank_for(vec![unskilled, unlicensed, qualified], &[], &["backflow testing".into()], &["Master Plumber".into()]);
+        assert_eq!(ranked, ["Qualified"]);
+        assert_eq!(
+            ineligible,
+            [
+                (
+                    "Unskilled".to_string(),
+                    vec!["Missing skills: backflow testing".to_string(), "No active license: Master Plumber".to_string()]
+                ),
+                ("Unlicensed".to_string(), vec!["No active license: Master Plumber".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn less_booked_technicians_rank_higher() {
+        let busy = tech("Busy", north_of_site(5.0));
+        let free = tech("Free", north_of_site(5.0));
+        // After the job, so it doesn't change where either of them sets off from
+        let afternoon = booked(&busy, 12, 17);
+
+        let (ranked, _) = rank_for(vec![busy, free], &[afternoon], &[], &[]);
+        assert_eq!(ranked, ["Free", "Busy"]);
+    }
+
+    #[test]
+    fn time_off_rules_out_and_conflicts_rank_lower() {
+        let mut away = tech("Away", north_of_site(1.0));
+        away.day.time_off.push(TimeOff {
+            id: Uuid::new_v4(),
+            kind: "vacation".into(),
+            status: "approved".into(),
+            interval: Interval::on(date(), time(8), time(17)),
+        });
+        let mut holiday = tech("Holiday", north_of_site(1.0));
+        holiday.day.holiday = Some("Founders' Day".into());
+        let double_booked = tech("Double", north_of_site(1.0));
+        let overlap = booked(&double_booked, 9, 11);
+        let open = tech("Open", north_of_site(3.0));
+
+        let (ranked, ineligible) = rank_for(vec![away, holiday, double_booked, open], &[overlap], &[], &[]);
+        // Closer, but the overlap costs more than the distance
+        assert_eq!(ranked, ["Open", "Double"]);
+        let ruled_out: Vec<&str> = ineligible.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(ruled_out, ["Away", "Holiday"]);
+        assert_eq!(ineligible[0].1, ["Overlaps approved vacation time off"]);
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn suggests_technicians_for_a_stored_job(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let team_id = test_support::team(&db, "acme").await;
+        let alex = test_support::member(&db, team_id, "alex@example.com", "technician").await;
+        let customer_id = test_support::customer(&db, team_id).await;
+        let job_id = test_support::job(&db, team_id, customer_id, "scheduled", None).await;
+        sqlx::query("UPDATE jobs SET priority = 'emergency', scheduled_date = $2 WHERE id = $1")
+            .bind(job_id)
+            .bind(date())
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let suggestions = suggest(&state, team_id, job_id, None, Some(time(9))).await.unwrap();
+        assert!(suggestions.emergency);
+        assert_eq!(suggestions.date, Some(date()));
+        let ranked: Vec<Uuid> = suggestions.suggestions.iter().map(|suggestion| suggestion.technician.user_id).collect();
+        assert_eq!(ranked, [alex]);
+    }
+}
@@ -0,0 +1,945 @@
+//! Printable invoices and estimates. Data is loaded from the database, laid out with
+//! [`crate::services::pdf`], stored in object storage and linked from `pdf_url`.
+
+use std::time::Duration;
+
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::services::outbound;
+use crate::services::pdf::{self, Font, Image, PdfDocument, LETTER_HEIGHT, LETTER_WIDTH};
+use crate::AppState;
+
+const MARGIN: f32 = 48.0;
+const RIGHT: f32 = LETTER_WIDTH - MARGIN;
+const CONTENT_WIDTH: f32 = RIGHT - MARGIN;
+const FOOTER_HEIGHT: f32 = 24.0;
+
+const LOGO_MAX_WIDTH: f32 = 150.0;
+const LOGO_MAX_HEIGHT: f32 = 60.0;
+const SIGNATURE_MAX_WIDTH: f32 = 200.0;
+const SIGNATURE_MAX_HEIGHT: f32 = 56.0;
+/// Logos and signatures larger than this are skipped rather than embedded
+const MAX_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+const IMAGE_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Right edges of the numeric table columns; the description fills the space before them
+const QTY_RIGHT: f32 = MARGIN + 350.0;
+const UNIT_PRICE_RIGHT: f32 = MARGIN + 440.0;
+const DESCRIPTION_WIDTH: f32 = 290.0;
+
+/// Order categories appear in on the document, matching `line_item_category`
+const CATEGORY_ORDER: [&str; 7] = ["labor", "materials", "equipment", "permits", "disposal", "overhead", "other"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    Invoice,
+    Estimate,
+}
+
+impl DocumentKind {
+    fn heading(self) -> &'static str {
+        match self {
+            DocumentKind::Invoice => "INVOICE",
+            DocumentKind::Estimate => "ESTIMATE",
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            DocumentKind::Invoice => "invoices",
+            DocumentKind::Estimate => "estimates",
+        }
+    }
+
+    fn slug(self) -> &'static str {
+        match self {
+            DocumentKind::Invoice => "invoice",
+            DocumentKind::Estimate => "estimate",
+        }
+    }
+
+    /// Foreign key on `line_items` and `signatures`
+    fn parent_column(self) -> &'static str {
+        match self {
+            DocumentKind::Invoice => "invoice_id",
+            DocumentKind::Estimate => "estimate_id",
+        }
+    }
+}
+
+pub struct RenderedPdf {
+    pub team_id: Uuid,
+    pub filename: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(sqlx::FromRow)]
+struct Branding {
+    name: String,
+    logo_url: Option<String>,
+    phone: Option<String>,
+    email: Option<String>,
+    website: Option<String>,
+    address_line1: Option<String>,
+    address_line2: Option<String>,
+    city: Option<String>,
+    state: Option<String>,
+    zip_code: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct Customer {
+    first_name: String,
+    last_name: String,
+    company_name: Option<String>,
+    email: Option<String>,
+    phone: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ServiceAddress {
+    address_line1: String,
+    address_line2: Option<String>,
+    city: String,
+    state: String,
+    zip_code: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct LineItem {
+    description: String,
+    category: String,
+    quantity: Decimal,
+    unit: String,
+    unit_price: Decimal,
+    total: Decimal,
+}
+
+#[derive(sqlx::FromRow)]
+struct SignatureRow {
+    signer_name: String,
+    signature_data: String,
+    signature_url: Option<String>,
+    signed_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct InvoiceRow {
+    team_id: Uuid,
+    customer_id: Uuid,
+    property_id: Option<Uuid>,
+    invoice_number: String,
+    status: String,
+    issued_at: DateTime<Utc>,
+    due_date: Option<NaiveDate>,
+    po_number: Option<String>,
+    subtotal: Decimal,
+    discount_amount: Decimal,
+    tax_amount: Decimal,
+    tax_rate: Option<Decimal>,
+    total: Decimal,
+    amount_paid: Decimal,
+    amount_due: Decimal,
+    notes: Option<String>,
+    payment_terms: Option<String>,
+    terms_and_conditions: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct EstimateRow {
+    team_id: Uuid,
+    customer_id: Uuid,
+    property_id: Option<Uuid>,
+    estimate_number: String,
+    version: i32,
+    status: String,
+    issued_at: DateTime<Utc>,
+    valid_until: Option<NaiveDate>,
+    title: Option<String>,
+    scope_of_work: Option<String>,
+    subtotal: Decimal,
+    discount_amount: Decimal,
+    discount_pct: Option<Decimal>,
+    tax_amount: Decimal,
+    tax_rate: Option<Decimal>,
+    total: Decimal,
+    deposit_required_pct: Option<Decimal>,
+    deposit_amount: Option<Decimal>,
+    payment_terms: Option<String>,
+    warranty_terms: Option<String>,
+    terms_and_conditions: Option<String>,
+    customer_signature: Option<String>,
+    signed_at: Option<DateTime<Utc>>,
+}
+
+/// A captured signature: an image (data URL or link) or a typed name
+struct Signature {
+    signer_name: Option<String>,
+    signed_at: Option<DateTime<Utc>>,
+    source: String,
+}
+
+struct TotalRow {
+    label: String,
+    amount: Decimal,
+    emphasis: bool,
+}
+
+/// Everything drawn on the page, whichever kind of document it is
+struct Document {
+    kind: DocumentKind,
+    team_id: Uuid,
+    number: String,
+    status: String,
+    dates: Vec<(&'static str, String)>,
+    team: Branding,
+    customer: Customer,
+    property: Option<ServiceAddress>,
+    title: Option<String>,
+    scope_of_work: Option<String>,
+    line_items: Vec<LineItem>,
+    totals: Vec<TotalRow>,
+    sections: Vec<(&'static str, String)>,
+    signature: Option<Signature>,
+}
+
+/// Render an invoice or estimate. `team_id` limits the lookup to one team; the queue
+/// worker passes `None`.
+pub async fn render(state: &AppState, kind: DocumentKind, team_id: Option<Uuid>, id: Uuid) -> ApiResult<RenderedPdf> {
+    let document = match kind {
+        DocumentKind::Invoice => load_invoice(&state.db, team_id, id).await?,
+        DocumentKind::Estimate => load_estimate(&state.db, team_id, id).await?,
+    };
+
+    let logo = match document.team.logo_url.as_deref() {
+        Some(source) => load_image(source).await,
+        None => None,
+    };
+    let signature_image = match &document.signature {
+        Some(signature) => load_image(&signature.source).await,
+        None => None,
+    };
+
+    let bytes = layout(&document, logo, signature_image).map_err(ApiError::Internal)?;
+    let number: String = document
+        .number
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+
+    Ok(RenderedPdf {
+        team_id: document.team_id,
+        filename: format!("{}-{}.pdf", kind.slug(), number),
+        bytes,
+    })
+}
+
+/// Upload a rendered PDF and point the document's `pdf_url` at it
+pub async fn store(state: &AppState, kind: DocumentKind, id: Uuid, rendered: &RenderedPdf) -> anyhow::Result<String> {
+    let key = format!("{}/{}/{}.pdf", rendered.team_id, kind.table(), id);
+    state.storage.put(&key, "application/pdf", rendered.bytes.clone()).await?;
+
+    let url = state.storage.url(&key);
+    sqlx::query(&format!("UPDATE {} SET pdf_url = $2 WHERE id = $1", kind.table()))
+        .bind(id)
+        .bind(&url)
+        .execute(&state.db)
+        .await?;
+
+    tracing::info!(document = kind.slug(), id = %id, url = %url, "Stored PDF");
+    Ok(url)
+}
+
+/// Render and store; used by the queue worker
+pub async fn generate(state: &AppState, kind: DocumentKind, id: Uuid) -> anyhow::Result<String> {
+    let rendered = render(state, kind, None, id).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    store(state, kind, id, &rendered).await
+}
+
+async fn load_invoice(db: &PgPool, team_id: Option<Uuid>, id: Uuid) -> ApiResult<Document> {
+    let invoice = sqlx::query_as::<_, InvoiceRow>(
+        r#"
+        SELECT team_id, customer_id, property_id, invoice_number, status::text AS status,
+               COALESCE(sent_at, created_at) AS issued_at, due_date, po_number,
+               subtotal, discount_amount, tax_amount, tax_rate, total, amount_paid, amount_due,
+               notes, payment_terms, terms_and_conditions
+        FROM invoices
+        WHERE id = $1 AND ($2::uuid IS NULL OR team_id = $2) AND deleted_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Invoice".into()))?;
+
+    let mut dates = vec![("Issued", format_date(invoice.issued_at.date_naive()))];
+    if let Some(due) = invoice.due_date {
+        dates.push(("Due", format_date(due)));
+    }
+    if let Some(po) = &invoice.po_number {
+        dates.push(("PO", po.clone()));
+    }
+
+    let mut totals = common_totals(invoice.subtotal, invoice.discount_amount, None, invoice.tax_amount, invoice.tax_rate, invoice.total);
+    if invoice.amount_paid > Decimal::ZERO {
+        totals.push(TotalRow { label: "Amount paid".into(), amount: -invoice.amount_paid, emphasis: false });
+    }
+    if invoice.status != "void" {
+        totals.push(TotalRow { label: "Amount due".into(), amount: invoice.amount_due, emphasis: true });
+    }
+
+    let sections = [
+        ("Notes", invoice.notes),
+        ("Payment terms", invoice.payment_terms),
+        ("Terms and conditions", invoice.terms_and_conditions),
+    ]
+    .into_iter()
+    .filter_map(|(heading, body)| body.filter(|b| !b.trim().is_empty()).map(|b| (heading, b)))
+    .collect();
+
+    Ok(Document {
+        kind: DocumentKind::Invoice,
+        team_id: invoice.team_id,
+        number: invoice.invoice_number,
+        status: invoice.status,
+        dates,
+        team: load_branding(db, invoice.team_id).await?,
+        customer: load_customer(db, invoice.customer_id).await?,
+        property: load_property(db, invoice.property_id).await?,
+        title: None,
+        scope_of_work: None,
+        line_items: load_line_items(db, DocumentKind::Invoice, id).await?,
+        totals,
+        sections,
+        signature: load_signature(db, DocumentKind::Invoice, id).await?,
+    })
+}
+
+async fn load_estimate(db: &PgPool, team_id: Option<Uuid>, id: Uuid) -> ApiResult<Document> {
+    let estimate = sqlx::query_as::<_, EstimateRow>(
+        r#"
+        SELECT team_id, customer_id, property_id, estimate_number, version, status::text AS status,
+               COALESCE(sent_at, created_at) AS issued_at, valid_until, title, scope_of_work,
+               subtotal, discount_amount, discount_pct, tax_amount, tax_rate, total,
+               deposit_required_pct, deposit_amount, payment_terms, warranty_terms,
+               terms_and_conditions, customer_signature, signed_at
+        FROM estimates
+        WHERE id = $1 AND ($2::uuid IS NULL OR team_id = $2) AND deleted_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Estimate".into()))?;
+
+    let mut dates = vec![("Issued", format_date(estimate.issued_at.date_naive()))];
+    if let Some(valid_until) = estimate.valid_until {
+        dates.push(("Valid until", format_date(valid_until)));
+    }
+
+    let mut totals = common_totals(
+        estimate.subtotal,
+        estimate.discount_amount,
+        estimate.discount_pct,
+        estimate.tax_amount,
+        estimate.tax_rate,
+        estimate.total,
+    );
+    let deposit = estimate.deposit_amount.or_else(|| {
+        estimate
+            .deposit_required_pct
+            .map(|pct| (estimate.total * pct / Decimal::ONE_HUNDRED).round_dp(2))
+    });
+    if let Some(deposit) = deposit.filter(|d| *d > Decimal::ZERO) {
+        let label = match estimate.deposit_required_pct {
+            Some(pct) => format!("Deposit required ({}%)", pct.normalize()),
+            None => "Deposit required".into(),
+        };
+        totals.push(TotalRow { label, amount: deposit, emphasis: true });
+    }
+
+    let sections = [
+        ("Payment terms", estimate.payment_terms),
+        ("Warranty", estimate.warranty_terms),
+        ("Terms and conditions", estimate.terms_and_conditions),
+    ]
+    .into_iter()
+    .filter_map(|(heading, body)| body.filter(|b| !b.trim().is_empty()).map(|b| (heading, b)))
+    .collect();
+
+    // Prefer the signatures table, which records who signed; fall back to the estimate's own copy
+    let signature = match load_signature(db, DocumentKind::Estimate, id).await? {
+        Some(signature) => Some(signature),
+        None => estimate.customer_signature.filter(|s| !s.is_empty()).map(|source| Signature {
+            signer_name: None,
+            signed_at: estimate.signed_at,
+            source,
+        }),
+    };
+
+    let number = if estimate.version > 1 {
+        format!("{} v{}", estimate.estimate_number, estimate.version)
+    } else {
+        estimate.estimate_number
+    };
+
+    Ok(Document {
+        kind: DocumentKind::Estimate,
+        team_id: estimate.team_id,
+        number,
+        status: estimate.status,
+        dates,
+        team: load_branding(db, estimate.team_id).await?,
+        customer: load_customer(db, estimate.customer_id).await?,
+        property: load_property(db, estimate.property_id).await?,
+        title: estimate.title,
+        scope_of_work: estimate.scope_of_work,
+        line_items: load_line_items(db, DocumentKind::Estimate, id).await?,
+        totals,
+        sections,
+        signature,
+    })
+}
+
+fn common_totals(
+    subtotal: Decimal,
+    discount: Decimal,
+    discount_pct: Option<Decimal>,
+    tax: Decimal,
+    tax_rate: Option<Decimal>,
+    total: Decimal,
+) -> Vec<TotalRow> {
+    let mut rows = vec![TotalRow { label: "Subtotal".into(), amount: subtotal, emphasis: false }];
+    if discount > Decimal::ZERO {
+        let label = match discount_pct {
+            Some(pct) => format!("Discount ({}%)", pct.normalize()),
+            None => "Discount".into(),
+        };
+        rows.push(TotalRow { label, amount: -discount, emphasis: false });
+    }
+    if tax > Decimal::ZERO {
+        let label = match tax_rate {
+            Some(rate) => format!("Tax ({}%)", (rate * Decimal::ONE_HUNDRED).normalize()),
+            None => "Tax".into(),
+        };
+        rows.push(TotalRow { label, amount: tax, emphasis: false });
+    }
+    rows.push(TotalRow { label: "Total".into(), amount: total, emphasis: true });
+    rows
+}
+
+async fn load_branding(db: &PgPool, team_id: Uuid) -> ApiResult<Branding> {
+    let team = sqlx::query_as::<_, Branding>(
+        r#"
+        SELECT name, logo_url, phone, email, website, address_line1, address_line2, city, state, zip_code
+        FROM teams WHERE id = $1
+        "#,
+    )
+    .bind(team_id)
+    .fetch_one(db)
+    .await?;
+    Ok(team)
+}
+
+async fn load_customer(db: &PgPool, customer_id: Uuid) -> ApiResult<Customer> {
+    let customer = sqlx::query_as::<_, Customer>(
+        "SELECT first_name, last_name, company_name, email, phone FROM customers WHERE id = $1",
+    )
+    .bind(customer_id)
+    .fetch_one(db)
+    .await?;
+    Ok(customer)
+}
+
+async fn load_property(db: &PgPool, property_id: Option<Uuid>) -> ApiResult<Option<ServiceAddress>> {
+    let Some(property_id) = property_id else {
+        return Ok(None);
+    };
+    let property = sqlx::query_as::<_, ServiceAddress>(
+        "SELECT address_line1, address_line2, city, state, zip_code FROM properties WHERE id = $1",
+    )
+    .bind(property_id)
+    .fetch_optional(db)
+    .await?;
+    Ok(property)
+}
+
+async fn load_line_items(db: &PgPool, kind: DocumentKind, id: Uuid) -> ApiResult<Vec<LineItem>> {
+    let items = sqlx::query_as::<_, LineItem>(&format!(
+        r#"
+        SELECT description, category::text AS category, quantity, unit, unit_price, total
+        FROM line_items WHERE {} = $1
+        ORDER BY sort_order, created_at
+        "#,
+        kind.parent_column()
+    ))
+    .bind(id)
+    .fetch_all(db)
+    .await?;
+    Ok(items)
+}
+
+async fn load_signature(db: &PgPool, kind: DocumentKind, id: Uuid) -> ApiResult<Option<Signature>> {
+    let row = sqlx::query_as::<_, SignatureRow>(&format!(
+        r#"
+        SELECT signer_name, signature_data, signature_url, signed_at
+        FROM signatures WHERE {} = $1
+        ORDER BY signed_at DESC LIMIT 1
+        "#,
+        kind.parent_column()
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| Signature {
+        signer_name: Some(row.signer_name),
+        signed_at: Some(row.signed_at),
+        source: match row.signature_url {
+            Some(url) if !row.signature_data.starts_with("data:") => url,
+            _ => row.signature_data,
+        },
+    }))
+}
+
+/// Fetch a logo or signature from a `data:` URL or over HTTP(S). Anything that can't be
+/// loaded is left off the document rather than failing it.
+async fn load_image(source: &str) -> Option<Image> {
+    let bytes = if source.starts_with("data:") {
+        decode_data_url(source)
+    } else if source.starts_with("http://") || source.starts_with("https://") {
+        match fetch_image(source).await {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                tracing::warn!(url = %source, error = %format!("{:#}", e), "Could not fetch image for PDF");
+                None
+            }
+        }
+    } else {
+        None
+    }?;
+
+    match Image::from_bytes(&bytes) {
+        Ok(image) => Some(image),
+        Err(e) => {
+            tracing::warn!(error = %e, "Skipping image in PDF");
+            None
+        }
+    }
+}
+
+fn decode_data_url(source: &str) -> Option<Vec<u8>> {
+    let (meta, data) = source.strip_prefix("data:")?.split_once(',')?;
+    if !meta.ends_with(";base64") {
+        return None;
+    }
+    base64::engine::general_purpose::STANDARD.decode(data.trim()).ok()
+}
+
+/// Logo and signature URLs are user-supplied, so they go through the same public-address
+/// check as webhooks, and the body is read in chunks so an endless response stops at the cap
+async fn fetch_image(url: &str) -> anyhow::Result<Vec<u8>> {
+    let target = outbound::resolve_public(url, false)
+        .await
+        .map_err(|e| anyhow::anyhow!("Image URL {}", e))?;
+    let mut response = target
+        .client_builder()
+        .timeout(IMAGE_FETCH_TIMEOUT)
+        .build()?
+        .get(target.url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    if response.content_length().is_some_and(|len| len as usize > MAX_IMAGE_BYTES) {
+        anyhow::bail!("Image is larger than {} bytes", MAX_IMAGE_BYTES);
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_IMAGE_BYTES {
+            anyhow::bail!("Image is larger than {} bytes", MAX_IMAGE_BYTES);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%b %-d, %Y").to_string()
+}
+
+/// `$1,234.56`, with a leading minus for credits
+fn format_money(amount: Decimal) -> String {
+    let fixed = format!("{:.2}", amount.abs().round_dp(2));
+    let (whole, cents) = fixed.split_once('.').unwrap_or((&fixed, "00"));
+    let mut grouped = String::with_capacity(whole.len() + whole.len() / 3);
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    let sign = if amount < Decimal::ZERO { "-" } else { "" };
+    format!("{}${}.{}", sign, grouped, cents)
+}
+
+fn format_quantity(quantity: Decimal, unit: &str) -> String {
+    let quantity = quantity.normalize();
+    if unit.is_empty() || unit == "each" {
+        quantity.to_string()
+    } else {
+        format!("{} {}", quantity, unit)
+    }
+}
+
+fn capitalize(value: &str) -> String {
+    let spaced = value.replace('_', " ");
+    let mut chars = spaced.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => spaced,
+    }
+}
+
+/// Scale `image` down to fit the box, never up
+fn fit(image: &Image, max_width: f32, max_height: f32) -> (f32, f32) {
+    let (width, height) = (image.width.max(1) as f32, image.height.max(1) as f32);
+    let scale = (max_width / width).min(max_height / height).min(1.0);
+    (width * scale, height * scale)
+}
+
+/// Tracks the pen position and starts new pages as content runs past the bottom margin
+struct Writer {
+    pdf: PdfDocument,
+    page: usize,
+    /// Top of the free space on the current page
+    y: f32,
+    running_header: String,
+}
+
+impl Writer {
+    fn new(title: &str) -> Self {
+        let mut pdf = PdfDocument::new(title);
+        let page = pdf.add_page();
+        Self {
+            pdf,
+            page,
+            y: LETTER_HEIGHT - MARGIN,
+            running_header: title.to_string(),
+        }
+    }
+
+    fn page(&mut self) -> &mut pdf::Page {
+        self.pdf.page(self.page)
+    }
+
+    /// Make sure `height` points fit on the current page. Returns `true` if a new page was started.
+    fn reserve(&mut self, height: f32) -> bool {
+        if self.y - height >= MARGIN + FOOTER_HEIGHT {
+            return false;
+        }
+        self.page = self.pdf.add_page();
+        self.y = LETTER_HEIGHT - MARGIN;
+        let header = self.running_header.clone();
+        let y = self.y - 9.0;
+        self.page().text(MARGIN, y, Font::Bold, 9.0, &header);
+        self.y -= 24.0;
+        true
+    }
+
+    /// One line of text; `x` is the left edge
+    fn line(&mut self, x: f32, font: Font, size: f32, text: &str) {
+        self.reserve(size * 1.4);
+        let baseline = self.y - size;
+        self.page().text(x, baseline, font, size, text);
+        self.y -= size * 1.4;
+    }
+
+    fn paragraph(&mut self, x: f32, width: f32, font: Font, size: f32, text: &str) {
+        for line in pdf::wrap(text, font, size, width) {
+            self.line(x, font, size, &line);
+        }
+    }
+
+    fn rule(&mut self, gray: f32) {
+        let y = self.y;
+        self.page().line(MARGIN, y, RIGHT, y, 0.5, gray);
+    }
+
+    fn finish(mut self, footer: &str) -> anyhow::Result<Vec<u8>> {
+        let count = self.pdf.page_count();
+        for index in 0..count {
+            let page = self.pdf.page(index);
+            let y = MARGIN - 12.0;
+            page.text(MARGIN, y, Font::Regular, 8.0, footer);
+            page.text_right(RIGHT, y, Font::Regular, 8.0, &format!("Page {} of {}", index + 1, count));
+        }
+        self.pdf.finish()
+    }
+}
+
+fn layout(document: &Document, logo: Option<Image>, signature_image: Option<Image>) -> anyhow::Result<Vec<u8>> {
+    let title = format!("{} {}", capitalize(document.kind.slug()), document.number);
+    let mut w = Writer::new(&title);
+
+    header(&mut w, document, logo);
+    parties(&mut w, document);
+
+    if let Some(title) = document.title.as_deref().filter(|t| !t.is_empty()) {
+        w.line(MARGIN, Font::Bold, 12.0, title);
+    }
+    if let Some(scope) = document.scope_of_work.as_deref().filter(|s| !s.is_empty()) {
+        w.paragraph(MARGIN, CONTENT_WIDTH, Font::Regular, 10.0, scope);
+        w.y -= 10.0;
+    }
+
+    line_items(&mut w, document);
+    totals(&mut w, document);
+
+    for (heading, body) in &document.sections {
+        w.reserve(40.0);
+        w.y -= 6.0;
+        w.line(MARGIN, Font::Bold, 10.0, heading);
+        w.paragraph(MARGIN, CONTENT_WIDTH, Font::Regular, 9.0, body);
+    }
+
+    if let Some(signature) = &document.signature {
+        signature_block(&mut w, signature, signature_image);
+    }
+
+    let footer = format!("{} · {}", document.team.name, title);
+    w.finish(&footer)
+}
+
+/// Logo and team details on the left; document type, number and dates on the right
+fn header(w: &mut Writer, document: &Document, logo: Option<Image>) {
+    let top = w.y;
+    let team = &document.team;
+
+    let mut left = top;
+    if let Some(logo) = logo {
+        let (width, height) = fit(&logo, LOGO_MAX_WIDTH, LOGO_MAX_HEIGHT);
+        let id = w.pdf.add_image(logo);
+        w.page().image(id, MARGIN, top - height, width, height);
+        left -= height + 8.0;
+    }
+
+    w.y = left;
+    w.line(MARGIN, Font::Bold, 14.0, &team.name);
+    let city_line = [team.city.as_deref(), team.state.as_deref(), team.zip_code.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let contact = [
+        team.address_line1.clone(),
+        team.address_line2.clone(),
+        Some(city_line),
+        team.phone.clone(),
+        team.email.clone(),
+        team.website.clone(),
+    ];
+    for line in contact.into_iter().flatten().filter(|l| !l.trim().is_empty()) {
+        w.line(MARGIN, Font::Regular, 9.0, &line);
+    }
+    left = w.y;
+
+    let page = w.page();
+    let mut right = top - 22.0;
+    page.text_right(RIGHT, right, Font::Bold, 22.0, document.kind.heading());
+    right -= 18.0;
+    page.text_right(RIGHT, right, Font::Regular, 11.0, &format!("#{}", document.number));
+    for (label, value) in &document.dates {
+        right -= 14.0;
+        page.text_right(RIGHT, right, Font::Regular, 9.0, &format!("{}: {}", label, value));
+    }
+    if matches!(document.status.as_str(), "void" | "paid" | "approved" | "declined" | "expired") {
+        right -= 16.0;
+        page.text_right(RIGHT, right, Font::Bold, 11.0, &document.status.to_uppercase());
+    }
+    right -= 6.0;
+
+    w.y = left.min(right) - 14.0;
+    w.rule(0.75);
+    w.y -= 14.0;
+}
+
+/// "Bill to" and "Service address" side by side
+fn parties(w: &mut Writer, document: &Document) {
+    let customer = &document.customer;
+    let mut bill_to = vec![format!("{} {}", customer.first_name, customer.last_name)];
+    bill_to.extend(customer.company_name.clone());
+    bill_to.extend(customer.email.clone());
+    bill_to.extend(customer.phone.clone());
+
+    let service = document.property.as_ref().map(|p| {
+        let mut lines = vec![p.address_line1.clone()];
+        lines.extend(p.address_line2.clone().filter(|l| !l.is_empty()));
+        lines.push(format!("{}, {} {}", p.city, p.state, p.zip_code));
+        lines
+    });
+
+    let top = w.y;
+    let column = MARGIN + CONTENT_WIDTH / 2.0;
+    let mut bottom = top;
+    for (x, heading, lines) in [(MARGIN, "BILL TO", Some(bill_to)), (column, "SERVICE ADDRESS", service)] {
+        let Some(lines) = lines else { continue };
+        w.y = top;
+        w.line(x, Font::Bold, 8.0, heading);
+        for line in lines.iter().filter(|l| !l.trim().is_empty()) {
+            w.line(x, Font::Regular, 10.0, line);
+        }
+        bottom = bottom.min(w.y);
+    }
+    w.y = bottom - 14.0;
+}
+
+fn table_header(w: &mut Writer) {
+    w.reserve(20.0);
+    let y = w.y;
+    let page = w.page();
+    page.fill_rect(MARGIN, y - 16.0, CONTENT_WIDTH, 16.0, 0.92);
+    let baseline = y - 11.5;
+    page.text(MARGIN + 4.0, baseline, Font::Bold, 9.0, "Description");
+    page.text_right(QTY_RIGHT, baseline, Font::Bold, 9.0, "Qty");
+    page.text_right(UNIT_PRICE_RIGHT, baseline, Font::Bold, 9.0, "Unit price");
+    page.text_right(RIGHT - 4.0, baseline, Font::Bold, 9.0, "Amount");
+    w.y -= 22.0;
+}
+
+/// Line items grouped under their category, with a subtotal per group when there are several
+fn line_items(w: &mut Writer, document: &Document) {
+    table_header(w);
+
+    if document.line_items.is_empty() {
+        w.line(MARGIN + 4.0, Font::Italic, 10.0, "No line items");
+        return;
+    }
+
+    let known = |c: &str| CATEGORY_ORDER.contains(&c);
+    let mut groups: Vec<(&str, Vec<&LineItem>)> = CATEGORY_ORDER
+        .iter()
+        .map(|category| (*category, document.line_items.iter().filter(|i| i.category == *category).collect()))
+        .collect();
+    // Categories added to the enum later still print, at the end
+    groups.push(("other", document.line_items.iter().filter(|i| !known(&i.category)).collect()));
+    groups.retain(|(_, items)| !items.is_empty());
+    let show_subtotals = groups.len() > 1;
+
+    const SIZE: f32 = 10.0;
+    const LINE_HEIGHT: f32 = SIZE * 1.4;
+    for (category, items) in groups {
+        if w.reserve(LINE_HEIGHT * 2.0) {
+            table_header(w);
+        }
+        w.line(MARGIN + 4.0, Font::Bold, SIZE, &capitalize(category));
+
+        for item in &items {
+            let lines = pdf::wrap(&item.description, Font::Regular, SIZE, DESCRIPTION_WIDTH);
+            if w.reserve(LINE_HEIGHT * lines.len().max(1) as f32) {
+                table_header(w);
+            }
+            let baseline = w.y - SIZE;
+            let page = w.page();
+            page.text_right(QTY_RIGHT, baseline, Font::Regular, SIZE, &format_quantity(item.quantity, &item.unit));
+            page.text_right(UNIT_PRICE_RIGHT, baseline, Font::Regular, SIZE, &format_money(item.unit_price));
+            page.text_right(RIGHT - 4.0, baseline, Font::Regular, SIZE, &format_money(item.total));
+            for line in &lines {
+                w.line(MARGIN + 12.0, Font::Regular, SIZE, line);
+            }
+            if lines.is_empty() {
+                w.y -= LINE_HEIGHT;
+            }
+        }
+
+        if show_subtotals {
+            let subtotal: Decimal = items.iter().map(|i| i.total).sum();
+            w.reserve(LINE_HEIGHT);
+            let baseline = w.y - 9.0;
+            let page = w.page();
+            page.text_right(UNIT_PRICE_RIGHT, baseline, Font::Italic, 9.0, &format!("{} subtotal", capitalize(category)));
+            page.text_right(RIGHT - 4.0, baseline, Font::Italic, 9.0, &format_money(subtotal));
+            w.y -= LINE_HEIGHT;
+        }
+        w.y -= 4.0;
+    }
+
+    w.rule(0.75);
+    w.y -= 10.0;
+}
+
+fn totals(w: &mut Writer, document: &Document) {
+    w.reserve(16.0 * document.totals.len() as f32 + 8.0);
+    for row in &document.totals {
+        let (font, size) = if row.emphasis { (Font::Bold, 11.0) } else { (Font::Regular, 10.0) };
+        let baseline = w.y - size;
+        let page = w.page();
+        page.text_right(UNIT_PRICE_RIGHT, baseline, font, size, &row.label);
+        page.text_right(RIGHT - 4.0, baseline, font, size, &format_money(row.amount));
+        w.y -= size * 1.6;
+    }
+    w.y -= 10.0;
+}
+
+fn signature_block(w: &mut Writer, signature: &Signature, image: Option<Image>) {
+    w.reserve(SIGNATURE_MAX_HEIGHT + 50.0);
+    w.y -= 8.0;
+    w.line(MARGIN, Font::Bold, 10.0, "Customer signature");
+
+    let top = w.y;
+    match image {
+        Some(image) => {
+            let (width, height) = fit(&image, SIGNATURE_MAX_WIDTH, SIGNATURE_MAX_HEIGHT);
+            let id = w.pdf.add_image(image);
+            w.page().image(id, MARGIN, top - height - 4.0, width, height);
+            w.y = top - height - 8.0;
+        }
+        // A typed signature, or an image that couldn't be loaded
+        None if !signature.source.starts_with("data:") && !signature.source.starts_with("http") => {
+            w.y -= 6.0;
+            w.line(MARGIN, Font::Italic, 18.0, &signature.source);
+        }
+        None => w.y -= SIGNATURE_MAX_HEIGHT,
+    }
+
+    let y = w.y;
+    w.page().line(MARGIN, y, MARGIN + SIGNATURE_MAX_WIDTH + 40.0, y, 0.5, 0.4);
+    w.y -= 4.0;
+
+    let mut caption = Vec::new();
+    if let Some(name) = &signature.signer_name {
+        caption.push(format!("Signed by {}", name));
+    }
+    if let Some(signed_at) = signature.signed_at {
+        caption.push(format!("on {}", format_date(signed_at.date_naive())));
+    }
+    if !caption.is_empty() {
+        w.line(MARGIN, Font::Regular, 9.0, &caption.join(" "));
+    }
+}
+
+/// Response for the `/pdf` download endpoints. The fresh render is stored too, so
+/// `pdf_url` follows the latest edits; a storage failure doesn't block the download.
+pub async fn download(state: &AppState, kind: DocumentKind, team_id: Uuid, id: Uuid) -> ApiResult<Response> {
+    let rendered = render(state, kind, Some(team_id), id).await?;
+    if let Err(e) = store(state, kind, id, &rendered).await {
+        tracing::warn!(document = kind.slug(), id = %id, error = %format!("{:#}", e), "Could not store PDF");
+    }
+
+    let disposition = format!("inline; filename=\"{}\"", rendered.filename);
+    Ok((
+        [(header::CONTENT_TYPE, "application/pdf".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        rendered.bytes,
+    )
+        .into_response())
+}
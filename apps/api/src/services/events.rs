@@ -0,0 +1,123 @@
+//! Domain events for outbound webhooks.
+//!
+//! Domain code calls [`publish`] with the same executor it used for the change, so the event
+//! is queued only if the change commits. A `publish_event` job then fans the event out to
+//! every active subscription, and each subscription gets its own `deliver_webhook` job so a
+//! slow or failing endpoint retries on its own schedule.
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::AppState;
+
+pub const JOB_STATUS_CHANGED: &str = "job.status_changed";
+pub const JOB_ARRIVED: &str = "job.arrived";
+pub const JOB_DEPARTED: &str = "job.departed";
+pub const INVOICE_PAID: &str = "invoice.paid";
+pub const ESTIMATE_APPROVED: &str = "estimate.approved";
+pub const PAYMENT_RECEIVED: &str = "payment.received";
+pub const CUSTOMER_CREATED: &str = "customer.created";
+/// Sent only by `POST /webhooks/{id}/test`
+pub const WEBHOOK_TEST: &str = "webhook.test";
+
+/// Events a subscription may list; `*` subscribes to all of them
+pub const EVENT_TYPES: &[&str] = &[
+    JOB_STATUS_CHANGED,
+    JOB_ARRIVED,
+    JOB_DEPARTED,
+    INVOICE_PAID,
+    ESTIMATE_APPROVED,
+    PAYMENT_RECEIVED,
+    CUSTOMER_CREATED,
+];
+
+pub fn validate_subscription(events: &[String]) -> ApiResult<()> {
+    if events.is_empty() {
+        return Err(ApiError::Validation("Subscribe to at least one event".into()));
+    }
+    match events.iter().find(|e| e.as_str() != "*" && !EVENT_TYPES.contains(&e.as_str())) {
+        Some(unknown) => Err(ApiError::Validation(format!(
+            "Unknown event '{}'; expected one of {} or *",
+            unknown,
+            EVENT_TYPES.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Body POSTed to subscribers
+pub fn envelope(event_id: Uuid, team_id: Uuid, event_type: &str, occurred_at: DateTime<Utc>, data: &Value) -> Value {
+    json!({
+        "id": event_id,
+        "type": event_type,
+        "team_id": team_id,
+        "created_at": occurred_at,
+        "data": data,
+    })
+}
+
+/// Queue an event for the team's webhook subscribers
+pub async fn publish<'e, E>(executor: E, state: &AppState, team_id: Uuid, event_type: &'static str, data: Value) -> ApiResult<()>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let job = BackgroundJob::PublishEvent {
+        team_id,
+        event_id: Uuid::new_v4(),
+        event_type: event_type.to_string(),
+        occurred_at: Utc::now(),
+        data,
+    };
+    job_queue::enqueue_with(executor, &job, Utc::now(), state.jobs.max_attempts(), None).await?;
+    Ok(())
+}
+
+/// Queue one delivery per matching subscription. Keyed on webhook and event so a retried
+/// fan-out doesn't deliver twice.
+pub async fn fan_out(
+    state: &AppState,
+    team_id: Uuid,
+    event_id: Uuid,
+    event_type: &str,
+    occurred_at: DateTime<Utc>,
+    data: &Value,
+) -> anyhow::Result<()> {
+    let webhooks = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT id FROM webhooks
+        WHERE team_id = $1 AND is_active AND ($2 = ANY(events) OR '*' = ANY(events))
+        "#,
+    )
+    .bind(team_id)
+    .bind(event_type)
+    .fetch_all(&state.db)
+    .await?;
+
+    let payload = envelope(event_id, team_id, event_type, occurred_at, data);
+    for webhook_id in &webhooks {
+        let job = BackgroundJob::DeliverWebhook {
+            team_id,
+            webhook_id: *webhook_id,
+            event_id,
+            event_type: event_type.to_string(),
+            payload: payload.clone(),
+            replay_of: None,
+        };
+        job_queue::enqueue_with(
+            &state.db,
+            &job,
+            Utc::now(),
+            state.config.webhooks.max_attempts,
+            Some(&format!("webhook:{}:{}", webhook_id, event_id)),
+        )
+        .await?;
+    }
+
+    if !webhooks.is_empty() {
+        tracing::debug!(event_id = %event_id, event_type = %event_type, subscribers = webhooks.len(), "Event fanned out");
+    }
+    Ok(())
+}
@@ -0,0 +1,390 @@
+//! Geofence check-in and check-out.
+//!
+//! Every GPS fix a technician uploads is checked against the properties of their open jobs.
+//! Coming within a job's radius records an `arrived` event; moving out past a wider exit
+//! radius records `departed`, so a fix wobbling at the edge doesn't flap. Events are
+//! append-only and keep the raw fix and the device and server clocks, which is what
+//! settles "when did the tech actually get there" disputes. With the team's
+//! auto-transition on, arrival starts (or resumes) the job and departure pauses it
+//! through the normal FSM, so time entries and status history follow.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::geofence::GeofenceEvent;
+use crate::models::job::JobStatusTransition;
+use crate::services::{events, job_service};
+use crate::services::side_effects::EffectContext;
+use crate::AppState;
+
+/// Fixes less precise than this are too coarse to place someone at a house
+const MAX_FIX_ACCURACY_METERS: f64 = 100.0;
+/// Departure needs the technician this much further out than the arrival radius
+const EXIT_RADIUS_FACTOR: f64 = 1.5;
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+/// Smaller than GPS error in a built-up area, or bigger than a property plausibly is
+const RADIUS_RANGE_METERS: std::ops::RangeInclusive<i32> = 25..=2000;
+
+/// A stored GPS fix
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub gps_location_id: Uuid,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy: Option<f64>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+pub fn validate_radius(radius_meters: i32) -> ApiResult<()> {
+    if RADIUS_RANGE_METERS.contains(&radius_meters) {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(format!(
+            "Geofence radius must be between {} and {} meters",
+            RADIUS_RANGE_METERS.start(),
+            RADIUS_RANGE_METERS.end()
+        )))
+    }
+}
+
+/// Great-circle (haversine) distance between two coordinates
+pub fn distance_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// An open job of the technician's whose property has coordinates
+#[derive(Debug, sqlx::FromRow)]
+struct Site {
+    job_id: Uuid,
+    status: String,
+    property_latitude: f64,
+    property_longitude: f64,
+    radius_meters: i32,
+    auto_transition: bool,
+}
+
+/// Jobs joined to their property's coordinates and the effective radius
+const SITE_QUERY: &str = r#"
+    SELECT j.id AS job_id, j.status::text AS status,
+           p.latitude AS property_latitude, p.longitude AS property_longitude,
+           COALESCE(p.geofence_radius_meters, t.geofence_radius_meters) AS radius_meters,
+           t.geofence_auto_transition AS auto_transition
+    FROM jobs j
+    JOIN properties p ON p.id = j.property_id
+    JOIN teams t ON t.id = j.team_id
+"#;
+
+/// Where a crossing was observed
+struct Observation {
+    source: &'static str,
+    gps_location_id: Option<Uuid>,
+    latitude: f64,
+    longitude: f64,
+    accuracy: Option<f64>,
+    recorded_at: DateTime<Utc>,
+}
+
+/// What checking one observation against a site found
+enum Crossing {
+    Recorded(Box<GeofenceEvent>),
+    /// No boundary crossed; whether the technician is on site afterwards
+    Unchanged { on_site: bool },
+    /// Older than the last crossing, so it says nothing about where the technician is now
+    Stale,
+}
+
+/// Check a technician's fixes, oldest first, against their jobs' geofences, recording any
+/// arrival or departure and moving the job when the team allows it. Returns the recorded
+/// events.
+pub async fn process_fixes(state: &AppState, team_id: Uuid, user_id: Uuid, fixes: &[Fix]) -> ApiResult<Vec<GeofenceEvent>> {
+    let fixes: Vec<&Fix> = fixes
+        .iter()
+        .filter(|fix| !fix.accuracy.is_some_and(|accuracy| accuracy > MAX_FIX_ACCURACY_METERS))
+        .collect();
+    let (Some(first), Some(last)) = (fixes.first(), fixes.last()) else {
+        return Ok(Vec::new());
+    };
+
+    // Scheduled work is matched a day either side of the fixes to allow for time zones;
+    // started jobs match whenever they were scheduled
+    let mut sites = sqlx::query_as::<_, Site>(&format!(
+        r#"
+        {}
+        WHERE j.team_id = $1 AND j.assigned_to = $2 AND j.deleted_at IS NULL
+          AND p.latitude IS NOT NULL AND p.longitude IS NOT NULL
+          AND (
+              j.status IN ('in_progress', 'paused')
+              OR (j.status IN ('scheduled', 'en_route') AND j.scheduled_date BETWEEN $3::date - 1 AND $4::date + 1)
+          )
+        "#,
+        SITE_QUERY
+    ))
+    .bind(team_id)
+    .bind(user_id)
+    .bind(first.recorded_at.date_naive())
+    .bind(last.recorded_at.date_naive())
+    .fetch_all(&state.db)
+    .await?;
+
+    // Whether the technician is on each site as of the last fix checked, once known. A fix
+    // that can't change that needs no trip to the database, which keeps long offline
+    // uploads to a handful of queries.
+    let mut on_site: Vec<Option<bool>> = vec![None; sites.len()];
+    let mut recorded = Vec::new();
+    for fix in fixes {
+        let observation = Observation {
+            source: "gps",
+            gps_location_id: Some(fix.gps_location_id),
+            latitude: fix.latitude,
+            longitude: fix.longitude,
+            accuracy: fix.accuracy,
+            recorded_at: fix.recorded_at,
+        };
+
+        for (site, known) in sites.iter_mut().zip(on_site.iter_mut()) {
+            let distance = distance_meters(fix.latitude, fix.longitude, site.property_latitude, site.property_longitude);
+            let radius = f64::from(site.radius_meters);
+            match *known {
+                Some(true) if distance <= radius * EXIT_RADIUS_FACTOR => continue,
+                Some(false) if distance > radius => continue,
+                _ => {}
+            }
+
+            let mut tx = state.db.begin().await?;
+            let crossing = record_crossing(state, &mut tx, team_id, user_id, site, &observation, false).await?;
+            tx.commit().await?;
+
+            let event = match crossing {
+                Crossing::Recorded(event) => *event,
+                Crossing::Unchanged { on_site } => {
+                    *known = Some(on_site);
+                    continue;
+                }
+                Crossing::Stale => continue,
+            };
+            *known = Some(event.event_type == "arrived");
+            if site.auto_transition {
+                if let Some(status) = auto_transition(state, team_id, user_id, site, &event).await {
+                    site.status = status.to_string();
+                }
+            }
+            recorded.push(event);
+        }
+    }
+
+    Ok(recorded)
+}
+
+/// Record an arrival or departure if this observation crosses the site's boundary.
+/// `arrival_only` records an arrival wherever the technician is, for manual check-ins.
+async fn record_crossing(
+    state: &AppState,
+    conn: &mut PgConnection,
+    team_id: Uuid,
+    user_id: Uuid,
+    site: &Site,
+    observation: &Observation,
+    arrival_only: bool,
+) -> ApiResult<Crossing> {
+    // Serializes fixes for the same job so two in flight can't both record an arrival
+    sqlx::query("SELECT 1 FROM jobs WHERE id = $1 FOR UPDATE")
+        .bind(site.job_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let last = sqlx::query_as::<_, (String, DateTime<Utc>)>(
+        r#"
+        SELECT event_type, recorded_at FROM job_geofence_events
+        WHERE job_id = $1 AND user_id = $2
+        ORDER BY recorded_at DESC LIMIT 1
+        "#,
+    )
+    .bind(site.job_id)
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    // Devices upload fixes buffered while offline; anything older than the last crossing is history
+    if last.as_ref().is_some_and(|(_, at)| *at >= observation.recorded_at) {
+        return Ok(Crossing::Stale);
+    }
+    let on_site = last.as_ref().is_some_and(|(event_type, _)| event_type == "arrived");
+
+    let distance = distance_meters(observation.latitude, observation.longitude, site.property_latitude, site.property_longitude);
+    let radius = f64::from(site.radius_meters);
+    let event_type = match on_site {
+        false if arrival_only || distance <= radius => "arrived",
+        true if !arrival_only && distance > radius * EXIT_RADIUS_FACTOR => "departed",
+        _ => return Ok(Crossing::Unchanged { on_site }),
+    };
+
+    let event = sqlx::query_as::<_, GeofenceEvent>(
+        r#"
+        INSERT INTO job_geofence_events (team_id, job_id, user_id, event_type, source, gps_location_id,
+                                         latitude, longitude, accuracy, property_latitude, property_longitude,
+                                         distance_meters, radius_meters, recorded_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+        RETURNING *
+        "#,
+    )
+    .bind(team_id)
+    .bind(site.job_id)
+    .bind(user_id)
+    .bind(event_type)
+    .bind(observation.source)
+    .bind(observation.gps_location_id)
+    .bind(observation.latitude)
+    .bind(observation.longitude)
+    .bind(observation.accuracy)
+    .bind(site.property_latitude)
+    .bind(site.property_longitude)
+    .bind(distance)
+    .bind(site.radius_meters)
+    .bind(observation.recorded_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let data = json!({
+        "job_id": event.job_id,
+        "user_id": user_id,
+        "geofence_event_id": event.id,
+        "source": event.source,
+        "latitude": event.latitude,
+        "longitude": event.longitude,
+        "distance_meters": event.distance_meters.round(),
+        "recorded_at": event.recorded_at,
+    });
+    let event_name = if event_type == "arrived" { events::JOB_ARRIVED } else { events::JOB_DEPARTED };
+    events::publish(&mut *conn, state, team_id, event_name, data).await?;
+
+    tracing::info!(job_id = %site.job_id, user_id = %user_id, event_type, distance = distance.round(), "Geofence crossing recorded");
+    Ok(Crossing::Recorded(Box::new(event)))
+}
+
+/// Arrival starts or resumes the job; departure pauses it. Returns the job's new status.
+/// A failed transition (the job moved on meanwhile, say) is logged; the crossing itself is
+/// already recorded.
+async fn auto_transition(state: &AppState, team_id: Uuid, user_id: Uuid, site: &Site, event: &GeofenceEvent) -> Option<&'static str> {
+    let status = match (event.event_type.as_str(), site.status.as_str()) {
+        ("arrived", "scheduled" | "en_route" | "paused") => "in_progress",
+        ("departed", "in_progress") => "paused",
+        _ => return None,
+    };
+    let verb = if event.event_type == "arrived" { "check-in" } else { "check-out" };
+
+    let transition = JobStatusTransition {
+        status: status.to_string(),
+        latitude: Some(event.latitude),
+        longitude: Some(event.longitude),
+        note: Some(format!("Geofence {}, {:.0} m from the property", verb, event.distance_meters)),
+        geofence_event_id: Some(event.id),
+    };
+    match job_service::transition_job(state, team_id, event.job_id, user_id, &transition).await {
+        Ok(_) => Some(status),
+        Err(e) => {
+            tracing::warn!(job_id = %event.job_id, status, error = %e, "Geofence transition skipped");
+            None
+        }
+    }
+}
+
+/// Record an arrival for a manual check-in made with coordinates, so jobs started by hand
+/// have the same evidence as geofenced ones. Returns the distance from the property, or
+/// `None` when there is nothing to record.
+pub async fn record_manual_arrival(
+    state: &AppState,
+    conn: &mut PgConnection,
+    ctx: &EffectContext,
+    latitude: f64,
+    longitude: f64,
+) -> ApiResult<Option<f64>> {
+    let site = sqlx::query_as::<_, Site>(&format!(
+        "{} WHERE j.id = $1 AND p.latitude IS NOT NULL AND p.longitude IS NOT NULL",
+        SITE_QUERY
+    ))
+    .bind(ctx.job_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(site) = site else {
+        return Ok(None);
+    };
+
+    let observation = Observation {
+        source: "transition",
+        gps_location_id: None,
+        latitude,
+        longitude,
+        accuracy: None,
+        recorded_at: Utc::now(),
+    };
+    match record_crossing(state, conn, ctx.team_id, ctx.technician(), &site, &observation, true).await? {
+        Crossing::Recorded(event) => Ok(Some(event.distance_meters)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    const SITE: (f64, f64) = (39.7817, -89.6501);
+
+    fn fix(latitude: f64, longitude: f64, recorded_at: DateTime<Utc>) -> Fix {
+        Fix { gps_location_id: Uuid::new_v4(), latitude, longitude, accuracy: Some(8.0), recorded_at }
+    }
+
+    async fn job_status(db: &PgPool, job_id: Uuid) -> String {
+        sqlx::query_scalar("SELECT status::text FROM jobs WHERE id = $1").bind(job_id).fetch_one(db).await.unwrap()
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn crossings_start_and_pause_the_job(db: PgPool) {
+        let team_id = test_support::team(&db, "acme").await;
+        let tech_id = test_support::member(&db, team_id, "tech@example.com", "technician").await;
+        let customer_id = test_support::customer(&db, team_id).await;
+        let property_id = test_support::property(&db, team_id, customer_id, SITE.0, SITE.1).await;
+        let job_id = test_support::job(&db, team_id, customer_id, "scheduled", Some(tech_id)).await;
+        sqlx::query("UPDATE jobs SET property_id = $2, scheduled_date = CURRENT_DATE WHERE id = $1")
+            .bind(job_id)
+            .bind(property_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+
+        // About a kilometre north, then at the door
+        let start = Utc::now() - Duration::minutes(30);
+        let approach = [fix(SITE.0 + 0.01, SITE.1, start), fix(SITE.0 + 0.0001, SITE.1, start + Duration::minutes(5))];
+        let events = process_fixes(&state, team_id, tech_id, &approach).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "arrived");
+        assert_eq!(job_status(&db, job_id).await, "in_progress");
+
+        let checked_in_by: Option<Uuid> = sqlx::query_scalar(
+            "SELECT geofence_event_id FROM job_status_history WHERE job_id = $1 AND to_status = 'in_progress'",
+        )
+        .bind(job_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(checked_in_by, Some(events[0].id));
+
+        let leave = [fix(SITE.0 + 0.01, SITE.1, start + Duration::minutes(20))];
+        let events = process_fixes(&state, team_id, tech_id, &leave).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "departed");
+        assert_eq!(job_status(&db, job_id).await, "paused");
+    }
+}
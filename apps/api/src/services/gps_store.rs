@@ -0,0 +1,442 @@
+//! GPS fix storage.
+//!
+//! Phones buffer fixes while offline and upload them in bursts, so fixes are written in
+//! batches and deduplicated on the device timestamp. `gps_locations` is partitioned by
+//! month: [`ensure_partitions`] keeps partitions ahead of the clock and
+//! [`drop_expired_partitions`] drops months no team retains any more. Each technician's
+//! latest fix is also kept in a per-team Redis hash, so the dispatch map and tracking
+//! pages read one key instead of scanning the table. The hash is a cache: when it's
+//! missing (expired, flushed) the first reader rebuilds it from the table.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::geofence::GeofenceEvent;
+use crate::models::gps::{GpsLocation, NewGpsLocation};
+use crate::services::geofence;
+use crate::services::realtime::{self, Audience, WsEvent};
+use crate::AppState;
+
+/// Most fixes accepted in one upload
+pub const MAX_BATCH: usize = 500;
+/// Oldest buffered fix accepted. Partitions are kept from the previous month, so this
+/// must stay under a month.
+const MAX_BACKFILL_DAYS: i64 = 7;
+/// How far ahead of the server a device clock may be
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+const PARTITION_PREFIX: &str = "gps_locations_";
+const PARTITION_MONTHS_AHEAD: i32 = 2;
+/// Serializes partition DDL across replicas
+const PARTITION_LOCK_KEY: i64 = 0x6770_735f_7061_7274;
+/// The latest-fix hash lives this long after its last write
+const LATEST_TTL_SECS: i64 = 24 * 60 * 60;
+/// Field set on a hash rebuilt from the table, so a technician missing from it is known
+/// to have no recent fix rather than to be uncached
+const WARM_FIELD: &str = "warm";
+
+const RETENTION_DAYS_RANGE: std::ops::RangeInclusive<i32> = 7..=3650;
+const DOWNSAMPLE_INTERVAL_RANGE_SECS: std::ops::RangeInclusive<i32> = 5..=3600;
+
+/// Compare-and-set on the latest fix: only a newer fix replaces the cached one. A cold
+/// hash is left alone unless ARGV[4] is 1, so a writer can't make a half-built hash look
+/// complete. Returns 1 if stored, 0 if an equal or newer fix is cached, -1 if cold.
+const SET_LATEST_SCRIPT: &str = r#"
+if ARGV[4] == '0' and redis.call('EXISTS', KEYS[1]) == 0 then return -1 end
+local current = redis.call('HGET', KEYS[1], ARGV[1])
+if current and tonumber(cjson.decode(current)['ts']) >= tonumber(ARGV[2]) then return 0 end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+redis.call('EXPIRE', KEYS[1], ARGV[5])
+return 1
+"#;
+
+/// A fix that failed validation, by its position in the upload
+#[derive(Debug, Serialize)]
+pub struct Rejected {
+    pub index: usize,
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub struct Ingested {
+    /// Newly stored fixes, oldest first
+    pub stored: Vec<GpsLocation>,
+    /// Valid fixes already stored by an earlier upload, or repeated within this one
+    pub duplicates: usize,
+    pub rejected: Vec<Rejected>,
+    pub geofence_events: Vec<GeofenceEvent>,
+}
+
+fn validate(fix: &NewGpsLocation, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if !(-90.0..=90.0).contains(&fix.latitude) || !(-180.0..=180.0).contains(&fix.longitude) {
+        return Err("Coordinates are out of range".into());
+    }
+    if fix.accuracy.is_some_and(|accuracy| accuracy < 0.0) || fix.speed.is_some_and(|speed| speed < 0.0) {
+        return Err("Accuracy and speed must not be negative".into());
+    }
+    let recorded_at = fix.recorded_at.ok_or("recorded_at is required")?;
+    if recorded_at > now + Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+        return Err("recorded_at is in the future".into());
+    }
+    if recorded_at < now - Duration::days(MAX_BACKFILL_DAYS) {
+        return Err(format!("recorded_at is more than {} days old", MAX_BACKFILL_DAYS));
+    }
+    Ok(recorded_at)
+}
+
+/// Store a technician's fixes. Invalid fixes are rejected individually so one bad reading
+/// doesn't bounce a whole offline buffer. The newest fix updates the latest-position cache
+/// and the dispatch map, then every stored fix is checked against geofences in order.
+pub async fn ingest(state: &AppState, team_id: Uuid, user_id: Uuid, fixes: Vec<NewGpsLocation>) -> ApiResult<Ingested> {
+    let now = Utc::now();
+    let mut rejected = Vec::new();
+    let mut accepted = Vec::with_capacity(fixes.len());
+    for (index, fix) in fixes.into_iter().enumerate() {
+        match validate(&fix, now) {
+            Ok(recorded_at) => accepted.push((recorded_at, fix)),
+            Err(error) => rejected.push(Rejected { index, error }),
+        }
+    }
+    let valid = accepted.len();
+    accepted.sort_by_key(|(recorded_at, _)| *recorded_at);
+    accepted.dedup_by_key(|(recorded_at, _)| *recorded_at);
+
+    let mut stored = if accepted.is_empty() {
+        Vec::new()
+    } else {
+        insert(&state.db, team_id, user_id, &accepted).await?
+    };
+    stored.sort_by_key(|location| location.recorded_at);
+
+    if let Some(latest) = stored.last() {
+        // An upload of old buffered fixes doesn't move the technician on the map
+        if set_latest(state, latest, false).await != Some(0) {
+            let event = WsEvent::TechnicianLocation {
+                user_id: user_id.to_string(),
+                latitude: latest.latitude,
+                longitude: latest.longitude,
+            };
+            realtime::publish(state, Audience::team(team_id).user(Some(user_id)), event).await;
+        }
+    }
+
+    // The fixes are stored either way; a geofence failure shouldn't make the device resend them
+    let geofence_fixes: Vec<geofence::Fix> = stored
+        .iter()
+        .map(|location| geofence::Fix {
+            gps_location_id: location.id,
+            latitude: location.latitude,
+            longitude: location.longitude,
+            accuracy: location.accuracy,
+            recorded_at: location.recorded_at,
+        })
+        .collect();
+    let geofence_events = match geofence::process_fixes(state, team_id, user_id, &geofence_fixes).await {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::error!(user_id = %user_id, error = %e, "Geofence check failed");
+            Vec::new()
+        }
+    };
+
+    Ok(Ingested {
+        duplicates: valid - stored.len(),
+        stored,
+        rejected,
+        geofence_events,
+    })
+}
+
+async fn insert(
+    pool: &PgPool,
+    team_id: Uuid,
+    user_id: Uuid,
+    fixes: &[(DateTime<Utc>, NewGpsLocation)],
+) -> ApiResult<Vec<GpsLocation>> {
+    let latitudes: Vec<f64> = fixes.iter().map(|(_, fix)| fix.latitude).collect();
+    let longitudes: Vec<f64> = fixes.iter().map(|(_, fix)| fix.longitude).collect();
+    let accuracies: Vec<Option<f64>> = fixes.iter().map(|(_, fix)| fix.accuracy).collect();
+    let speeds: Vec<Option<f64>> = fixes.iter().map(|(_, fix)| fix.speed).collect();
+    let headings: Vec<Option<f64>> = fixes.iter().map(|(_, fix)| fix.heading).collect();
+    let recorded: Vec<DateTime<Utc>> = fixes.iter().map(|(recorded_at, _)| *recorded_at).collect();
+
+    sqlx::query_as::<_, GpsLocation>(
+        r#"
+        INSERT INTO gps_locations (team_id, user_id, latitude, longitude, accuracy, speed, heading, recorded_at)
+        SELECT $1, $2, fix.* FROM UNNEST($3::float8[], $4::float8[], $5::float8[], $6::float8[], $7::float8[], $8::timestamptz[]) AS fix
+        ON CONFLICT (team_id, user_id, recorded_at) DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .bind(latitudes)
+    .bind(longitudes)
+    .bind(accuracies)
+    .bind(speeds)
+    .bind(headings)
+    .bind(recorded)
+    .fetch_all(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// The stored fix for a device timestamp, for answering a re-sent single fix
+pub async fn find(pool: &PgPool, team_id: Uuid, user_id: Uuid, recorded_at: DateTime<Utc>) -> ApiResult<Option<GpsLocation>> {
+    sqlx::query_as::<_, GpsLocation>("SELECT * FROM gps_locations WHERE team_id = $1 AND user_id = $2 AND recorded_at = $3")
+        .bind(team_id)
+        .bind(user_id)
+        .bind(recorded_at)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+}
+
+fn latest_key(team_id: Uuid) -> String {
+    format!("fieldforge:gps:latest:{}", team_id)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFix {
+    /// `recorded_at` in milliseconds, for the compare-and-set
+    ts: i64,
+    location: GpsLocation,
+}
+
+/// Cache a fix as the technician's latest. Returns the script's result, or `None` when
+/// Redis is unavailable; the cache is advisory, so failures are only logged.
+async fn set_latest(state: &AppState, location: &GpsLocation, create: bool) -> Option<i64> {
+    let ts = location.recorded_at.timestamp_millis();
+    let value = serde_json::to_string(&CachedFix { ts, location: location.clone() }).ok()?;
+
+    let mut conn = state.redis.clone();
+    let result = redis::Script::new(SET_LATEST_SCRIPT)
+        .key(latest_key(location.team_id))
+        .arg(location.user_id.to_string())
+        .arg(ts)
+        .arg(value)
+        .arg(if create { "1" } else { "0" })
+        .arg(LATEST_TTL_SECS)
+        .invoke_async::<_, i64>(&mut conn)
+        .await;
+
+    match result {
+        Ok(outcome) => Some(outcome),
+        Err(e) => {
+            tracing::warn!(team_id = %location.team_id, error = %e, "Failed to cache latest GPS fix");
+            None
+        }
+    }
+}
+
+/// Latest fix per technician in the team over the cache's lifetime, straight from the table
+async fn latest_from_table(pool: &PgPool, team_id: Uuid) -> ApiResult<Vec<GpsLocation>> {
+    sqlx::query_as::<_, GpsLocation>(
+        r#"
+        SELECT DISTINCT ON (user_id) *
+        FROM gps_locations
+        WHERE team_id = $1 AND recorded_at > now() - make_interval(secs => $2)
+        ORDER BY user_id, recorded_at DESC
+        "#,
+    )
+    .bind(team_id)
+    .bind(LATEST_TTL_SECS as f64)
+    .fetch_all(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// Rebuild a cold hash from the table. Fixes cached meanwhile by writers are newer and win.
+async fn warm(state: &AppState, team_id: Uuid, locations: &[GpsLocation]) {
+    for location in locations {
+        if set_latest(state, location, true).await.is_none() {
+            return;
+        }
+    }
+    let mut conn = state.redis.clone();
+    let key = latest_key(team_id);
+    let result = redis::pipe()
+        .atomic()
+        .cmd("HSET")
+        .arg(&key)
+        .arg(WARM_FIELD)
+        .arg(1)
+        .ignore()
+        .cmd("EXPIRE")
+        .arg(&key)
+        .arg(LATEST_TTL_SECS)
+        .ignore()
+        .query_async::<_, ()>(&mut conn)
+        .await;
+    if let Err(e) = result {
+        tracing::warn!(team_id = %team_id, error = %e, "Failed to mark GPS cache warm");
+    }
+}
+
+/// Each technician's latest fix, if newer than `max_age`, ordered by user
+pub async fn latest_positions(state: &AppState, team_id: Uuid, max_age: Duration) -> ApiResult<Vec<GpsLocation>> {
+    let mut conn = state.redis.clone();
+    let cached = redis::cmd("HGETALL")
+        .arg(latest_key(team_id))
+        .query_async::<_, HashMap<String, String>>(&mut conn)
+        .await;
+
+    let mut locations = match cached {
+        Ok(entries) if !entries.is_empty() => entries
+            .into_iter()
+            .filter(|(field, _)| field != WARM_FIELD)
+            .filter_map(|(_, value)| serde_json::from_str::<CachedFix>(&value).ok())
+            .map(|cached| cached.location)
+            .collect(),
+        Ok(_) => {
+            let locations = latest_from_table(&state.db, team_id).await?;
+            warm(state, team_id, &locations).await;
+            locations
+        }
+        Err(e) => {
+            tracing::warn!(team_id = %team_id, error = %e, "GPS cache unavailable; reading latest fixes from the table");
+            latest_from_table(&state.db, team_id).await?
+        }
+    };
+
+    let cutoff = Utc::now() - max_age;
+    locations.retain(|location| location.recorded_at > cutoff);
+    locations.sort_by_key(|location| location.user_id);
+    Ok(locations)
+}
+
+/// One technician's latest fix, if newer than `max_age`
+pub async fn latest_position(state: &AppState, team_id: Uuid, user_id: Uuid, max_age: Duration) -> ApiResult<Option<GpsLocation>> {
+    let cutoff = Utc::now() - max_age;
+
+    let mut conn = state.redis.clone();
+    let cached = redis::cmd("HMGET")
+        .arg(latest_key(team_id))
+        .arg(user_id.to_string())
+        .arg(WARM_FIELD)
+        .query_async::<_, Vec<Option<String>>>(&mut conn)
+        .await;
+
+    match cached.as_deref() {
+        Ok([Some(value), _]) => {
+            if let Ok(cached) = serde_json::from_str::<CachedFix>(value) {
+                return Ok(Some(cached.location).filter(|location| location.recorded_at > cutoff));
+            }
+        }
+        // A warm hash without the technician means no fix within the cache's lifetime
+        Ok([None, Some(_)]) => return Ok(None),
+        Ok(_) => {}
+        Err(e) => tracing::warn!(team_id = %team_id, error = %e, "GPS cache unavailable; reading latest fix from the table"),
+    }
+
+    sqlx::query_as::<_, GpsLocation>(
+        r#"
+        SELECT * FROM gps_locations
+        WHERE team_id = $1 AND user_id = $2 AND recorded_at > $3
+        ORDER BY recorded_at DESC LIMIT 1
+        "#,
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .bind(cutoff)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(Into::into)
+}
+
+/// Validate a team's retention settings. A downsample age of 0 turns downsampling off.
+pub fn validate_retention(retention_days: Option<i32>, downsample_after_days: Option<i32>, downsample_interval_secs: Option<i32>) -> ApiResult<()> {
+    if retention_days.is_some_and(|days| !RETENTION_DAYS_RANGE.contains(&days)) {
+        return Err(ApiError::Validation(format!(
+            "GPS retention must be between {} and {} days",
+            RETENTION_DAYS_RANGE.start(),
+            RETENTION_DAYS_RANGE.end()
+        )));
+    }
+    if downsample_after_days.is_some_and(|days| !(0..=*RETENTION_DAYS_RANGE.end()).contains(&days)) {
+        return Err(ApiError::Validation("GPS downsampling age must be 0 (off) or a number of days".into()));
+    }
+    if downsample_interval_secs.is_some_and(|secs| !DOWNSAMPLE_INTERVAL_RANGE_SECS.contains(&secs)) {
+        return Err(ApiError::Validation(format!(
+            "GPS downsampling interval must be between {} and {} seconds",
+            DOWNSAMPLE_INTERVAL_RANGE_SECS.start(),
+            DOWNSAMPLE_INTERVAL_RANGE_SECS.end()
+        )));
+    }
+    Ok(())
+}
+
+/// Create partitions from last month (for backfilled fixes) through a couple of months ahead
+pub async fn ensure_partitions(pool: &PgPool) -> ApiResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(PARTITION_LOCK_KEY)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        r#"
+        SELECT gps_locations_ensure_partition(((now() AT TIME ZONE 'UTC')::date + make_interval(months => m))::date)
+        FROM generate_series(-1, $1) AS m
+        "#,
+    )
+    .bind(PARTITION_MONTHS_AHEAD)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// End of the month a partition holds, from its `gps_locations_YYYYMM` name
+fn partition_end(name: &str) -> Option<DateTime<Utc>> {
+    let month = name.strip_prefix(PARTITION_PREFIX)?;
+    if month.len() != 6 || !month.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let start = NaiveDate::parse_from_str(&format!("{}01", month), "%Y%m%d").ok()?;
+    Some(start.checked_add_months(Months::new(1))?.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// Drop monthly partitions entirely older than the longest retention any team has.
+/// Returns how many were dropped.
+pub async fn drop_expired_partitions(pool: &PgPool) -> ApiResult<usize> {
+    let Some(retention_days) = sqlx::query_scalar::<_, Option<i32>>("SELECT max(gps_retention_days) FROM teams")
+        .fetch_one(pool)
+        .await?
+    else {
+        return Ok(0);
+    };
+    let cutoff = Utc::now() - Duration::days(retention_days.into());
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(PARTITION_LOCK_KEY)
+        .execute(&mut *tx)
+        .await?;
+
+    let partitions = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT c.relname::text FROM pg_inherits i
+        JOIN pg_class c ON c.oid = i.inhrelid
+        WHERE i.inhparent = 'gps_locations'::regclass
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut dropped = 0;
+    for name in partitions {
+        // Only names that parse as ours are interpolated
+        if partition_end(&name).is_some_and(|end| end <= cutoff) {
+            sqlx::query(&format!("DROP TABLE {}", name)).execute(&mut *tx).await?;
+            tracing::info!(partition = %name, "Dropped expired GPS partition");
+            dropped += 1;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(dropped)
+}
@@ -1,28 +1,67 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::Semaphore;
 use tracing;
+use uuid::Uuid;
 
+use crate::config::QueueSettings;
+use crate::errors::{ApiError, ApiResult};
+use crate::models::background_job::BackgroundJobRecord;
+use crate::services::document_pdf::{self, DocumentKind};
+use crate::services::{events, payment_service, sweeps, tracking, verification_service, webhook_service};
 use crate::AppState;
 
 /// Background job types that can be queued for async processing
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum BackgroundJob {
     SendEmail {
+        /// `None` for account mail (verification, password reset) sent outside any team
+        #[serde(default)]
+        team_id: Option<uuid::Uuid>,
         to: String,
         subject: String,
         body: String,
     },
     SendSms {
+        #[serde(default)]
+        team_id: Option<uuid::Uuid>,
         to: String,
         body: String,
     },
+    /// Carries only the row id; the link is minted when it is sent so it is never stored
+    SendPasswordReset {
+        reset_id: uuid::Uuid,
+    },
+    /// Carries only the row id; the code is minted when it is sent so it is never stored
+    SendVerificationCode {
+        code_id: uuid::Uuid,
+    },
+    /// Carries only the row id; the link is minted when it is sent so it is never stored
+    SendTrackingLink {
+        team_id: uuid::Uuid,
+        link_id: uuid::Uuid,
+    },
     GenerateInvoicePdf {
+        team_id: uuid::Uuid,
         invoice_id: uuid::Uuid,
     },
+    GenerateEstimatePdf {
+        team_id: uuid::Uuid,
+        estimate_id: uuid::Uuid,
+    },
     ProcessPhoto {
+        team_id: uuid::Uuid,
         photo_id: uuid::Uuid,
     },
     SendPushNotification {
+        team_id: uuid::Uuid,
         user_id: uuid::Uuid,
         title: String,
         body: String,
@@ -33,72 +72,564 @@ pub enum BackgroundJob {
         entity_id: uuid::Uuid,
     },
     RequestReview {
+        team_id: uuid::Uuid,
         customer_id: uuid::Uuid,
         job_id: uuid::Uuid,
     },
     ScheduleReminder {
+        team_id: uuid::Uuid,
         job_id: uuid::Uuid,
         remind_at: chrono::DateTime<chrono::Utc>,
     },
     RecurringJobGeneration {
+        team_id: uuid::Uuid,
         recurring_rule_id: uuid::Uuid,
     },
+    AppointmentReminderSweep {
+        team_id: uuid::Uuid,
+    },
+    LicenseExpirySweep {
+        team_id: uuid::Uuid,
+    },
+    OverdueInvoiceSweep {
+        team_id: uuid::Uuid,
+    },
+    RecurringJobSweep {
+        team_id: uuid::Uuid,
+    },
+    GpsRetentionSweep {
+        team_id: uuid::Uuid,
+    },
+    RecordPaymentFees {
+        team_id: uuid::Uuid,
+        payment_id: uuid::Uuid,
+    },
+    PublishEvent {
+        team_id: uuid::Uuid,
+        event_id: uuid::Uuid,
+        event_type: String,
+        occurred_at: chrono::DateTime<chrono::Utc>,
+        data: serde_json::Value,
+    },
+    DeliverWebhook {
+        team_id: uuid::Uuid,
+        webhook_id: uuid::Uuid,
+        event_id: uuid::Uuid,
+        event_type: String,
+        payload: serde_json::Value,
+        replay_of: Option<uuid::Uuid>,
+    },
+}
+
+impl BackgroundJob {
+    /// Stable name stored in `background_jobs.job_type`
+    pub fn job_type(&self) -> &'static str {
+        match self {
+            BackgroundJob::SendEmail { .. } => "send_email",
+            BackgroundJob::SendSms { .. } => "send_sms",
+            BackgroundJob::SendPasswordReset { .. } => "send_password_reset",
+            BackgroundJob::SendVerificationCode { .. } => "send_verification_code",
+            BackgroundJob::SendTrackingLink { .. } => "send_tracking_link",
+            BackgroundJob::GenerateInvoicePdf { .. } => "generate_invoice_pdf",
+            BackgroundJob::GenerateEstimatePdf { .. } => "generate_estimate_pdf",
+            BackgroundJob::ProcessPhoto { .. } => "process_photo",
+            BackgroundJob::SendPushNotification { .. } => "send_push_notification",
+            BackgroundJob::SyncQuickBooks { .. } => "sync_quickbooks",
+            BackgroundJob::RequestReview { .. } => "request_review",
+            BackgroundJob::ScheduleReminder { .. } => "schedule_reminder",
+            BackgroundJob::RecurringJobGeneration { .. } => "recurring_job_generation",
+            BackgroundJob::AppointmentReminderSweep { .. } => "appointment_reminder_sweep",
+            BackgroundJob::LicenseExpirySweep { .. } => "license_expiry_sweep",
+            BackgroundJob::OverdueInvoiceSweep { .. } => "overdue_invoice_sweep",
+            BackgroundJob::RecurringJobSweep { .. } => "recurring_job_sweep",
+            BackgroundJob::GpsRetentionSweep { .. } => "gps_retention_sweep",
+            BackgroundJob::RecordPaymentFees { .. } => "record_payment_fees",
+            BackgroundJob::PublishEvent { .. } => "publish_event",
+            BackgroundJob::DeliverWebhook { .. } => "deliver_webhook",
+        }
+    }
+
+    /// Owning team; only account mail and texts have none
+    pub fn team_id(&self) -> Option<Uuid> {
+        match self {
+            BackgroundJob::SendEmail { team_id, .. } | BackgroundJob::SendSms { team_id, .. } => *team_id,
+            BackgroundJob::SendPasswordReset { .. } | BackgroundJob::SendVerificationCode { .. } => None,
+            BackgroundJob::SendTrackingLink { team_id, .. }
+            | BackgroundJob::GenerateInvoicePdf { team_id, .. }
+            | BackgroundJob::GenerateEstimatePdf { team_id, .. }
+            | BackgroundJob::ProcessPhoto { team_id, .. }
+            | BackgroundJob::SendPushNotification { team_id, .. }
+            | BackgroundJob::SyncQuickBooks { team_id, .. }
+            | BackgroundJob::RequestReview { team_id, .. }
+            | BackgroundJob::ScheduleReminder { team_id, .. }
+            | BackgroundJob::RecurringJobGeneration { team_id, .. }
+            | BackgroundJob::RecordPaymentFees { team_id, .. }
+            | BackgroundJob::AppointmentReminderSweep { team_id }
+            | BackgroundJob::LicenseExpirySweep { team_id }
+            | BackgroundJob::OverdueInvoiceSweep { team_id }
+            | BackgroundJob::RecurringJobSweep { team_id }
+            | BackgroundJob::GpsRetentionSweep { team_id }
+            | BackgroundJob::PublishEvent { team_id, .. }
+            | BackgroundJob::DeliverWebhook { team_id, .. } => Some(*team_id),
+        }
+    }
+
+    /// Earliest time the job may run when enqueued without an explicit run-at
+    pub fn default_run_at(&self) -> DateTime<Utc> {
+        match self {
+            BackgroundJob::ScheduleReminder { remind_at, .. } => (*remind_at).max(Utc::now()),
+            _ => Utc::now(),
+        }
+    }
+}
+
+/// Rows deleted per statement when purging, so one purge never holds a long lock
+const PURGE_BATCH_SIZE: i64 = 5000;
+
+/// Enqueue a job on any executor so callers can make it part of their own transaction.
+/// With a `dedup_key`, a second enqueue of the same key is a no-op and returns `None`.
+pub async fn enqueue_with<'e, E>(
+    executor: E,
+    job: &BackgroundJob,
+    run_at: DateTime<Utc>,
+    max_attempts: i32,
+    dedup_key: Option<&str>,
+) -> ApiResult<Option<Uuid>>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let payload = serde_json::to_value(job)
+        .map_err(|e| ApiError::Internal(anyhow::anyhow!("Failed to serialize job: {}", e)))?;
+
+    let id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO background_jobs (team_id, job_type, payload, run_at, max_attempts, dedup_key)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (dedup_key) DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(job.team_id())
+    .bind(job.job_type())
+    .bind(&payload)
+    .bind(run_at)
+    .bind(max_attempts)
+    .bind(dedup_key)
+    .fetch_optional(executor)
+    .await?;
+
+    match id {
+        Some(id) => tracing::debug!(job_id = %id, job_type = job.job_type(), run_at = %run_at, "Background job enqueued"),
+        None => tracing::debug!(job_type = job.job_type(), dedup_key = ?dedup_key, "Duplicate background job skipped"),
+    }
+    Ok(id)
 }
 
-/// Job queue backed by a Tokio mpsc channel
+/// Handle for enqueueing durable background jobs backed by the `background_jobs` table
+#[derive(Clone)]
 pub struct JobQueue {
-    sender: mpsc::UnboundedSender<BackgroundJob>,
+    db: PgPool,
+    max_attempts: i32,
 }
 
 impl JobQueue {
-    pub fn new(state: Arc<AppState>) -> Self {
-        let (tx, rx) = mpsc::unbounded_channel();
-        tokio::spawn(process_jobs(rx, state));
-        Self { sender: tx }
+    pub fn new(db: PgPool, settings: &QueueSettings) -> Self {
+        Self {
+            db,
+            max_attempts: settings.max_attempts,
+        }
+    }
+
+    pub async fn enqueue(&self, job: BackgroundJob) -> ApiResult<Option<Uuid>> {
+        let run_at = job.default_run_at();
+        self.enqueue_at(job, run_at).await
+    }
+
+    pub async fn enqueue_at(&self, job: BackgroundJob, run_at: DateTime<Utc>) -> ApiResult<Option<Uuid>> {
+        enqueue_with(&self.db, &job, run_at, self.max_attempts, None).await
     }
 
-    pub fn enqueue(&self, job: BackgroundJob) -> Result<(), String> {
-        self.sender
-            .send(job)
-            .map_err(|e| format!("Failed to enqueue job: {}", e))
+    pub async fn enqueue_unique(&self, job: BackgroundJob, run_at: DateTime<Utc>, dedup_key: &str) -> ApiResult<Option<Uuid>> {
+        enqueue_with(&self.db, &job, run_at, self.max_attempts, Some(dedup_key)).await
     }
+
+    pub fn max_attempts(&self) -> i32 {
+        self.max_attempts
+    }
+}
+
+/// Start the queue worker for this replica. Safe to run on every replica: claims are
+/// serialized per job type and rows are locked with SKIP LOCKED.
+pub fn spawn_worker(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(process_jobs(state))
 }
 
-async fn process_jobs(mut rx: mpsc::UnboundedReceiver<BackgroundJob>, state: Arc<AppState>) {
-    tracing::info!("Background job processor started");
+async fn process_jobs(state: Arc<AppState>) {
+    let settings = state.config.queue.clone();
+    let worker_id = format!("worker-{}", Uuid::new_v4());
+    let permits = Arc::new(Semaphore::new(settings.worker_concurrency));
+    let mut interval = tokio::time::interval(Duration::from_millis(settings.poll_interval_ms));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-    while let Some(job) = rx.recv().await {
-        let state = state.clone();
-        tokio::spawn(async move {
-            if let Err(e) = execute_job(&job, &state).await {
-                tracing::error!(?job, error = %e, "Background job failed");
-                // TODO: implement retry with exponential backoff
-                // TODO: move to dead letter queue after max retries
+    tracing::info!(worker_id = %worker_id, "Background job processor started");
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = requeue_stale_jobs(&state.db, &settings).await {
+            tracing::error!(error = %e, "Failed to requeue stale background jobs");
+        }
+
+        let ready_types = match sqlx::query_scalar::<_, String>(
+            "SELECT DISTINCT job_type FROM background_jobs WHERE status = 'queued' AND run_at <= now()",
+        )
+        .fetch_all(&state.db)
+        .await
+        {
+            Ok(types) => types,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to poll background jobs");
+                continue;
             }
-        });
+        };
+
+        for job_type in ready_types {
+            let available = permits.available_permits() as i64;
+            if available == 0 {
+                break;
+            }
+
+            let claimed = match claim_jobs(&state.db, &settings, &job_type, available, &worker_id).await {
+                Ok(claimed) => claimed,
+                Err(e) => {
+                    tracing::error!(job_type = %job_type, error = %e, "Failed to claim background jobs");
+                    continue;
+                }
+            };
+
+            for record in claimed {
+                let permit = match permits.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        release_job(&state.db, record.id, &worker_id).await;
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                let worker_id = worker_id.clone();
+                tokio::spawn(async move {
+                    run_claimed_job(&state, record, &worker_id).await;
+                    drop(permit);
+                });
+            }
+        }
+    }
+}
+
+/// Claim up to `capacity` due jobs of one type, respecting the per-type concurrency limit.
+/// The advisory lock serializes claimers of the same type across replicas so the running
+/// count cannot be raced past the limit.
+async fn claim_jobs(
+    pool: &PgPool,
+    settings: &QueueSettings,
+    job_type: &str,
+    capacity: i64,
+    worker_id: &str,
+) -> ApiResult<Vec<BackgroundJobRecord>> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(format!("background_jobs:{}", job_type))
+        .execute(&mut *tx)
+        .await?;
+
+    let running = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM background_jobs WHERE job_type = $1 AND status = 'running'",
+    )
+    .bind(job_type)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let limit = settings.type_concurrency.get(job_type).copied().unwrap_or(settings.default_type_concurrency);
+    let slots = (limit - running).min(capacity);
+    if slots <= 0 {
+        tx.commit().await?;
+        return Ok(Vec::new());
+    }
+
+    let claimed = sqlx::query_as::<_, BackgroundJobRecord>(
+        r#"
+        UPDATE background_jobs SET
+            status = 'running',
+            attempts = attempts + 1,
+            locked_by = $3,
+            locked_at = now(),
+            heartbeat_at = now()
+        WHERE id IN (
+            SELECT id FROM background_jobs
+            WHERE job_type = $1 AND status = 'queued' AND run_at <= now()
+            ORDER BY run_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(job_type)
+    .bind(slots)
+    .bind(worker_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(claimed)
+}
+
+/// Hand a claimed job back without counting the attempt (local capacity ran out).
+async fn release_job(pool: &PgPool, id: Uuid, worker_id: &str) {
+    let result = sqlx::query(
+        r#"
+        UPDATE background_jobs SET status = 'queued', attempts = attempts - 1, locked_by = NULL, locked_at = NULL, heartbeat_at = NULL
+        WHERE id = $1 AND locked_by = $2 AND status = 'running'
+        "#,
+    )
+    .bind(id)
+    .bind(worker_id)
+    .execute(pool)
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!(job_id = %id, error = %e, "Failed to release background job");
+    }
+}
+
+async fn run_claimed_job(state: &AppState, record: BackgroundJobRecord, worker_id: &str) {
+    let work = async {
+        match serde_json::from_value::<BackgroundJob>(record.payload.clone()) {
+            Ok(job) => execute_job(&job, state).await,
+            Err(e) => Err(format!("Undecodable payload: {}", e)),
+        }
+    };
+
+    // Losing the lease drops `work`, so the job stops before another worker can run it too
+    let result = tokio::select! {
+        result = work => result,
+        reason = hold_lease(&state.db, record.id, worker_id, &state.config.queue) => {
+            tracing::warn!(job_id = %record.id, job_type = %record.job_type, reason, "Background job abandoned");
+            return;
+        }
+    };
+
+    let outcome = match result {
+        Ok(()) => mark_succeeded(&state.db, record.id, worker_id).await,
+        Err(error) => {
+            tracing::error!(job_id = %record.id, job_type = %record.job_type, attempt = record.attempts, error = %error, "Background job failed");
+            mark_failed(&state.db, &record, worker_id, &error, &state.config.queue).await
+        }
+    };
+
+    match outcome {
+        Ok(true) => {}
+        Ok(false) => tracing::warn!(job_id = %record.id, "Background job lease lost before its outcome was recorded"),
+        Err(e) => tracing::error!(job_id = %record.id, error = %e, "Failed to record background job outcome"),
+    }
+}
+
+/// Heartbeat a running job for as long as this worker holds it. Returns once it doesn't:
+/// the job was requeued as stale (and maybe claimed elsewhere), or enough heartbeats in a
+/// row failed that it soon will be. Beats are spaced so the last allowed failure comes
+/// before the heartbeat timeout.
+async fn hold_lease(pool: &PgPool, id: Uuid, worker_id: &str, settings: &QueueSettings) -> &'static str {
+    let timeout_ms = (settings.heartbeat_timeout_secs * 1000) as u64;
+    let every = Duration::from_millis((timeout_ms / (u64::from(settings.heartbeat_max_failures) + 1)).max(1));
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+    let mut failures = 0;
+    loop {
+        interval.tick().await;
+        let result = sqlx::query(
+            "UPDATE background_jobs SET heartbeat_at = now() WHERE id = $1 AND locked_by = $2 AND status = 'running'",
+        )
+        .bind(id)
+        .bind(worker_id)
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(r) if r.rows_affected() == 0 => return "lease lost",
+            Ok(_) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                tracing::warn!(job_id = %id, error = %e, failures, "Background job heartbeat failed");
+                if failures >= settings.heartbeat_max_failures {
+                    return "heartbeats failing";
+                }
+            }
+        }
+    }
+}
+
+/// `false` if the job is no longer this worker's
+async fn mark_succeeded(pool: &PgPool, id: Uuid, worker_id: &str) -> ApiResult<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE background_jobs SET status = 'succeeded', completed_at = now(), last_error = NULL,
+            locked_by = NULL, locked_at = NULL, heartbeat_at = NULL
+        WHERE id = $1 AND locked_by = $2 AND status = 'running'
+        "#,
+    )
+    .bind(id)
+    .bind(worker_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Schedule a retry with exponential backoff, or move the job to the dead-letter state
+/// once it has used up its attempts. `false` if the job is no longer this worker's.
+async fn mark_failed(pool: &PgPool, record: &BackgroundJobRecord, worker_id: &str, error: &str, settings: &QueueSettings) -> ApiResult<bool> {
+    if record.attempts >= record.max_attempts {
+        let result = sqlx::query(
+            r#"
+            UPDATE background_jobs SET status = 'dead', last_error = $3,
+                locked_by = NULL, locked_at = NULL, heartbeat_at = NULL
+            WHERE id = $1 AND locked_by = $2 AND status = 'running'
+            "#,
+        )
+        .bind(record.id)
+        .bind(worker_id)
+        .bind(error)
+        .execute(pool)
+        .await?;
+        if result.rows_affected() > 0 {
+            tracing::warn!(job_id = %record.id, job_type = %record.job_type, "Background job moved to dead letter");
+        }
+        return Ok(result.rows_affected() > 0);
+    }
+
+    let delay = retry_delay(record.attempts, settings);
+    let result = sqlx::query(
+        r#"
+        UPDATE background_jobs SET status = 'queued', last_error = $3, run_at = $4,
+            locked_by = NULL, locked_at = NULL, heartbeat_at = NULL
+        WHERE id = $1 AND locked_by = $2 AND status = 'running'
+        "#,
+    )
+    .bind(record.id)
+    .bind(worker_id)
+    .bind(error)
+    .bind(Utc::now() + delay)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// base * 2^(attempt - 1), capped, with up to 20% jitter so retries from a burst spread out
+fn retry_delay(attempt: i32, settings: &QueueSettings) -> chrono::Duration {
+    let exp = (attempt - 1).clamp(0, 20) as u32;
+    let secs = settings
+        .backoff_base_secs
+        .saturating_mul(2_i64.saturating_pow(exp))
+        .min(settings.backoff_max_secs);
+    let jitter = rand::thread_rng().gen_range(0..=secs / 5);
+    chrono::Duration::seconds(secs + jitter)
+}
+
+/// Re-queue jobs whose worker stopped heartbeating (crash, deploy, lost connection).
+/// Jobs already out of attempts go straight to the dead-letter state.
+async fn requeue_stale_jobs(pool: &PgPool, settings: &QueueSettings) -> ApiResult<()> {
+    let result = sqlx::query(
+        r#"
+        UPDATE background_jobs SET
+            status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'queued' END,
+            last_error = 'Worker heartbeat lost',
+            locked_by = NULL, locked_at = NULL, heartbeat_at = NULL
+        WHERE status = 'running' AND heartbeat_at < now() - make_interval(secs => $1)
+        "#,
+    )
+    .bind(settings.heartbeat_timeout_secs as f64)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        tracing::warn!(count = result.rows_affected(), "Requeued background jobs with stale heartbeats");
+    }
+    Ok(())
+}
+
+/// Delete succeeded jobs, and dead jobs nobody retried, once they are past retention.
+/// Their dedup keys go with them, so the unique index doesn't grow with every tick.
+pub async fn purge_finished(pool: &PgPool, settings: &QueueSettings) -> ApiResult<u64> {
+    let mut purged = 0;
+    loop {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM background_jobs WHERE id IN (
+                SELECT id FROM background_jobs
+                WHERE (status = 'succeeded' AND completed_at < now() - make_interval(days => $1))
+                   OR (status = 'dead' AND updated_at < now() - make_interval(days => $2))
+                LIMIT $3
+            )
+            "#,
+        )
+        .bind(settings.retention_days)
+        .bind(settings.dead_retention_days)
+        .bind(PURGE_BATCH_SIZE)
+        .execute(pool)
+        .await?;
+
+        purged += result.rows_affected();
+        if result.rows_affected() < PURGE_BATCH_SIZE as u64 {
+            return Ok(purged);
+        }
     }
+}
 
-    tracing::warn!("Background job processor stopped");
+/// Counts of jobs per status, used by the admin listing
+pub async fn status_counts(pool: &PgPool, team_id: Uuid) -> ApiResult<HashMap<String, i64>> {
+    let rows = sqlx::query_as::<_, (String, i64)>(
+        "SELECT status, COUNT(*) FROM background_jobs WHERE team_id = $1 GROUP BY status",
+    )
+    .bind(team_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().collect())
 }
 
-async fn execute_job(job: &BackgroundJob, _state: &AppState) -> Result<(), String> {
+async fn execute_job(job: &BackgroundJob, state: &AppState) -> Result<(), String> {
     match job {
-        BackgroundJob::SendEmail { to, subject, .. } => {
+        BackgroundJob::SendEmail { to, subject, body, .. } => {
             tracing::info!(to = %to, subject = %subject, "Sending email");
-            // TODO: integrate with SendGrid
-            Ok(())
+            state.messenger.send_email(to, subject, body).await.map_err(|e| format!("{:#}", e))
         }
-        BackgroundJob::SendSms { to, .. } => {
+        BackgroundJob::SendSms { to, body, .. } => {
             tracing::info!(to = %to, "Sending SMS");
-            // TODO: integrate with Twilio
-            Ok(())
+            state.messenger.send_sms(to, body).await.map_err(|e| format!("{:#}", e))
         }
-        BackgroundJob::GenerateInvoicePdf { invoice_id } => {
+        BackgroundJob::SendPasswordReset { reset_id } => {
+            verification_service::deliver_password_reset(state, *reset_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::SendVerificationCode { code_id } => {
+            verification_service::deliver_verification_code(state, *code_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::SendTrackingLink { link_id, .. } => {
+            tracking::deliver_link(state, *link_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::GenerateInvoicePdf { invoice_id, .. } => {
             tracing::info!(invoice_id = %invoice_id, "Generating invoice PDF");
-            // TODO: generate PDF with WeasyPrint or similar
-            Ok(())
+            document_pdf::generate(state, DocumentKind::Invoice, *invoice_id)
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("{:#}", e))
         }
-        BackgroundJob::ProcessPhoto { photo_id } => {
+        BackgroundJob::GenerateEstimatePdf { estimate_id, .. } => {
+            tracing::info!(estimate_id = %estimate_id, "Generating estimate PDF");
+            document_pdf::generate(state, DocumentKind::Estimate, *estimate_id)
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("{:#}", e))
+        }
+        BackgroundJob::ProcessPhoto { photo_id, .. } => {
             tracing::info!(photo_id = %photo_id, "Processing photo (resize, watermark, thumbnail)");
             // TODO: process with image crate
             Ok(())
@@ -113,20 +644,111 @@ async fn execute_job(job: &BackgroundJob, _state: &AppState) -> Result<(), Strin
             // TODO: integrate with QuickBooks Online API
             Ok(())
         }
-        BackgroundJob::RequestReview { customer_id, job_id } => {
+        BackgroundJob::RequestReview { customer_id, job_id, .. } => {
             tracing::info!(customer_id = %customer_id, job_id = %job_id, "Requesting review from customer");
-            // TODO: send review request SMS/email
-            Ok(())
+            sweeps::send_review_request(state, *job_id).await.map_err(|e| e.to_string())
         }
-        BackgroundJob::ScheduleReminder { job_id, remind_at } => {
-            tracing::info!(job_id = %job_id, remind_at = %remind_at, "Scheduling reminder");
-            // TODO: schedule delayed task
-            Ok(())
+        BackgroundJob::ScheduleReminder { job_id, remind_at, .. } => {
+            if *remind_at > Utc::now() + chrono::Duration::seconds(5) {
+                // Enqueued without going through default_run_at; wait for the reminder time
+                state
+                    .jobs
+                    .enqueue_at(job.clone(), *remind_at)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                return Ok(());
+            }
+            tracing::info!(job_id = %job_id, remind_at = %remind_at, "Sending appointment reminder");
+            sweeps::send_appointment_reminder(state, *job_id).await.map_err(|e| e.to_string())
         }
-        BackgroundJob::RecurringJobGeneration { recurring_rule_id } => {
+        BackgroundJob::RecurringJobGeneration { recurring_rule_id, .. } => {
             tracing::info!(recurring_rule_id = %recurring_rule_id, "Generating recurring job instances");
-            // TODO: generate next job instances from RRULE
-            Ok(())
+            sweeps::generate_recurring(state, *recurring_rule_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::AppointmentReminderSweep { team_id } => {
+            sweeps::appointment_reminders(state, *team_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::LicenseExpirySweep { team_id } => {
+            sweeps::license_expiry(state, *team_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::OverdueInvoiceSweep { team_id } => {
+            sweeps::overdue_invoices(state, *team_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::RecurringJobSweep { team_id } => {
+            sweeps::recurring_jobs(state, *team_id).await.map_err(|e| e.to_string())
         }
+        BackgroundJob::GpsRetentionSweep { team_id } => {
+            sweeps::gps_retention(state, *team_id).await.map_err(|e| e.to_string())
+        }
+        BackgroundJob::RecordPaymentFees { payment_id, .. } => {
+            payment_service::record_fees(state, *payment_id).await.map_err(|e| format!("{:#}", e))
+        }
+        BackgroundJob::PublishEvent { team_id, event_id, event_type, occurred_at, data } => {
+            events::fan_out(state, *team_id, *event_id, event_type, *occurred_at, data)
+                .await
+                .map_err(|e| format!("{:#}", e))
+        }
+        BackgroundJob::DeliverWebhook { webhook_id, event_id, event_type, payload, replay_of, .. } => {
+            webhook_service::deliver(state, *webhook_id, *event_id, event_type, payload, *replay_of)
+                .await
+                .map_err(|e| format!("{:#}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    async fn queue_settings(db: &PgPool) -> QueueSettings {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        QueueSettings { heartbeat_timeout_secs: 1, ..state.config.queue.clone() }
+    }
+
+    async fn enqueue_email(db: &PgPool) -> Uuid {
+        let job = BackgroundJob::SendEmail { team_id: None, to: "dana@example.com".into(), subject: "Hi".into(), body: "Hello".into() };
+        enqueue_with(db, &job, Utc::now(), 5, None).await.unwrap().unwrap()
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn claims_stop_at_the_configured_limit(db: PgPool) {
+        let mut settings = queue_settings(&db).await;
+        settings.type_concurrency.insert("send_email".into(), 1);
+        enqueue_email(&db).await;
+        enqueue_email(&db).await;
+
+        assert_eq!(claim_jobs(&db, &settings, "send_email", 5, "worker-a").await.unwrap().len(), 1);
+        assert!(claim_jobs(&db, &settings, "send_email", 5, "worker-b").await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn a_worker_lets_go_once_its_job_is_requeued(db: PgPool) {
+        let settings = queue_settings(&db).await;
+        let id = enqueue_email(&db).await;
+        claim_jobs(&db, &settings, "send_email", 1, "worker-a").await.unwrap();
+        let lease = tokio::spawn({
+            let (db, settings) = (db.clone(), settings.clone());
+            async move { hold_lease(&db, id, "worker-a", &settings).await }
+        });
+
+        // Worker A stalls long enough to be requeued, and worker B picks the job up
+        sqlx::query("UPDATE background_jobs SET heartbeat_at = now() - interval '1 hour' WHERE id = $1")
+            .bind(id)
+            .execute(&db)
+            .await
+            .unwrap();
+        requeue_stale_jobs(&db, &settings).await.unwrap();
+        assert_eq!(claim_jobs(&db, &settings, "send_email", 1, "worker-b").await.unwrap().len(), 1);
+
+        let reason = tokio::time::timeout(Duration::from_secs(2), lease).await.expect("worker A gives up the job").unwrap();
+        assert_eq!(reason, "lease lost");
+        assert!(!mark_succeeded(&db, id, "worker-a").await.unwrap());
+        assert!(mark_succeeded(&db, id, "worker-b").await.unwrap());
+        let status: String = sqlx::query_scalar("SELECT status FROM background_jobs WHERE id = $1").bind(id).fetch_one(&db).await.unwrap();
+        assert_eq!(status, "succeeded");
     }
 }
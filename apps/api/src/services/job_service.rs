@@ -1,3 +1,14 @@
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::db::repository;
+use crate::errors::{ApiError, ApiResult};
+use crate::models::job::{Job, JobStatusTransition};
+use crate::services::events;
+use crate::services::realtime::{self, Audience, WsEvent};
+use crate::services::side_effects::{self, EffectContext, EffectOutcome};
+use crate::AppState;
+
 /// Valid state transitions for the job lifecycle FSM.
 /// Lead → Estimated → Approved → Scheduled → EnRoute → InProgress → Paused → Completed → Invoiced → Paid → Closed
 pub fn is_valid_transition(from: &str, to: &str) -> bool {
@@ -33,6 +44,8 @@ pub fn get_transition_side_effects(from: &str, to: &str) -> Vec<&'static str> {
     match (from, to) {
         ("approved", "scheduled") => vec!["send_confirmation", "add_to_calendar"],
         ("scheduled", "en_route") => vec!["start_gps_tracking", "send_customer_eta"],
+        // Starting without going en route first, by hand or on a geofence arrival
+        ("scheduled", "in_progress") => vec!["start_time_tracking", "geofence_checkin"],
         ("en_route", "in_progress") => vec!["stop_navigation", "start_time_tracking", "geofence_checkin"],
         ("in_progress", "paused") => vec!["pause_time_tracking"],
         ("paused", "in_progress") => vec!["resume_time_tracking"],
@@ -42,3 +55,121 @@ pub fn get_transition_side_effects(from: &str, to: &str) -> Vec<&'static str> {
         _ => vec![],
     }
 }
+
+/// Apply a status transition and run its side effects in one transaction.
+///
+/// The job row is locked for the duration so concurrent transitions serialize; side
+/// effects that fail are rolled back to their savepoint and reported, not propagated.
+pub async fn transition_job(
+    state: &AppState,
+    team_id: Uuid,
+    job_id: Uuid,
+    actor_id: Uuid,
+    transition: &JobStatusTransition,
+) -> ApiResult<(Job, Vec<EffectOutcome>)> {
+    let mut tx = state.db.begin().await?;
+
+    let mut ctx = sqlx::query_as::<_, EffectContext>(
+        r#"
+        SELECT id AS job_id, team_id, customer_id, property_id, assigned_to, title, status::text AS status,
+               scheduled_date, scheduled_start_time
+        FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+    )
+    .bind(job_id)
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Job".into()))?;
+
+    if !is_valid_transition(&ctx.status, &transition.status) {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid status transition: {} → {}",
+            ctx.status, transition.status
+        )));
+    }
+
+    ctx.actor_id = actor_id;
+    ctx.latitude = transition.latitude;
+    ctx.longitude = transition.longitude;
+    ctx.geofence_event_id = transition.geofence_event_id;
+
+    let updated = repository::update_job_status(&mut tx, team_id, job_id, &ctx.status, actor_id, transition).await?;
+
+    let effects = get_transition_side_effects(&ctx.status, &transition.status);
+    let outcomes = side_effects::run_all(state, &mut tx, &ctx, &effects).await?;
+
+    let data = json!({
+        "job_id": job_id,
+        "customer_id": ctx.customer_id,
+        "from": ctx.status,
+        "to": transition.status,
+        "changed_by": actor_id,
+    });
+    events::publish(&mut *tx, state, team_id, events::JOB_STATUS_CHANGED, data).await?;
+
+    tx.commit().await?;
+
+    let event = WsEvent::JobStatusChanged {
+        job_id: job_id.to_string(),
+        old_status: ctx.status.clone(),
+        new_status: transition.status.clone(),
+    };
+    realtime::publish(state, Audience::team(team_id).job(Some(job_id)).user(ctx.assigned_to), event).await;
+
+    Ok((updated, outcomes))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::services::side_effects::EffectStatus;
+    use crate::test_support;
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn starting_a_job_opens_a_timer_at_the_team_rate(db: PgPool) {
+        let team_id = test_support::team(&db, "acme").await;
+        let tech_id = test_support::member(&db, team_id, "tech@example.com", "technician").await;
+        let customer_id = test_support::customer(&db, team_id).await;
+        let job_id = test_support::job(&db, team_id, customer_id, "scheduled", Some(tech_id)).await;
+        // The user's own rate belongs to their default team; this team pays more
+        sqlx::query("UPDATE users SET hourly_rate = 40 WHERE id = $1").bind(tech_id).execute(&db).await.unwrap();
+        sqlx::query("UPDATE team_memberships SET hourly_rate = 85 WHERE user_id = $1").bind(tech_id).execute(&db).await.unwrap();
+
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let transition = JobStatusTransition {
+            status: "in_progress".into(),
+            latitude: None,
+            longitude: None,
+            note: None,
+            geofence_event_id: None,
+        };
+        let (job, outcomes) = transition_job(&state, team_id, job_id, tech_id, &transition).await.unwrap();
+
+        assert_eq!(job.status, "in_progress");
+        assert_eq!(job.priority, "normal");
+        assert!(job.started_at.is_some());
+        let timer = outcomes.iter().find(|o| o.effect == "start_time_tracking").unwrap();
+        assert_eq!(timer.status, EffectStatus::Succeeded);
+
+        let rate: Option<Decimal> = sqlx::query_scalar("SELECT hourly_rate FROM time_entries WHERE job_id = $1 AND ended_at IS NULL")
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(rate, Some(Decimal::from(85)));
+
+        let history: String = sqlx::query_scalar("SELECT to_status::text FROM job_status_history WHERE job_id = $1")
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(history, "in_progress");
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::config::Settings;
+
+/// Delivers outbound email and SMS. The queue worker is the only caller, so sends are
+/// retried with the job's backoff when an implementation returns an error.
+#[async_trait]
+pub trait MessageSender: Send + Sync {
+    async fn send_email(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()>;
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()>;
+}
+
+/// Pick the sender named by `MESSAGE_SENDER`: `provider` (SendGrid + Twilio), `capture`
+/// (in-memory, for tests) or `log` (the default, for local development)
+pub fn from_settings(settings: &Settings) -> anyhow::Result<Arc<dyn MessageSender>> {
+    match settings.messaging.sender.as_str() {
+        "provider" => Ok(Arc::new(ProviderSender::new(settings))),
+        "capture" => Ok(Arc::new(CaptureSender::default())),
+        "log" => Ok(Arc::new(LogSender)),
+        other => Err(anyhow!("Unknown MESSAGE_SENDER '{}'; expected provider, capture or log", other)),
+    }
+}
+
+/// Sends email through SendGrid and SMS through Twilio
+pub struct ProviderSender {
+    client: reqwest::Client,
+    sendgrid_api_key: String,
+    from_email: String,
+    twilio_account_sid: String,
+    twilio_auth_token: String,
+    twilio_from: String,
+}
+
+impl ProviderSender {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            sendgrid_api_key: settings.sendgrid.api_key.clone(),
+            from_email: settings.sendgrid.from_email.clone(),
+            twilio_account_sid: settings.twilio.account_sid.clone(),
+            twilio_auth_token: settings.twilio.auth_token.clone(),
+            twilio_from: settings.twilio.phone_number.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageSender for ProviderSender {
+    async fn send_email(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
+        if self.sendgrid_api_key.is_empty() {
+            return Err(anyhow!("SENDGRID_API_KEY is not configured"));
+        }
+
+        let payload = json!({
+            "personalizations": [{ "to": [{ "email": to }] }],
+            "from": { "email": self.from_email },
+            "subject": subject,
+            "content": [{ "type": "text/plain", "value": body }],
+        });
+
+        self.client
+            .post("https://api.sendgrid.com/v3/mail/send")
+            .bearer_auth(&self.sendgrid_api_key)
+            .json(&payload)
+            .send()
+            .await
+            .context("SendGrid request failed")?
+            .error_for_status()
+            .context("SendGrid rejected the message")?;
+        Ok(())
+    }
+
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        if self.twilio_account_sid.is_empty() {
+            return Err(anyhow!("TWILIO_ACCOUNT_SID is not configured"));
+        }
+
+        let url = format!(
+            "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+            self.twilio_account_sid
+        );
+        self.client
+            .post(url)
+            .basic_auth(&self.twilio_account_sid, Some(&self.twilio_auth_token))
+            .form(&[("To", to), ("From", self.twilio_from.as_str()), ("Body", body)])
+            .send()
+            .await
+            .context("Twilio request failed")?
+            .error_for_status()
+            .context("Twilio rejected the message")?;
+        Ok(())
+    }
+}
+
+/// Writes messages to the log instead of delivering them
+pub struct LogSender;
+
+#[async_trait]
+impl MessageSender for LogSender {
+    async fn send_email(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
+        tracing::info!(to = %to, subject = %subject, body = %body, "Email (not delivered)");
+        Ok(())
+    }
+
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        tracing::info!(to = %to, body = %body, "SMS (not delivered)");
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Email,
+    Sms,
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedMessage {
+    pub channel: Channel,
+    pub to: String,
+    pub subject: Option<String>,
+    pub body: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// Keeps every message in memory so tests can read links and codes back out
+#[derive(Clone, Default)]
+pub struct CaptureSender {
+    messages: Arc<Mutex<Vec<CapturedMessage>>>,
+}
+
+impl CaptureSender {
+    pub fn messages(&self) -> Vec<CapturedMessage> {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Most recent message sent to `to`
+    pub fn last_to(&self, to: &str) -> Option<CapturedMessage> {
+        self.messages().into_iter().rev().find(|m| m.to == to)
+    }
+
+    fn push(&self, channel: Channel, to: &str, subject: Option<&str>, body: &str) {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner()).push(CapturedMessage {
+            channel,
+            to: to.to_string(),
+            subject: subject.map(str::to_string),
+            body: body.to_string(),
+            sent_at: Utc::now(),
+        });
+    }
+}
+
+#[async_trait]
+impl MessageSender for CaptureSender {
+    async fn send_email(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
+        self.push(Channel::Email, to, Some(subject), body);
+        Ok(())
+    }
+
+    async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        self.push(Channel::Sms, to, None, body);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn capture_keeps_what_was_sent() {
+        let outbox = CaptureSender::default();
+        let sender: Arc<dyn MessageSender> = Arc::new(outbox.clone());
+
+        sender.send_email("dana@example.com", "Your invoice", "Invoice INV-1001 is ready").await.unwrap();
+        sender.send_sms("+15555550100", "Your technician is on the way").await.unwrap();
+        sender.send_email("dana@example.com", "Receipt", "Thanks for your payment").await.unwrap();
+
+        let messages = outbox.messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].channel, Channel::Sms);
+        assert_eq!(messages[1].to, "+15555550100");
+        assert_eq!(messages[1].subject, None);
+        assert_eq!(messages[1].body, "Your technician is on the way");
+
+        let last = outbox.last_to("dana@example.com").unwrap();
+        assert_eq!(last.channel, Channel::Email);
+        assert_eq!(last.subject.as_deref(), Some("Receipt"));
+        assert_eq!(last.body, "Thanks for your payment");
+        assert!(outbox.last_to("nobody@example.com").is_none());
+    }
+}
@@ -0,0 +1,498 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::services::auth_service;
+
+const TOTP_PERIOD_SECS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// Steps either side of now that are still accepted, to absorb clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+/// Wrong codes in a row, across all of a user's challenges, before the factor locks
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+const LOCKOUT_MINUTES: i32 = 15;
+const NONCE_LEN: usize = 12;
+const ISSUER: &str = "FieldForge";
+
+/// New 160-bit TOTP secret, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// Seal a TOTP secret for storage: AES-256-GCM with a random nonce, bound to the user so a
+/// ciphertext copied onto another account won't open. Stored as base64 of nonce ‖ ciphertext.
+pub fn seal_secret(key: &[u8; 32], user_id: Uuid, secret: &str) -> ApiResult<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = Aes256Gcm::new(key.into())
+        .encrypt(&Nonce::from(nonce), Payload { msg: secret.as_bytes(), aad: user_id.as_bytes() })
+        .map_err(|_| ApiError::Internal(anyhow::anyhow!("Failed to seal TOTP secret")))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(sealed))
+}
+
+pub fn open_secret(key: &[u8; 32], user_id: Uuid, sealed: &str) -> ApiResult<String> {
+    let unreadable = || ApiError::Internal(anyhow::anyhow!("Stored TOTP secret could not be decrypted"));
+
+    let bytes = STANDARD.decode(sealed).map_err(|_| unreadable())?;
+    if bytes.len() <= NONCE_LEN {
+        return Err(unreadable());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let nonce = <[u8; NONCE_LEN]>::try_from(nonce).map_err(|_| unreadable())?;
+    let secret = Aes256Gcm::new(key.into())
+        .decrypt(&Nonce::from(nonce), Payload { msg: ciphertext, aad: user_id.as_bytes() })
+        .map_err(|_| unreadable())?;
+    String::from_utf8(secret).map_err(|_| unreadable())
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `otpauth://` URI for rendering as a QR code in the enrollment screen
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = percent_encode(ISSUER),
+        account = percent_encode(account),
+        secret = secret,
+        digits = TOTP_DIGITS,
+        period = TOTP_PERIOD_SECS,
+    )
+}
+
+/// RFC 4226 HOTP value for one counter step
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+        & 0x7fff_ffff;
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Check a TOTP code against the steps around `now`. Returns the matching step so the
+/// caller can refuse it next time; steps at or before `last_used_step` never match.
+pub fn verify_totp(secret: &str, code: &str, now: DateTime<Utc>, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let expected: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let current = now.timestamp() / TOTP_PERIOD_SECS;
+    (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS)
+        .filter(|step| *step >= 0 && last_used_step.map_or(true, |last| *step > last))
+        .find(|step| hotp(&key, *step as u64) == expected)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_ascii_lowercase().replace(['-', ' '], "")
+}
+
+fn generate_recovery_code() -> String {
+    let raw: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    format!("{}-{}", &raw[..5], &raw[5..])
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct TotpEnrollment {
+    pub secret_ciphertext: String,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+const ENROLLMENT_QUERY: &str =
+    "SELECT secret_ciphertext, confirmed_at, last_used_step, locked_until FROM user_totp WHERE user_id = $1";
+
+pub async fn get_enrollment(conn: &mut PgConnection, user_id: Uuid) -> ApiResult<Option<TotpEnrollment>> {
+    let enrollment = sqlx::query_as::<_, TotpEnrollment>(ENROLLMENT_QUERY)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(enrollment)
+}
+
+/// The enrollment, locked so concurrent attempts for one user are counted one at a time
+async fn lock_enrollment(conn: &mut PgConnection, user_id: Uuid) -> ApiResult<Option<TotpEnrollment>> {
+    let enrollment = sqlx::query_as::<_, TotpEnrollment>(&format!("{} FOR UPDATE", ENROLLMENT_QUERY))
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(enrollment)
+}
+
+pub async fn is_enrolled(pool: &PgPool, user_id: Uuid) -> ApiResult<bool> {
+    let enrolled = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL)",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(enrolled)
+}
+
+/// Start (or restart) enrollment with a fresh secret. Refused once TOTP is confirmed;
+/// disable it first to switch devices.
+pub async fn begin_enrollment(pool: &PgPool, key: &[u8; 32], user_id: Uuid) -> ApiResult<String> {
+    let secret = generate_secret();
+
+    let stored = sqlx::query(
+        r#"
+        INSERT INTO user_totp (user_id, secret_ciphertext)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET secret_ciphertext = EXCLUDED.secret_ciphertext, last_used_step = NULL
+        WHERE user_totp.confirmed_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(seal_secret(key, user_id, &secret)?)
+    .execute(pool)
+    .await?;
+
+    if stored.rows_affected() == 0 {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled".into()));
+    }
+    Ok(secret)
+}
+
+/// Replace the user's recovery codes, returning the new plaintext codes once
+async fn replace_recovery_codes(conn: &mut PgConnection, user_id: Uuid) -> ApiResult<Vec<String>> {
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| auth_service::hash_token(&normalize_recovery_code(c)))
+        .collect();
+
+    sqlx::query("INSERT INTO user_recovery_codes (user_id, code_hash) SELECT $1, unnest($2::text[])")
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(codes)
+}
+
+/// Check a TOTP code against the stored secret and record its step
+async fn check_totp(conn: &mut PgConnection, key: &[u8; 32], user_id: Uuid, enrollment: &TotpEnrollment, code: &str) -> ApiResult<bool> {
+    let secret = open_secret(key, user_id, &enrollment.secret_ciphertext)?;
+    let Some(step) = verify_totp(&secret, code, Utc::now(), enrollment.last_used_step) else {
+        return Ok(false);
+    };
+    sqlx::query("UPDATE user_totp SET last_used_step = $2 WHERE user_id = $1")
+        .bind(user_id)
+        .bind(step)
+        .execute(&mut *conn)
+        .await?;
+    Ok(true)
+}
+
+/// Finish enrollment with a code from the authenticator app. Returns recovery codes.
+pub async fn confirm_enrollment(pool: &PgPool, key: &[u8; 32], user_id: Uuid, code: &str) -> ApiResult<Vec<String>> {
+    let mut tx = pool.begin().await?;
+
+    let enrollment = get_enrollment(&mut tx, user_id)
+        .await?
+        .ok_or_else(|| ApiError::BadRequest("Start two-factor setup first".into()))?;
+    if enrollment.confirmed_at.is_some() {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled".into()));
+    }
+    if !check_totp(&mut tx, key, user_id, &enrollment, code).await? {
+        return Err(ApiError::Validation("Incorrect authentication code".into()));
+    }
+
+    sqlx::query("UPDATE user_totp SET confirmed_at = now() WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let codes = replace_recovery_codes(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(user_id = %user_id, "Two-factor authentication enabled");
+    Ok(codes)
+}
+
+/// Verify a second factor: a current TOTP code, or failing that an unused recovery
+/// code, which is spent. Wrong codes count towards a lockout however many login
+/// challenges they're spread over, so callers must commit even when this returns false.
+pub async fn verify_second_factor(conn: &mut PgConnection, key: &[u8; 32], user_id: Uuid, code: &str) -> ApiResult<bool> {
+    let Some(enrollment) = lock_enrollment(conn, user_id).await?.filter(|e| e.confirmed_at.is_some()) else {
+        return Ok(false);
+    };
+    if enrollment.locked_until.is_some_and(|until| until > Utc::now()) {
+        tracing::warn!(user_id = %user_id, "Second factor attempted while locked");
+        return Err(ApiError::RateLimited);
+    }
+
+    let verified = check_totp(conn, key, user_id, &enrollment, code).await? || spend_recovery_code(conn, user_id, code).await?;
+    record_attempt(conn, user_id, verified).await?;
+    Ok(verified)
+}
+
+async fn spend_recovery_code(conn: &mut PgConnection, user_id: Uuid, code: &str) -> ApiResult<bool> {
+    let used = sqlx::query(
+        "UPDATE user_recovery_codes SET used_at = now() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(auth_service::hash_token(&normalize_recovery_code(code)))
+    .execute(&mut *conn)
+    .await?;
+
+    if used.rows_affected() > 0 {
+        tracing::info!(user_id = %user_id, "Recovery code used");
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Clear the failure count on success; otherwise count the failure, locking the factor
+/// (and starting the count over) when it reaches the limit
+async fn record_attempt(conn: &mut PgConnection, user_id: Uuid, verified: bool) -> ApiResult<()> {
+    let locked_until = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        r#"
+        UPDATE user_totp SET
+            failed_attempts = CASE WHEN $2 OR failed_attempts + 1 >= $3 THEN 0 ELSE failed_attempts + 1 END,
+            locked_until = CASE
+                WHEN $2 THEN NULL
+                WHEN failed_attempts + 1 >= $3 THEN now() + make_interval(mins => $4)
+                ELSE locked_until
+            END
+        WHERE user_id = $1
+        RETURNING locked_until
+        "#,
+    )
+    .bind(user_id)
+    .bind(verified)
+    .bind(MAX_FAILED_ATTEMPTS)
+    .bind(LOCKOUT_MINUTES)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !verified && locked_until.is_some_and(|until| until > Utc::now()) {
+        tracing::warn!(user_id = %user_id, "Second factor locked after repeated wrong codes");
+    }
+    Ok(())
+}
+
+/// New set of recovery codes after checking a current second factor
+pub async fn regenerate_recovery_codes(pool: &PgPool, key: &[u8; 32], user_id: Uuid, code: &str) -> ApiResult<Vec<String>> {
+    let mut tx = pool.begin().await?;
+    if !verify_second_factor(&mut tx, key, user_id, code).await? {
+        tx.commit().await?;
+        return Err(ApiError::Validation("Incorrect authentication code".into()));
+    }
+    let codes = replace_recovery_codes(&mut tx, user_id).await?;
+    tx.commit().await?;
+    Ok(codes)
+}
+
+pub async fn remaining_recovery_codes(pool: &PgPool, user_id: Uuid) -> ApiResult<i64> {
+    let remaining = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM user_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(remaining)
+}
+
+/// Remove the second factor and recovery codes
+pub async fn disable(conn: &mut PgConnection, user_id: Uuid) -> ApiResult<bool> {
+    let removed = sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("UPDATE mfa_challenges SET consumed_at = now() WHERE user_id = $1 AND consumed_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(removed.rows_affected() > 0)
+}
+
+/// Token a password login hands back in place of a session when a second factor is due
+pub async fn create_challenge(pool: &PgPool, user_id: Uuid) -> ApiResult<(String, DateTime<Utc>)> {
+    let token = auth_service::generate_token();
+    let expires_at = Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES);
+
+    sqlx::query("INSERT INTO mfa_challenges (user_id, token_hash, expires_at) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(auth_service::hash_token(&token))
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+
+    Ok((token, expires_at))
+}
+
+/// Redeem a login challenge with a second factor. Returns the user id on success;
+/// each wrong code counts against the challenge and the user's lockout.
+pub async fn complete_challenge(pool: &PgPool, key: &[u8; 32], token: &str, code: &str) -> ApiResult<Uuid> {
+    let expired = || ApiError::BadRequest("This sign-in attempt has expired; log in again".into());
+
+    let mut tx = pool.begin().await?;
+
+    let (challenge_id, user_id, attempts, expires_at): (Uuid, Uuid, i32, DateTime<Utc>) = sqlx::query_as(
+        "SELECT id, user_id, attempts, expires_at FROM mfa_challenges WHERE token_hash = $1 AND consumed_at IS NULL FOR UPDATE",
+    )
+    .bind(auth_service::hash_token(token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(expired)?;
+
+    if expires_at <= Utc::now() || attempts >= MAX_CHALLENGE_ATTEMPTS {
+        return Err(expired());
+    }
+
+    if !verify_second_factor(&mut tx, key, user_id, code).await? {
+        sqlx::query("UPDATE mfa_challenges SET attempts = attempts + 1 WHERE id = $1")
+            .bind(challenge_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        tracing::warn!(user_id = %user_id, "Incorrect second factor at login");
+        return Err(ApiError::Unauthorized);
+    }
+
+    sqlx::query("UPDATE mfa_challenges SET consumed_at = now() WHERE id = $1")
+        .bind(challenge_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    /// The RFC 6238 appendix B SHA-1 seed, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const KEY: [u8; 32] = [7; 32];
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        // Appendix B's 8-digit SHA-1 values, truncated to the 6 digits authenticator apps show
+        for (time, code) in [
+            (59, "287082"),
+            (1_111_111_109, "081804"),
+            (1_111_111_111, "050471"),
+            (1_234_567_890, "005924"),
+            (2_000_000_000, "279037"),
+            (20_000_000_000, "353130"),
+        ] {
+            let step = time / TOTP_PERIOD_SECS;
+            assert_eq!(verify_totp(RFC_SECRET, code, at(time), None), Some(step), "T = {time}");
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift_and_refuses_replays() {
+        // 287082 is step 1 (t = 30..59)
+        assert_eq!(verify_totp(RFC_SECRET, "287082", at(75), None), Some(1));
+        assert_eq!(verify_totp(RFC_SECRET, "287082", at(95), None), None);
+        assert_eq!(verify_totp(RFC_SECRET, "287082", at(59), Some(1)), None);
+        assert_eq!(verify_totp(RFC_SECRET, "28708", at(59), None), None);
+        assert_eq!(verify_totp(RFC_SECRET, "28708a", at(59), None), None);
+    }
+
+    #[test]
+    fn sealed_secrets_only_open_for_their_user() {
+        let user_id = Uuid::new_v4();
+        let sealed = seal_secret(&KEY, user_id, RFC_SECRET).unwrap();
+        assert!(!sealed.contains(RFC_SECRET));
+        assert_eq!(open_secret(&KEY, user_id, &sealed).unwrap(), RFC_SECRET);
+        assert!(open_secret(&KEY, Uuid::new_v4(), &sealed).is_err());
+        assert!(open_secret(&[8; 32], user_id, &sealed).is_err());
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn wrong_codes_lock_the_factor_across_challenges(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let key = &state.config.auth.mfa_encryption_key;
+        let team_id = test_support::team(&db, "acme").await;
+        let user_id = test_support::member(&db, team_id, "owner@example.com", "owner").await;
+
+        let secret = begin_enrollment(&db, key, user_id).await.unwrap();
+        let stored: String = sqlx::query_scalar("SELECT secret_ciphertext FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_ne!(stored, secret);
+        sqlx::query("UPDATE user_totp SET confirmed_at = now() WHERE user_id = $1").bind(user_id).execute(&db).await.unwrap();
+
+        // A fresh challenge per password login used to mean a fresh budget of guesses
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            let (token, _) = create_challenge(&db, user_id).await.unwrap();
+            let result = complete_challenge(&db, key, &token, "000000").await;
+            assert!(matches!(result, Err(ApiError::Unauthorized)));
+        }
+
+        let step = Utc::now().timestamp() / TOTP_PERIOD_SECS;
+        let code = format!("{:06}", hotp(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), step as u64));
+        let (token, _) = create_challenge(&db, user_id).await.unwrap();
+        let result = complete_challenge(&db, key, &token, &code).await;
+        assert!(matches!(result, Err(ApiError::RateLimited)));
+
+        sqlx::query("UPDATE user_totp SET locked_until = now() - interval '1 second' WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(complete_challenge(&db, key, &token, &code).await.unwrap(), user_id);
+    }
+}
@@ -1,3 +1,30 @@
 pub mod auth_service;
+pub mod availability;
+pub mod dispatch;
+pub mod document_pdf;
+pub mod events;
+pub mod geofence;
+pub mod gps_store;
 pub mod job_service;
 pub mod job_queue;
+pub mod mfa_service;
+pub mod messaging;
+pub mod outbound;
+pub mod payment_provider;
+pub mod payment_service;
+pub mod pdf;
+pub mod realtime;
+pub mod recurring_service;
+pub mod route_plan;
+pub mod routing;
+pub mod schedule;
+pub mod scheduler;
+pub mod storage;
+pub mod sweeps;
+pub mod side_effects;
+pub mod stripe_webhook;
+pub mod team_service;
+pub mod tracking;
+pub mod trips;
+pub mod verification_service;
+pub mod webhook_service;
@@ -0,0 +1,135 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{anyhow, bail};
+use reqwest::Url;
+
+/// A URL whose host resolved only to public addresses
+#[derive(Debug, Clone)]
+pub struct PublicTarget {
+    pub url: Url,
+    pub addrs: Vec<SocketAddr>,
+}
+
+impl PublicTarget {
+    /// A client builder that connects to the vetted addresses instead of resolving the host
+    /// again, so DNS can't be flipped to an internal address between the check and the
+    /// request. Redirects are off because their targets haven't been checked.
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+        match self.url.domain() {
+            Some(domain) => builder.resolve_to_addrs(domain, &self.addrs),
+            None => builder,
+        }
+    }
+}
+
+/// Check a URL the server is about to request on a user's behalf (webhook endpoints, team
+/// logos, signatures). Every address the host resolves to must be public, which keeps
+/// these requests off loopback, the private network and cloud metadata endpoints.
+pub async fn resolve_public(url: &str, require_https: bool) -> anyhow::Result<PublicTarget> {
+    let url = Url::parse(url).map_err(|_| anyhow!("is not an absolute URL"))?;
+    match url.scheme() {
+        "https" => {}
+        "http" if !require_https => {}
+        _ if require_https => bail!("must use https"),
+        _ => bail!("must use http or https"),
+    }
+
+    let host = url.host_str().ok_or_else(|| anyhow!("has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| anyhow!("host {} could not be resolved", host))?
+        .collect();
+
+    if addrs.is_empty() {
+        bail!("host {} could not be resolved", host);
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        bail!("host {} resolves to a non-public address ({})", host, addr.ip());
+    }
+
+    Ok(PublicTarget { url, addrs })
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // NAT64, 64:ff9b::/96, which reaches IPv4 addresses through the gateway
+        || (first == 0x0064 && ip.segments()[1] == 0xff9b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} should be rejected");
+        }
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} should be allowed");
+        }
+    }
+
+    #[tokio::test]
+    async fn literal_internal_hosts_are_rejected() {
+        assert!(resolve_public("http://169.254.169.254/latest/meta-data", false).await.is_err());
+        assert!(resolve_public("https://[::1]:8080/hook", true).await.is_err());
+        assert!(resolve_public("http://1.1.1.1/", true).await.is_err());
+        assert!(resolve_public("https://1.1.1.1/", true).await.is_ok());
+    }
+}
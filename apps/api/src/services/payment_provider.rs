@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use stripe::{
+    BalanceTransaction, Charge, ChargeId, Client, CreatePaymentIntent, CreatePaymentIntentAutomaticPaymentMethods,
+    CreateRefund, Currency, Expandable, PaymentIntent, Refund, RefundReasonFilter, RequestStrategy,
+};
+use uuid::Uuid;
+
+use crate::config::Settings;
+
+/// A card payment to be confirmed client-side. Amounts are in cents.
+#[derive(Debug, Clone)]
+pub struct NewPaymentIntent {
+    pub amount_cents: i64,
+    pub currency: String,
+    pub description: String,
+    pub receipt_email: Option<String>,
+    /// Copied onto the intent so it can be traced back from the Stripe dashboard
+    pub metadata: HashMap<String, String>,
+    /// Retrying with the same key returns the original intent instead of creating another
+    pub idempotency_key: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreatedPaymentIntent {
+    pub id: String,
+    pub client_secret: String,
+}
+
+/// A (partial) refund of a settled charge. Amounts are in cents.
+#[derive(Debug, Clone)]
+pub struct NewRefund {
+    pub charge_id: String,
+    pub amount_cents: i64,
+    pub reason: Option<String>,
+    /// Copied onto the refund, so it can be picked out of the charge's refunds before its
+    /// id is on file
+    pub metadata: HashMap<String, String>,
+    pub idempotency_key: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreatedRefund {
+    pub id: String,
+    /// `pending` or `succeeded`; a refund the processor declined is returned as an error
+    pub status: String,
+}
+
+/// What the processor kept and what reaches the team, in cents
+#[derive(Debug, Clone, Copy)]
+pub struct ChargeFees {
+    pub fee_cents: i64,
+    pub net_cents: i64,
+}
+
+/// Card processing. Every call goes over the network for the real provider, so callers
+/// keep them out of transactions that hold row locks for long.
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    async fn create_payment_intent(&self, intent: &NewPaymentIntent) -> anyhow::Result<CreatedPaymentIntent>;
+
+    /// Fees for a settled charge, or `None` while the processor hasn't booked them yet
+    async fn charge_fees(&self, charge_id: &str) -> anyhow::Result<Option<ChargeFees>>;
+
+    async fn refund(&self, refund: &NewRefund) -> anyhow::Result<CreatedRefund>;
+}
+
+/// Pick the provider named by `PAYMENT_PROVIDER`: `stripe` (the default) or `fake`
+/// (in-process, for tests and local development)
+pub fn from_settings(settings: &Settings) -> anyhow::Result<Arc<dyn PaymentProvider>> {
+    match settings.stripe.provider.as_str() {
+        "stripe" => Ok(Arc::new(StripeProvider::new(settings))),
+        "fake" => Ok(Arc::new(FakeProvider::default())),
+        other => Err(anyhow!("Unknown PAYMENT_PROVIDER '{}'; expected stripe or fake", other)),
+    }
+}
+
+pub struct StripeProvider {
+    secret_key: String,
+}
+
+impl StripeProvider {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            secret_key: settings.stripe.secret_key.clone(),
+        }
+    }
+
+    fn client(&self) -> anyhow::Result<Client> {
+        if self.secret_key.is_empty() {
+            return Err(anyhow!("STRIPE_SECRET_KEY is not configured"));
+        }
+        Ok(Client::new(self.secret_key.clone()))
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for StripeProvider {
+    async fn create_payment_intent(&self, intent: &NewPaymentIntent) -> anyhow::Result<CreatedPaymentIntent> {
+        let client = self
+            .client()?
+            .with_strategy(RequestStrategy::Idempotent(intent.idempotency_key.clone()));
+
+        let currency: Currency = intent
+            .currency
+            .parse()
+            .map_err(|_| anyhow!("Unsupported currency '{}'", intent.currency))?;
+
+        let mut params = CreatePaymentIntent::new(intent.amount_cents, currency);
+        params.automatic_payment_methods = Some(CreatePaymentIntentAutomaticPaymentMethods {
+            enabled: true,
+            allow_redirects: None,
+        });
+        params.description = Some(&intent.description);
+        params.receipt_email = intent.receipt_email.as_deref();
+        params.metadata = Some(intent.metadata.clone());
+
+        let created = PaymentIntent::create(&client, params)
+            .await
+            .context("Stripe rejected the PaymentIntent")?;
+        let client_secret = created
+            .client_secret
+            .ok_or_else(|| anyhow!("Stripe returned a PaymentIntent without a client secret"))?;
+
+        Ok(CreatedPaymentIntent {
+            id: created.id.to_string(),
+            client_secret,
+        })
+    }
+
+    async fn charge_fees(&self, charge_id: &str) -> anyhow::Result<Option<ChargeFees>> {
+        let client = self.client()?;
+        let id: ChargeId = charge_id.parse().map_err(|_| anyhow!("Invalid charge id '{}'", charge_id))?;
+
+        let charge = Charge::retrieve(&client, &id, &["balance_transaction"])
+            .await
+            .context("Failed to fetch charge from Stripe")?;
+
+        Ok(match charge.balance_transaction {
+            Some(Expandable::Object(tx)) => Some(fees_of(&tx)),
+            _ => None,
+        })
+    }
+
+    async fn refund(&self, refund: &NewRefund) -> anyhow::Result<CreatedRefund> {
+        let client = self
+            .client()?
+            .with_strategy(RequestStrategy::Idempotent(refund.idempotency_key.clone()));
+        let charge: ChargeId = refund
+            .charge_id
+            .parse()
+            .map_err(|_| anyhow!("Invalid charge id '{}'", refund.charge_id))?;
+
+        let mut params = CreateRefund::new();
+        params.charge = Some(charge);
+        params.amount = Some(refund.amount_cents);
+        params.reason = Some(RefundReasonFilter::RequestedByCustomer);
+        let mut metadata = refund.metadata.clone();
+        if let Some(reason) = &refund.reason {
+            metadata.insert("reason".to_string(), reason.clone());
+        }
+        params.metadata = Some(metadata);
+
+        let created = Refund::create(&client, params).await.context("Stripe rejected the refund")?;
+        let status = created.status.unwrap_or_default();
+        if matches!(status.as_str(), "failed" | "canceled") {
+            return Err(anyhow!("Stripe refund {} {}", created.id, status));
+        }
+
+        Ok(CreatedRefund {
+            id: created.id.to_string(),
+            status,
+        })
+    }
+}
+
+fn fees_of(tx: &BalanceTransaction) -> ChargeFees {
+    ChargeFees {
+        fee_cents: tx.fee,
+        net_cents: tx.net,
+    }
+}
+
+/// Standard card pricing, used by [`FakeProvider`] so fee accounting has something to show
+const FAKE_FEE_BASIS_POINTS: i64 = 290;
+const FAKE_FEE_FIXED_CENTS: i64 = 30;
+
+#[derive(Debug, Clone)]
+pub struct FakeIntent {
+    pub id: String,
+    pub charge_id: String,
+    pub request: NewPaymentIntent,
+}
+
+/// Keeps intents in memory and never talks to the network. Tests drive the rest of the flow
+/// by posting locally signed webhook events that reference [`FakeIntent::charge_id`].
+#[derive(Clone, Default)]
+pub struct FakeProvider {
+    intents: Arc<Mutex<Vec<FakeIntent>>>,
+    refunds: Arc<Mutex<Vec<NewRefund>>>,
+}
+
+impl FakeProvider {
+    pub fn intents(&self) -> Vec<FakeIntent> {
+        self.intents.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn refunds(&self) -> Vec<NewRefund> {
+        self.refunds.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn intent(&self, id: &str) -> Option<FakeIntent> {
+        self.intents().into_iter().find(|i| i.id == id)
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for FakeProvider {
+    async fn create_payment_intent(&self, intent: &NewPaymentIntent) -> anyhow::Result<CreatedPaymentIntent> {
+        let mut intents = self.intents.lock().unwrap_or_else(|e| e.into_inner());
+
+        let existing = intents.iter().find(|i| i.request.idempotency_key == intent.idempotency_key);
+        let stored = match existing {
+            Some(stored) => stored.clone(),
+            None => {
+                let suffix = Uuid::new_v4().simple().to_string();
+                let stored = FakeIntent {
+                    id: format!("pi_fake_{}", suffix),
+                    charge_id: format!("ch_fake_{}", suffix),
+                    request: intent.clone(),
+                };
+                intents.push(stored.clone());
+                stored
+            }
+        };
+
+        Ok(CreatedPaymentIntent {
+            client_secret: format!("{}_secret_fake", stored.id),
+            id: stored.id,
+        })
+    }
+
+    async fn charge_fees(&self, charge_id: &str) -> anyhow::Result<Option<ChargeFees>> {
+        let Some(intent) = self.intents().into_iter().find(|i| i.charge_id == charge_id) else {
+            return Ok(None);
+        };
+        let amount = intent.request.amount_cents;
+        let fee_cents = (amount * FAKE_FEE_BASIS_POINTS + 5_000) / 10_000 + FAKE_FEE_FIXED_CENTS;
+        Ok(Some(ChargeFees {
+            fee_cents,
+            net_cents: amount - fee_cents,
+        }))
+    }
+
+    async fn refund(&self, refund: &NewRefund) -> anyhow::Result<CreatedRefund> {
+        let mut refunds = self.refunds.lock().unwrap_or_else(|e| e.into_inner());
+        let position = match refunds.iter().position(|r| r.idempotency_key == refund.idempotency_key) {
+            Some(position) => position,
+            None => {
+                refunds.push(refund.clone());
+                refunds.len() - 1
+            }
+        };
+        Ok(CreatedRefund {
+            id: format!("re_fake_{}", position + 1),
+            status: "succeeded".into(),
+        })
+    }
+}
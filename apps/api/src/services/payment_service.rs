@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::payment::Payment;
+use crate::services::payment_provider::{CreatedRefund, NewRefund};
+use crate::AppState;
+
+/// `payments` columns for [`Payment`], with the enum columns cast so they decode as strings
+pub const PAYMENT_COLUMNS: &str = r#"
+    id, team_id, invoice_id, customer_id, amount, tip_amount, processing_fee, net_amount,
+    payment_method::text AS payment_method, status::text AS status, stripe_payment_intent_id,
+    stripe_charge_id, check_number, reference_number, notes, refunded_amount, refund_reason,
+    collected_by, collected_at, created_at, updated_at
+"#;
+
+/// Payment statuses whose money reached the team; refunds are netted out via `refunded_amount`
+pub const COLLECTED_STATUSES: &[&str] = &["succeeded", "partially_refunded", "refunded"];
+
+pub fn is_collected(status: &str) -> bool {
+    COLLECTED_STATUSES.contains(&status)
+}
+
+/// Stripe amounts are integer cents
+pub fn from_cents(cents: i64) -> Decimal {
+    Decimal::new(cents, 2)
+}
+
+pub fn to_cents(amount: Decimal) -> ApiResult<i64> {
+    (amount * Decimal::ONE_HUNDRED)
+        .round()
+        .to_i64()
+        .ok_or_else(|| ApiError::Validation(format!("Amount {} is out of range", amount)))
+}
+
+pub async fn find_by_intent(conn: &mut PgConnection, payment_intent_id: &str) -> ApiResult<Option<Payment>> {
+    let payment = sqlx::query_as::<_, Payment>(&format!(
+        "SELECT {} FROM payments WHERE stripe_payment_intent_id = $1 FOR UPDATE",
+        PAYMENT_COLUMNS
+    ))
+    .bind(payment_intent_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(payment)
+}
+
+/// Look a charge up by its id, falling back to the PaymentIntent it belongs to for payments
+/// whose charge id hasn't been recorded yet
+pub async fn find_by_charge(
+    conn: &mut PgConnection,
+    charge_id: &str,
+    payment_intent_id: Option<&str>,
+) -> ApiResult<Option<Payment>> {
+    let payment = sqlx::query_as::<_, Payment>(&format!(
+        r#"
+        SELECT {} FROM payments
+        WHERE stripe_charge_id = $1 OR ($2::text IS NOT NULL AND stripe_payment_intent_id = $2)
+        ORDER BY (stripe_charge_id = $1) DESC NULLS LAST
+        LIMIT 1
+        FOR UPDATE
+        "#,
+        PAYMENT_COLUMNS
+    ))
+    .bind(charge_id)
+    .bind(payment_intent_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(payment)
+}
+
+#[derive(sqlx::FromRow)]
+struct InvoiceTotals {
+    customer_id: Uuid,
+    total: Decimal,
+    amount_paid: Decimal,
+    status: String,
+}
+
+/// Recompute an invoice's `amount_paid`, `amount_due` and status from its collected payments,
+/// and move the customer's `lifetime_value` / `outstanding_balance` by the difference.
+///
+/// Deriving the totals from `payments` rather than adding the latest amount keeps this safe to
+/// call again for the same change. Tips are not part of the invoice, so a refund is counted
+/// against the invoiced amount first. Returns `true` when this call is what moved the invoice
+/// to paid, so the caller can publish `invoice.paid` exactly once.
+pub async fn reconcile_invoice(conn: &mut PgConnection, invoice_id: Uuid) -> ApiResult<bool> {
+    let invoice = sqlx::query_as::<_, InvoiceTotals>(
+        "SELECT customer_id, total, amount_paid, status::text AS status FROM invoices WHERE id = $1 FOR UPDATE",
+    )
+    .bind(invoice_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Invoice".into()))?;
+
+    let collected = sqlx::query_scalar::<_, Decimal>(
+        r#"
+        SELECT COALESCE(SUM(amount - LEAST(refunded_amount, amount)), 0)
+        FROM payments
+        WHERE invoice_id = $1 AND status::text = ANY($2)
+        "#,
+    )
+    .bind(invoice_id)
+    .bind(COLLECTED_STATUSES)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let change = collected - invoice.amount_paid;
+    if change.is_zero() {
+        return Ok(false);
+    }
+
+    let is_void = invoice.status == "void";
+    let status = if is_void {
+        "void"
+    } else if collected >= invoice.total && invoice.total > Decimal::ZERO {
+        "paid"
+    } else if collected > Decimal::ZERO {
+        "partially_paid"
+    } else if matches!(invoice.status.as_str(), "paid" | "partially_paid") {
+        "sent"
+    } else {
+        invoice.status.as_str()
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE invoices SET
+            amount_paid = $2,
+            amount_due = total - $2,
+            status = $3::invoice_status,
+            paid_at = CASE WHEN $3 = 'paid' THEN COALESCE(paid_at, now()) WHEN $3 = 'void' THEN paid_at ELSE NULL END
+        WHERE id = $1
+        "#,
+    )
+    .bind(invoice_id)
+    .bind(collected)
+    .bind(status)
+    .execute(&mut *conn)
+    .await?;
+
+    // A void invoice no longer counts towards what the customer owes
+    let balance_change = if is_void { Decimal::ZERO } else { change };
+    sqlx::query(
+        r#"
+        UPDATE customers SET
+            lifetime_value = lifetime_value + $2,
+            outstanding_balance = outstanding_balance - $3
+        WHERE id = $1
+        "#,
+    )
+    .bind(invoice.customer_id)
+    .bind(change)
+    .bind(balance_change)
+    .execute(&mut *conn)
+    .await?;
+
+    tracing::info!(invoice_id = %invoice_id, amount_paid = %collected, status = %status, "Invoice reconciled");
+    Ok(status == "paid" && invoice.status != "paid")
+}
+
+/// Copy the processor's fee for a settled card payment onto `processing_fee` / `net_amount`.
+/// Runs from the queue after `payment_intent.succeeded`; an error means the balance
+/// transaction isn't booked yet and the job is retried with backoff.
+pub async fn record_fees(state: &AppState, payment_id: Uuid) -> anyhow::Result<()> {
+    let charge_id = sqlx::query_scalar::<_, Option<String>>("SELECT stripe_charge_id FROM payments WHERE id = $1")
+        .bind(payment_id)
+        .fetch_optional(&state.db)
+        .await?
+        .flatten();
+    let Some(charge_id) = charge_id else {
+        tracing::warn!(payment_id = %payment_id, "No charge to record fees for");
+        return Ok(());
+    };
+
+    let fees = state
+        .payments
+        .charge_fees(&charge_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Charge {} has no balance transaction yet", charge_id))?;
+
+    sqlx::query("UPDATE payments SET processing_fee = $2, net_amount = $3 WHERE id = $1")
+        .bind(payment_id)
+        .bind(from_cents(fees.fee_cents))
+        .bind(from_cents(fees.net_cents))
+        .execute(&state.db)
+        .await?;
+
+    tracing::info!(payment_id = %payment_id, fee_cents = fees.fee_cents, "Recorded processing fee");
+    Ok(())
+}
+
+/// Fail portal payments left pending past `expiry_minutes`, so an abandoned checkout stops
+/// holding part of the invoice's balance. A `payment_intent.succeeded` that arrives later
+/// still settles the payment.
+pub async fn expire_abandoned(pool: &PgPool, expiry_minutes: i64) -> ApiResult<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE payments SET status = 'failed'::payment_status, updated_at = NOW()
+        WHERE status = 'pending'::payment_status
+          AND created_at <= NOW() - make_interval(mins => $1::int)
+        "#,
+    )
+    .bind(expiry_minutes)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Refund part or all of a collected payment. Card payments with a Stripe charge are refunded
+/// at the processor; anything else (cash, check, card taken on another terminal) is recorded
+/// as a manual refund. Refunds accumulate in `refunded_amount`, and the invoice and customer
+/// balances are reversed together with it.
+///
+/// A processor refund is recorded in `payment_refunds` as pending and committed before Stripe
+/// is called, so the payment row isn't locked across the network and a refund Stripe made is
+/// always on file. Only one can be pending per payment.
+pub async fn refund_payment(
+    state: &AppState,
+    team_id: Uuid,
+    payment_id: Uuid,
+    amount: Option<Decimal>,
+    reason: Option<&str>,
+) -> ApiResult<(Payment, Option<CreatedRefund>)> {
+    let mut tx = state.db.begin().await?;
+
+    let payment = sqlx::query_as::<_, Payment>(&format!(
+        "SELECT {} FROM payments WHERE id = $1 AND team_id = $2 FOR UPDATE",
+        PAYMENT_COLUMNS
+    ))
+    .bind(payment_id)
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Payment".into()))?;
+
+    if payment.status == "refunded" {
+        return Err(ApiError::Validation("Payment already refunded".into()));
+    }
+    if !is_collected(&payment.status) {
+        return Err(ApiError::Validation("Only completed payments can be refunded".into()));
+    }
+
+    let in_flight = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM payment_refunds WHERE payment_id = $1 AND status = 'pending')",
+    )
+    .bind(payment.id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if in_flight {
+        return Err(ApiError::Conflict("Another refund of this payment is still being processed".into()));
+    }
+
+    let refundable = payment.amount + payment.tip_amount - payment.refunded_amount;
+    let amount = amount.unwrap_or(refundable);
+    if amount <= Decimal::ZERO {
+        return Err(ApiError::Validation("Refund amount must be greater than zero".into()));
+    }
+    if amount > refundable {
+        return Err(ApiError::Validation(format!(
+            "Refund amount {} exceeds the refundable {}",
+            amount, refundable
+        )));
+    }
+
+    let charge_id = match (&payment.stripe_charge_id, &payment.stripe_payment_intent_id) {
+        (Some(charge_id), _) => charge_id.clone(),
+        (None, Some(_)) => {
+            return Err(ApiError::Validation("The card payment hasn't settled yet".into()));
+        }
+        (None, None) => {
+            sqlx::query(
+                r#"
+                INSERT INTO payment_refunds (team_id, payment_id, amount, reason, status, completed_at)
+                VALUES ($1, $2, $3, $4, 'succeeded', now())
+                "#,
+            )
+            .bind(team_id)
+            .bind(payment.id)
+            .bind(amount)
+            .bind(reason)
+            .execute(&mut *tx)
+            .await?;
+
+            let updated = apply_refund(&mut tx, &payment, amount, reason).await?;
+            tx.commit().await?;
+
+            tracing::info!(payment_id = %payment.id, amount = %amount, "Payment refunded manually");
+            return Ok((updated, None));
+        }
+    };
+
+    let refund_id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO payment_refunds (team_id, payment_id, amount, reason) VALUES ($1, $2, $3, $4) RETURNING id",
+    )
+    .bind(team_id)
+    .bind(payment.id)
+    .bind(amount)
+    .bind(reason)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let request = NewRefund {
+        charge_id,
+        amount_cents: to_cents(amount)?,
+        reason: reason.map(str::to_string),
+        metadata: HashMap::from([("refund_id".to_string(), refund_id.to_string())]),
+        // Keyed on our refund record so a retried call can't refund twice
+        idempotency_key: format!("refund-{}", refund_id),
+    };
+    let created = match state.payments.refund(&request).await {
+        Ok(created) => created,
+        Err(e) => {
+            sqlx::query(
+                r#"
+                UPDATE payment_refunds SET status = 'failed', error = $2, completed_at = now()
+                WHERE id = $1 AND status = 'pending'
+                "#,
+            )
+            .bind(refund_id)
+            .bind(format!("{:#}", e))
+            .execute(&state.db)
+            .await?;
+            return Err(ApiError::Internal(e.context("Processor refund failed")));
+        }
+    };
+
+    let mut tx = state.db.begin().await?;
+    let payment = sqlx::query_as::<_, Payment>(&format!(
+        "SELECT {} FROM payments WHERE id = $1 FOR UPDATE",
+        PAYMENT_COLUMNS
+    ))
+    .bind(payment.id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // `charge.refunded` settles pending refunds it has already counted; only add this one if
+    // the webhook hasn't got there first
+    let settled_here = sqlx::query(
+        r#"
+        UPDATE payment_refunds SET
+            status = 'succeeded',
+            processor_refund_id = $2,
+            processor_status = $3,
+            completed_at = now()
+        WHERE id = $1 AND status = 'pending'
+        "#,
+    )
+    .bind(refund_id)
+    .bind(&created.id)
+    .bind(&created.status)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        == 1;
+
+    let updated = if settled_here {
+        apply_refund(&mut tx, &payment, amount, reason).await?
+    } else {
+        sqlx::query("UPDATE payment_refunds SET processor_refund_id = $2, processor_status = $3 WHERE id = $1")
+            .bind(refund_id)
+            .bind(&created.id)
+            .bind(&created.status)
+            .execute(&mut *tx)
+            .await?;
+        payment
+    };
+    tx.commit().await?;
+
+    tracing::info!(
+        payment_id = %updated.id,
+        amount = %amount,
+        refunded_amount = %updated.refunded_amount,
+        processor_refund = %created.id,
+        "Payment refunded"
+    );
+
+    Ok((updated, Some(created)))
+}
+
+/// Add a refund to a locked payment's `refunded_amount` and reverse it on the invoice
+async fn apply_refund(
+    conn: &mut PgConnection,
+    payment: &Payment,
+    amount: Decimal,
+    reason: Option<&str>,
+) -> ApiResult<Payment> {
+    let refunded_amount = (payment.refunded_amount + amount).min(payment.amount + payment.tip_amount);
+    let status = if refunded_amount >= payment.amount + payment.tip_amount {
+        "refunded"
+    } else {
+        "partially_refunded"
+    };
+
+    let updated = sqlx::query_as::<_, Payment>(&format!(
+        r#"
+        UPDATE payments SET
+            status = $2::payment_status,
+            refunded_amount = $3,
+            refund_reason = COALESCE($4, refund_reason)
+        WHERE id = $1
+        RETURNING {}
+        "#,
+        PAYMENT_COLUMNS
+    ))
+    .bind(payment.id)
+    .bind(status)
+    .bind(refunded_amount)
+    .bind(reason)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    reconcile_invoice(conn, payment.invoice_id).await?;
+    Ok(updated)
+}
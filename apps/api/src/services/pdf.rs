@@ -0,0 +1,483 @@
+//! Minimal PDF 1.4 writer: text in the standard Helvetica faces, lines, filled rectangles
+//! and JPEG/PNG images. Enough for invoices and estimates without a native dependency.
+//!
+//! Coordinates are PDF points with the origin at the bottom-left of the page.
+
+use std::io::Write;
+
+use anyhow::{anyhow, Context};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+pub const LETTER_WIDTH: f32 = 612.0;
+pub const LETTER_HEIGHT: f32 = 792.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+    Italic,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Italic => "F3",
+        }
+    }
+
+    fn base_font(self) -> &'static str {
+        match self {
+            Font::Regular => "Helvetica",
+            Font::Bold => "Helvetica-Bold",
+            Font::Italic => "Helvetica-Oblique",
+        }
+    }
+
+    const ALL: [Font; 3] = [Font::Regular, Font::Bold, Font::Italic];
+}
+
+/// Advance widths (1/1000 em) of WinAnsi 32..=126 from the Adobe AFM files
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556, 556, 556, 556,
+    556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667,
+    556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556,
+    556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722,
+    500, 500, 500, 334, 260, 334, 584,
+];
+
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556, 556, 556, 556,
+    556, 556, 556, 556, 333, 333, 584, 584, 584, 611, 975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722,
+    611, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556, 333, 556,
+    611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611, 611, 611, 389, 556, 333, 611, 556, 778,
+    556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Map a character to its WinAnsiEncoding byte; anything the standard fonts can't show becomes `?`
+fn win_ansi(c: char) -> u8 {
+    match c {
+        ' '..='~' => c as u8,
+        '\u{A0}'..='\u{FF}' => c as u32 as u8,
+        '\t' => b' ',
+        '€' => 0x80,
+        '‚' => 0x82,
+        '…' => 0x85,
+        '‘' => 0x91,
+        '’' => 0x92,
+        '“' => 0x93,
+        '”' => 0x94,
+        '•' => 0x95,
+        '–' => 0x96,
+        '—' => 0x97,
+        '™' => 0x99,
+        _ => b'?',
+    }
+}
+
+fn byte_width(byte: u8, font: Font) -> u16 {
+    let bold = font == Font::Bold;
+    match byte {
+        32..=126 => {
+            let table = if bold { &HELVETICA_BOLD_WIDTHS } else { &HELVETICA_WIDTHS };
+            table[(byte - 32) as usize]
+        }
+        0x85 | 0x97 => 1000,
+        0x91 | 0x92 => if bold { 278 } else { 222 },
+        0x93 | 0x94 => if bold { 500 } else { 333 },
+        0x95 => 350,
+        0xA0 => 278,
+        0xA9 | 0xAE => 737,
+        0xB0 => 400,
+        _ => 556,
+    }
+}
+
+/// Width of `text` in points when set in `font` at `size`
+pub fn text_width(text: &str, font: Font, size: f32) -> f32 {
+    let units: u32 = text.chars().map(|c| byte_width(win_ansi(c), font) as u32).sum();
+    units as f32 * size / 1000.0
+}
+
+/// Break `text` into lines no wider than `max_width`, keeping explicit line breaks.
+/// A single word wider than the line is split by character.
+pub fn wrap(text: &str, font: Font, size: f32, max_width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if text_width(&candidate, font, size) <= max_width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            for c in word.chars() {
+                line.push(c);
+                if text_width(&line, font, size) > max_width && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::take(&mut line));
+                    line.push(c);
+                }
+            }
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    for c in text.chars() {
+        match win_ansi(c) {
+            b'(' => out.push_str("\\("),
+            b')' => out.push_str("\\)"),
+            b'\\' => out.push_str("\\\\"),
+            byte @ 32..=126 => out.push(byte as char),
+            byte => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out
+}
+
+/// Handle to an image added with [`PdfDocument::add_image`]
+#[derive(Debug, Clone, Copy)]
+pub struct ImageId(usize);
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    color_space: &'static str,
+    filter: &'static str,
+    /// Adobe CMYK JPEGs store inverted values
+    invert: bool,
+    data: Vec<u8>,
+    /// Flate-compressed 8-bit alpha channel
+    alpha: Option<Vec<u8>>,
+}
+
+impl Image {
+    /// Decode a JPEG or PNG. JPEG data is embedded as-is; PNG is expanded to 8-bit samples.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Image> {
+        if bytes.starts_with(&[0xFF, 0xD8]) {
+            Self::from_jpeg(bytes)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Self::from_png(bytes)
+        } else {
+            Err(anyhow!("Unsupported image format; expected JPEG or PNG"))
+        }
+    }
+
+    fn from_jpeg(bytes: &[u8]) -> anyhow::Result<Image> {
+        let mut pos = 2;
+        while pos + 4 <= bytes.len() {
+            if bytes[pos] != 0xFF {
+                return Err(anyhow!("Malformed JPEG"));
+            }
+            let marker = bytes[pos + 1];
+            // Standalone markers carry no length
+            if marker == 0xFF || marker == 0x01 || (0xD0..=0xD8).contains(&marker) {
+                pos += if marker == 0xFF { 1 } else { 2 };
+                continue;
+            }
+            let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            let is_frame = (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+            if is_frame {
+                let segment = bytes.get(pos + 4..pos + 2 + length).ok_or_else(|| anyhow!("Truncated JPEG"))?;
+                if segment.len() < 6 {
+                    return Err(anyhow!("Truncated JPEG"));
+                }
+                let height = u16::from_be_bytes([segment[1], segment[2]]) as u32;
+                let width = u16::from_be_bytes([segment[3], segment[4]]) as u32;
+                let (color_space, invert) = match segment[5] {
+                    1 => ("DeviceGray", false),
+                    3 => ("DeviceRGB", false),
+                    4 => ("DeviceCMYK", true),
+                    n => return Err(anyhow!("Unsupported JPEG with {} components", n)),
+                };
+                return Ok(Image {
+                    width,
+                    height,
+                    color_space,
+                    filter: "DCTDecode",
+                    invert,
+                    data: bytes.to_vec(),
+                    alpha: None,
+                });
+            }
+            pos += 2 + length;
+        }
+        Err(anyhow!("JPEG has no frame header"))
+    }
+
+    fn from_png(bytes: &[u8]) -> anyhow::Result<Image> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().context("Invalid PNG")?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer).context("Invalid PNG")?;
+        let pixels = &buffer[..frame.buffer_size()];
+
+        let (channels, color_space) = match frame.color_type {
+            png::ColorType::Grayscale => (1, "DeviceGray"),
+            png::ColorType::GrayscaleAlpha => (2, "DeviceGray"),
+            png::ColorType::Rgb => (3, "DeviceRGB"),
+            png::ColorType::Rgba => (4, "DeviceRGB"),
+            png::ColorType::Indexed => return Err(anyhow!("Unexpected indexed PNG after expansion")),
+        };
+        let has_alpha = channels % 2 == 0;
+        let color_channels = if has_alpha { channels - 1 } else { channels };
+
+        let mut color = Vec::with_capacity(pixels.len());
+        let mut alpha = Vec::new();
+        for pixel in pixels.chunks_exact(channels) {
+            color.extend_from_slice(&pixel[..color_channels]);
+            if has_alpha {
+                alpha.push(pixel[color_channels]);
+            }
+        }
+
+        Ok(Image {
+            width: frame.width,
+            height: frame.height,
+            color_space,
+            filter: "FlateDecode",
+            invert: false,
+            data: deflate(&color)?,
+            alpha: if has_alpha { Some(deflate(&alpha)?) } else { None },
+        })
+    }
+}
+
+/// Start object `number`, recording its offset for the xref table. Objects must be written in order.
+fn begin(out: &mut Vec<u8>, offsets: &mut Vec<usize>, number: usize) {
+    debug_assert_eq!(offsets.len() + 1, number);
+    offsets.push(out.len());
+    out.extend_from_slice(format!("{} 0 obj\n", number).as_bytes());
+}
+
+fn deflate(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Drawing operations for one page, in PDF content-stream syntax
+#[derive(Default)]
+pub struct Page {
+    ops: String,
+}
+
+impl Page {
+    pub fn text(&mut self, x: f32, y: f32, font: Font, size: f32, text: &str) {
+        self.ops.push_str(&format!(
+            "BT /{} {:.1} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+            font.resource(),
+            size,
+            x,
+            y,
+            escape_text(text)
+        ));
+    }
+
+    /// Text whose right edge sits at `right`
+    pub fn text_right(&mut self, right: f32, y: f32, font: Font, size: f32, text: &str) {
+        self.text(right - text_width(text, font, size), y, font, size, text);
+    }
+
+    /// `gray` runs from 0 (black) to 1 (white)
+    pub fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32, gray: f32) {
+        self.ops.push_str(&format!(
+            "q {:.2} G {:.2} w {:.2} {:.2} m {:.2} {:.2} l S Q\n",
+            gray, width, x1, y1, x2, y2
+        ));
+    }
+
+    pub fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, gray: f32) {
+        self.ops.push_str(&format!(
+            "q {:.2} g {:.2} {:.2} {:.2} {:.2} re f Q\n",
+            gray, x, y, width, height
+        ));
+    }
+
+    /// Draw an image with its bottom-left corner at (`x`, `y`)
+    pub fn image(&mut self, image: ImageId, x: f32, y: f32, width: f32, height: f32) {
+        self.ops.push_str(&format!(
+            "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im{} Do Q\n",
+            width, height, x, y, image.0
+        ));
+    }
+}
+
+#[derive(Default)]
+pub struct PdfDocument {
+    pages: Vec<Page>,
+    images: Vec<Image>,
+    title: Option<String>,
+}
+
+impl PdfDocument {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: Some(title.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Append a page and return its index
+    pub fn add_page(&mut self) -> usize {
+        self.pages.push(Page::default());
+        self.pages.len() - 1
+    }
+
+    pub fn page(&mut self, index: usize) -> &mut Page {
+        &mut self.pages[index]
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn add_image(&mut self, image: Image) -> ImageId {
+        self.images.push(image);
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Serialize to a complete PDF file
+    pub fn finish(self) -> anyhow::Result<Vec<u8>> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut offsets: Vec<usize> = Vec::new();
+        out.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+
+        // Object numbers: 1 catalog, 2 page tree, 3 info, then fonts, images (+ masks), pages
+        let font_base = 4;
+        let image_base = font_base + Font::ALL.len();
+        let mut image_numbers = Vec::with_capacity(self.images.len());
+        let mut next = image_base;
+        for image in &self.images {
+            image_numbers.push((next, image.alpha.as_ref().map(|_| next + 1)));
+            next += if image.alpha.is_some() { 2 } else { 1 };
+        }
+        let page_base = next;
+        let object_count = page_base + self.pages.len() * 2;
+
+        begin(&mut out, &mut offsets, 1);
+        out.extend_from_slice(b"<< /Type /Catalog /Pages 2 0 R >>\nendobj\n");
+
+        begin(&mut out, &mut offsets, 2);
+        let kids: Vec<String> = (0..self.pages.len()).map(|i| format!("{} 0 R", page_base + i * 2)).collect();
+        out.extend_from_slice(
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>\nendobj\n", kids.join(" "), self.pages.len()).as_bytes(),
+        );
+
+        begin(&mut out, &mut offsets, 3);
+        let title = self.title.as_deref().map(escape_text).unwrap_or_default();
+        out.extend_from_slice(format!("<< /Title ({}) /Producer (FieldForge) >>\nendobj\n", title).as_bytes());
+
+        for (i, font) in Font::ALL.iter().enumerate() {
+            begin(&mut out, &mut offsets, font_base + i);
+            out.extend_from_slice(
+                format!(
+                    "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>\nendobj\n",
+                    font.base_font()
+                )
+                .as_bytes(),
+            );
+        }
+
+        for (image, (number, mask_number)) in self.images.iter().zip(&image_numbers) {
+            begin(&mut out, &mut offsets, *number);
+            let mut dict = format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} /BitsPerComponent 8 /Filter /{} /Length {}",
+                image.width,
+                image.height,
+                image.color_space,
+                image.filter,
+                image.data.len()
+            );
+            if image.invert {
+                dict.push_str(" /Decode [1 0 1 0 1 0 1 0]");
+            }
+            if let Some(mask) = mask_number {
+                dict.push_str(&format!(" /SMask {} 0 R", mask));
+            }
+            dict.push_str(" >>\nstream\n");
+            out.extend_from_slice(dict.as_bytes());
+            out.extend_from_slice(&image.data);
+            out.extend_from_slice(b"\nendstream\nendobj\n");
+
+            if let (Some(mask_number), Some(alpha)) = (mask_number, &image.alpha) {
+                begin(&mut out, &mut offsets, *mask_number);
+                out.extend_from_slice(
+                    format!(
+                        "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>\nstream\n",
+                        image.width,
+                        image.height,
+                        alpha.len()
+                    )
+                    .as_bytes(),
+                );
+                out.extend_from_slice(alpha);
+                out.extend_from_slice(b"\nendstream\nendobj\n");
+            }
+        }
+
+        let fonts: Vec<String> = Font::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, f)| format!("/{} {} 0 R", f.resource(), font_base + i))
+            .collect();
+        let images: Vec<String> = image_numbers
+            .iter()
+            .enumerate()
+            .map(|(i, (number, _))| format!("/Im{} {} 0 R", i, number))
+            .collect();
+        let resources = format!(
+            "<< /Font << {} >> /XObject << {} >> >>",
+            fonts.join(" "),
+            images.join(" ")
+        );
+
+        for (i, page) in self.pages.iter().enumerate() {
+            let page_number = page_base + i * 2;
+            begin(&mut out, &mut offsets, page_number);
+            out.extend_from_slice(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources {} /Contents {} 0 R >>\nendobj\n",
+                    LETTER_WIDTH,
+                    LETTER_HEIGHT,
+                    resources,
+                    page_number + 1
+                )
+                .as_bytes(),
+            );
+
+            let content = deflate(page.ops.as_bytes())?;
+            begin(&mut out, &mut offsets, page_number + 1);
+            out.extend_from_slice(format!("<< /Filter /FlateDecode /Length {} >>\nstream\n", content.len()).as_bytes());
+            out.extend_from_slice(&content);
+            out.extend_from_slice(b"\nendstream\nendobj\n");
+        }
+
+        let xref_offset = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", object_count).as_bytes());
+        for offset in &offsets {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R /Info 3 0 R >>\nstartxref\n{}\n%%EOF\n",
+                object_count, xref_offset
+            )
+            .as_bytes(),
+        );
+        Ok(out)
+    }
+}
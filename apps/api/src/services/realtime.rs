@@ -0,0 +1,310 @@
+//! Real-time events for WebSocket clients.
+//!
+//! Domain code calls [`publish`] once its change has committed, naming the team and, where
+//! it has them, the job and user the event concerns. Events go out on a per-team Redis
+//! channel, so every API replica sees them: each replica runs one [`spawn_listener`] that
+//! pattern-subscribes to all team channels and hands messages to its sockets through a
+//! broadcast channel. Each socket then keeps the events matching the [`Channel`]s it
+//! subscribed to.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::middleware::permissions::Permission;
+use crate::AppState;
+
+const CHANNEL_PREFIX: &str = "fieldforge:ws";
+
+/// Messages buffered per replica before slow sockets start missing events
+const HUB_CAPACITY: usize = 1024;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Event topics, with the permission needed to receive events of that kind on any channel
+pub const TOPICS: &[(&str, Permission)] = &[
+    ("jobs", Permission::JobsRead),
+    ("schedule", Permission::JobsRead),
+    ("payments", Permission::PaymentsRead),
+    ("messages", Permission::MessagesRead),
+    ("inventory", Permission::InventoryRead),
+    ("locations", Permission::GpsView),
+];
+
+pub fn topic_permission(topic: &str) -> Option<Permission> {
+    TOPICS.iter().find(|(name, _)| *name == topic).map(|(_, permission)| *permission)
+}
+
+/// What a socket can subscribe to:
+///
+/// - `team:<team_id>:<topic>`: every event of one topic in the team
+/// - `job:<job_id>`: every event about one job
+/// - `user:<user_id>`: events about one user, such as their assigned jobs and location
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Team { team_id: Uuid, topic: &'static str },
+    Job(Uuid),
+    User(Uuid),
+}
+
+impl Channel {
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut parts = name.splitn(3, ':');
+        match (parts.next()?, parts.next()?, parts.next()) {
+            ("team", team_id, Some(topic)) => {
+                let topic = TOPICS.iter().find(|(name, _)| *name == topic)?.0;
+                Some(Channel::Team { team_id: team_id.parse().ok()?, topic })
+            }
+            ("job", job_id, None) => Some(Channel::Job(job_id.parse().ok()?)),
+            ("user", user_id, None) => Some(Channel::User(user_id.parse().ok()?)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Channel::Team { team_id, topic } => format!("team:{}:{}", team_id, topic),
+            Channel::Job(job_id) => format!("job:{}", job_id),
+            Channel::User(user_id) => format!("user:{}", user_id),
+        }
+    }
+
+    pub fn matches(&self, message: &RealtimeMessage) -> bool {
+        match self {
+            Channel::Team { team_id, topic } => message.team_id == *team_id && message.topic == *topic,
+            Channel::Job(job_id) => message.job_id == Some(*job_id),
+            Channel::User(user_id) => message.user_id == Some(*user_id),
+        }
+    }
+}
+
+/// Who an event is about, beyond the team it belongs to
+#[derive(Debug, Clone, Copy)]
+pub struct Audience {
+    pub team_id: Uuid,
+    pub job_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+}
+
+impl Audience {
+    pub fn team(team_id: Uuid) -> Self {
+        Self { team_id, job_id: None, user_id: None }
+    }
+
+    pub fn job(mut self, job_id: Option<Uuid>) -> Self {
+        self.job_id = job_id;
+        self
+    }
+
+    pub fn user(mut self, user_id: Option<Uuid>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+}
+
+/// Event types that can be broadcast to connected clients
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type")]
+pub enum WsEvent {
+    #[serde(rename = "job_status_changed")]
+    JobStatusChanged {
+        job_id: String,
+        old_status: String,
+        new_status: String,
+    },
+    #[serde(rename = "payment_received")]
+    PaymentReceived {
+        payment_id: String,
+        invoice_id: String,
+        amount: String,
+    },
+    #[serde(rename = "new_message")]
+    NewMessage {
+        message_id: String,
+        customer_id: String,
+        direction: String,
+    },
+    #[serde(rename = "schedule_changed")]
+    ScheduleChanged {
+        job_id: String,
+        change_type: String,
+    },
+    #[serde(rename = "inventory_alert")]
+    InventoryAlert {
+        item_id: String,
+        item_name: String,
+        current_quantity: i32,
+        min_quantity: i32,
+    },
+    #[serde(rename = "technician_location")]
+    TechnicianLocation {
+        user_id: String,
+        latitude: f64,
+        longitude: f64,
+    },
+}
+
+impl WsEvent {
+    pub fn topic(&self) -> &'static str {
+        match self {
+            WsEvent::JobStatusChanged { .. } => "jobs",
+            WsEvent::ScheduleChanged { .. } => "schedule",
+            WsEvent::PaymentReceived { .. } => "payments",
+            WsEvent::NewMessage { .. } => "messages",
+            WsEvent::InventoryAlert { .. } => "inventory",
+            WsEvent::TechnicianLocation { .. } => "locations",
+        }
+    }
+}
+
+fn redis_channel(team_id: Uuid) -> String {
+    format!("{}:{}", CHANNEL_PREFIX, team_id)
+}
+
+/// What goes over Redis: the event plus enough routing to match it against channels
+#[derive(Serialize, Deserialize)]
+struct Envelope<E> {
+    topic: String,
+    job_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    event: E,
+}
+
+/// An event as received from Redis; `payload` is the serialized [`WsEvent`], sent to
+/// sockets as-is
+#[derive(Debug, Clone)]
+pub struct RealtimeMessage {
+    pub team_id: Uuid,
+    pub topic: String,
+    pub job_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub payload: Arc<str>,
+}
+
+/// This replica's side of the fan-out: the listener sends, each socket holds a receiver
+#[derive(Clone)]
+pub struct RealtimeHub {
+    sender: broadcast::Sender<RealtimeMessage>,
+}
+
+impl RealtimeHub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(HUB_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RealtimeMessage> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for RealtimeHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Publish an event to the team's sockets on every replica. Real-time updates are advisory,
+/// so a Redis failure is logged rather than failing the change that caused it.
+pub async fn publish(state: &AppState, audience: Audience, event: WsEvent) {
+    let topic = event.topic();
+    let envelope = Envelope {
+        topic: topic.to_string(),
+        job_id: audience.job_id,
+        user_id: audience.user_id,
+        event,
+    };
+    let payload = match serde_json::to_string(&envelope) {
+        Ok(payload) => payload,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to serialize real-time event");
+            return;
+        }
+    };
+
+    let mut conn = state.redis.clone();
+    let result = redis::cmd("PUBLISH")
+        .arg(redis_channel(audience.team_id))
+        .arg(payload)
+        .query_async::<_, i64>(&mut conn)
+        .await;
+
+    if let Err(e) = result {
+        tracing::warn!(team_id = %audience.team_id, topic, error = %e, "Failed to publish real-time event");
+    }
+}
+
+/// Events gathered while a transaction is open, published once it has committed so sockets
+/// never see a change that was rolled back
+#[derive(Debug, Default)]
+pub struct Pending(Vec<(Audience, WsEvent)>);
+
+impl Pending {
+    pub fn push(&mut self, audience: Audience, event: WsEvent) {
+        self.0.push((audience, event));
+    }
+
+    pub async fn publish(self, state: &AppState) {
+        for (audience, event) in self.0 {
+            publish(state, audience, event).await;
+        }
+    }
+}
+
+/// Relay every team's Redis channel into this replica's hub, reconnecting if the pub/sub
+/// connection drops. Events published while disconnected are lost; clients refetch on
+/// reconnect, as they would after their own socket dropped.
+pub fn spawn_listener(client: redis::Client, hub: RealtimeHub) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match listen(&client, &hub).await {
+                Ok(()) => tracing::warn!("Real-time pub/sub connection closed; reconnecting"),
+                Err(e) => tracing::error!(error = %e, "Real-time pub/sub connection failed; reconnecting"),
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    })
+}
+
+async fn listen(client: &redis::Client, hub: &RealtimeHub) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.psubscribe(format!("{}:*", CHANNEL_PREFIX)).await?;
+    tracing::info!("Real-time listener subscribed to team channels");
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let team_id = msg
+            .get_channel_name()
+            .strip_prefix(CHANNEL_PREFIX)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|team_id| team_id.parse::<Uuid>().ok());
+        let Some(team_id) = team_id else {
+            continue;
+        };
+        let envelope = msg
+            .get_payload::<String>()
+            .map_err(|e| e.to_string())
+            .and_then(|payload| serde_json::from_str::<Envelope<serde_json::Value>>(&payload).map_err(|e| e.to_string()));
+        let envelope = match envelope {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                tracing::warn!(channel = %msg.get_channel_name(), error = %e, "Undecodable real-time message");
+                continue;
+            }
+        };
+
+        // An error only means no socket on this replica is listening right now
+        let _ = hub.sender.send(RealtimeMessage {
+            team_id,
+            topic: envelope.topic,
+            job_id: envelope.job_id,
+            user_id: envelope.user_id,
+            payload: envelope.event.to_string().into(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::recurring_rule::RecurringRule;
+
+/// Result of generating one occurrence of a recurring rule
+#[derive(Debug, Clone)]
+pub struct GeneratedOccurrence {
+    pub job_id: Option<Uuid>,
+    pub team_id: Uuid,
+    pub next_occurrence: Option<NaiveDate>,
+    pub advance_days: i32,
+}
+
+/// Create the job for the rule's next occurrence and advance the rule.
+///
+/// Safe to call more than once for the same occurrence: if a job already exists for
+/// the rule on that date the rule is only advanced. `team_id` scopes the lookup for
+/// API callers; workers pass `None`.
+pub async fn generate_next_occurrence(pool: &PgPool, rule_id: Uuid, team_id: Option<Uuid>) -> ApiResult<GeneratedOccurrence> {
+    let mut tx = pool.begin().await?;
+
+    let rule = sqlx::query_as::<_, RecurringRule>(
+        "SELECT * FROM recurring_rules WHERE id = $1 AND ($2::uuid IS NULL OR team_id = $2) FOR UPDATE",
+    )
+    .bind(rule_id)
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Recurring rule".into()))?;
+
+    let occurrence = rule.next_occurrence.unwrap_or(rule.start_date);
+    let exhausted = rule.end_date.is_some_and(|end| occurrence > end)
+        || rule.max_occurrences.is_some_and(|max| rule.occurrences_created >= max);
+
+    if !rule.is_active || exhausted {
+        if rule.is_active {
+            sqlx::query("UPDATE recurring_rules SET is_active = false WHERE id = $1")
+                .bind(rule.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        return Ok(GeneratedOccurrence {
+            job_id: None,
+            team_id: rule.team_id,
+            next_occurrence: None,
+            advance_days: rule.advance_days,
+        });
+    }
+
+    let existing = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM jobs WHERE recurring_rule_id = $1 AND scheduled_date = $2 AND deleted_at IS NULL",
+    )
+    .bind(rule.id)
+    .bind(occurrence)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let job_id = match existing {
+        Some(id) => id,
+        None => {
+            sqlx::query_scalar::<_, Uuid>(
+                r#"INSERT INTO jobs (team_id, customer_id, property_id, recurring_rule_id, title, description, status, priority, job_type, estimated_duration_minutes, assigned_to, scheduled_date)
+                   VALUES ($1, $2, $3, $4, $5, $6, 'scheduled'::job_status, $7::job_priority, $8, $9, $10, $11)
+                   RETURNING id"#,
+            )
+            .bind(rule.team_id)
+            .bind(rule.customer_id)
+            .bind(rule.property_id)
+            .bind(rule.id)
+            .bind(&rule.title)
+            .bind(&rule.description)
+            .bind(&rule.priority)
+            .bind(&rule.job_type)
+            .bind(rule.estimated_duration_minutes)
+            .bind(rule.assigned_to)
+            .bind(occurrence)
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+
+    let following = advance(&rule, occurrence);
+    let still_active = following.is_some_and(|next| rule.end_date.map_or(true, |end| next <= end))
+        && rule.max_occurrences.map_or(true, |max| rule.occurrences_created + 1 < max);
+
+    sqlx::query(
+        r#"UPDATE recurring_rules SET
+               occurrences_created = occurrences_created + $2,
+               next_occurrence = $3,
+               is_active = $4
+           WHERE id = $1"#,
+    )
+    .bind(rule.id)
+    .bind(if existing.is_some() { 0 } else { 1 })
+    .bind(following)
+    .bind(still_active)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    tracing::info!(rule_id = %rule.id, job_id = %job_id, occurrence = %occurrence, "Generated job from recurring rule");
+
+    Ok(GeneratedOccurrence {
+        job_id: Some(job_id),
+        team_id: rule.team_id,
+        next_occurrence: if still_active { following } else { None },
+        advance_days: rule.advance_days,
+    })
+}
+
+/// Date of the occurrence after `from` according to the rule's frequency
+fn advance(rule: &RecurringRule, from: NaiveDate) -> Option<NaiveDate> {
+    let interval = rule.interval_value.max(1) as u32;
+    let months = |n: u32| {
+        let anchor = rule.day_of_month.map(|d| d as u32).unwrap_or(rule.start_date.day());
+        add_months_anchored(from, n * interval, anchor)
+    };
+
+    match rule.frequency.as_str() {
+        "daily" | "custom" => from.checked_add_signed(Duration::days(interval as i64)),
+        "weekly" => from.checked_add_signed(Duration::weeks(interval as i64)),
+        "biweekly" => from.checked_add_signed(Duration::weeks(2 * interval as i64)),
+        "monthly" => months(1),
+        "quarterly" => months(3),
+        "semi_annual" => months(6),
+        "annual" => months(12),
+        _ => None,
+    }
+}
+
+/// Add months while keeping the preferred day of month, clamped to short months
+/// (a rule anchored on the 31st lands on Feb 28/29 and returns to the 31st in March).
+fn add_months_anchored(from: NaiveDate, months: u32, anchor_day: u32) -> Option<NaiveDate> {
+    let first = from.with_day(1)?.checked_add_months(Months::new(months))?;
+    let last_day = first.checked_add_months(Months::new(1))?.pred_opt()?.day();
+    first.with_day(anchor_day.clamp(1, last_day))
+}
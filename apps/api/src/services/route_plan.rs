@@ -0,0 +1,593 @@
+//! Daily route planning.
+//!
+//! Orders a technician's jobs for a day to cut driving while keeping arrivals inside the
+//! customers' arrival windows. A job's current start time isn't a constraint, since that's
+//! what planning rewrites; jobs already under way (en route, in progress, paused) stay at
+//! the front and keep their times. A route sets off from the technician's GPS position when
+//! planning today, else their last known location, else the first stop, at the start of
+//! their working time or now, whichever is later. Drive times come from the team's routing
+//! provider, falling back to straight-line estimates if it fails.
+//!
+//! Routes are built by cheapest insertion, tightest windows first, then improved by moving
+//! single stops and reversing runs (2-opt) until nothing helps. A second late for a window
+//! costs twenty seconds of driving and a second past the end of the shift costs three. The
+//! team-wide variant plans every working technician's route; with `reassign` it pools the
+//! day's movable and unassigned jobs and gives each to the technician it's cheapest to add
+//! it to, among those with the job's required skills and licenses.
+
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::repository::JOB_COLUMNS;
+use crate::errors::{ApiError, ApiResult};
+use crate::models::job::Job;
+use crate::services::availability::Calendar;
+use crate::services::dispatch::Qualifications;
+use crate::services::gps_store;
+use crate::services::realtime::{self, Audience, WsEvent};
+use crate::services::routing::{Coordinate, DistanceMatrix, RouteEstimate, StraightLine};
+use crate::services::schedule::{self, ScheduledJob, Technician, MOVABLE_STATUSES};
+use crate::AppState;
+
+const LATE_WEIGHT: i64 = 20;
+const OVERTIME_WEIGHT: i64 = 3;
+/// Planned starts are rounded up to this many minutes
+const ROUND_MINUTES: u32 = 5;
+/// Length assumed for a job with no times or estimate
+const DEFAULT_SERVICE_MINUTES: i64 = 60;
+/// Jobs the technician is already on
+const UNDER_WAY_STATUSES: &[&str] = &["en_route", "in_progress", "paused"];
+const MAX_PASSES: usize = 50;
+
+struct Stop {
+    job: ScheduledJob,
+    /// Index into the distance matrix
+    point: usize,
+    service: Duration,
+    pinned: bool,
+}
+
+impl Stop {
+    /// Tightest arrival windows first, then by current start
+    fn urgency(&self) -> (bool, Option<NaiveTime>, Option<NaiveTime>, Option<NaiveTime>) {
+        let job = &self.job;
+        (job.arrival_window_end.is_none(), job.arrival_window_end, job.arrival_window_start, job.slot().map(|slot| slot.start))
+    }
+
+    /// The order jobs were in before planning: under way first, then by start, then untimed
+    fn current_order(&self) -> (bool, bool, Option<NaiveTime>) {
+        let start = self.job.slot().map(|slot| slot.start);
+        (!self.pinned, start.is_none(), start)
+    }
+
+    /// Whether the job has its own end time, which moves with its start
+    fn has_end(&self) -> bool {
+        matches!((self.job.scheduled_start_time, self.job.scheduled_end_time), (Some(start), Some(end)) if end > start)
+    }
+}
+
+struct Vehicle {
+    technician: Technician,
+    /// Index into the distance matrix; `None` starts at the first stop
+    origin: Option<usize>,
+    starts_from: Option<&'static str>,
+    day_start: NaiveDateTime,
+    day_end: Option<NaiveDateTime>,
+    /// Working that day; a technician who is off keeps jobs already theirs but gets no new ones
+    available: bool,
+    /// Indices into the stops, the first `pinned` of them under way
+    order: Vec<usize>,
+    pinned: usize,
+    /// Movable jobs still to be placed on this route
+    pending: Vec<usize>,
+    /// Their jobs in the order they were in before planning
+    current: Vec<usize>,
+}
+
+struct Visit {
+    arrival: NaiveDateTime,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    drive: RouteEstimate,
+    late: Duration,
+}
+
+struct Timeline {
+    visits: Vec<Visit>,
+    cost: i64,
+    drive_secs: i64,
+    drive_meters: f64,
+    late_secs: i64,
+    overtime_secs: i64,
+}
+
+struct Planner<'a> {
+    date: NaiveDate,
+    stops: &'a [Stop],
+    matrix: &'a DistanceMatrix,
+}
+
+impl Planner<'_> {
+    /// Drive the route in `order`, waiting for arrival windows to open
+    fn simulate(&self, vehicle: &Vehicle, order: &[usize]) -> Timeline {
+        let mut timeline =
+            Timeline { visits: Vec::with_capacity(order.len()), cost: 0, drive_secs: 0, drive_meters: 0.0, late_secs: 0, overtime_secs: 0 };
+        let mut at = vehicle.origin;
+        let mut clock = vehicle.day_start;
+
+        for &index in order {
+            let stop = &self.stops[index];
+            let drive = at.map_or(RouteEstimate::ZERO, |from| self.matrix[from][stop.point]);
+            let arrival = clock + Duration::seconds(drive.duration_secs);
+            let start = if stop.pinned {
+                arrival
+            } else {
+                let opens = stop.job.arrival_window_start.map(|time| self.date.and_time(time));
+                round_up(opens.map_or(arrival, |opens| opens.max(arrival)))
+            };
+            let late = stop
+                .job
+                .arrival_window_end
+                .map_or(Duration::zero(), |closes| (start - self.date.and_time(closes)).max(Duration::zero()));
+            let end = start + stop.service;
+
+            timeline.drive_secs += drive.duration_secs;
+            timeline.drive_meters += drive.distance_meters;
+            timeline.late_secs += late.num_seconds();
+            timeline.visits.push(Visit { arrival, start, end, drive, late });
+            clock = end;
+            at = Some(stop.point);
+        }
+
+        timeline.overtime_secs = vehicle.day_end.map_or(0, |day_end| (clock - day_end).num_seconds().max(0));
+        timeline.cost = timeline.drive_secs + LATE_WEIGHT * timeline.late_secs + OVERTIME_WEIGHT * timeline.overtime_secs;
+        timeline
+    }
+
+    fn cost(&self, vehicle: &Vehicle, order: &[usize]) -> i64 {
+        self.simulate(vehicle, order).cost
+    }
+
+    /// The cheapest place after the pinned stops to add a stop, and what it adds to the cost
+    fn best_insertion(&self, vehicle: &Vehicle, index: usize) -> (i64, usize) {
+        let base = self.cost(vehicle, &vehicle.order);
+        let mut candidate = vehicle.order.clone();
+        let mut best = (i64::MAX, vehicle.order.len());
+        for position in vehicle.pinned..=vehicle.order.len() {
+            candidate.insert(position, index);
+            let added = self.cost(vehicle, &candidate) - base;
+            if added < best.0 {
+                best = (added, position);
+            }
+            candidate.remove(position);
+        }
+        best
+    }
+
+    /// Move single stops and reverse runs of stops while that lowers the cost
+    fn improve(&self, vehicle: &mut Vehicle) {
+        let mut best = self.cost(vehicle, &vehicle.order);
+        let len = vehicle.order.len();
+        for _ in 0..MAX_PASSES {
+            let mut improved = false;
+
+            for from in vehicle.pinned..len {
+                for to in vehicle.pinned..len {
+                    if from == to {
+                        continue;
+                    }
+                    let mut candidate = vehicle.order.clone();
+                    let index = candidate.remove(from);
+                    candidate.insert(to, index);
+                    let cost = self.cost(vehicle, &candidate);
+                    if cost < best {
+                        best = cost;
+                        vehicle.order = candidate;
+                        improved = true;
+                    }
+                }
+            }
+
+            for first in vehicle.pinned..len {
+                for last in first + 1..len {
+                    let mut candidate = vehicle.order.clone();
+                    candidate[first..=last].reverse();
+                    let cost = self.cost(vehicle, &candidate);
+                    if cost < best {
+                        best = cost;
+                        vehicle.order = candidate;
+                        improved = true;
+                    }
+                }
+            }
+
+            if !improved {
+                break;
+            }
+        }
+    }
+}
+
+fn round_up(time: NaiveDateTime) -> NaiveDateTime {
+    let step = ROUND_MINUTES * 60;
+    let time = time.with_nanosecond(0).unwrap_or(time);
+    match time.time().num_seconds_from_midnight() % step {
+        0 => time,
+        over => time + Duration::seconds((step - over).into()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedStop {
+    pub job_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub customer_name: String,
+    pub property_address: Option<String>,
+    pub arrival_window_start: Option<NaiveTime>,
+    pub arrival_window_end: Option<NaiveTime>,
+    /// From the previous stop, or from the route's start
+    pub drive_secs: i64,
+    pub drive_meters: f64,
+    pub arrival: NaiveDateTime,
+    /// Arrival rounded up, or when the arrival window opens
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub late_minutes: i64,
+    pub current_start_time: Option<NaiveTime>,
+    pub current_assignee: Option<Uuid>,
+    /// Already under way, so first on the route and left as it is
+    pub pinned: bool,
+    #[serde(skip)]
+    version: i32,
+    #[serde(skip)]
+    has_end: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Route {
+    #[serde(flatten)]
+    pub technician: Technician,
+    /// `gps` or `last_location`; unknown starts at the first stop
+    pub starts_from: Option<&'static str>,
+    pub starts_at: NaiveDateTime,
+    /// End of their working time
+    pub shift_ends_at: Option<NaiveDateTime>,
+    pub stops: Vec<PlannedStop>,
+    pub drive_secs: i64,
+    pub drive_meters: f64,
+    pub late_minutes: i64,
+    pub overtime_minutes: i64,
+    /// Driving in the order the technician's jobs were in before planning
+    pub current_drive_secs: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Unrouted {
+    pub job_id: Uuid,
+    pub title: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub date: NaiveDate,
+    /// Where drive times came from
+    pub provider: &'static str,
+    pub reassign: bool,
+    pub routes: Vec<Route>,
+    pub unrouted: Vec<Unrouted>,
+}
+
+/// Plan routes for `date`: just `only`'s, or every technician's with work or working time
+/// that day. With `reassign`, jobs can move between technicians and unassigned jobs are
+/// handed out.
+pub async fn plan(state: &AppState, team_id: Uuid, date: NaiveDate, only: Option<Uuid>, reassign: bool) -> ApiResult<Plan> {
+    let mut conn = state.db.acquire().await?;
+
+    let jobs = schedule::jobs_between(&mut conn, team_id, date, date, only).await?;
+    let assignees: Vec<Uuid> = jobs.iter().filter_map(|job| job.assigned_to).collect();
+    let technicians = schedule::technicians(&mut conn, team_id, &assignees, only).await?;
+    if only.is_some() && technicians.is_empty() {
+        return Err(ApiError::NotFound("Technician".into()));
+    }
+    let users: Vec<Uuid> = technicians.iter().map(|technician| technician.user_id).collect();
+
+    let calendar = Calendar::load(&mut conn, team_id, &users, date, date).await?;
+    let qualifications = if reassign { Some(Qualifications::load(&mut conn, team_id, &users, date).await?) } else { None };
+    let last_locations: HashMap<Uuid, Coordinate> = sqlx::query_as::<_, (Uuid, Option<f64>, Option<f64>)>(
+        "SELECT id, last_location_lat, last_location_lng FROM users WHERE id = ANY($1)",
+    )
+    .bind(&users)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .filter_map(|(id, latitude, longitude)| Some((id, Coordinate { latitude: latitude?, longitude: longitude? })))
+    .collect();
+    drop(conn);
+
+    let hours = calendar.hours();
+    let tz: Tz = hours.timezone.parse().unwrap_or(Tz::UTC);
+    let now = Utc::now().with_timezone(&tz).naive_local();
+    let today = now.date() == date;
+    let fixes: HashMap<Uuid, Coordinate> = if today {
+        let max_age = Duration::seconds(state.config.tracking.location_max_age_secs);
+        gps_store::latest_positions(state, team_id, max_age)
+            .await?
+            .into_iter()
+            .map(|fix| (fix.user_id, Coordinate { latitude: fix.latitude, longitude: fix.longitude }))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let default_start = hours.working_hours_start.or_else(|| NaiveTime::from_hms_opt(8, 0, 0)).unwrap_or(NaiveTime::MIN);
+
+    let mut points: Vec<Coordinate> = Vec::new();
+    let mut vehicles: Vec<Vehicle> = Vec::new();
+    for technician in technicians {
+        let user_id = technician.user_id;
+        let working = calendar.day(Some(user_id), date).working();
+        let has_jobs = jobs.iter().any(|job| job.assigned_to == Some(user_id));
+        if working.is_empty() && !has_jobs && only.is_none() {
+            continue;
+        }
+
+        let mut day_start = working.first().map_or(date.and_time(default_start), |interval| interval.start);
+        if today {
+            day_start = day_start.max(now);
+        }
+        let origin = fixes
+            .get(&user_id)
+            .map(|&location| (location, "gps"))
+            .or_else(|| last_locations.get(&user_id).map(|&location| (location, "last_location")));
+        let (origin, starts_from) = match origin {
+            Some((location, source)) => {
+                points.push(location);
+                (Some(points.len() - 1), Some(source))
+            }
+            None => (None, None),
+        };
+
+        vehicles.push(Vehicle {
+            technician,
+            origin,
+            starts_from,
+            day_start,
+            day_end: working.last().map(|interval| interval.end),
+            available: !working.is_empty(),
+            order: Vec::new(),
+            pinned: 0,
+            pending: Vec::new(),
+            current: Vec::new(),
+        });
+    }
+    let vehicle_of: HashMap<Uuid, usize> =
+        vehicles.iter().enumerate().map(|(i, vehicle)| (vehicle.technician.user_id, i)).collect();
+
+    let mut stops: Vec<Stop> = Vec::new();
+    let mut pool: Vec<usize> = Vec::new();
+    let mut unrouted = Vec::new();
+    for job in jobs {
+        let pinned = UNDER_WAY_STATUSES.contains(&job.status.as_str());
+        if !pinned && !MOVABLE_STATUSES.contains(&job.status.as_str()) {
+            continue;
+        }
+        let vehicle = job.assigned_to.and_then(|user_id| vehicle_of.get(&user_id).copied());
+        if vehicle.is_none() && !reassign {
+            continue;
+        }
+        let Some(location) = job.location() else {
+            unrouted.push(Unrouted { job_id: job.id, title: job.title, reason: "The property isn't geocoded".into() });
+            continue;
+        };
+
+        let service = match job.slot() {
+            Some(slot) => slot.end - slot.start,
+            None => Duration::minutes(job.estimated_duration_minutes.map_or(DEFAULT_SERVICE_MINUTES, i64::from)),
+        };
+        points.push(location);
+        let index = stops.len();
+        stops.push(Stop { job, point: points.len() - 1, service, pinned });
+
+        match vehicle {
+            Some(vehicle) => {
+                let vehicle = &mut vehicles[vehicle];
+                vehicle.current.push(index);
+                if pinned {
+                    vehicle.order.push(index);
+                } else if reassign {
+                    pool.push(index);
+                } else {
+                    vehicle.pending.push(index);
+                }
+            }
+            None => pool.push(index),
+        }
+    }
+
+    let (matrix, provider) = match state.routing.matrix(&points).await {
+        Ok(matrix) => (matrix, state.routing.name()),
+        Err(e) => {
+            tracing::warn!(provider = state.routing.name(), error = %e, "Routing provider failed; planning with straight-line drives");
+            (StraightLine::new(&state.config.tracking).matrix_now(&points), "straight_line")
+        }
+    };
+    let planner = Planner { date, stops: &stops, matrix: &matrix };
+
+    for vehicle in &mut vehicles {
+        vehicle.current.sort_by_key(|&index| stops[index].current_order());
+        vehicle.order.sort_by_key(|&index| stops[index].current_order());
+        vehicle.pinned = vehicle.order.len();
+
+        let mut pending = std::mem::take(&mut vehicle.pending);
+        pending.sort_by_key(|&index| stops[index].urgency());
+        for index in pending {
+            let (_, position) = planner.best_insertion(vehicle, index);
+            vehicle.order.insert(position, index);
+        }
+    }
+
+    pool.sort_by_key(|&index| stops[index].urgency());
+    for index in pool {
+        let job = &stops[index].job;
+        let best = vehicles
+            .iter()
+            .enumerate()
+            .filter(|(_, vehicle)| vehicle.available || job.assigned_to == Some(vehicle.technician.user_id))
+            .filter(|(_, vehicle)| {
+                qualifications.as_ref().map_or(true, |qualifications| {
+                    qualifications
+                        .missing(vehicle.technician.user_id, &job.required_skills, &job.required_license_types)
+                        .is_empty()
+                })
+            })
+            .map(|(i, vehicle)| {
+                let (added, position) = planner.best_insertion(vehicle, index);
+                (added, i, position)
+            })
+            .min_by_key(|(added, _, _)| *added);
+        match best {
+            Some((_, vehicle, position)) => vehicles[vehicle].order.insert(position, index),
+            None => unrouted.push(Unrouted {
+                job_id: job.id,
+                title: job.title.clone(),
+                reason: "No technician working that day has the skills and licenses it needs".into(),
+            }),
+        }
+    }
+
+    let routes = vehicles
+        .into_iter()
+        .filter(|vehicle| !vehicle.order.is_empty() || only.is_some())
+        .map(|mut vehicle| {
+            planner.improve(&mut vehicle);
+            let timeline = planner.simulate(&vehicle, &vehicle.order);
+            let current_drive_secs = planner.simulate(&vehicle, &vehicle.current).drive_secs;
+
+            let planned = vehicle
+                .order
+                .iter()
+                .zip(timeline.visits)
+                .map(|(&index, visit)| {
+                    let stop = &stops[index];
+                    let job = &stop.job;
+                    PlannedStop {
+                        job_id: job.id,
+                        title: job.title.clone(),
+                        status: job.status.clone(),
+                        customer_name: job.customer_name.clone(),
+                        property_address: job.property_address.clone(),
+                        arrival_window_start: job.arrival_window_start,
+                        arrival_window_end: job.arrival_window_end,
+                        drive_secs: visit.drive.duration_secs,
+                        drive_meters: visit.drive.distance_meters,
+                        arrival: visit.arrival,
+                        start: visit.start,
+                        end: visit.end,
+                        late_minutes: visit.late.num_minutes(),
+                        current_start_time: job.scheduled_start_time,
+                        current_assignee: job.assigned_to,
+                        pinned: stop.pinned,
+                        version: job.version,
+                        has_end: stop.has_end(),
+                    }
+                })
+                .collect();
+
+            Route {
+                technician: vehicle.technician,
+                starts_from: vehicle.starts_from,
+                starts_at: vehicle.day_start,
+                shift_ends_at: vehicle.day_end,
+                stops: planned,
+                drive_secs: timeline.drive_secs,
+                drive_meters: timeline.drive_meters,
+                late_minutes: timeline.late_secs / 60,
+                overtime_minutes: timeline.overtime_secs / 60,
+                current_drive_secs,
+            }
+        })
+        .collect();
+
+    Ok(Plan { date, provider, reassign, routes, unrouted })
+}
+
+/// Write a plan's start times, and its assignees when it reassigns. Under-way jobs are
+/// left alone. A job changed since the plan was made fails the whole write.
+pub async fn apply(state: &AppState, team_id: Uuid, plan: &Plan) -> ApiResult<Vec<Job>> {
+    let mut technicians: Vec<Uuid> = plan
+        .routes
+        .iter()
+        .flat_map(|route| std::iter::once(route.technician.user_id).chain(route.stops.iter().filter_map(|stop| stop.current_assignee)))
+        .collect();
+    technicians.sort();
+    technicians.dedup();
+
+    let mut tx = state.db.begin().await?;
+    // In a fixed order, so two plans for overlapping technicians can't deadlock
+    for technician in &technicians {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('schedule:' || $1::text))")
+            .bind(technician)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let mut moved: Vec<(Job, Option<Uuid>)> = Vec::new();
+    for route in &plan.routes {
+        let technician = route.technician.user_id;
+        for stop in route.stops.iter().filter(|stop| !stop.pinned) {
+            if stop.end.date() != plan.date {
+                return Err(ApiError::Validation(format!(
+                    "{} {}'s route runs past midnight",
+                    route.technician.first_name, route.technician.last_name
+                )));
+            }
+            let start = stop.start.time();
+            if stop.current_start_time == Some(start) && stop.current_assignee == Some(technician) {
+                continue;
+            }
+
+            let job = sqlx::query_as::<_, Job>(&format!(
+                r#"
+                UPDATE jobs SET
+                    assigned_to = $3,
+                    scheduled_start_time = $4,
+                    scheduled_end_time = $5,
+                    version = version + 1
+                WHERE id = $1 AND team_id = $2 AND version = $6 AND deleted_at IS NULL
+                RETURNING {}
+                "#,
+                JOB_COLUMNS
+            ))
+            .bind(stop.job_id)
+            .bind(team_id)
+            .bind(technician)
+            .bind(start)
+            .bind(stop.has_end.then(|| stop.end.time()))
+            .bind(stop.version)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| ApiError::Conflict(format!("\"{}\" changed while the route was planned; plan again", stop.title)))?;
+            moved.push((job, stop.current_assignee));
+        }
+    }
+
+    tx.commit().await?;
+
+    tracing::info!(date = %plan.date, jobs = moved.len(), reassign = plan.reassign, "Route plan applied");
+
+    for (job, previous) in &moved {
+        let change_type = if job.assigned_to != *previous { "reassigned" } else { "rescheduled" };
+        let event = WsEvent::ScheduleChanged { job_id: job.id.to_string(), change_type: change_type.into() };
+        realtime::publish(state, Audience::team(team_id).job(Some(job.id)).user(job.assigned_to), event.clone()).await;
+        if previous.is_some() && *previous != job.assigned_to {
+            realtime::publish(state, Audience::team(team_id).user(*previous), event).await;
+        }
+    }
+
+    Ok(moved.into_iter().map(|(job, _)| job).collect())
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use serde::Deserialize;
+
+use crate::config::{Settings, TrackingSettings};
+use crate::services::geofence;
+
+const GOOGLE_DISTANCE_MATRIX_URL: &str = "https://maps.googleapis.com/maps/api/distancematrix/json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Distance Matrix allows 100 elements a request
+const MATRIX_BLOCK: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Coordinate {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Expected drive from one point to another
+#[derive(Debug, Clone, Copy)]
+pub struct RouteEstimate {
+    pub distance_meters: f64,
+    pub duration_secs: i64,
+}
+
+impl RouteEstimate {
+    pub const ZERO: RouteEstimate = RouteEstimate { distance_meters: 0.0, duration_secs: 0 };
+}
+
+/// Drives between every pair of points, indexed `[from][to]`
+pub type DistanceMatrix = Vec<Vec<RouteEstimate>>;
+
+/// Travel-time estimates for customer ETAs and route planning. Implementations may call
+/// out over the network, so callers keep them out of transactions.
+#[async_trait]
+pub trait RoutingProvider: Send + Sync {
+    /// Short name reported alongside the estimate
+    fn name(&self) -> &'static str;
+
+    async fn estimate(&self, from: Coordinate, to: Coordinate) -> anyhow::Result<RouteEstimate>;
+
+    /// Drives between every pair of `points`, for route planning. The default asks
+    /// [`estimate`](Self::estimate) for each pair in turn.
+    async fn matrix(&self, points: &[Coordinate]) -> anyhow::Result<DistanceMatrix> {
+        let mut matrix = vec![vec![RouteEstimate::ZERO; points.len()]; points.len()];
+        for (i, &from) in points.iter().enumerate() {
+            for (j, &to) in points.iter().enumerate() {
+                if i != j {
+                    matrix[i][j] = self.estimate(from, to).await?;
+                }
+            }
+        }
+        Ok(matrix)
+    }
+}
+
+/// Pick the provider named by `ROUTING_PROVIDER`: `straight_line` (the default; no external
+/// calls) or `google` (Distance Matrix with live traffic, needs `GOOGLE_MAPS_API_KEY`)
+pub fn from_settings(settings: &Settings) -> anyhow::Result<Arc<dyn RoutingProvider>> {
+    match settings.tracking.routing_provider.as_str() {
+        "straight_line" => Ok(Arc::new(StraightLine::new(&settings.tracking))),
+        "google" => Ok(Arc::new(GoogleDistanceMatrix::new(&settings.tracking)?)),
+        other => Err(anyhow!("Unknown ROUTING_PROVIDER '{}'; expected straight_line or google", other)),
+    }
+}
+
+/// Distance as the crow flies at a fixed average speed
+pub struct StraightLine {
+    meters_per_sec: f64,
+}
+
+impl StraightLine {
+    pub fn new(settings: &TrackingSettings) -> Self {
+        Self { meters_per_sec: settings.average_speed_kmh * 1000.0 / 3600.0 }
+    }
+
+    pub fn estimate_now(&self, from: Coordinate, to: Coordinate) -> RouteEstimate {
+        let distance_meters = geofence::distance_meters(from.latitude, from.longitude, to.latitude, to.longitude);
+        RouteEstimate {
+            distance_meters,
+            duration_secs: (distance_meters / self.meters_per_sec).round() as i64,
+        }
+    }
+
+    pub fn matrix_now(&self, points: &[Coordinate]) -> DistanceMatrix {
+        points.iter().map(|&from| points.iter().map(|&to| self.estimate_now(from, to)).collect()).collect()
+    }
+}
+
+#[async_trait]
+impl RoutingProvider for StraightLine {
+    fn name(&self) -> &'static str {
+        "straight_line"
+    }
+
+    async fn estimate(&self, from: Coordinate, to: Coordinate) -> anyhow::Result<RouteEstimate> {
+        Ok(self.estimate_now(from, to))
+    }
+
+    async fn matrix(&self, points: &[Coordinate]) -> anyhow::Result<DistanceMatrix> {
+        Ok(self.matrix_now(points))
+    }
+}
+
+pub struct GoogleDistanceMatrix {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl GoogleDistanceMatrix {
+    pub fn new(settings: &TrackingSettings) -> anyhow::Result<Self> {
+        if settings.google_maps_api_key.is_empty() {
+            return Err(anyhow!("ROUTING_PROVIDER=google needs GOOGLE_MAPS_API_KEY"));
+        }
+        Ok(Self {
+            api_key: settings.google_maps_api_key.clone(),
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+        })
+    }
+
+    /// Drives from each origin to each destination, with live traffic
+    async fn request(&self, origins: &[Coordinate], destinations: &[Coordinate]) -> anyhow::Result<DistanceMatrix> {
+        let join = |points: &[Coordinate]| {
+            points.iter().map(|point| format!("{},{}", point.latitude, point.longitude)).collect::<Vec<_>>().join("|")
+        };
+        let response: MatrixResponse = self
+            .client
+            .get(GOOGLE_DISTANCE_MATRIX_URL)
+            .query(&[
+                ("origins", join(origins)),
+                ("destinations", join(destinations)),
+                ("departure_time", "now".to_string()),
+                ("key", self.api_key.clone()),
+            ])
+            .send()
+            .await
+            .context("Distance Matrix request failed")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Unreadable Distance Matrix response")?;
+
+        if response.status != "OK" {
+            return Err(anyhow!("Distance Matrix returned {}", response.status));
+        }
+        if response.rows.len() != origins.len() {
+            return Err(anyhow!("Distance Matrix returned {} rows for {} origins", response.rows.len(), origins.len()));
+        }
+
+        response
+            .rows
+            .into_iter()
+            .map(|row| {
+                if row.elements.len() != destinations.len() {
+                    return Err(anyhow!("Distance Matrix returned a short row"));
+                }
+                row.elements
+                    .into_iter()
+                    .map(|element| {
+                        if element.status != "OK" {
+                            return Err(anyhow!("No route found: {}", element.status));
+                        }
+                        let distance = element.distance.ok_or_else(|| anyhow!("Route has no distance"))?;
+                        let duration = element
+                            .duration_in_traffic
+                            .or(element.duration)
+                            .ok_or_else(|| anyhow!("Route has no duration"))?;
+                        Ok(RouteEstimate {
+                            distance_meters: distance.value,
+                            duration_secs: duration.value.round() as i64,
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct MatrixResponse {
+    status: String,
+    #[serde(default)]
+    rows: Vec<MatrixRow>,
+}
+
+#[derive(Deserialize)]
+struct MatrixRow {
+    elements: Vec<MatrixElement>,
+}
+
+#[derive(Deserialize)]
+struct MatrixElement {
+    status: String,
+    distance: Option<MatrixValue>,
+    duration: Option<MatrixValue>,
+    duration_in_traffic: Option<MatrixValue>,
+}
+
+#[derive(Deserialize)]
+struct MatrixValue {
+    value: f64,
+}
+
+#[async_trait]
+impl RoutingProvider for GoogleDistanceMatrix {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    async fn estimate(&self, from: Coordinate, to: Coordinate) -> anyhow::Result<RouteEstimate> {
+        self.request(&[from], &[to])
+            .await?
+            .into_iter()
+            .next()
+            .and_then(|row| row.into_iter().next())
+            .ok_or_else(|| anyhow!("Distance Matrix returned no route"))
+    }
+
+    async fn matrix(&self, points: &[Coordinate]) -> anyhow::Result<DistanceMatrix> {
+        let mut matrix = vec![vec![RouteEstimate::ZERO; points.len()]; points.len()];
+        for (block_row, origins) in points.chunks(MATRIX_BLOCK).enumerate() {
+            for (block_col, destinations) in points.chunks(MATRIX_BLOCK).enumerate() {
+                let block = self.request(origins, destinations).await?;
+                for (i, row) in block.into_iter().enumerate() {
+                    for (j, estimate) in row.into_iter().enumerate() {
+                        matrix[block_row * MATRIX_BLOCK + i][block_col * MATRIX_BLOCK + j] = estimate;
+                    }
+                }
+            }
+        }
+        Ok(matrix)
+    }
+}
+
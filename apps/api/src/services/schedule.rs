@@ -0,0 +1,747 @@
+//! Dispatch board scheduling.
+//!
+//! A scheduled job occupies its technician from its start (the scheduled start, or else
+//! the start of its arrival window) to its scheduled end, or for its estimated duration,
+//! or for an hour. [`check`] tests a slot against the technician's other jobs that day and
+//! their availability (shifts or team hours, holidays and approved time off), and wants a
+//! gap between consecutive jobs: the team's travel buffer, or the straight-line drive
+//! between the two properties if that's longer. Times are wall-clock times in the team's
+//! time zone, like `jobs.scheduled_*`.
+
+use std::collections::HashMap;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::db::repository::JOB_COLUMNS;
+use crate::errors::{ApiError, ApiResult};
+use crate::models::job::Job;
+use crate::services::availability::{Calendar, Day, Interval};
+use crate::services::realtime::{self, Audience, WsEvent};
+use crate::services::routing::{Coordinate, StraightLine};
+use crate::AppState;
+
+/// How long a job with no end or estimate is assumed to take
+const DEFAULT_DURATION_MINUTES: i64 = 60;
+/// Once work has started a job stays where it is on the board
+pub const MOVABLE_STATUSES: &[&str] = &["lead", "estimated", "approved", "scheduled"];
+const TRAVEL_BUFFER_RANGE_MINUTES: std::ops::RangeInclusive<i32> = 0..=240;
+
+/// Jobs with their customer and property, for the board and conflict checks
+const SCHEDULED_JOB_QUERY: &str = r#"
+    SELECT j.id, j.title, j.status::text AS status, j.priority::text AS priority, j.assigned_to,
+           j.scheduled_date, j.scheduled_start_time, j.scheduled_end_time,
+           j.arrival_window_start, j.arrival_window_end, j.estimated_duration_minutes,
+           j.customer_id, c.first_name || ' ' || c.last_name AS customer_name,
+           j.property_id, p.address_line1 AS property_address, p.latitude, p.longitude,
+           j.required_skills, j.required_license_types, j.version
+    FROM jobs j
+    JOIN customers c ON c.id = j.customer_id
+    LEFT JOIN properties p ON p.id = j.property_id
+    WHERE j.team_id = $1 AND j.deleted_at IS NULL AND j.status <> 'cancelled'
+"#;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct TeamHours {
+    pub timezone: String,
+    pub working_hours_start: Option<NaiveTime>,
+    pub working_hours_end: Option<NaiveTime>,
+    /// 0 is Sunday; `None` means every day
+    pub working_days: Option<Vec<i32>>,
+    pub schedule_travel_buffer_minutes: i32,
+}
+
+impl TeamHours {
+    pub fn is_working_day(&self, date: NaiveDate) -> bool {
+        let day = date.weekday().num_days_from_sunday() as i32;
+        match &self.working_days {
+            Some(days) => days.contains(&day),
+            None => true,
+        }
+    }
+}
+
+pub async fn team_hours(conn: &mut PgConnection, team_id: Uuid) -> ApiResult<TeamHours> {
+    sqlx::query_as::<_, TeamHours>(
+        r#"
+        SELECT timezone, working_hours_start, working_hours_end, working_days, schedule_travel_buffer_minutes
+        FROM teams WHERE id = $1
+        "#,
+    )
+    .bind(team_id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// Validate working hours and travel buffer settings for a team update
+pub fn validate_team_hours(
+    start: Option<NaiveTime>,
+    end: Option<NaiveTime>,
+    days: Option<&[i32]>,
+    travel_buffer_minutes: Option<i32>,
+) -> ApiResult<()> {
+    if let (Some(start), Some(end)) = (start, end) {
+        if end <= start {
+            return Err(ApiError::Validation("Working hours must end after they start".into()));
+        }
+    }
+    if days.is_some_and(|days| days.iter().any(|day| !(0..=6).contains(day))) {
+        return Err(ApiError::Validation("Working days are 0 (Sunday) to 6 (Saturday)".into()));
+    }
+    if travel_buffer_minutes.is_some_and(|minutes| !TRAVEL_BUFFER_RANGE_MINUTES.contains(&minutes)) {
+        return Err(ApiError::Validation(format!(
+            "Travel buffer must be between {} and {} minutes",
+            TRAVEL_BUFFER_RANGE_MINUTES.start(),
+            TRAVEL_BUFFER_RANGE_MINUTES.end()
+        )));
+    }
+    Ok(())
+}
+
+/// The time a job takes up on its day
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Slot {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Slot {
+    /// `None` for jobs with a date but no time, which sit at the end of the day's lane
+    pub fn of(
+        start: Option<NaiveTime>,
+        end: Option<NaiveTime>,
+        arrival_window_start: Option<NaiveTime>,
+        estimated_duration_minutes: Option<i32>,
+    ) -> Option<Slot> {
+        let start = start.or(arrival_window_start)?;
+        let end = end.filter(|end| *end > start).unwrap_or_else(|| {
+            let minutes = estimated_duration_minutes.map_or(DEFAULT_DURATION_MINUTES, i64::from);
+            // A job running past midnight is cut off at the end of its day
+            match start.overflowing_add_signed(Duration::minutes(minutes)) {
+                (end, 0) => end,
+                _ => NaiveTime::from_hms_opt(23, 59, 59).unwrap_or(start),
+            }
+        });
+        Some(Slot { start, end })
+    }
+
+    fn overlaps(&self, other: &Slot) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// Idle time between two slots that don't overlap
+    fn gap(&self, other: &Slot) -> Duration {
+        if self.end <= other.start {
+            other.start - self.end
+        } else {
+            self.start - other.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ScheduledJob {
+    pub id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub priority: String,
+    pub assigned_to: Option<Uuid>,
+    pub scheduled_date: NaiveDate,
+    pub scheduled_start_time: Option<NaiveTime>,
+    pub scheduled_end_time: Option<NaiveTime>,
+    pub arrival_window_start: Option<NaiveTime>,
+    pub arrival_window_end: Option<NaiveTime>,
+    pub estimated_duration_minutes: Option<i32>,
+    pub customer_id: Uuid,
+    pub customer_name: String,
+    pub property_id: Option<Uuid>,
+    pub property_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub required_skills: Vec<String>,
+    pub required_license_types: Vec<String>,
+    pub version: i32,
+}
+
+impl ScheduledJob {
+    pub fn slot(&self) -> Option<Slot> {
+        Slot::of(
+            self.scheduled_start_time,
+            self.scheduled_end_time,
+            self.arrival_window_start,
+            self.estimated_duration_minutes,
+        )
+    }
+
+    pub fn location(&self) -> Option<Coordinate> {
+        Some(Coordinate { latitude: self.latitude?, longitude: self.longitude? })
+    }
+}
+
+/// Something wrong with where a job is, or would be, scheduled
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    /// `overlap`, `travel_buffer`, `outside_working_hours`, `non_working_day`, `holiday`
+    /// or `time_off`
+    pub kind: &'static str,
+    pub message: String,
+    /// The other job, for `overlap` and `travel_buffer`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<Uuid>,
+}
+
+/// A job's place on a technician's day, actual or proposed
+#[derive(Debug, Clone)]
+pub struct Placement {
+    /// The job being placed, left out of its own conflicts
+    pub job_id: Option<Uuid>,
+    pub date: NaiveDate,
+    pub slot: Option<Slot>,
+    pub location: Option<Coordinate>,
+}
+
+impl Placement {
+    fn of(job: &ScheduledJob) -> Self {
+        Placement { job_id: Some(job.id), date: job.scheduled_date, slot: job.slot(), location: job.location() }
+    }
+}
+
+/// Check a placement against the technician's day and their other jobs
+pub fn check(
+    placement: &Placement,
+    hours: &TeamHours,
+    day: &Day,
+    others: &[ScheduledJob],
+    travel: &StraightLine,
+) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+
+    if let Some(holiday) = &day.holiday {
+        conflicts.push(Conflict {
+            kind: "holiday",
+            message: format!("{} is a holiday ({})", placement.date.format("%Y-%m-%d"), holiday),
+            job_id: None,
+        });
+    } else if day.shifts.is_empty() {
+        conflicts.push(Conflict {
+            kind: "non_working_day",
+            message: format!("{} is not a working day", weekday_name(placement.date.weekday())),
+            job_id: None,
+        });
+    }
+
+    let Some(slot) = placement.slot else {
+        // A job with only a date needs some part of the day free
+        if day.holiday.is_none() && !day.shifts.is_empty() && day.working().is_empty() {
+            conflicts.push(Conflict { kind: "time_off", message: "Off for the whole day".into(), job_id: None });
+        }
+        return conflicts;
+    };
+
+    let interval = Interval::on(placement.date, slot.start, slot.end);
+    if !day.shifts.is_empty() && !day.shifts.iter().any(|shift| shift.contains(&interval)) {
+        conflicts.push(Conflict {
+            kind: "outside_working_hours",
+            message: format!(
+                "{}-{} is outside working hours",
+                slot.start.format("%H:%M"),
+                slot.end.format("%H:%M")
+            ),
+            job_id: None,
+        });
+    }
+    for time_off in day.approved_time_off() {
+        if time_off.interval.overlaps(&interval) {
+            conflicts.push(Conflict {
+                kind: "time_off",
+                message: format!("Overlaps approved {} time off", time_off.kind),
+                job_id: None,
+            });
+        }
+    }
+
+    let buffer = Duration::minutes(hours.schedule_travel_buffer_minutes.into());
+    for other in others {
+        if Some(other.id) == placement.job_id || other.scheduled_date != placement.date {
+            continue;
+        }
+        let Some(other_slot) = other.slot() else {
+            continue;
+        };
+
+        if slot.overlaps(&other_slot) {
+            conflicts.push(Conflict {
+                kind: "overlap",
+                message: format!(
+                    "Overlaps \"{}\" ({}-{})",
+                    other.title,
+                    other_slot.start.format("%H:%M"),
+                    other_slot.end.format("%H:%M")
+                ),
+                job_id: Some(other.id),
+            });
+            continue;
+        }
+
+        let drive = match (placement.location, other.location()) {
+            (Some(from), Some(to)) => Duration::seconds(travel.estimate_now(from, to).duration_secs),
+            _ => Duration::zero(),
+        };
+        let needed = buffer.max(drive);
+        let gap = slot.gap(&other_slot);
+        if gap < needed {
+            conflicts.push(Conflict {
+                kind: "travel_buffer",
+                message: format!(
+                    "Only {} min between this and \"{}\"; {} min needed for travel",
+                    gap.num_minutes(),
+                    other.title,
+                    needed.num_minutes()
+                ),
+                job_id: Some(other.id),
+            });
+        }
+    }
+
+    conflicts
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+/// Scheduled jobs over `from..=to`, for everyone or just `only`
+pub async fn jobs_between(
+    conn: &mut PgConnection,
+    team_id: Uuid,
+    from: NaiveDate,
+    to: NaiveDate,
+    only: Option<Uuid>,
+) -> ApiResult<Vec<ScheduledJob>> {
+    sqlx::query_as::<_, ScheduledJob>(&format!(
+        "{} AND j.scheduled_date BETWEEN $2 AND $3 AND ($4::uuid IS NULL OR j.assigned_to = $4)",
+        SCHEDULED_JOB_QUERY
+    ))
+    .bind(team_id)
+    .bind(from)
+    .bind(to)
+    .bind(only)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// Active technicians and apprentices, plus anyone in `also`, or just `only`
+pub async fn technicians(conn: &mut PgConnection, team_id: Uuid, also: &[Uuid], only: Option<Uuid>) -> ApiResult<Vec<Technician>> {
+    sqlx::query_as::<_, Technician>(
+        r#"
+        SELECT u.id AS user_id, u.first_name, u.last_name, u.avatar_url, m.role
+        FROM team_memberships m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.team_id = $1
+          AND ((m.is_active AND m.role IN ('technician', 'apprentice')) OR u.id = ANY($2))
+          AND ($3::uuid IS NULL OR u.id = $3)
+        ORDER BY u.first_name, u.last_name
+        "#,
+    )
+    .bind(team_id)
+    .bind(also)
+    .bind(only)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(Into::into)
+}
+
+async fn technician_jobs(conn: &mut PgConnection, team_id: Uuid, user_id: Uuid, date: NaiveDate) -> ApiResult<Vec<ScheduledJob>> {
+    sqlx::query_as::<_, ScheduledJob>(&format!("{} AND j.assigned_to = $2 AND j.scheduled_date = $3", SCHEDULED_JOB_QUERY))
+        .bind(team_id)
+        .bind(user_id)
+        .bind(date)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(Into::into)
+}
+
+pub async fn property_location(conn: &mut PgConnection, team_id: Uuid, property_id: Uuid) -> ApiResult<Option<Coordinate>> {
+    let row = sqlx::query_as::<_, (Option<f64>, Option<f64>)>("SELECT latitude, longitude FROM properties WHERE id = $1 AND team_id = $2")
+        .bind(property_id)
+        .bind(team_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(match row {
+        Some((Some(latitude), Some(longitude))) => Some(Coordinate { latitude, longitude }),
+        _ => None,
+    })
+}
+
+/// Conflicts a placement would have on a technician's day
+pub async fn conflicts_for(
+    state: &AppState,
+    conn: &mut PgConnection,
+    team_id: Uuid,
+    technician: Uuid,
+    placement: &Placement,
+) -> ApiResult<Vec<Conflict>> {
+    let calendar = Calendar::load(conn, team_id, &[technician], placement.date, placement.date).await?;
+    let others = technician_jobs(conn, team_id, technician, placement.date).await?;
+    let day = calendar.day(Some(technician), placement.date);
+    Ok(check(placement, calendar.hours(), &day, &others, &StraightLine::new(&state.config.tracking)))
+}
+
+/// Conflicts of a job where it's scheduled now, for reporting after a create or update
+pub async fn job_conflicts(state: &AppState, team_id: Uuid, job: &Job) -> ApiResult<Vec<Conflict>> {
+    let (Some(technician), Some(_)) = (job.assigned_to, job.scheduled_date) else {
+        return Ok(Vec::new());
+    };
+    let mut conn = state.db.acquire().await?;
+    let Some(scheduled) = sqlx::query_as::<_, ScheduledJob>(&format!("{} AND j.id = $2", SCHEDULED_JOB_QUERY))
+        .bind(team_id)
+        .bind(job.id)
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(Vec::new());
+    };
+    conflicts_for(state, &mut conn, team_id, technician, &Placement::of(&scheduled)).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct BoardJob {
+    #[serde(flatten)]
+    pub job: ScheduledJob,
+    pub slot: Option<Slot>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LaneDay {
+    #[serde(flatten)]
+    pub availability: Day,
+    pub working_day: bool,
+    /// Timed jobs by start, then jobs with only a date
+    pub jobs: Vec<BoardJob>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Technician {
+    pub user_id: Uuid,
+    pub first_name: String,
+    pub last_name: String,
+    pub avatar_url: Option<String>,
+    pub role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lane {
+    #[serde(flatten)]
+    pub technician: Technician,
+    pub days: Vec<LaneDay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Board {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    #[serde(flatten)]
+    pub hours: TeamHours,
+    pub lanes: Vec<Lane>,
+    /// Scheduled but not assigned to anyone
+    pub unassigned: Vec<BoardJob>,
+}
+
+fn sort_day(jobs: &mut [BoardJob]) {
+    jobs.sort_by_key(|job| (job.slot.is_none(), job.slot.map(|slot| slot.start), job.job.title.clone()));
+}
+
+/// Lanes for every technician over `from..=to`, or just `only`'s lane, with each job's
+/// conflicts. Active technicians and apprentices get a lane even on an empty day, as does
+/// anyone with a job in the range.
+pub async fn board(state: &AppState, team_id: Uuid, from: NaiveDate, to: NaiveDate, only: Option<Uuid>) -> ApiResult<Board> {
+    let mut conn = state.db.acquire().await?;
+    let jobs = jobs_between(&mut conn, team_id, from, to, only).await?;
+    let assignees: Vec<Uuid> = jobs.iter().filter_map(|job| job.assigned_to).collect();
+    let technicians = technicians(&mut conn, team_id, &assignees, only).await?;
+    let users: Vec<Uuid> = technicians.iter().map(|technician| technician.user_id).collect();
+    let calendar = Calendar::load(&mut conn, team_id, &users, from, to).await?;
+    let hours = calendar.hours();
+
+    let travel = StraightLine::new(&state.config.tracking);
+    let mut by_technician: HashMap<Uuid, Vec<ScheduledJob>> = HashMap::new();
+    let mut unassigned = Vec::new();
+    for job in jobs {
+        match job.assigned_to {
+            Some(technician) => by_technician.entry(technician).or_default().push(job),
+            None => {
+                let day = calendar.day(None, job.scheduled_date);
+                let conflicts = check(&Placement::of(&job), hours, &day, &[], &travel);
+                unassigned.push(BoardJob { slot: job.slot(), job, conflicts });
+            }
+        }
+    }
+    sort_day(&mut unassigned);
+
+    let dates: Vec<NaiveDate> = from.iter_days().take_while(|date| *date <= to).collect();
+    let lanes = technicians
+        .into_iter()
+        .map(|technician| {
+            let jobs = by_technician.remove(&technician.user_id).unwrap_or_default();
+            let days = dates
+                .iter()
+                .map(|&date| {
+                    let availability = calendar.day(Some(technician.user_id), date);
+                    let mut day: Vec<BoardJob> = jobs
+                        .iter()
+                        .filter(|job| job.scheduled_date == date)
+                        .map(|job| BoardJob {
+                            slot: job.slot(),
+                            conflicts: check(&Placement::of(job), hours, &availability, &jobs, &travel),
+                            job: job.clone(),
+                        })
+                        .collect();
+                    sort_day(&mut day);
+                    LaneDay { working_day: availability.is_working_day(), availability, jobs: day }
+                })
+                .collect();
+            Lane { technician, days }
+        })
+        .collect();
+
+    Ok(Board { from, to, hours: hours.clone(), lanes, unassigned })
+}
+
+/// A drag on the board: a new time, a new technician, or both
+#[derive(Debug, Deserialize)]
+pub struct MoveRequest {
+    /// Defaults to the current assignee
+    pub assigned_to: Option<Uuid>,
+    /// Defaults to the current date
+    pub scheduled_date: Option<NaiveDate>,
+    /// Defaults to the current start
+    pub scheduled_start_time: Option<NaiveTime>,
+    /// Defaults to the new start plus the job's current length
+    pub scheduled_end_time: Option<NaiveTime>,
+    /// Move even if the new placement has conflicts
+    #[serde(default)]
+    pub force: bool,
+    /// The `version` the client last saw; a mismatch means someone else moved the job first
+    pub version: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Moved {
+    pub job: Job,
+    /// Conflicts overridden with `force`
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Move a job on the board. The technician's schedule is locked while the new placement
+/// is checked, so two dispatchers can't book the same gap at once.
+pub async fn move_job(state: &AppState, team_id: Uuid, job_id: Uuid, req: &MoveRequest) -> ApiResult<Moved> {
+    let mut tx = state.db.begin().await?;
+    let (job, moved) = apply_move(state, &mut tx, team_id, job_id, req).await?;
+    tx.commit().await?;
+
+    let change_type = if moved.job.assigned_to != job.assigned_to { "reassigned" } else { "rescheduled" };
+    tracing::info!(job_id = %job.id, change_type, forced = !moved.conflicts.is_empty(), "Job moved on the schedule");
+
+    let event = WsEvent::ScheduleChanged { job_id: job.id.to_string(), change_type: change_type.into() };
+    realtime::publish(state, Audience::team(team_id).job(Some(job.id)).user(moved.job.assigned_to), event.clone()).await;
+    // The previous assignee's board needs to drop the job
+    if job.assigned_to.is_some() && job.assigned_to != moved.job.assigned_to {
+        realtime::publish(state, Audience::team(team_id).user(job.assigned_to), event).await;
+    }
+
+    Ok(moved)
+}
+
+/// The checks and update behind [`move_job`], in the caller's transaction, for anything
+/// else that changes who does a job or when: the technician must be an active member,
+/// their schedule is locked, and conflicts reject the change unless `force` is set.
+/// Returns the job as it was and as moved; publishing the change is left to the caller.
+pub async fn apply_move(
+    state: &AppState,
+    conn: &mut PgConnection,
+    team_id: Uuid,
+    job_id: Uuid,
+    req: &MoveRequest,
+) -> ApiResult<(Job, Moved)> {
+    let job = sqlx::query_as::<_, Job>(&format!(
+        "SELECT {} FROM jobs WHERE id = $1 AND team_id = $2 AND deleted_at IS NULL FOR UPDATE",
+        JOB_COLUMNS
+    ))
+    .bind(job_id)
+    .bind(team_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Job".into()))?;
+
+    if req.version.is_some_and(|version| version != job.version) {
+        return Err(ApiError::Conflict("The job was changed by someone else; reload the schedule".into()));
+    }
+    if !MOVABLE_STATUSES.contains(&job.status.as_str()) {
+        return Err(ApiError::Conflict(format!("A job that is {} can't be rescheduled", job.status.replace('_', " "))));
+    }
+
+    if let Some(technician) = req.assigned_to {
+        let is_member = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM team_memberships WHERE team_id = $1 AND user_id = $2 AND is_active)",
+        )
+        .bind(team_id)
+        .bind(technician)
+        .fetch_one(&mut *conn)
+        .await?;
+        if !is_member {
+            return Err(ApiError::Validation("Technician is not an active member of this team".into()));
+        }
+    }
+
+    let technician = req.assigned_to.or(job.assigned_to);
+    // An unscheduled job can still be given a technician; there is nothing to conflict with yet
+    let date = req.scheduled_date.or(job.scheduled_date);
+    let start = req.scheduled_start_time.or(job.scheduled_start_time);
+    if date.is_none() && (req.scheduled_start_time.is_some() || req.scheduled_end_time.is_some()) {
+        return Err(ApiError::Validation("scheduled_date is required".into()));
+    }
+    let end = match (req.scheduled_end_time, start, job.scheduled_start_time, job.scheduled_end_time) {
+        (Some(end), _, _, _) => Some(end),
+        // Keep the job's length when it's dragged to a new start
+        (None, Some(start), Some(old_start), Some(old_end)) if old_end > old_start => {
+            match start.overflowing_add_signed(old_end - old_start) {
+                (end, 0) => Some(end),
+                _ => return Err(ApiError::Validation("The job would run past midnight".into())),
+            }
+        }
+        _ => None,
+    };
+    if let (Some(start), Some(end)) = (start, end) {
+        if end <= start {
+            return Err(ApiError::Validation("scheduled_end_time must be after scheduled_start_time".into()));
+        }
+    } else if end.is_some() {
+        return Err(ApiError::Validation("scheduled_end_time needs a scheduled_start_time".into()));
+    }
+
+    let mut conflicts = Vec::new();
+    if let (Some(technician), Some(date)) = (technician, date) {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('schedule:' || $1::text))")
+            .bind(technician)
+            .execute(&mut *conn)
+            .await?;
+
+        let location = match job.property_id {
+            Some(property_id) => property_location(&mut *conn, team_id, property_id).await?,
+            None => None,
+        };
+        let placement = Placement {
+            job_id: Some(job.id),
+            date,
+            slot: Slot::of(start, end, job.arrival_window_start, job.estimated_duration_minutes),
+            location,
+        };
+        conflicts = conflicts_for(state, &mut *conn, team_id, technician, &placement).await?;
+        if !conflicts.is_empty() && !req.force {
+            let details: Vec<&str> = conflicts.iter().map(|conflict| conflict.message.as_str()).collect();
+            return Err(ApiError::Conflict(format!("Schedule conflict: {}", details.join("; "))));
+        }
+    }
+
+    let moved = sqlx::query_as::<_, Job>(&format!(
+        r#"
+        UPDATE jobs SET
+            assigned_to = $3,
+            scheduled_date = $4,
+            scheduled_start_time = $5,
+            scheduled_end_time = $6,
+            version = version + 1
+        WHERE id = $1 AND team_id = $2
+        RETURNING {}
+        "#,
+        JOB_COLUMNS
+    ))
+    .bind(job.id)
+    .bind(team_id)
+    .bind(technician)
+    .bind(date)
+    .bind(start)
+    .bind(end)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok((job, Moved { job: moved, conflicts }))
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    fn time(hour: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn move_to(technician: Uuid, date: NaiveDate, start: u32, end: u32) -> MoveRequest {
+        MoveRequest {
+            assigned_to: Some(technician),
+            scheduled_date: Some(date),
+            scheduled_start_time: Some(time(start)),
+            scheduled_end_time: Some(time(end)),
+            force: false,
+            version: None,
+        }
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn moves_check_the_technicians_day(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let team_id = test_support::team(&db, "acme").await;
+        let alex = test_support::member(&db, team_id, "alex@example.com", "technician").await;
+        let blake = test_support::member(&db, team_id, "blake@example.com", "technician").await;
+        let customer_id = test_support::customer(&db, team_id).await;
+        // A Wednesday, inside default working hours
+        let date = NaiveDate::from_ymd_opt(2030, 6, 5).unwrap();
+
+        let booked = test_support::job(&db, team_id, customer_id, "scheduled", None).await;
+        move_job(&state, team_id, booked, &move_to(alex, date, 9, 11)).await.unwrap();
+
+        let job_id = test_support::job(&db, team_id, customer_id, "scheduled", None).await;
+        let clash = move_job(&state, team_id, job_id, &move_to(alex, date, 10, 12)).await;
+        assert!(matches!(clash, Err(ApiError::Conflict(_))), "{clash:?}");
+
+        let moved = move_job(&state, team_id, job_id, &move_to(alex, date, 13, 15)).await.unwrap();
+        assert!(moved.conflicts.is_empty());
+        assert_eq!(moved.job.assigned_to, Some(alex));
+        assert_eq!(moved.job.scheduled_start_time, Some(time(13)));
+        assert_eq!(moved.job.status, "scheduled");
+        let version = moved.job.version;
+
+        // Dragging to another technician keeps the length when only the start is given
+        let req = MoveRequest {
+            scheduled_end_time: None,
+            version: Some(version),
+            ..move_to(blake, date, 10, 0)
+        };
+        let moved = move_job(&state, team_id, job_id, &req).await.unwrap();
+        assert_eq!(moved.job.assigned_to, Some(blake));
+        assert_eq!(moved.job.scheduled_end_time, Some(time(12)));
+        assert_eq!(moved.job.version, version + 1);
+
+        // The client's version is now stale
+        let stale = move_job(&state, team_id, job_id, &req).await;
+        assert!(matches!(stale, Err(ApiError::Conflict(_))), "{stale:?}");
+    }
+}
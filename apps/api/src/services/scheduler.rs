@@ -0,0 +1,220 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::services::payment_service;
+use crate::AppState;
+
+type TaskFactory = fn(Uuid) -> BackgroundJob;
+
+/// Scheduled tasks every team gets; names are unique per team so this can be re-run safely
+const DEFAULT_TASKS: &[(&str, &str, TaskFactory)] = &[
+    ("appointment_reminders", "*/15 * * * *", |team_id| BackgroundJob::AppointmentReminderSweep { team_id }),
+    ("license_expiry", "0 7 * * *", |team_id| BackgroundJob::LicenseExpirySweep { team_id }),
+    ("overdue_invoices", "0 6 * * *", |team_id| BackgroundJob::OverdueInvoiceSweep { team_id }),
+    ("recurring_jobs", "0 * * * *", |team_id| BackgroundJob::RecurringJobSweep { team_id }),
+    ("gps_retention", "30 3 * * *", |team_id| BackgroundJob::GpsRetentionSweep { team_id }),
+];
+
+const TICK_BATCH_SIZE: i64 = 100;
+/// How often finished background jobs are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+/// How often abandoned portal payments are expired
+const PAYMENT_SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, sqlx::FromRow)]
+struct DueTask {
+    id: Uuid,
+    name: String,
+    cron: String,
+    payload: serde_json::Value,
+    next_run_at: DateTime<Utc>,
+    timezone: String,
+}
+
+/// Parse a cron expression. Accepts standard 5-field expressions (minute precision)
+/// as well as the 6/7-field form with seconds and year.
+pub fn parse_cron(expr: &str) -> Result<cron::Schedule, String> {
+    let expr = expr.trim();
+    let normalized = if expr.split_whitespace().count() == 5 {
+        format!("0 {}", expr)
+    } else {
+        expr.to_string()
+    };
+    cron::Schedule::from_str(&normalized).map_err(|e| format!("Invalid cron expression '{}': {}", expr, e))
+}
+
+/// Unknown zone names fall back to UTC rather than stopping the team's schedules
+pub fn parse_timezone(name: &str) -> Tz {
+    name.parse::<Tz>().unwrap_or_else(|_| {
+        tracing::warn!(timezone = %name, "Unknown team timezone, using UTC");
+        Tz::UTC
+    })
+}
+
+pub async fn team_timezone(pool: &PgPool, team_id: Uuid) -> ApiResult<Tz> {
+    let name = sqlx::query_scalar::<_, String>("SELECT timezone FROM teams WHERE id = $1")
+        .bind(team_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Team".into()))?;
+    Ok(parse_timezone(&name))
+}
+
+/// Current calendar date in the given timezone
+pub fn today_in(tz: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&tz).date_naive()
+}
+
+/// Next fire time strictly after `after`, evaluated as wall-clock time in `tz`
+pub fn next_run_after(schedule: &cron::Schedule, tz: Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&after.with_timezone(&tz))
+        .next()
+        .map(|next| next.with_timezone(&Utc))
+}
+
+/// Create any missing default scheduled tasks for a team
+pub async fn ensure_team_defaults(conn: &mut PgConnection, team_id: Uuid) -> ApiResult<()> {
+    for (name, cron, job) in DEFAULT_TASKS {
+        let payload = serde_json::to_value(job(team_id))
+            .map_err(|e| ApiError::Internal(anyhow::anyhow!("Failed to serialize task payload: {}", e)))?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO scheduled_tasks (team_id, name, cron, payload)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (team_id, name) DO NOTHING
+            "#,
+        )
+        .bind(team_id)
+        .bind(name)
+        .bind(cron)
+        .bind(&payload)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Backfill default tasks for every team (teams created before the scheduler existed)
+pub async fn ensure_all_team_defaults(pool: &PgPool) -> ApiResult<()> {
+    let team_ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM teams").fetch_all(pool).await?;
+    let mut conn = pool.acquire().await?;
+    for team_id in team_ids {
+        ensure_team_defaults(&mut conn, team_id).await?;
+    }
+    Ok(())
+}
+
+/// Start the scheduler loop for this replica. Due tasks are claimed with SKIP LOCKED
+/// and enqueued with a per-fire dedup key, so running it on every replica is safe.
+pub fn spawn_scheduler(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(state.config.queue.scheduler_tick_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        tracing::info!("Scheduler started");
+
+        let mut next_purge = tokio::time::Instant::now();
+        let mut next_payment_sweep = tokio::time::Instant::now();
+        loop {
+            interval.tick().await;
+            match tick(&state).await {
+                Ok(0) => {}
+                Ok(count) => tracing::debug!(count, "Scheduled tasks enqueued"),
+                Err(e) => tracing::error!(error = %e, "Scheduler tick failed"),
+            }
+
+            if tokio::time::Instant::now() >= next_purge {
+                next_purge += PURGE_INTERVAL;
+                match job_queue::purge_finished(&state.db, &state.config.queue).await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!(count, "Purged finished background jobs"),
+                    Err(e) => tracing::error!(error = %e, "Background job purge failed"),
+                }
+            }
+
+            if tokio::time::Instant::now() >= next_payment_sweep {
+                next_payment_sweep += PAYMENT_SWEEP_INTERVAL;
+                match payment_service::expire_abandoned(&state.db, state.config.stripe.pending_payment_expiry_minutes).await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!(count, "Expired abandoned portal payments"),
+                    Err(e) => tracing::error!(error = %e, "Pending payment sweep failed"),
+                }
+            }
+        }
+    })
+}
+
+async fn tick(state: &AppState) -> ApiResult<usize> {
+    let mut tx = state.db.begin().await?;
+
+    let due = sqlx::query_as::<_, DueTask>(
+        r#"
+        SELECT st.id, st.name, st.cron, st.payload, st.next_run_at, t.timezone
+        FROM scheduled_tasks st
+        JOIN teams t ON t.id = st.team_id
+        WHERE st.is_active = true AND st.next_run_at <= now()
+        ORDER BY st.next_run_at
+        LIMIT $1
+        FOR UPDATE OF st SKIP LOCKED
+        "#,
+    )
+    .bind(TICK_BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let now = Utc::now();
+    let count = due.len();
+
+    for task in due {
+        let schedule = match parse_cron(&task.cron) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                tracing::error!(task_id = %task.id, name = %task.name, error = %e, "Disabling scheduled task");
+                sqlx::query("UPDATE scheduled_tasks SET is_active = false WHERE id = $1")
+                    .bind(task.id)
+                    .execute(&mut *tx)
+                    .await?;
+                continue;
+            }
+        };
+
+        match serde_json::from_value::<BackgroundJob>(task.payload.clone()) {
+            Ok(job) => {
+                let dedup_key = format!("scheduled_task:{}:{}", task.id, task.next_run_at.timestamp());
+                job_queue::enqueue_with(&mut *tx, &job, now, state.jobs.max_attempts(), Some(&dedup_key)).await?;
+            }
+            Err(e) => {
+                tracing::error!(task_id = %task.id, name = %task.name, error = %e, "Scheduled task has an invalid payload");
+            }
+        }
+
+        // Computed from now rather than the missed fire time so downtime collapses into a single run
+        let tz = parse_timezone(&task.timezone);
+        let next_run_at = next_run_after(&schedule, tz, now);
+
+        sqlx::query(
+            r#"
+            UPDATE scheduled_tasks SET last_run_at = $2, next_run_at = COALESCE($3, next_run_at), is_active = $3 IS NOT NULL
+            WHERE id = $1
+            "#,
+        )
+        .bind(task.id)
+        .bind(now)
+        .bind(next_run_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(count)
+}
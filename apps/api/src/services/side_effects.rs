@@ -0,0 +1,510 @@
+//! Registry of job FSM side effects.
+//!
+//! Each effect named by `job_service::get_transition_side_effects` maps to a handler
+//! that either runs inline inside the transition's transaction (time tracking, invoice
+//! drafts) or enqueues background jobs in that same transaction (customer messages),
+//! so queued work is only visible once the transition commits. Every inline handler
+//! runs in its own savepoint: a failing effect is rolled back and reported without
+//! undoing the status change.
+
+use chrono::{Duration, NaiveDate, NaiveTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::services::{geofence, tracking};
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::AppState;
+
+/// Delay before asking a customer for a review after payment
+const REVIEW_REQUEST_DELAY_HOURS: i64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EffectStatus {
+    /// Ran inline and committed with the transition
+    Succeeded,
+    /// Handed to the background job queue
+    Queued,
+    /// Nothing to do (no handler, missing data, or handled elsewhere)
+    Skipped,
+    /// Handler errored; its changes were rolled back
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectOutcome {
+    pub effect: &'static str,
+    pub status: EffectStatus,
+    pub detail: Option<String>,
+}
+
+/// Snapshot of the job taken under the transition's row lock
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct EffectContext {
+    pub job_id: Uuid,
+    pub team_id: Uuid,
+    pub customer_id: Uuid,
+    pub property_id: Option<Uuid>,
+    pub assigned_to: Option<Uuid>,
+    pub title: String,
+    pub status: String,
+    pub scheduled_date: Option<NaiveDate>,
+    pub scheduled_start_time: Option<NaiveTime>,
+    #[sqlx(skip)]
+    pub actor_id: Uuid,
+    #[sqlx(skip)]
+    pub latitude: Option<f64>,
+    #[sqlx(skip)]
+    pub longitude: Option<f64>,
+    #[sqlx(skip)]
+    pub geofence_event_id: Option<Uuid>,
+}
+
+impl EffectContext {
+    /// Technician whose time is tracked: the assignee, falling back to whoever moved the job
+    pub fn technician(&self) -> Uuid {
+        self.assigned_to.unwrap_or(self.actor_id)
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct CustomerContact {
+    team_id: Uuid,
+    first_name: String,
+    email: Option<String>,
+    phone: Option<String>,
+    team_name: String,
+}
+
+type Handled = (EffectStatus, Option<String>);
+
+fn done(detail: impl Into<String>) -> ApiResult<Handled> {
+    Ok((EffectStatus::Succeeded, Some(detail.into())))
+}
+
+fn queued(detail: impl Into<String>) -> ApiResult<Handled> {
+    Ok((EffectStatus::Queued, Some(detail.into())))
+}
+
+fn skipped(detail: impl Into<String>) -> ApiResult<Handled> {
+    Ok((EffectStatus::Skipped, Some(detail.into())))
+}
+
+/// Run every effect for a transition on the transition's connection
+pub async fn run_all(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext, effects: &[&'static str]) -> ApiResult<Vec<EffectOutcome>> {
+    let mut outcomes = Vec::with_capacity(effects.len());
+
+    for &effect in effects {
+        let mut savepoint = conn.begin().await?;
+        let result = dispatch(state, &mut savepoint, ctx, effect).await;
+
+        let outcome = match result {
+            Ok((status, detail)) => {
+                savepoint.commit().await?;
+                EffectOutcome { effect, status, detail }
+            }
+            Err(e) => {
+                savepoint.rollback().await?;
+                tracing::error!(job_id = %ctx.job_id, effect, error = %e, "Side effect failed");
+                EffectOutcome { effect, status: EffectStatus::Failed, detail: Some(e.to_string()) }
+            }
+        };
+
+        tracing::info!(job_id = %ctx.job_id, effect, status = ?outcome.status, "Side effect processed");
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+/// The registry: effect name → handler
+async fn dispatch(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext, effect: &str) -> ApiResult<Handled> {
+    match effect {
+        "send_confirmation" => send_confirmation(state, conn, ctx).await,
+        "send_customer_eta" => send_customer_eta(state, conn, ctx).await,
+        "start_time_tracking" | "resume_time_tracking" => open_time_entry(conn, ctx).await,
+        "pause_time_tracking" | "stop_time_tracking" => close_time_entries(conn, ctx).await,
+        "prompt_completion_photos" => skipped("Push notifications are not delivered yet"),
+        "generate_invoice_draft" => generate_invoice_draft(conn, ctx).await,
+        "create_invoice" => create_invoice(conn, ctx).await,
+        "send_invoice" => send_invoice(state, conn, ctx).await,
+        "send_receipt" => send_receipt(state, conn, ctx).await,
+        "schedule_review_request" => schedule_review_request(state, conn, ctx).await,
+        "record_payment" => skipped("Payments are recorded against the invoice"),
+        "add_to_calendar" => skipped("No calendar integration configured"),
+        "geofence_checkin" => geofence_checkin(state, conn, ctx).await,
+        "start_gps_tracking" | "stop_navigation" => skipped("Handled by the mobile client"),
+        _ => skipped("No handler registered"),
+    }
+}
+
+async fn enqueue(state: &AppState, conn: &mut PgConnection, job: BackgroundJob, run_at: chrono::DateTime<Utc>) -> ApiResult<()> {
+    job_queue::enqueue_with(&mut *conn, &job, run_at, state.jobs.max_attempts(), None).await?;
+    Ok(())
+}
+
+async fn customer_contact(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<CustomerContact> {
+    sqlx::query_as::<_, CustomerContact>(
+        r#"
+        SELECT c.team_id, c.first_name, c.email, c.phone, t.name AS team_name
+        FROM customers c JOIN teams t ON t.id = c.team_id
+        WHERE c.id = $1
+        "#,
+    )
+    .bind(ctx.customer_id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// Email and text the customer; returns how many messages were queued
+async fn message_customer(state: &AppState, conn: &mut PgConnection, contact: &CustomerContact, subject: &str, body: &str) -> ApiResult<usize> {
+    let now = Utc::now();
+    let mut sent = 0;
+
+    if let Some(to) = contact.email.clone().filter(|e| !e.is_empty()) {
+        let job = BackgroundJob::SendEmail { team_id: Some(contact.team_id), to, subject: subject.to_string(), body: body.to_string() };
+        enqueue(state, conn, job, now).await?;
+        sent += 1;
+    }
+    if let Some(to) = contact.phone.clone().filter(|p| !p.is_empty()) {
+        let job = BackgroundJob::SendSms { team_id: Some(contact.team_id), to, body: format!("{}: {}", contact.team_name, body) };
+        enqueue(state, conn, job, now).await?;
+        sent += 1;
+    }
+
+    Ok(sent)
+}
+
+async fn send_confirmation(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let contact = customer_contact(conn, ctx).await?;
+    let when = match (ctx.scheduled_date, ctx.scheduled_start_time) {
+        (Some(date), Some(time)) => format!(" on {} at {}", date.format("%A, %B %-d"), time.format("%-I:%M %p")),
+        (Some(date), None) => format!(" on {}", date.format("%A, %B %-d")),
+        _ => String::new(),
+    };
+    let body = format!("Hi {}, your appointment \"{}\" is confirmed{}.", contact.first_name, ctx.title, when);
+
+    match message_customer(state, conn, &contact, "Appointment confirmed", &body).await? {
+        0 => skipped("Customer has no email or phone"),
+        n => queued(format!("{} message(s) queued", n)),
+    }
+}
+
+/// Tell the customer the technician has set off, with a link to follow them in
+async fn send_customer_eta(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let contact = customer_contact(conn, ctx).await?;
+    let mut channels = Vec::new();
+    if contact.email.as_deref().is_some_and(|e| !e.is_empty()) {
+        channels.push("email");
+    }
+    if contact.phone.as_deref().is_some_and(|p| !p.is_empty()) {
+        channels.push("sms");
+    }
+
+    match tracking::send_links(state, conn, ctx.team_id, ctx.job_id, &channels).await? {
+        0 => skipped("Customer has no email or phone"),
+        n => queued(format!("{} message(s) queued", n)),
+    }
+}
+
+/// Geofenced arrivals are recorded before the transition; a manual check-in with
+/// coordinates records one here so every started job has arrival evidence
+async fn geofence_checkin(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    if ctx.geofence_event_id.is_some() {
+        return skipped("Arrival recorded by geofence");
+    }
+    let (Some(latitude), Some(longitude)) = (ctx.latitude, ctx.longitude) else {
+        return skipped("No coordinates sent with the check-in");
+    };
+    match geofence::record_manual_arrival(state, conn, ctx, latitude, longitude).await? {
+        Some(distance) => done(format!("Arrival recorded {:.0} m from the property", distance)),
+        None => skipped("Property has no coordinates, or arrival already recorded"),
+    }
+}
+
+async fn open_time_entry(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let user_id = ctx.technician();
+
+    let active = sqlx::query_scalar::<_, Uuid>(
+        "SELECT job_id FROM time_entries WHERE user_id = $1 AND ended_at IS NULL LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match active {
+        Some(job_id) if job_id == ctx.job_id => return skipped("Timer already running for this job"),
+        Some(_) => return Err(ApiError::Conflict("Technician has an active timer on another job".into())),
+        None => {}
+    }
+
+    let entry_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO time_entries (team_id, job_id, user_id, entry_type, started_at, hourly_rate, latitude_start, longitude_start)
+        SELECT $1, $2, $3, 'work'::time_entry_type, now(),
+               (SELECT m.hourly_rate FROM team_memberships m WHERE m.team_id = $1 AND m.user_id = $3),
+               $4, $5
+        RETURNING id
+        "#,
+    )
+    .bind(ctx.team_id)
+    .bind(ctx.job_id)
+    .bind(user_id)
+    .bind(ctx.latitude)
+    .bind(ctx.longitude)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    done(format!("Time entry {} started", entry_id))
+}
+
+/// Close every open timer on the job, mirroring `POST /time-entries/{id}/stop`
+async fn close_time_entries(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let durations = sqlx::query_scalar::<_, Option<i32>>(
+        r#"
+        UPDATE time_entries SET
+            ended_at = now(),
+            duration_minutes = EXTRACT(EPOCH FROM (now() - started_at))::int / 60,
+            total_cost = CASE
+                WHEN hourly_rate IS NOT NULL
+                THEN hourly_rate * (EXTRACT(EPOCH FROM (now() - started_at))::numeric / 3600)
+                ELSE NULL
+            END,
+            latitude_end = $2,
+            longitude_end = $3
+        WHERE job_id = $1 AND ended_at IS NULL
+        RETURNING duration_minutes
+        "#,
+    )
+    .bind(ctx.job_id)
+    .bind(ctx.latitude)
+    .bind(ctx.longitude)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if durations.is_empty() {
+        return skipped("No running timers");
+    }
+
+    let minutes: i32 = durations.iter().flatten().sum();
+    sqlx::query("UPDATE jobs SET actual_duration_minutes = COALESCE(actual_duration_minutes, 0) + $2 WHERE id = $1")
+        .bind(ctx.job_id)
+        .bind(minutes)
+        .execute(&mut *conn)
+        .await?;
+
+    done(format!("{} time entr{} stopped", durations.len(), if durations.len() == 1 { "y" } else { "ies" }))
+}
+
+async fn existing_invoice(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Option<(Uuid, String, String)>> {
+    sqlx::query_as::<_, (Uuid, String, String)>(
+        r#"
+        SELECT id, invoice_number, status::text FROM invoices
+        WHERE job_id = $1 AND status != 'void'::invoice_status AND deleted_at IS NULL
+        ORDER BY created_at DESC LIMIT 1
+        "#,
+    )
+    .bind(ctx.job_id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(Into::into)
+}
+
+async fn generate_invoice_draft(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    if let Some((_, number, _)) = existing_invoice(conn, ctx).await? {
+        return skipped(format!("Invoice {} already exists", number));
+    }
+    match draft_invoice(conn, ctx).await? {
+        Some(number) => done(format!("Draft invoice {} created", number)),
+        None => skipped("No billable time or materials recorded"),
+    }
+}
+
+async fn create_invoice(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    if let Some((_, number, _)) = existing_invoice(conn, ctx).await? {
+        return done(format!("Using existing invoice {}", number));
+    }
+    match draft_invoice(conn, ctx).await? {
+        Some(number) => done(format!("Invoice {} created", number)),
+        None => Err(ApiError::BadRequest("No billable time or materials recorded for this job".into())),
+    }
+}
+
+/// Build a draft invoice from completed time entries (labor, grouped per technician and
+/// rate) and billable materials (cost plus the team's default markup). Returns `None`
+/// when there is nothing to bill.
+async fn draft_invoice(conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Option<String>> {
+    let (default_rate, markup_pct, tax_rate) = sqlx::query_as::<_, (Option<Decimal>, Option<Decimal>, Option<Decimal>)>(
+        "SELECT default_hourly_rate, default_markup_pct, tax_rate FROM teams WHERE id = $1",
+    )
+    .bind(ctx.team_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let labor = sqlx::query_as::<_, (String, Option<Decimal>, i64)>(
+        r#"
+        SELECT u.first_name || ' ' || u.last_name, te.hourly_rate, SUM(COALESCE(te.duration_minutes, 0))::bigint
+        FROM time_entries te JOIN users u ON u.id = te.user_id
+        WHERE te.job_id = $1 AND te.ended_at IS NOT NULL AND te.entry_type IN ('work', 'overtime')
+        GROUP BY te.user_id, u.first_name, u.last_name, te.hourly_rate
+        ORDER BY MIN(te.started_at)
+        "#,
+    )
+    .bind(ctx.job_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let materials = sqlx::query_as::<_, (String, Decimal, String, Option<Decimal>)>(
+        "SELECT name, quantity, unit, unit_cost FROM materials_used WHERE job_id = $1 AND billable = true ORDER BY created_at",
+    )
+    .bind(ctx.job_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // (description, category, quantity, unit, unit_price, taxable)
+    let mut lines: Vec<(String, &str, Decimal, String, Decimal, bool)> = Vec::new();
+
+    for (name, rate, minutes) in labor {
+        let rate = rate.or(default_rate).unwrap_or_default();
+        if minutes <= 0 {
+            continue;
+        }
+        let hours = (Decimal::from(minutes) / Decimal::from(60)).round_dp(2);
+        lines.push((format!("Labor — {}", name), "labor", hours, "hour".into(), rate, false));
+    }
+
+    let markup = Decimal::ONE + markup_pct.unwrap_or_default() / Decimal::from(100);
+    for (name, quantity, unit, unit_cost) in materials {
+        let price = (unit_cost.unwrap_or_default() * markup).round_dp(2);
+        lines.push((name, "materials", quantity, unit, price, true));
+    }
+
+    if lines.is_empty() {
+        return Ok(None);
+    }
+
+    let mut subtotal = Decimal::ZERO;
+    let mut taxable = Decimal::ZERO;
+    for (_, _, quantity, _, price, is_taxable) in &lines {
+        let total = (*quantity * *price).round_dp(2);
+        subtotal += total;
+        if *is_taxable {
+            taxable += total;
+        }
+    }
+    let tax_amount = (taxable * tax_rate.unwrap_or_default()).round_dp(2);
+    let total = subtotal + tax_amount;
+
+    let invoice_number = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE teams SET invoice_next_number = invoice_next_number + 1
+        WHERE id = $1
+        RETURNING invoice_prefix || '-' || LPAD((invoice_next_number - 1)::text, 4, '0')
+        "#,
+    )
+    .bind(ctx.team_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let invoice_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO invoices (team_id, job_id, customer_id, property_id, invoice_number,
+                              subtotal, tax_amount, tax_rate, total, amount_due, due_date)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9, $10)
+        RETURNING id
+        "#,
+    )
+    .bind(ctx.team_id)
+    .bind(ctx.job_id)
+    .bind(ctx.customer_id)
+    .bind(ctx.property_id)
+    .bind(&invoice_number)
+    .bind(subtotal)
+    .bind(tax_amount)
+    .bind(tax_rate)
+    .bind(total)
+    .bind(Utc::now().date_naive() + Duration::days(30))
+    .fetch_one(&mut *conn)
+    .await?;
+
+    for (sort_order, (description, category, quantity, unit, price, is_taxable)) in lines.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO line_items (team_id, invoice_id, description, category, quantity, unit, unit_price, total, taxable, sort_order)
+            VALUES ($1, $2, $3, $4::line_item_category, $5, $6, $7, $8, $9, $10)
+            "#,
+        )
+        .bind(ctx.team_id)
+        .bind(invoice_id)
+        .bind(description)
+        .bind(category)
+        .bind(quantity)
+        .bind(unit)
+        .bind(price)
+        .bind((*quantity * *price).round_dp(2))
+        .bind(is_taxable)
+        .bind(sort_order as i32)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query("UPDATE jobs SET total_amount = $2 WHERE id = $1")
+        .bind(ctx.job_id)
+        .bind(total)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(Some(invoice_number))
+}
+
+async fn send_invoice(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let Some((invoice_id, number, status)) = existing_invoice(conn, ctx).await? else {
+        return skipped("No invoice to send");
+    };
+    if status != "draft" {
+        return skipped(format!("Invoice {} already {}", number, status));
+    }
+
+    let amount_due = sqlx::query_scalar::<_, Decimal>(
+        "UPDATE invoices SET status = 'sent'::invoice_status, sent_at = now() WHERE id = $1 RETURNING amount_due",
+    )
+    .bind(invoice_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let contact = customer_contact(conn, ctx).await?;
+    let body = format!("Hi {}, invoice {} for ${} is ready for \"{}\".", contact.first_name, number, amount_due, ctx.title);
+    let sent = message_customer(state, conn, &contact, &format!("Invoice {}", number), &body).await?;
+
+    done(format!("Invoice {} marked sent, {} message(s) queued", number, sent))
+}
+
+async fn send_receipt(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let Some((_, number, _)) = existing_invoice(conn, ctx).await? else {
+        return skipped("No invoice on this job");
+    };
+    let contact = customer_contact(conn, ctx).await?;
+    let Some(to) = contact.email.clone().filter(|e| !e.is_empty()) else {
+        return skipped("Customer has no email");
+    };
+
+    let job = BackgroundJob::SendEmail {
+        team_id: Some(ctx.team_id),
+        to,
+        subject: format!("Receipt for invoice {}", number),
+        body: format!("Hi {}, thank you for your payment on invoice {}.", contact.first_name, number),
+    };
+    enqueue(state, conn, job, Utc::now()).await?;
+    queued("Receipt email queued")
+}
+
+async fn schedule_review_request(state: &AppState, conn: &mut PgConnection, ctx: &EffectContext) -> ApiResult<Handled> {
+    let run_at = Utc::now() + Duration::hours(REVIEW_REQUEST_DELAY_HOURS);
+    let job = BackgroundJob::RequestReview { team_id: ctx.team_id, customer_id: ctx.customer_id, job_id: ctx.job_id };
+    enqueue(state, conn, job, run_at).await?;
+    queued(format!("Review request scheduled for {}", run_at.to_rfc3339()))
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context};
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use axum::async_trait;
+
+use crate::config::{Settings, StorageSettings};
+
+/// Object storage for generated files. Keys are `{team_id}/...` paths inside the bucket.
+#[async_trait]
+pub trait ObjectStorage: Send + Sync {
+    async fn put(&self, key: &str, content_type: &str, body: Vec<u8>) -> anyhow::Result<()>;
+
+    /// `None` when nothing is stored under `key`
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Location recorded on rows that point at an object, e.g. `invoices.pdf_url`
+    fn url(&self, key: &str) -> String;
+}
+
+/// Pick the backend named by `STORAGE_BACKEND`: `s3` (the default; also MinIO via
+/// `S3_ENDPOINT`) or `memory` (in-process, for tests)
+pub fn from_settings(settings: &Settings) -> anyhow::Result<Arc<dyn ObjectStorage>> {
+    match settings.storage.backend.as_str() {
+        "s3" => Ok(Arc::new(S3Storage::new(&settings.storage))),
+        "memory" => Ok(Arc::new(MemoryStorage::new(&settings.storage))),
+        other => Err(anyhow!("Unknown STORAGE_BACKEND '{}'; expected s3 or memory", other)),
+    }
+}
+
+fn object_url(settings: &StorageSettings, key: &str) -> String {
+    format!("{}/{}/{}", settings.endpoint.trim_end_matches('/'), settings.bucket, key)
+}
+
+pub struct S3Storage {
+    client: aws_sdk_s3::Client,
+    settings: StorageSettings,
+}
+
+impl S3Storage {
+    pub fn new(settings: &StorageSettings) -> Self {
+        let credentials = Credentials::new(&settings.access_key, &settings.secret_key, None, None, "fieldforge");
+        let mut config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(settings.region.clone()))
+            .credentials_provider(credentials)
+            // MinIO and most S3-compatible stores only support path-style addressing
+            .force_path_style(true);
+        if !settings.endpoint.is_empty() {
+            config = config.endpoint_url(&settings.endpoint);
+        }
+
+        Self {
+            client: aws_sdk_s3::Client::from_conf(config.build()),
+            settings: settings.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for S3Storage {
+    async fn put(&self, key: &str, content_type: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.settings.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload {}", key))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let object = match self.client.get_object().bucket(&self.settings.bucket).key(key).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to download {}", key))),
+        };
+        let bytes = object.body.collect().await.context("Failed to read object body")?;
+        Ok(Some(bytes.into_bytes().to_vec()))
+    }
+
+    fn url(&self, key: &str) -> String {
+        object_url(&self.settings, key)
+    }
+}
+
+/// Content type and body, by key
+type Objects = HashMap<String, (String, Vec<u8>)>;
+
+/// Keeps objects in memory so tests can read generated files back
+#[derive(Clone)]
+pub struct MemoryStorage {
+    objects: Arc<Mutex<Objects>>,
+    settings: StorageSettings,
+}
+
+impl MemoryStorage {
+    pub fn new(settings: &StorageSettings) -> Self {
+        Self {
+            objects: Arc::default(),
+            settings: settings.clone(),
+        }
+    }
+
+    pub fn content_type(&self, key: &str) -> Option<String> {
+        let objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        objects.get(key).map(|(content_type, _)| content_type.clone())
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for MemoryStorage {
+    async fn put(&self, key: &str, content_type: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        let mut objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        objects.insert(key.to_string(), (content_type.to_string(), body));
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(objects.get(key).map(|(_, body)| body.clone()))
+    }
+
+    fn url(&self, key: &str) -> String {
+        object_url(&self.settings, key)
+    }
+}
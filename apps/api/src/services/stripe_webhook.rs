@@ -0,0 +1,485 @@
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::payment::Payment;
+use crate::services::events;
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::services::payment_service::{self, from_cents, is_collected};
+use crate::services::realtime::{self, Audience, WsEvent};
+use crate::AppState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Check a `Stripe-Signature` header (`t=<unix>,v1=<hex>[,v1=<hex>...]`) against the raw body.
+///
+/// The signed payload is `"{t}.{body}"`. Any `v1` entry may match, which is how Stripe rolls
+/// secrets; timestamps further than `tolerance_secs` from `now` are rejected so a captured
+/// request can't be replayed later.
+pub fn verify_signature(
+    payload: &str,
+    header: &str,
+    secret: &str,
+    tolerance_secs: i64,
+    now: DateTime<Utc>,
+) -> ApiResult<()> {
+    if secret.is_empty() {
+        tracing::error!("STRIPE_WEBHOOK_SECRET is not configured; rejecting webhook");
+        return Err(ApiError::BadRequest("Webhook signing secret is not configured".into()));
+    }
+
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signatures.push(value),
+            _ => {}
+        }
+    }
+
+    let timestamp = timestamp.ok_or_else(|| ApiError::BadRequest("Malformed Stripe-Signature header".into()))?;
+    if signatures.is_empty() {
+        return Err(ApiError::BadRequest("Malformed Stripe-Signature header".into()));
+    }
+
+    if (now.timestamp() - timestamp).abs() > tolerance_secs {
+        return Err(ApiError::BadRequest("Stripe signature timestamp outside tolerance".into()));
+    }
+
+    for signature in signatures {
+        let Ok(expected) = HEXLOWER.decode(signature.as_bytes()) else {
+            continue;
+        };
+        if signed_mac(payload, secret, timestamp).verify_slice(&expected).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(ApiError::BadRequest("Invalid Stripe signature".into()))
+}
+
+/// Produce a header value the way Stripe would, for locally signed fixtures and replaying
+/// captured events against a development server.
+pub fn sign_payload(payload: &str, secret: &str, timestamp: i64) -> String {
+    let signature = HEXLOWER.encode(&signed_mac(payload, secret, timestamp).finalize().into_bytes());
+    format!("t={timestamp},v1={signature}")
+}
+
+fn signed_mac(payload: &str, secret: &str, timestamp: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Record an event id before applying it. Returns `false` when the event was already applied,
+/// in which case the caller should acknowledge it without doing anything else.
+pub async fn claim_event(conn: &mut PgConnection, event_id: &str, event_type: &str) -> ApiResult<bool> {
+    let inserted = sqlx::query("INSERT INTO stripe_events (id, event_type) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING")
+        .bind(event_id)
+        .bind(event_type)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+    Ok(inserted == 1)
+}
+
+/// Apply a verified event. Runs inside the caller's transaction together with
+/// [`claim_event`], so a failure leaves nothing behind and Stripe's retry starts clean.
+/// Real-time events go into `realtime` for the caller to publish after it commits.
+pub async fn apply_event(
+    conn: &mut PgConnection,
+    state: &AppState,
+    realtime: &mut realtime::Pending,
+    event_type: &str,
+    object: &Value,
+) -> ApiResult<()> {
+    match event_type {
+        "payment_intent.succeeded" => payment_intent_succeeded(conn, state, realtime, object).await,
+        "payment_intent.payment_failed" => payment_intent_failed(conn, object).await,
+        "charge.refunded" => charge_refunded(conn, object).await,
+        "customer.subscription.deleted" => subscription_deleted(conn, object).await,
+        "invoice.payment_succeeded" => {
+            // Subscription billing; nothing of ours to reconcile
+            tracing::info!(invoice = %str_field(object, "id").unwrap_or_default(), "Subscription payment succeeded");
+            Ok(())
+        }
+        _ => {
+            tracing::debug!(event_type = %event_type, "Unhandled Stripe event");
+            Ok(())
+        }
+    }
+}
+
+fn str_field<'a>(object: &'a Value, key: &str) -> Option<&'a str> {
+    object.get(key).and_then(|v| v.as_str())
+}
+
+/// Expandable fields arrive either as an id or as the expanded object
+fn expandable_id<'a>(object: &'a Value, key: &str) -> Option<&'a str> {
+    match object.get(key) {
+        Some(Value::String(id)) => Some(id),
+        Some(expanded) => str_field(expanded, "id"),
+        None => None,
+    }
+}
+
+fn required_str<'a>(object: &'a Value, key: &str) -> ApiResult<&'a str> {
+    str_field(object, key).ok_or_else(|| ApiError::BadRequest(format!("Stripe event object is missing `{}`", key)))
+}
+
+async fn payment_intent_succeeded(
+    conn: &mut PgConnection,
+    state: &AppState,
+    realtime: &mut realtime::Pending,
+    intent: &Value,
+) -> ApiResult<()> {
+    let intent_id = required_str(intent, "id")?;
+    let Some(payment) = payment_service::find_by_intent(conn, intent_id).await? else {
+        tracing::warn!(payment_intent = %intent_id, "Succeeded PaymentIntent has no matching payment");
+        return Ok(());
+    };
+    if is_collected(&payment.status) {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE payments SET
+            status = 'succeeded'::payment_status,
+            stripe_charge_id = COALESCE($2, stripe_charge_id),
+            collected_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(payment.id)
+    .bind(expandable_id(intent, "latest_charge"))
+    .execute(&mut *conn)
+    .await?;
+
+    let invoice_paid = payment_service::reconcile_invoice(conn, payment.invoice_id).await?;
+
+    let data = json!({
+        "payment_id": payment.id,
+        "invoice_id": payment.invoice_id,
+        "customer_id": payment.customer_id,
+        "amount": payment.amount,
+        "tip_amount": payment.tip_amount,
+        "payment_method": payment.payment_method,
+    });
+    events::publish(&mut *conn, state, payment.team_id, events::PAYMENT_RECEIVED, data).await?;
+    if invoice_paid {
+        let data = json!({ "invoice_id": payment.invoice_id, "customer_id": payment.customer_id });
+        events::publish(&mut *conn, state, payment.team_id, events::INVOICE_PAID, data).await?;
+    }
+
+    // Stripe books the fee on the charge's balance transaction, fetched outside this transaction
+    let job = BackgroundJob::RecordPaymentFees { team_id: payment.team_id, payment_id: payment.id };
+    job_queue::enqueue_with(&mut *conn, &job, Utc::now(), state.jobs.max_attempts(), None).await?;
+
+    realtime.push(
+        Audience::team(payment.team_id),
+        WsEvent::PaymentReceived {
+            payment_id: payment.id.to_string(),
+            invoice_id: payment.invoice_id.to_string(),
+            amount: payment.amount.to_string(),
+        },
+    );
+
+    tracing::info!(payment_id = %payment.id, payment_intent = %intent_id, "Payment succeeded");
+    Ok(())
+}
+
+async fn payment_intent_failed(conn: &mut PgConnection, intent: &Value) -> ApiResult<()> {
+    let intent_id = required_str(intent, "id")?;
+
+    // A late failure notice never overrides a payment that already settled
+    let updated = sqlx::query(
+        r#"
+        UPDATE payments SET status = 'failed'::payment_status
+        WHERE stripe_payment_intent_id = $1 AND status IN ('pending', 'processing')
+        "#,
+    )
+    .bind(intent_id)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let reason = intent
+        .get("last_payment_error")
+        .and_then(|e| str_field(e, "message"))
+        .unwrap_or("unknown");
+    tracing::warn!(payment_intent = %intent_id, updated, reason = %reason, "Payment failed");
+    Ok(())
+}
+
+/// `amount_refunded` on the charge is cumulative, so applying it is the same no matter how
+/// many partial refunds came before or in which order their events arrive.
+async fn charge_refunded(conn: &mut PgConnection, charge: &Value) -> ApiResult<()> {
+    let charge_id = required_str(charge, "id")?;
+    let intent_id = expandable_id(charge, "payment_intent");
+    let Some(payment) = payment_service::find_by_charge(conn, charge_id, intent_id).await? else {
+        tracing::warn!(charge = %charge_id, "Refunded charge has no matching payment");
+        return Ok(());
+    };
+
+    let charged = charge.get("amount").and_then(|v| v.as_i64()).unwrap_or(0);
+    let refunded_cents = charge.get("amount_refunded").and_then(|v| v.as_i64()).unwrap_or(0);
+    let refunded = from_cents(refunded_cents);
+    if refunded <= payment.refunded_amount {
+        return Ok(());
+    }
+
+    let fully_refunded = charge.get("refunded").and_then(|v| v.as_bool()).unwrap_or(false) || refunded_cents >= charged;
+    let status = if fully_refunded { "refunded" } else { "partially_refunded" };
+
+    sqlx::query(
+        r#"
+        UPDATE payments SET
+            status = $2::payment_status,
+            refunded_amount = $3,
+            stripe_charge_id = COALESCE(stripe_charge_id, $4)
+        WHERE id = $1
+        "#,
+    )
+    .bind(payment.id)
+    .bind(status)
+    .bind(refunded)
+    .bind(charge_id)
+    .execute(&mut *conn)
+    .await?;
+
+    // A refund the API started and hasn't finalized yet is already in the cumulative total;
+    // mark it settled so finalizing doesn't count it a second time
+    settle_pending_refunds(conn, &payment, charge, refunded).await?;
+
+    payment_service::reconcile_invoice(conn, payment.invoice_id).await?;
+
+    tracing::info!(payment_id = %payment.id, charge = %charge_id, refunded = %refunded, "Charge refunded");
+    Ok(())
+}
+
+/// Settle the payment's pending refund if this charge counts it. Stripe lists the charge's
+/// refunds when the event includes them, and ours carry their `refund_id` in metadata from
+/// before the processor id is on file. Without the list it only counts if the rise in the
+/// refunded total covers it, so a refund made in the dashboard can't settle one that failed.
+async fn settle_pending_refunds(conn: &mut PgConnection, payment: &Payment, charge: &Value, refunded: Decimal) -> ApiResult<()> {
+    let settled = match charge.pointer("/refunds/data").and_then(Value::as_array) {
+        Some(refunds) => {
+            let live: Vec<&Value> = refunds
+                .iter()
+                .filter(|refund| !matches!(str_field(refund, "status"), Some("failed" | "canceled")))
+                .collect();
+            let processor_ids: Vec<&str> = live.iter().filter_map(|refund| str_field(refund, "id")).collect();
+            let ours: Vec<Uuid> = live
+                .iter()
+                .filter_map(|refund| refund.pointer("/metadata/refund_id")?.as_str()?.parse().ok())
+                .collect();
+            sqlx::query(
+                r#"
+                UPDATE payment_refunds SET status = 'succeeded', completed_at = now()
+                WHERE payment_id = $1 AND status = 'pending'
+                  AND (processor_refund_id = ANY($2) OR id = ANY($3))
+                "#,
+            )
+            .bind(payment.id)
+            .bind(&processor_ids)
+            .bind(&ours)
+            .execute(&mut *conn)
+            .await?
+        }
+        None => {
+            sqlx::query(
+                r#"
+                UPDATE payment_refunds SET status = 'succeeded', completed_at = now()
+                WHERE payment_id = $1 AND status = 'pending' AND amount <= $2
+                "#,
+            )
+            .bind(payment.id)
+            .bind(refunded - payment.refunded_amount)
+            .execute(&mut *conn)
+            .await?
+        }
+    };
+
+    if settled.rows_affected() > 0 {
+        tracing::info!(payment_id = %payment.id, settled = settled.rows_affected(), "Pending refund settled by webhook");
+    }
+    Ok(())
+}
+
+/// A cancelled subscription is either a customer's maintenance agreement or the team's own
+/// FieldForge plan
+async fn subscription_deleted(conn: &mut PgConnection, subscription: &Value) -> ApiResult<()> {
+    let subscription_id = required_str(subscription, "id")?;
+
+    let plans = sqlx::query(
+        r#"
+        UPDATE customer_service_plans SET
+            status = 'cancelled',
+            auto_renew = false,
+            end_date = COALESCE(end_date, CURRENT_DATE)
+        WHERE stripe_subscription_id = $1 AND status <> 'cancelled'
+        "#,
+    )
+    .bind(subscription_id)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let teams = sqlx::query("UPDATE teams SET stripe_subscription_id = NULL WHERE stripe_subscription_id = $1")
+        .bind(subscription_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+    tracing::info!(subscription = %subscription_id, plans, teams, "Subscription cancelled");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support::{self, WEBHOOK_SECRET};
+
+    const PAYLOAD: &str = r#"{"id":"evt_1","type":"charge.refunded"}"#;
+    const TOLERANCE_SECS: i64 = 300;
+
+    #[test]
+    fn a_signature_from_the_secret_verifies() {
+        let now = Utc::now();
+        let header = sign_payload(PAYLOAD, WEBHOOK_SECRET, now.timestamp());
+        assert!(verify_signature(PAYLOAD, &header, WEBHOOK_SECRET, TOLERANCE_SECS, now).is_ok());
+        assert!(verify_signature(PAYLOAD, &header, "whsec_other", TOLERANCE_SECS, now).is_err());
+        assert!(verify_signature(PAYLOAD, &header, "", TOLERANCE_SECS, now).is_err());
+    }
+
+    #[test]
+    fn timestamps_outside_the_tolerance_are_rejected() {
+        let now = Utc::now();
+        for skew in [-TOLERANCE_SECS - 1, TOLERANCE_SECS + 1] {
+            let header = sign_payload(PAYLOAD, WEBHOOK_SECRET, now.timestamp() + skew);
+            assert!(verify_signature(PAYLOAD, &header, WEBHOOK_SECRET, TOLERANCE_SECS, now).is_err(), "skew {skew}");
+        }
+        let header = sign_payload(PAYLOAD, WEBHOOK_SECRET, now.timestamp() - TOLERANCE_SECS);
+        assert!(verify_signature(PAYLOAD, &header, WEBHOOK_SECRET, TOLERANCE_SECS, now).is_ok());
+    }
+
+    #[test]
+    fn any_v1_entry_may_match() {
+        // While a secret rolls, Stripe signs with both the old and the new one
+        let now = Utc::now();
+        let old = sign_payload(PAYLOAD, "whsec_old", now.timestamp());
+        let current = sign_payload(PAYLOAD, WEBHOOK_SECRET, now.timestamp());
+        let v1 = |header: &str| header.split_once(",v1=").unwrap().1.to_string();
+        let header = format!("t={},v1={},v1=not-hex,v1={}", now.timestamp(), v1(&old), v1(&current));
+        assert!(verify_signature(PAYLOAD, &header, WEBHOOK_SECRET, TOLERANCE_SECS, now).is_ok());
+
+        let header = format!("t={},v1={}", now.timestamp(), v1(&old));
+        assert!(verify_signature(PAYLOAD, &header, WEBHOOK_SECRET, TOLERANCE_SECS, now).is_err());
+        assert!(verify_signature(PAYLOAD, &format!("t={}", now.timestamp()), WEBHOOK_SECRET, TOLERANCE_SECS, now).is_err());
+    }
+
+    #[test]
+    fn a_tampered_body_is_rejected() {
+        let now = Utc::now();
+        let header = sign_payload(PAYLOAD, WEBHOOK_SECRET, now.timestamp());
+        let tampered = PAYLOAD.replace("evt_1", "evt_2");
+        assert!(verify_signature(&tampered, &header, WEBHOOK_SECRET, TOLERANCE_SECS, now).is_err());
+    }
+
+    async fn deliver(app: &axum::Router, id: &str, charge: Value) -> StatusCode {
+        let event = json!({ "id": id, "type": "charge.refunded", "data": { "object": charge } }).to_string();
+        let signature = sign_payload(&event, WEBHOOK_SECRET, Utc::now().timestamp());
+        let request = Request::post("/webhooks/stripe").header("stripe-signature", signature).body(Body::from(event)).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    async fn pending_refund(db: &PgPool, team_id: Uuid, payment_id: Uuid, amount: i64) -> Uuid {
+        sqlx::query_scalar("INSERT INTO payment_refunds (team_id, payment_id, amount) VALUES ($1, $2, $3) RETURNING id")
+            .bind(team_id)
+            .bind(payment_id)
+            .bind(Decimal::from(amount))
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    async fn refund_status(db: &PgPool, refund_id: Uuid) -> String {
+        sqlx::query_scalar("SELECT status FROM payment_refunds WHERE id = $1").bind(refund_id).fetch_one(db).await.unwrap()
+    }
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn refund_events_settle_only_the_refunds_they_count(db: PgPool) {
+        let state = test_support::state(db.clone(), FakeProvider::default()).await;
+        let app = crate::routes::stripe::router().with_state(state);
+        let team_id = test_support::team(&db, "acme").await;
+        let customer_id = test_support::customer(&db, team_id).await;
+        let invoice_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO invoices (team_id, customer_id, invoice_number, status, total, amount_paid, amount_due)
+            VALUES ($1, $2, 'INV-0001', 'paid'::invoice_status, 150, 150, 0)
+            RETURNING id
+            "#,
+        )
+        .bind(team_id)
+        .bind(customer_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let payment_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO payments (team_id, invoice_id, customer_id, amount, payment_method, status, stripe_charge_id)
+            VALUES ($1, $2, $3, 150, 'card'::payment_method, 'succeeded'::payment_status, 'ch_1')
+            RETURNING id
+            "#,
+        )
+        .bind(team_id)
+        .bind(invoice_id)
+        .bind(customer_id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let ours = pending_refund(&db, team_id, payment_id, 50).await;
+
+        // Someone refunds $50 from the dashboard while ours is still in flight
+        let dashboard = json!({ "id": "re_dashboard", "status": "succeeded", "amount": 5000, "metadata": {} });
+        let charge = json!({ "id": "ch_1", "amount": 15000, "amount_refunded": 5000, "refunds": { "data": [dashboard] } });
+        assert_eq!(deliver(&app, "evt_1", charge).await, StatusCode::OK);
+        assert_eq!(refund_status(&db, ours).await, "pending");
+
+        let listed = json!({ "id": "re_ours", "status": "succeeded", "amount": 5000, "metadata": { "refund_id": ours.to_string() } });
+        let charge =
+            json!({ "id": "ch_1", "amount": 15000, "amount_refunded": 10000, "refunds": { "data": [dashboard, listed] } });
+        assert_eq!(deliver(&app, "evt_2", charge).await, StatusCode::OK);
+        assert_eq!(refund_status(&db, ours).await, "succeeded");
+
+        // Without the list, a pending refund needs the rise in the total to cover it
+        let next = pending_refund(&db, team_id, payment_id, 30).await;
+        let charge = json!({ "id": "ch_1", "amount": 15000, "amount_refunded": 11000 });
+        assert_eq!(deliver(&app, "evt_3", charge).await, StatusCode::OK);
+        assert_eq!(refund_status(&db, next).await, "pending");
+        let charge = json!({ "id": "ch_1", "amount": 15000, "amount_refunded": 14000 });
+        assert_eq!(deliver(&app, "evt_4", charge).await, StatusCode::OK);
+        assert_eq!(refund_status(&db, next).await, "succeeded");
+
+        let refunded: Decimal =
+            sqlx::query_scalar("SELECT refunded_amount FROM payments WHERE id = $1").bind(payment_id).fetch_one(&db).await.unwrap();
+        assert_eq!(refunded, Decimal::from(140));
+    }
+}
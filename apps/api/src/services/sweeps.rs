@@ -0,0 +1,527 @@
+//! Periodic sweeps driven by the scheduler. Each sweep is idempotent: rows are flagged
+//! or notifications are enqueued with dedup keys, so re-running a sweep is harmless.
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use uuid::Uuid;
+
+use crate::errors::ApiResult;
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::services::{gps_store, recurring_service, scheduler};
+use crate::AppState;
+
+/// How long before the appointment the customer and technician are reminded
+const REMINDER_LEAD_HOURS: i64 = 24;
+
+/// License reminder windows, nearest first. Firing a window also marks the wider ones
+/// so a license entered 20 days before expiry gets one reminder, not three.
+const LICENSE_WINDOWS: &[(i64, &str)] = &[
+    (30, "reminder_sent_30 = true, reminder_sent_60 = true, reminder_sent_90 = true"),
+    (60, "reminder_sent_60 = true, reminder_sent_90 = true"),
+    (90, "reminder_sent_90 = true"),
+];
+
+/// GPS rows deleted per statement, so retention doesn't hold long locks
+const GPS_DELETE_BATCH: i64 = 10_000;
+
+#[derive(Debug, sqlx::FromRow)]
+struct UpcomingJob {
+    id: Uuid,
+    scheduled_date: NaiveDate,
+    scheduled_start_time: NaiveTime,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ReminderJob {
+    team_id: Uuid,
+    title: String,
+    status: String,
+    scheduled_date: Option<NaiveDate>,
+    scheduled_start_time: Option<NaiveTime>,
+    assigned_to: Option<Uuid>,
+    timezone: String,
+    team_name: String,
+    customer_first_name: String,
+    customer_email: Option<String>,
+    customer_phone: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ReviewRequestJob {
+    team_id: Uuid,
+    title: String,
+    status: String,
+    team_name: String,
+    website: Option<String>,
+    customer_first_name: String,
+    customer_email: Option<String>,
+    customer_phone: Option<String>,
+    reviewed: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ExpiringLicense {
+    id: Uuid,
+    license_type: String,
+    license_number: String,
+    expiry_date: NaiveDate,
+    email: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct OverdueInvoice {
+    id: Uuid,
+    invoice_number: String,
+    amount_due: rust_decimal::Decimal,
+    due_date: NaiveDate,
+    first_name: String,
+    email: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct GpsPolicy {
+    gps_retention_days: i32,
+    gps_downsample_after_days: Option<i32>,
+    gps_downsample_interval_secs: i32,
+    gps_downsampled_before: Option<DateTime<Utc>>,
+}
+
+fn local_to_utc(tz: Tz, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Enqueue a reminder for every scheduled job starting within the lead window
+pub async fn appointment_reminders(state: &AppState, team_id: Uuid) -> ApiResult<()> {
+    let tz = scheduler::team_timezone(&state.db, team_id).await?;
+    let today = scheduler::today_in(tz);
+
+    let jobs = sqlx::query_as::<_, UpcomingJob>(
+        r#"
+        SELECT id, scheduled_date, scheduled_start_time FROM jobs
+        WHERE team_id = $1 AND status = 'scheduled' AND deleted_at IS NULL
+          AND scheduled_start_time IS NOT NULL
+          AND scheduled_date BETWEEN $2 AND $2 + 2
+        "#,
+    )
+    .bind(team_id)
+    .bind(today)
+    .fetch_all(&state.db)
+    .await?;
+
+    let now = Utc::now();
+    let lead = Duration::hours(REMINDER_LEAD_HOURS);
+
+    for job in jobs {
+        let Some(starts_at) = local_to_utc(tz, job.scheduled_date, job.scheduled_start_time) else {
+            continue;
+        };
+        if starts_at <= now || starts_at > now + lead + Duration::hours(1) {
+            continue;
+        }
+
+        let remind_at = (starts_at - lead).max(now);
+        // Keyed on the start time so a rescheduled job gets a fresh reminder
+        let dedup_key = format!("appointment_reminder:{}:{}", job.id, starts_at.timestamp());
+        state
+            .jobs
+            .enqueue_unique(BackgroundJob::ScheduleReminder { team_id, job_id: job.id, remind_at }, remind_at, &dedup_key)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Notify the customer and assigned technician about an upcoming appointment
+pub async fn send_appointment_reminder(state: &AppState, job_id: Uuid) -> ApiResult<()> {
+    let Some(job) = sqlx::query_as::<_, ReminderJob>(
+        r#"
+        SELECT j.team_id, j.title, j.status::text AS status, j.scheduled_date, j.scheduled_start_time, j.assigned_to,
+               t.timezone, t.name AS team_name,
+               c.first_name AS customer_first_name, c.email AS customer_email, c.phone AS customer_phone
+        FROM jobs j
+        JOIN teams t ON t.id = j.team_id
+        JOIN customers c ON c.id = j.customer_id
+        WHERE j.id = $1 AND j.deleted_at IS NULL
+        "#,
+    )
+    .bind(job_id)
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let (Some(date), Some(time)) = (job.scheduled_date, job.scheduled_start_time) else {
+        return Ok(());
+    };
+    if job.status != "scheduled" {
+        tracing::debug!(job_id = %job_id, status = %job.status, "Skipping reminder for job no longer scheduled");
+        return Ok(());
+    }
+
+    let tz = scheduler::parse_timezone(&job.timezone);
+    if local_to_utc(tz, date, time).map_or(true, |starts_at| starts_at <= Utc::now()) {
+        return Ok(());
+    }
+
+    let when = format!("{} at {}", date.format("%A, %B %-d"), time.format("%-I:%M %p"));
+    let key = format!("{}:{}T{}", job_id, date, time);
+    let now = Utc::now();
+
+    if let Some(email) = job.customer_email.filter(|e| !e.is_empty()) {
+        let body = format!(
+            "Hi {}, this is a reminder from {} about your appointment \"{}\" on {}.",
+            job.customer_first_name, job.team_name, job.title, when
+        );
+        state
+            .jobs
+            .enqueue_unique(
+                BackgroundJob::SendEmail {
+                    team_id: Some(job.team_id),
+                    to: email,
+                    subject: format!("Appointment reminder: {}", when),
+                    body,
+                },
+                now,
+                &format!("reminder_email:{}", key),
+            )
+            .await?;
+    }
+
+    if let Some(phone) = job.customer_phone.filter(|p| !p.is_empty()) {
+        let body = format!("{}: reminder of your appointment on {}.", job.team_name, when);
+        state
+            .jobs
+            .enqueue_unique(BackgroundJob::SendSms { team_id: Some(job.team_id), to: phone, body }, now, &format!("reminder_sms:{}", key))
+            .await?;
+    }
+
+    if let Some(user_id) = job.assigned_to {
+        state
+            .jobs
+            .enqueue_unique(
+                BackgroundJob::SendPushNotification {
+                    team_id: job.team_id,
+                    user_id,
+                    title: "Upcoming job".into(),
+                    body: format!("{} on {}", job.title, when),
+                },
+                now,
+                &format!("reminder_push:{}", key),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Ask the customer of a paid job for a review, unless the job has been reopened,
+/// deleted or already reviewed since the request was scheduled
+pub async fn send_review_request(state: &AppState, job_id: Uuid) -> ApiResult<()> {
+    let Some(job) = sqlx::query_as::<_, ReviewRequestJob>(
+        r#"
+        SELECT j.team_id, j.title, j.status::text AS status, t.name AS team_name, t.website,
+               c.first_name AS customer_first_name, c.email AS customer_email, c.phone AS customer_phone,
+               EXISTS (SELECT 1 FROM reviews r WHERE r.job_id = j.id) AS reviewed
+        FROM jobs j
+        JOIN teams t ON t.id = j.team_id
+        JOIN customers c ON c.id = j.customer_id
+        WHERE j.id = $1 AND j.deleted_at IS NULL
+        "#,
+    )
+    .bind(job_id)
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    if !matches!(job.status.as_str(), "paid" | "closed") || job.reviewed {
+        tracing::debug!(job_id = %job_id, status = %job.status, reviewed = job.reviewed, "Skipping review request");
+        return Ok(());
+    }
+
+    let link = job
+        .website
+        .filter(|w| !w.is_empty())
+        .map(|w| format!(" Leave us a review at {}", w))
+        .unwrap_or_default();
+    let now = Utc::now();
+
+    if let Some(email) = job.customer_email.filter(|e| !e.is_empty()) {
+        let body = format!(
+            "Hi {}, thanks for choosing {}. We'd love to hear how \"{}\" went.{}",
+            job.customer_first_name, job.team_name, job.title, link
+        );
+        state
+            .jobs
+            .enqueue_unique(
+                BackgroundJob::SendEmail { team_id: Some(job.team_id), to: email, subject: "How did we do?".into(), body },
+                now,
+                &format!("review_request_email:{}", job_id),
+            )
+            .await?;
+    }
+
+    if let Some(phone) = job.customer_phone.filter(|p| !p.is_empty()) {
+        let body = format!("{}: thanks for your business! How did we do?{}", job.team_name, link);
+        state
+            .jobs
+            .enqueue_unique(
+                BackgroundJob::SendSms { team_id: Some(job.team_id), to: phone, body },
+                now,
+                &format!("review_request_sms:{}", job_id),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Flag licenses entering the 90/60/30-day windows, notify their holders, and expire
+/// licenses whose date has passed
+pub async fn license_expiry(state: &AppState, team_id: Uuid) -> ApiResult<()> {
+    let tz = scheduler::team_timezone(&state.db, team_id).await?;
+    let today = scheduler::today_in(tz);
+    let mut tx = state.db.begin().await?;
+
+    sqlx::query("UPDATE licenses SET status = 'expired' WHERE team_id = $1 AND status = 'active' AND expiry_date < $2")
+        .bind(team_id)
+        .bind(today)
+        .execute(&mut *tx)
+        .await?;
+
+    for (days, flags) in LICENSE_WINDOWS {
+        let flag = format!("reminder_sent_{}", days);
+        let licenses = sqlx::query_as::<_, ExpiringLicense>(&format!(
+            r#"
+            WITH flagged AS (
+                UPDATE licenses SET {flags}
+                WHERE team_id = $1 AND status = 'active' AND NOT {flag}
+                  AND expiry_date BETWEEN $2 AND $2 + $3::int
+                RETURNING id, user_id, license_type, license_number, expiry_date
+            )
+            SELECT f.id, f.license_type, f.license_number, f.expiry_date, COALESCE(u.email, t.email) AS email
+            FROM flagged f
+            JOIN teams t ON t.id = $1
+            LEFT JOIN users u ON u.id = f.user_id
+            "#,
+        ))
+        .bind(team_id)
+        .bind(today)
+        .bind(*days as i32)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for license in licenses {
+            let Some(email) = license.email.filter(|e| !e.is_empty()) else {
+                continue;
+            };
+            let remaining = (license.expiry_date - today).num_days();
+            let job = BackgroundJob::SendEmail {
+                team_id: Some(team_id),
+                to: email,
+                subject: format!("{} license expires in {} days", license.license_type, remaining),
+                body: format!(
+                    "License {} ({}) expires on {}. Renew it to stay compliant.",
+                    license.license_number, license.license_type, license.expiry_date
+                ),
+            };
+            let dedup_key = format!("license_expiry:{}:{}", license.id, days);
+            job_queue::enqueue_with(&mut *tx, &job, Utc::now(), state.jobs.max_attempts(), Some(&dedup_key)).await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Mark unpaid invoices past their due date as overdue and email the customer once
+pub async fn overdue_invoices(state: &AppState, team_id: Uuid) -> ApiResult<()> {
+    let tz = scheduler::team_timezone(&state.db, team_id).await?;
+    let today = scheduler::today_in(tz);
+    let mut tx = state.db.begin().await?;
+
+    let invoices = sqlx::query_as::<_, OverdueInvoice>(
+        r#"
+        WITH flagged AS (
+            UPDATE invoices SET status = 'overdue'
+            WHERE team_id = $1 AND status IN ('sent', 'viewed', 'partially_paid')
+              AND due_date < $2 AND amount_due > 0
+            RETURNING id, invoice_number, amount_due, due_date, customer_id
+        )
+        SELECT f.id, f.invoice_number, f.amount_due, f.due_date, c.first_name, c.email
+        FROM flagged f
+        JOIN customers c ON c.id = f.customer_id
+        "#,
+    )
+    .bind(team_id)
+    .bind(today)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for invoice in &invoices {
+        let Some(email) = invoice.email.clone().filter(|e| !e.is_empty()) else {
+            continue;
+        };
+        let job = BackgroundJob::SendEmail {
+            team_id: Some(team_id),
+            to: email,
+            subject: format!("Invoice {} is past due", invoice.invoice_number),
+            body: format!(
+                "Hi {}, invoice {} for ${} was due on {}. Please submit payment at your earliest convenience.",
+                invoice.first_name, invoice.invoice_number, invoice.amount_due, invoice.due_date
+            ),
+        };
+        let dedup_key = format!("invoice_overdue:{}", invoice.id);
+        job_queue::enqueue_with(&mut *tx, &job, Utc::now(), state.jobs.max_attempts(), Some(&dedup_key)).await?;
+    }
+
+    tx.commit().await?;
+
+    if !invoices.is_empty() {
+        tracing::info!(team_id = %team_id, count = invoices.len(), "Invoices marked overdue");
+    }
+    Ok(())
+}
+
+/// Enqueue generation for every auto-scheduled rule whose next occurrence falls inside
+/// its advance window
+pub async fn recurring_jobs(state: &AppState, team_id: Uuid) -> ApiResult<()> {
+    let tz = scheduler::team_timezone(&state.db, team_id).await?;
+    let today = scheduler::today_in(tz);
+
+    let due = sqlx::query_as::<_, (Uuid, NaiveDate)>(
+        r#"
+        SELECT id, next_occurrence FROM recurring_rules
+        WHERE team_id = $1 AND is_active = true AND auto_schedule = true
+          AND next_occurrence IS NOT NULL AND next_occurrence <= $2 + advance_days
+        "#,
+    )
+    .bind(team_id)
+    .bind(today)
+    .fetch_all(&state.db)
+    .await?;
+
+    for (rule_id, occurrence) in due {
+        enqueue_recurring_generation(state, team_id, rule_id, occurrence).await?;
+    }
+
+    Ok(())
+}
+
+/// Apply the team's GPS retention: delete fixes past the retention period and thin older
+/// fixes to one per technician per interval. Fixes a geofence event points at are kept
+/// until they expire. Also keeps the table's monthly partitions ahead of the clock and
+/// drops months past every team's retention.
+pub async fn gps_retention(state: &AppState, team_id: Uuid) -> ApiResult<()> {
+    gps_store::ensure_partitions(&state.db).await?;
+
+    let policy = sqlx::query_as::<_, GpsPolicy>(
+        r#"
+        SELECT gps_retention_days, gps_downsample_after_days, gps_downsample_interval_secs, gps_downsampled_before
+        FROM teams WHERE id = $1
+        "#,
+    )
+    .bind(team_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let now = Utc::now();
+    let retain_from = now - Duration::days(policy.gps_retention_days.into());
+
+    let mut expired = 0;
+    loop {
+        let deleted = sqlx::query(
+            r#"
+            DELETE FROM gps_locations WHERE (id, recorded_at) IN (
+                SELECT id, recorded_at FROM gps_locations
+                WHERE team_id = $1 AND recorded_at < $2
+                LIMIT $3
+            )
+            "#,
+        )
+        .bind(team_id)
+        .bind(retain_from)
+        .bind(GPS_DELETE_BATCH)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+        expired += deleted;
+        if deleted < GPS_DELETE_BATCH as u64 {
+            break;
+        }
+    }
+
+    let mut thinned = 0;
+    if let Some(after_days) = policy.gps_downsample_after_days {
+        let until = now - Duration::days(after_days.into());
+        // A day at a time from where the last run stopped; already thinned fixes aren't revisited
+        let mut from = policy.gps_downsampled_before.map_or(retain_from, |before| before.max(retain_from));
+        while from < until {
+            let to = (from + Duration::days(1)).min(until);
+            thinned += sqlx::query(
+                r#"
+                DELETE FROM gps_locations g
+                USING (
+                    SELECT id, recorded_at, row_number() OVER (
+                        PARTITION BY user_id, floor(extract(epoch FROM recorded_at) / $4)
+                        ORDER BY recorded_at
+                    ) AS rank
+                    FROM gps_locations
+                    WHERE team_id = $1 AND recorded_at >= $2 AND recorded_at < $3
+                ) ranked
+                WHERE g.id = ranked.id AND g.recorded_at = ranked.recorded_at AND ranked.rank > 1
+                  AND NOT EXISTS (SELECT 1 FROM job_geofence_events e WHERE e.gps_location_id = g.id)
+                "#,
+            )
+            .bind(team_id)
+            .bind(from)
+            .bind(to)
+            .bind(policy.gps_downsample_interval_secs)
+            .execute(&state.db)
+            .await?
+            .rows_affected();
+            from = to;
+        }
+
+        sqlx::query("UPDATE teams SET gps_downsampled_before = GREATEST(gps_downsampled_before, $2) WHERE id = $1")
+            .bind(team_id)
+            .bind(until)
+            .execute(&state.db)
+            .await?;
+    }
+
+    let dropped = gps_store::drop_expired_partitions(&state.db).await?;
+    if expired > 0 || thinned > 0 || dropped > 0 {
+        tracing::info!(team_id = %team_id, expired, thinned, dropped, "GPS retention applied");
+    }
+
+    Ok(())
+}
+
+/// Generate one occurrence, then chain the next one if it is also inside the window
+pub async fn generate_recurring(state: &AppState, rule_id: Uuid) -> ApiResult<()> {
+    let generated = recurring_service::generate_next_occurrence(&state.db, rule_id, None).await?;
+
+    if let Some(next) = generated.next_occurrence {
+        let tz = scheduler::team_timezone(&state.db, generated.team_id).await?;
+        let horizon = scheduler::today_in(tz) + Duration::days(generated.advance_days as i64);
+        if next <= horizon {
+            enqueue_recurring_generation(state, generated.team_id, rule_id, next).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn enqueue_recurring_generation(state: &AppState, team_id: Uuid, rule_id: Uuid, occurrence: NaiveDate) -> ApiResult<()> {
+    let dedup_key = format!("recurring_rule:{}:{}", rule_id, occurrence);
+    state
+        .jobs
+        .enqueue_unique(BackgroundJob::RecurringJobGeneration { team_id, recurring_rule_id: rule_id }, Utc::now(), &dedup_key)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use chrono::{Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::team::{CreateInvitationRequest, CreateTeamRequest, Team, TeamInvitation};
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::services::{auth_service, scheduler};
+use crate::AppState;
+
+/// Team columns with `plan_tier` cast to text so rows decode into `Team`
+pub const TEAM_COLUMNS: &str = r#"
+    id, name, slug, owner_id, logo_url, phone, email, website, address_line1, address_line2,
+    city, state, zip_code, country, timezone, default_hourly_rate, default_markup_pct, tax_rate,
+    primary_trade, service_radius_miles, plan_tier::text AS plan_tier, stripe_customer_id,
+    stripe_subscription_id, require_mfa, geofence_radius_meters, geofence_auto_transition,
+    mileage_rate, gps_retention_days, gps_downsample_after_days, gps_downsample_interval_secs,
+    working_hours_start, working_hours_end, working_days, schedule_travel_buffer_minutes,
+    is_active, created_at, updated_at
+"#;
+
+/// Invitation columns; `token_hash` never leaves the database
+pub const INVITATION_COLUMNS: &str = r#"
+    id, team_id, email, first_name, last_name, role, hourly_rate, invited_by, expires_at,
+    sent_count, last_sent_at, accepted_at, revoked_at, created_at
+"#;
+
+const SLUG_ATTEMPTS: usize = 5;
+
+fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "team".into()
+    } else {
+        slug.chars().take(48).collect()
+    }
+}
+
+fn slug_suffix() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(6)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect()
+}
+
+/// Create a team owned by `owner_id`: the team row with a unique slug, an owner
+/// membership, the owner's default team if they had none, and the default scheduled
+/// tasks. Runs on the caller's connection so it can share a transaction.
+pub async fn create_team(conn: &mut PgConnection, owner_id: Uuid, req: &CreateTeamRequest) -> ApiResult<Team> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::Validation("Team name is required".into()));
+    }
+    if let Some(tz) = &req.timezone {
+        tz.parse::<chrono_tz::Tz>()
+            .map_err(|_| ApiError::Validation(format!("Unknown timezone '{}'", tz)))?;
+    }
+
+    let base = slugify(name);
+    let mut team = None;
+    for attempt in 0..SLUG_ATTEMPTS {
+        let slug = if attempt == 0 { base.clone() } else { format!("{}-{}", base, slug_suffix()) };
+
+        team = sqlx::query_as::<_, Team>(&format!(
+            r#"
+            INSERT INTO teams (name, slug, owner_id, phone, primary_trade, timezone)
+            VALUES ($1, $2, $3, $4, $5, COALESCE($6, 'America/New_York'))
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING {}
+            "#,
+            TEAM_COLUMNS
+        ))
+        .bind(name)
+        .bind(&slug)
+        .bind(owner_id)
+        .bind(&req.phone)
+        .bind(&req.primary_trade)
+        .bind(&req.timezone)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if team.is_some() {
+            break;
+        }
+    }
+    let team = team.ok_or_else(|| ApiError::Conflict("Could not allocate a unique team slug".into()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO team_memberships (team_id, user_id, role)
+        VALUES ($1, $2, 'owner')
+        "#,
+    )
+    .bind(team.id)
+    .bind(owner_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("UPDATE users SET team_id = COALESCE(team_id, $1) WHERE id = $2")
+        .bind(team.id)
+        .bind(owner_id)
+        .execute(&mut *conn)
+        .await?;
+
+    scheduler::ensure_team_defaults(conn, team.id).await?;
+
+    tracing::info!(team_id = %team.id, owner_id = %owner_id, slug = %team.slug, "Team created");
+
+    Ok(team)
+}
+
+/// Queue the invitation email inside the caller's transaction, so a rolled-back
+/// invite never sends a link
+async fn queue_invitation_email(
+    conn: &mut PgConnection,
+    state: &AppState,
+    invitation: &TeamInvitation,
+    token: &str,
+) -> ApiResult<()> {
+    let team_name = sqlx::query_scalar::<_, String>("SELECT name FROM teams WHERE id = $1")
+        .bind(invitation.team_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let link = format!("{}/invite/{}", state.config.server.app_url.trim_end_matches('/'), token);
+    let job = BackgroundJob::SendEmail {
+        team_id: Some(invitation.team_id),
+        to: invitation.email.clone(),
+        subject: format!("You're invited to join {} on FieldForge", team_name),
+        body: format!(
+            "Hi {},\n\nYou've been invited to join {} as {}. Accept the invitation here:\n\n{}\n\nThis link expires on {}.",
+            invitation.first_name,
+            team_name,
+            invitation.role.replace('_', " "),
+            link,
+            invitation.expires_at.format("%B %-d, %Y"),
+        ),
+    };
+    job_queue::enqueue_with(&mut *conn, &job, Utc::now(), state.jobs.max_attempts(), None).await?;
+    Ok(())
+}
+
+/// Create an invitation and email its link. Fails if the email already belongs to an
+/// active member or has an open invitation (resend that one instead).
+pub async fn create_invitation(
+    state: &AppState,
+    team_id: Uuid,
+    invited_by: Uuid,
+    req: &CreateInvitationRequest,
+) -> ApiResult<TeamInvitation> {
+    let email = req.email.trim().to_lowercase();
+    if email.is_empty() || !email.contains('@') {
+        return Err(ApiError::Validation("A valid email is required".into()));
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let already_member = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM team_memberships m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.team_id = $1 AND lower(u.email) = $2 AND m.is_active = true
+        )
+        "#,
+    )
+    .bind(team_id)
+    .bind(&email)
+    .fetch_one(&mut *tx)
+    .await?;
+    if already_member {
+        return Err(ApiError::Conflict("This user is already a member of the team".into()));
+    }
+
+    let token = auth_service::generate_token();
+    let expires_at = Utc::now() + Duration::days(state.config.auth.invitation_expiry_days);
+
+    let invitation = sqlx::query_as::<_, TeamInvitation>(&format!(
+        r#"
+        INSERT INTO team_invitations (team_id, email, first_name, last_name, role, hourly_rate, token_hash, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT DO NOTHING
+        RETURNING {}
+        "#,
+        INVITATION_COLUMNS
+    ))
+    .bind(team_id)
+    .bind(&email)
+    .bind(&req.first_name)
+    .bind(&req.last_name)
+    .bind(&req.role)
+    .bind(req.hourly_rate)
+    .bind(auth_service::hash_token(&token))
+    .bind(invited_by)
+    .bind(expires_at)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::Conflict("An invitation is already pending for this email; resend it instead".into()))?;
+
+    queue_invitation_email(&mut tx, state, &invitation, &token).await?;
+    tx.commit().await?;
+
+    tracing::info!(invitation_id = %invitation.id, team_id = %team_id, email = %email, "Team invitation sent");
+
+    Ok(invitation)
+}
+
+/// Rotate an open invitation's token, push out its expiry and email the new link.
+/// The previous link stops working.
+pub async fn resend_invitation(state: &AppState, team_id: Uuid, invitation_id: Uuid) -> ApiResult<TeamInvitation> {
+    let token = auth_service::generate_token();
+    let expires_at = Utc::now() + Duration::days(state.config.auth.invitation_expiry_days);
+
+    let mut tx = state.db.begin().await?;
+
+    let invitation = sqlx::query_as::<_, TeamInvitation>(&format!(
+        r#"
+        UPDATE team_invitations SET
+            token_hash = $3,
+            expires_at = $4,
+            sent_count = sent_count + 1,
+            last_sent_at = now()
+        WHERE id = $1 AND team_id = $2 AND accepted_at IS NULL AND revoked_at IS NULL
+        RETURNING {}
+        "#,
+        INVITATION_COLUMNS
+    ))
+    .bind(invitation_id)
+    .bind(team_id)
+    .bind(auth_service::hash_token(&token))
+    .bind(expires_at)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Invitation".into()))?;
+
+    queue_invitation_email(&mut tx, state, &invitation, &token).await?;
+    tx.commit().await?;
+
+    tracing::info!(invitation_id = %invitation.id, sent_count = invitation.sent_count, "Team invitation resent");
+
+    Ok(invitation)
+}
+
+/// Look up an open, unexpired invitation by its raw token
+pub async fn find_open_invitation(conn: &mut PgConnection, token: &str, lock: bool) -> ApiResult<TeamInvitation> {
+    let invitation = sqlx::query_as::<_, TeamInvitation>(&format!(
+        "SELECT {} FROM team_invitations WHERE token_hash = $1 AND accepted_at IS NULL AND revoked_at IS NULL{}",
+        INVITATION_COLUMNS,
+        if lock { " FOR UPDATE" } else { "" }
+    ))
+    .bind(auth_service::hash_token(token))
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Invitation".into()))?;
+
+    if invitation.expires_at <= Utc::now() {
+        return Err(ApiError::BadRequest("This invitation has expired; ask for a new one".into()));
+    }
+    Ok(invitation)
+}
+
+/// Result of accepting an invitation
+pub struct AcceptedInvitation {
+    pub invitation: TeamInvitation,
+    pub user_id: Uuid,
+    /// True when the invitee had no account and one was created with their password
+    pub created_user: bool,
+}
+
+/// Join the invitee to the team. New emails get an account with the supplied password;
+/// existing accounts keep their password and gain a membership.
+pub async fn accept_invitation(state: &AppState, token: &str, password: Option<&str>) -> ApiResult<AcceptedInvitation> {
+    let mut tx = state.db.begin().await?;
+
+    let invitation = find_open_invitation(&mut tx, token, true).await?;
+
+    let existing = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE lower(email) = $1")
+        .bind(&invitation.email)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let (user_id, created_user) = match existing {
+        Some(user_id) => (user_id, false),
+        None => {
+            let password = password
+                .filter(|p| p.len() >= 8)
+                .ok_or_else(|| ApiError::Validation("Password must be at least 8 characters".into()))?;
+            let password_hash = auth_service::hash_password(password)?;
+
+            // The emailed link proves ownership of the address
+            let user_id = sqlx::query_scalar::<_, Uuid>(
+                r#"
+                INSERT INTO users (team_id, email, password_hash, first_name, last_name, role, hourly_rate, email_verified)
+                VALUES ($1, $2, $3, $4, $5, $6::user_role, $7, true)
+                RETURNING id
+                "#,
+            )
+            .bind(invitation.team_id)
+            .bind(&invitation.email)
+            .bind(&password_hash)
+            .bind(&invitation.first_name)
+            .bind(&invitation.last_name)
+            .bind(&invitation.role)
+            .bind(invitation.hourly_rate)
+            .fetch_one(&mut *tx)
+            .await?;
+            (user_id, true)
+        }
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO team_memberships (team_id, user_id, role, hourly_rate)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (team_id, user_id) DO UPDATE SET
+            role = EXCLUDED.role,
+            hourly_rate = EXCLUDED.hourly_rate,
+            is_active = true
+        "#,
+    )
+    .bind(invitation.team_id)
+    .bind(user_id)
+    .bind(&invitation.role)
+    .bind(invitation.hourly_rate)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE users SET team_id = $1 WHERE id = $2 AND team_id IS NULL")
+        .bind(invitation.team_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE team_invitations SET accepted_at = now(), accepted_by = $2 WHERE id = $1")
+        .bind(invitation.id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    tracing::info!(invitation_id = %invitation.id, team_id = %invitation.team_id, user_id = %user_id, created_user, "Team invitation accepted");
+
+    Ok(AcceptedInvitation {
+        invitation,
+        user_id,
+        created_user,
+    })
+}
@@ -0,0 +1,266 @@
+//! Customer-facing "technician on the way" tracking.
+//!
+//! Going en route sends the customer a tokenized link, minted when the message goes out. The portal page behind it shows the
+//! assigned technician's latest fix, an ETA to the property from the configured
+//! [`RoutingProvider`](crate::services::routing::RoutingProvider), and the arrival window.
+//! The link dies at its expiry or as soon as the job leaves scheduled/en route, so the
+//! technician's location stops being visible once work starts.
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::services::{auth_service, gps_store};
+use crate::services::routing::{Coordinate, RouteEstimate, StraightLine};
+use crate::AppState;
+
+/// Queue a tracking link to the customer on each of `channels` (`email`, `sms`), replacing
+/// any earlier links for the job. Returns how many were queued.
+pub async fn send_links(
+    state: &AppState,
+    conn: &mut PgConnection,
+    team_id: Uuid,
+    job_id: Uuid,
+    channels: &[&str],
+) -> ApiResult<usize> {
+    sqlx::query("UPDATE job_tracking_links SET revoked_at = now() WHERE job_id = $1 AND revoked_at IS NULL")
+        .bind(job_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let expires_at = Utc::now() + Duration::hours(state.config.tracking.link_expiry_hours);
+    for channel in channels {
+        let link_id = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            INSERT INTO job_tracking_links (team_id, job_id, channel, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+        )
+        .bind(team_id)
+        .bind(job_id)
+        .bind(channel)
+        .bind(expires_at)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let job = BackgroundJob::SendTrackingLink { team_id, link_id };
+        job_queue::enqueue_with(&mut *conn, &job, Utc::now(), state.jobs.max_attempts(), None).await?;
+    }
+
+    Ok(channels.len())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct PendingLink {
+    channel: String,
+    title: String,
+    first_name: String,
+    email: Option<String>,
+    phone: Option<String>,
+    team_name: String,
+}
+
+/// Mint the token for a queued link and send it. Each attempt mints a new token, so a
+/// retry voids any copy an earlier attempt may have sent. Links that were replaced,
+/// expired or belong to a job that has already started are dropped.
+pub async fn deliver_link(state: &AppState, link_id: Uuid) -> ApiResult<()> {
+    let token = auth_service::generate_token();
+
+    let Some(link) = sqlx::query_as::<_, PendingLink>(
+        r#"
+        UPDATE job_tracking_links l SET token_hash = $2
+        FROM jobs j, customers c, teams t
+        WHERE l.id = $1 AND j.id = l.job_id AND c.id = j.customer_id AND t.id = j.team_id
+          AND l.revoked_at IS NULL AND l.expires_at > now()
+          AND j.deleted_at IS NULL AND j.status IN ('scheduled', 'en_route')
+        RETURNING l.channel, j.title, c.first_name, c.email, c.phone, t.name AS team_name
+        "#,
+    )
+    .bind(link_id)
+    .bind(auth_service::hash_token(&token))
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        tracing::debug!(link_id = %link_id, "Tracking link replaced, expired or no longer needed; not sending");
+        return Ok(());
+    };
+
+    let url = format!("{}/track/{}", state.config.server.app_url.trim_end_matches('/'), token);
+    let body = format!(
+        "Hi {}, your technician is on the way for \"{}\". Track their arrival: {}",
+        link.first_name, link.title, url
+    );
+    match link.channel.as_str() {
+        "sms" => match link.phone.filter(|p| !p.is_empty()) {
+            Some(to) => state.messenger.send_sms(&to, &format!("{}: {}", link.team_name, body)).await?,
+            None => return Ok(()),
+        },
+        _ => match link.email.filter(|e| !e.is_empty()) {
+            Some(to) => state.messenger.send_email(&to, "Your technician is on the way", &body).await?,
+            None => return Ok(()),
+        },
+    }
+
+    tracing::info!(link_id = %link_id, channel = %link.channel, "Tracking link sent");
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct TrackedJob {
+    link_id: Uuid,
+    expires_at: DateTime<Utc>,
+    team_id: Uuid,
+    status: String,
+    title: String,
+    assigned_to: Option<Uuid>,
+    scheduled_date: Option<NaiveDate>,
+    scheduled_start_time: Option<NaiveTime>,
+    arrival_window_start: Option<NaiveTime>,
+    arrival_window_end: Option<NaiveTime>,
+    property_latitude: Option<f64>,
+    property_longitude: Option<f64>,
+    team_name: String,
+    team_phone: Option<String>,
+    timezone: String,
+    technician_first_name: Option<String>,
+    technician_avatar_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TechnicianLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy: Option<f64>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Eta {
+    pub distance_meters: f64,
+    pub duration_seconds: i64,
+    pub arrives_at: DateTime<Utc>,
+    pub provider: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArrivalWindow {
+    pub date: Option<NaiveDate>,
+    pub start: Option<NaiveTime>,
+    pub end: Option<NaiveTime>,
+    /// Zone the window's times are in
+    pub timezone: String,
+}
+
+/// What the customer's tracking page shows. Nothing here identifies the customer.
+#[derive(Debug, Serialize)]
+pub struct TrackingView {
+    pub job_title: String,
+    /// `scheduled` until the technician sets off, then `en_route`
+    pub status: String,
+    pub team_name: String,
+    pub team_phone: Option<String>,
+    pub technician_first_name: Option<String>,
+    pub technician_avatar_url: Option<String>,
+    pub arrival_window: ArrivalWindow,
+    /// Only while en route, and only if the fix is recent
+    pub location: Option<TechnicianLocation>,
+    pub eta: Option<Eta>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Resolve a tracking token. Unknown, expired and revoked links, and jobs that have
+/// started or been cancelled, all look the same: not found.
+pub async fn view(state: &AppState, token: &str) -> ApiResult<TrackingView> {
+    let job = sqlx::query_as::<_, TrackedJob>(
+        r#"
+        SELECT l.id AS link_id, l.expires_at, j.team_id, j.status::text AS status, j.title, j.assigned_to,
+               j.scheduled_date, j.scheduled_start_time, j.arrival_window_start, j.arrival_window_end,
+               p.latitude AS property_latitude, p.longitude AS property_longitude,
+               t.name AS team_name, t.phone AS team_phone, t.timezone,
+               u.first_name AS technician_first_name, u.avatar_url AS technician_avatar_url
+        FROM job_tracking_links l
+        JOIN jobs j ON j.id = l.job_id
+        JOIN teams t ON t.id = j.team_id
+        LEFT JOIN properties p ON p.id = j.property_id
+        LEFT JOIN users u ON u.id = j.assigned_to
+        WHERE l.token_hash = $1 AND l.revoked_at IS NULL AND l.expires_at > now()
+          AND j.deleted_at IS NULL AND j.status IN ('scheduled', 'en_route')
+        "#,
+    )
+    .bind(auth_service::hash_token(token))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Tracking link".into()))?;
+
+    sqlx::query("UPDATE job_tracking_links SET view_count = view_count + 1, last_viewed_at = now() WHERE id = $1")
+        .bind(job.link_id)
+        .execute(&state.db)
+        .await?;
+
+    let location = match (job.status.as_str(), job.assigned_to) {
+        ("en_route", Some(technician)) => latest_location(state, job.team_id, technician).await?,
+        _ => None,
+    };
+    let destination = match (job.property_latitude, job.property_longitude) {
+        (Some(latitude), Some(longitude)) => Some(Coordinate { latitude, longitude }),
+        _ => None,
+    };
+    let eta = match (&location, destination) {
+        (Some(location), Some(destination)) => {
+            let from = Coordinate { latitude: location.latitude, longitude: location.longitude };
+            Some(estimate(state, from, destination).await)
+        }
+        _ => None,
+    };
+
+    Ok(TrackingView {
+        job_title: job.title,
+        status: job.status,
+        team_name: job.team_name,
+        team_phone: job.team_phone,
+        technician_first_name: job.technician_first_name,
+        technician_avatar_url: job.technician_avatar_url,
+        arrival_window: ArrivalWindow {
+            date: job.scheduled_date,
+            start: job.arrival_window_start.or(job.scheduled_start_time),
+            end: job.arrival_window_end,
+            timezone: job.timezone,
+        },
+        location,
+        eta,
+        expires_at: job.expires_at,
+    })
+}
+
+async fn latest_location(state: &AppState, team_id: Uuid, user_id: Uuid) -> ApiResult<Option<TechnicianLocation>> {
+    let max_age = Duration::seconds(state.config.tracking.location_max_age_secs);
+    let location = gps_store::latest_position(state, team_id, user_id, max_age).await?;
+    Ok(location.map(|location| TechnicianLocation {
+        latitude: location.latitude,
+        longitude: location.longitude,
+        accuracy: location.accuracy,
+        recorded_at: location.recorded_at,
+    }))
+}
+
+/// Ask the routing provider, falling back to the straight-line model if it fails so the
+/// customer still gets an estimate
+async fn estimate(state: &AppState, from: Coordinate, to: Coordinate) -> Eta {
+    let (route, provider): (RouteEstimate, &'static str) = match state.routing.estimate(from, to).await {
+        Ok(route) => (route, state.routing.name()),
+        Err(e) => {
+            tracing::warn!(provider = state.routing.name(), error = %e, "Routing provider failed; using straight-line ETA");
+            (StraightLine::new(&state.config.tracking).estimate_now(from, to), "straight_line")
+        }
+    };
+    Eta {
+        distance_meters: route.distance_meters.round(),
+        duration_seconds: route.duration_secs,
+        arrives_at: Utc::now() + Duration::seconds(route.duration_secs),
+        provider,
+    }
+}
@@ -0,0 +1,330 @@
+//! Trip detection over a technician's GPS breadcrumbs.
+//!
+//! A day's fixes are split into stops and trips. A stop is a run of fixes that stay within
+//! [`STOP_RADIUS_METERS`] of where the run began, without reported movement, for at least
+//! [`MIN_DWELL_SECS`]; a phone that goes quiet while parked counts too, since the run
+//! spans the gap. The movement between two stops is a trip. Trip distance is the
+//! haversine sum of its legs, skipping legs that would need an implausible speed (GPS
+//! jumps). Stops are matched to the technician's job properties, and each trip carries a
+//! Douglas-Peucker simplified path for replay.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::errors::ApiResult;
+use crate::services::geofence::distance_meters;
+use crate::AppState;
+
+/// Fixes this close to where a stop began still belong to it
+const STOP_RADIUS_METERS: f64 = 75.0;
+/// Stationary this long counts as a stop rather than traffic
+const MIN_DWELL_SECS: i64 = 5 * 60;
+/// Reported speed (m/s, about 7 km/h) above which a fix is moving, whatever its position
+const MOVING_SPEED_MPS: f64 = 2.0;
+/// Legs faster than this (about 250 km/h) are GPS jumps, not driving
+const MAX_PLAUSIBLE_SPEED_MPS: f64 = 70.0;
+/// Movement shorter than this between stops is someone walking around a site
+const MIN_TRIP_METERS: f64 = 250.0;
+/// Simplified paths stay within this distance of the raw breadcrumbs
+const SIMPLIFY_TOLERANCE_METERS: f64 = 15.0;
+/// Upper bound on fixes read for one day
+const MAX_POINTS: i64 = 20_000;
+pub const METERS_PER_MILE: f64 = 1609.344;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Point {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub speed: Option<f64>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// One vertex of a replay path
+#[derive(Debug, Clone, Serialize)]
+pub struct PathPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stop {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub arrived_at: DateTime<Utc>,
+    pub departed_at: DateTime<Utc>,
+    pub duration_secs: i64,
+    /// The job whose property the stop was at, when one is within its geofence radius
+    pub job_id: Option<Uuid>,
+    pub job_title: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Trip {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub duration_secs: i64,
+    pub distance_meters: f64,
+    pub distance_miles: f64,
+    /// Index into the day's stops where the trip began and ended, if it did at one
+    pub from_stop: Option<usize>,
+    pub to_stop: Option<usize>,
+    pub path: Vec<PathPoint>,
+    /// Raw fixes behind the simplified path
+    pub point_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DayReport {
+    pub user_id: Uuid,
+    pub date: NaiveDate,
+    pub timezone: String,
+    pub point_count: usize,
+    pub trips: Vec<Trip>,
+    pub stops: Vec<Stop>,
+    pub total_distance_meters: f64,
+    pub total_distance_miles: f64,
+}
+
+impl DayReport {
+    /// Miles driven, to the tenth of a mile kept on mileage expenses
+    pub fn miles(&self) -> Decimal {
+        Decimal::from_f64(self.total_distance_miles).unwrap_or_default().round_dp(1)
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct JobSite {
+    job_id: Uuid,
+    title: String,
+    latitude: f64,
+    longitude: f64,
+    radius_meters: i32,
+}
+
+/// Reconstruct a technician's driving on a day, in the team's time zone
+pub async fn analyze_day(state: &AppState, team_id: Uuid, user_id: Uuid, date: NaiveDate) -> ApiResult<DayReport> {
+    let timezone = sqlx::query_scalar::<_, String>("SELECT timezone FROM teams WHERE id = $1")
+        .bind(team_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    let points = sqlx::query_as::<_, Point>(
+        r#"
+        SELECT latitude, longitude, speed, recorded_at FROM gps_locations
+        WHERE team_id = $1 AND user_id = $2 AND (recorded_at AT TIME ZONE $3)::date = $4
+        ORDER BY recorded_at
+        LIMIT $5
+        "#,
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .bind(&timezone)
+    .bind(date)
+    .bind(MAX_POINTS)
+    .fetch_all(&state.db)
+    .await?;
+
+    // Any job of theirs scheduled or worked that day is a candidate for a stop
+    let sites = sqlx::query_as::<_, JobSite>(
+        r#"
+        SELECT j.id AS job_id, j.title, p.latitude, p.longitude,
+               COALESCE(p.geofence_radius_meters, t.geofence_radius_meters) AS radius_meters
+        FROM jobs j
+        JOIN properties p ON p.id = j.property_id
+        JOIN teams t ON t.id = j.team_id
+        WHERE j.team_id = $1 AND j.assigned_to = $2 AND j.deleted_at IS NULL
+          AND p.latitude IS NOT NULL AND p.longitude IS NOT NULL
+          AND (j.scheduled_date = $3 OR (j.started_at AT TIME ZONE t.timezone)::date = $3)
+        "#,
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .bind(date)
+    .fetch_all(&state.db)
+    .await?;
+
+    let (trips, mut stops) = segment(&points);
+    for stop in &mut stops {
+        if let Some(site) = nearest_site(&sites, stop.latitude, stop.longitude) {
+            stop.job_id = Some(site.job_id);
+            stop.job_title = Some(site.title.clone());
+        }
+    }
+
+    let total_distance_meters: f64 = trips.iter().map(|t| t.distance_meters).sum();
+    Ok(DayReport {
+        user_id,
+        date,
+        timezone,
+        point_count: points.len(),
+        trips,
+        stops,
+        total_distance_meters: total_distance_meters.round(),
+        total_distance_miles: round_to(total_distance_meters / METERS_PER_MILE, 2),
+    })
+}
+
+fn nearest_site(sites: &[JobSite], latitude: f64, longitude: f64) -> Option<&JobSite> {
+    sites
+        .iter()
+        .map(|site| (site, distance_meters(latitude, longitude, site.latitude, site.longitude)))
+        .filter(|(site, distance)| *distance <= f64::from(site.radius_meters))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(site, _)| site)
+}
+
+fn round_to(value: f64, places: i32) -> f64 {
+    let factor = 10f64.powi(places);
+    (value * factor).round() / factor
+}
+
+fn leg(a: &Point, b: &Point) -> f64 {
+    distance_meters(a.latitude, a.longitude, b.latitude, b.longitude)
+}
+
+fn is_moving(point: &Point) -> bool {
+    point.speed.is_some_and(|speed| speed >= MOVING_SPEED_MPS)
+}
+
+/// Split time-ordered fixes into trips and stops
+fn segment(points: &[Point]) -> (Vec<Trip>, Vec<Stop>) {
+    // (first, last) fix index of each stop
+    let mut dwell: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < points.len() {
+        let mut j = i;
+        while j + 1 < points.len() && !is_moving(&points[j + 1]) && leg(&points[i], &points[j + 1]) <= STOP_RADIUS_METERS {
+            j += 1;
+        }
+        if (points[j].recorded_at - points[i].recorded_at).num_seconds() >= MIN_DWELL_SECS {
+            dwell.push((i, j));
+            i = j + 1;
+        } else {
+            i += 1;
+        }
+    }
+
+    let stops: Vec<Stop> = dwell
+        .iter()
+        .map(|&(first, last)| {
+            let cluster = &points[first..=last];
+            let n = cluster.len() as f64;
+            Stop {
+                latitude: cluster.iter().map(|p| p.latitude).sum::<f64>() / n,
+                longitude: cluster.iter().map(|p| p.longitude).sum::<f64>() / n,
+                arrived_at: points[first].recorded_at,
+                departed_at: points[last].recorded_at,
+                duration_secs: (points[last].recorded_at - points[first].recorded_at).num_seconds(),
+                job_id: None,
+                job_title: None,
+            }
+        })
+        .collect();
+
+    // Trips run from the last fix of one stop to the first fix of the next, plus any
+    // movement before the first stop or after the last
+    let mut spans: Vec<(usize, usize, Option<usize>, Option<usize>)> = Vec::new();
+    let mut start = 0;
+    let mut from_stop = None;
+    for (index, &(first, last)) in dwell.iter().enumerate() {
+        if first > start {
+            spans.push((start, first, from_stop, Some(index)));
+        }
+        start = last;
+        from_stop = Some(index);
+    }
+    if points.len() > start + 1 {
+        spans.push((start, points.len() - 1, from_stop, None));
+    }
+
+    let trips = spans
+        .into_iter()
+        .filter_map(|(first, last, from_stop, to_stop)| build_trip(&points[first..=last], from_stop, to_stop))
+        .collect();
+
+    (trips, stops)
+}
+
+fn build_trip(points: &[Point], from_stop: Option<usize>, to_stop: Option<usize>) -> Option<Trip> {
+    let (first, last) = (points.first()?, points.last()?);
+
+    let distance: f64 = points
+        .windows(2)
+        .map(|pair| {
+            let meters = leg(&pair[0], &pair[1]);
+            let secs = (pair[1].recorded_at - pair[0].recorded_at).num_milliseconds() as f64 / 1000.0;
+            if secs > 0.0 && meters / secs > MAX_PLAUSIBLE_SPEED_MPS {
+                0.0
+            } else {
+                meters
+            }
+        })
+        .sum();
+    if distance < MIN_TRIP_METERS {
+        return None;
+    }
+
+    let path = simplify(points, SIMPLIFY_TOLERANCE_METERS)
+        .into_iter()
+        .map(|p| PathPoint { latitude: p.latitude, longitude: p.longitude, recorded_at: p.recorded_at })
+        .collect();
+
+    Some(Trip {
+        started_at: first.recorded_at,
+        ended_at: last.recorded_at,
+        duration_secs: (last.recorded_at - first.recorded_at).num_seconds(),
+        distance_meters: distance.round(),
+        distance_miles: round_to(distance / METERS_PER_MILE, 2),
+        from_stop,
+        to_stop,
+        path,
+        point_count: points.len(),
+    })
+}
+
+/// Douglas-Peucker: keep the endpoints, then recursively the fix furthest from the chord
+/// while it's further than `tolerance` meters
+pub fn simplify(points: &[Point], tolerance: f64) -> Vec<&Point> {
+    if points.len() <= 2 {
+        return points.iter().collect();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = ranges.pop() {
+        let furthest = (start + 1..end)
+            .map(|i| (i, cross_track_meters(&points[i], &points[start], &points[end])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((index, _)) = furthest.filter(|(_, distance)| *distance > tolerance) {
+            keep[index] = true;
+            ranges.push((start, index));
+            ranges.push((index, end));
+        }
+    }
+
+    points.iter().zip(keep).filter(|(_, kept)| *kept).map(|(p, _)| p).collect()
+}
+
+/// Distance from `p` to the segment `a`-`b`, on a local flat projection (fine at street scale)
+fn cross_track_meters(p: &Point, a: &Point, b: &Point) -> f64 {
+    let meters_per_degree = 111_320.0;
+    let scale = a.latitude.to_radians().cos();
+    let project = |q: &Point| ((q.longitude - a.longitude) * meters_per_degree * scale, (q.latitude - a.latitude) * meters_per_degree);
+
+    let (px, py) = project(p);
+    let (bx, by) = project(b);
+    let length_sq = bx * bx + by * by;
+    if length_sq == 0.0 {
+        return (px * px + py * py).sqrt();
+    }
+    let t = ((px * bx + py * by) / length_sq).clamp(0.0, 1.0);
+    let (dx, dy) = (px - t * bx, py - t * by);
+    (dx * dx + dy * dy).sqrt()
+}
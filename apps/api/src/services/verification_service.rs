@@ -0,0 +1,482 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::errors::{ApiError, ApiResult};
+use crate::models::user::ContactChannel;
+use crate::services::auth_service;
+use crate::services::job_queue::{self, BackgroundJob};
+use crate::AppState;
+
+/// Wrong guesses allowed against one code before a new one must be requested
+const MAX_CODE_ATTEMPTS: i32 = 5;
+
+#[derive(Debug, sqlx::FromRow)]
+struct Contact {
+    id: Uuid,
+    email: Option<String>,
+    phone: Option<String>,
+    first_name: String,
+    is_active: bool,
+    email_verified: bool,
+    phone_verified: bool,
+}
+
+impl Contact {
+    fn destination(&self, channel: ContactChannel) -> Option<&str> {
+        match channel {
+            ContactChannel::Email => self.email.as_deref(),
+            ContactChannel::Phone => self.phone.as_deref(),
+        }
+    }
+
+    fn is_verified(&self, channel: ContactChannel) -> bool {
+        match channel {
+            ContactChannel::Email => self.email_verified,
+            ContactChannel::Phone => self.phone_verified,
+        }
+    }
+}
+
+const CONTACT_COLUMNS: &str = "id, email, phone, first_name, is_active, email_verified, phone_verified";
+
+async fn find_contact(conn: &mut PgConnection, channel: ContactChannel, destination: &str) -> ApiResult<Option<Contact>> {
+    let condition = match channel {
+        ContactChannel::Email => "lower(email) = lower($1)",
+        ContactChannel::Phone => "phone = $1",
+    };
+    let contact = sqlx::query_as::<_, Contact>(&format!("SELECT {} FROM users WHERE {}", CONTACT_COLUMNS, condition))
+        .bind(destination.trim())
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(contact)
+}
+
+async fn get_contact(conn: &mut PgConnection, user_id: Uuid) -> ApiResult<Contact> {
+    sqlx::query_as::<_, Contact>(&format!("SELECT {} FROM users WHERE id = $1", CONTACT_COLUMNS))
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("User".into()))
+}
+
+/// Enforce the per-hour cap and the resend cooldown for a destination. Counted whether or
+/// not an account has it, so being rate limited says nothing about who has an account.
+async fn ensure_request_allowed(state: &AppState, purpose: &str, channel: ContactChannel, destination: &str) -> ApiResult<()> {
+    let normalized = match channel {
+        ContactChannel::Email => destination.trim().to_lowercase(),
+        ContactChannel::Phone => destination.trim().to_string(),
+    };
+    let key = format!("verification_rate:{}:{}:{}", purpose, channel.as_str(), auth_service::hash_token(&normalized));
+    let settings = &state.config.auth;
+
+    let mut conn = state.redis.clone();
+    let (fresh, recent): (Option<String>, i64) = redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(format!("{}:cooldown", key))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(settings.verification_resend_cooldown_secs)
+        .cmd("SET")
+        .arg(format!("{}:hour", key))
+        .arg(0)
+        .arg("NX")
+        .arg("EX")
+        .arg(3600)
+        .ignore()
+        .cmd("INCR")
+        .arg(format!("{}:hour", key))
+        .query_async(&mut conn)
+        .await?;
+
+    if fresh.is_none() || recent > settings.verification_max_requests_per_hour {
+        tracing::warn!(channel = channel.as_str(), purpose, "Verification request rate limited");
+        return Err(ApiError::RateLimited);
+    }
+    Ok(())
+}
+
+async fn send_message(state: &AppState, channel: ContactChannel, to: &str, subject: &str, body: &str) -> ApiResult<()> {
+    match channel {
+        ContactChannel::Email => state.messenger.send_email(to, subject, body).await?,
+        ContactChannel::Phone => state.messenger.send_sms(to, body).await?,
+    }
+    Ok(())
+}
+
+fn channel_of(value: &str) -> ContactChannel {
+    match value {
+        "phone" => ContactChannel::Phone,
+        _ => ContactChannel::Email,
+    }
+}
+
+/// Send a reset link to the account with this email or phone. Unknown or inactive
+/// accounts are ignored so the response can't be used to discover who has an account.
+/// The link itself is minted when the queued job sends it, so it is never stored.
+pub async fn request_password_reset(state: &AppState, channel: ContactChannel, destination: &str) -> ApiResult<()> {
+    ensure_request_allowed(state, "password_reset", channel, destination).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    let Some(contact) = find_contact(&mut tx, channel, destination).await?.filter(|c| c.is_active) else {
+        tracing::info!(channel = channel.as_str(), "Password reset requested for unknown account");
+        return Ok(());
+    };
+
+    let reset_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO password_reset_tokens (user_id, channel, expires_at)
+        VALUES ($1, $2, $3)
+        RETURNING id
+        "#,
+    )
+    .bind(contact.id)
+    .bind(channel.as_str())
+    .bind(Utc::now() + Duration::minutes(state.config.auth.password_reset_expiry_minutes))
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let job = BackgroundJob::SendPasswordReset { reset_id };
+    job_queue::enqueue_with(&mut *tx, &job, Utc::now(), state.jobs.max_attempts(), None).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(user_id = %contact.id, channel = channel.as_str(), "Password reset requested");
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct PendingReset {
+    channel: String,
+    expires_at: DateTime<Utc>,
+    email: Option<String>,
+    phone: Option<String>,
+    first_name: String,
+}
+
+/// Mint the token for a requested reset and send the link. A retry mints a new token,
+/// which also voids any link an attempt that reported failure may have delivered.
+pub async fn deliver_password_reset(state: &AppState, reset_id: Uuid) -> ApiResult<()> {
+    let token = auth_service::generate_token();
+
+    let Some(reset) = sqlx::query_as::<_, PendingReset>(
+        r#"
+        UPDATE password_reset_tokens r SET token_hash = $2
+        FROM users u
+        WHERE r.id = $1 AND u.id = r.user_id AND r.used_at IS NULL AND r.expires_at > now() AND u.is_active
+        RETURNING r.channel, r.expires_at, u.email, u.phone, u.first_name
+        "#,
+    )
+    .bind(reset_id)
+    .bind(auth_service::hash_token(&token))
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        tracing::debug!(reset_id = %reset_id, "Password reset already used or expired; not sending");
+        return Ok(());
+    };
+
+    let channel = channel_of(&reset.channel);
+    let to = match channel {
+        ContactChannel::Email => reset.email,
+        ContactChannel::Phone => reset.phone,
+    };
+    let Some(to) = to.filter(|to| !to.is_empty()) else {
+        return Ok(());
+    };
+
+    let link = format!(
+        "{}/reset-password?token={}",
+        state.config.server.app_url.trim_end_matches('/'),
+        token
+    );
+    let expiry_minutes = (reset.expires_at - Utc::now()).num_minutes().max(1);
+    let body = match channel {
+        ContactChannel::Email => format!(
+            "Hi {},\n\nUse this link to choose a new FieldForge password:\n\n{}\n\nIt expires in {} minutes and can only be used once. If you didn't ask for this, you can ignore this email.",
+            reset.first_name, link, expiry_minutes
+        ),
+        ContactChannel::Phone => format!("Reset your FieldForge password: {} (expires in {} min)", link, expiry_minutes),
+    };
+    send_message(state, channel, &to, "Reset your FieldForge password", &body).await
+}
+
+/// Set a new password from a reset token. The token and any other outstanding reset
+/// tokens for the user are spent, and every session is signed out.
+pub async fn reset_password(state: &AppState, token: &str, new_password: &str) -> ApiResult<Uuid> {
+    if new_password.len() < 8 {
+        return Err(ApiError::Validation("Password must be at least 8 characters".into()));
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let (user_id, channel, expires_at): (Uuid, String, DateTime<Utc>) = sqlx::query_as(
+        "SELECT user_id, channel, expires_at FROM password_reset_tokens WHERE token_hash = $1 AND used_at IS NULL FOR UPDATE",
+    )
+    .bind(auth_service::hash_token(token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::BadRequest("This reset link is invalid or has already been used".into()))?;
+
+    if expires_at <= Utc::now() {
+        return Err(ApiError::BadRequest("This reset link has expired; request a new one".into()));
+    }
+
+    let password_hash = auth_service::hash_password(new_password)?;
+
+    // Receiving the link proves control of the channel it was sent to
+    sqlx::query(
+        r#"
+        UPDATE users SET
+            password_hash = $2,
+            email_verified = email_verified OR $3 = 'email',
+            phone_verified = phone_verified OR $3 = 'phone',
+            updated_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .bind(&password_hash)
+    .bind(&channel)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = now() WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    let revoked = auth_service::revoke_all_refresh_tokens(&state.db, user_id).await?;
+    tracing::info!(user_id = %user_id, revoked_sessions = revoked, "Password reset");
+
+    Ok(user_id)
+}
+
+/// Email or text a fresh six-digit code to the user's current address or number.
+/// Returns when the code expires.
+pub async fn send_verification_code(
+    conn: &mut PgConnection,
+    state: &AppState,
+    user_id: Uuid,
+    channel: ContactChannel,
+) -> ApiResult<DateTime<Utc>> {
+    let contact = get_contact(conn, user_id).await?;
+    let to = contact
+        .destination(channel)
+        .ok_or_else(|| ApiError::Validation(format!("This account has no {} to verify", channel.as_str())))?
+        .to_string();
+    if contact.is_verified(channel) {
+        return Err(ApiError::Conflict(format!("This {} is already verified", channel.as_str())));
+    }
+
+    ensure_request_allowed(state, "verification", channel, &to).await?;
+    issue_code(conn, state, user_id, channel, &to).await
+}
+
+/// Send a code to whoever owns `destination`. Unknown and already verified destinations
+/// are ignored, and rate limited like any other, so the response reveals nothing.
+pub async fn send_verification_code_to(state: &AppState, channel: ContactChannel, destination: &str) -> ApiResult<()> {
+    ensure_request_allowed(state, "verification", channel, destination).await?;
+
+    let mut tx = state.db.begin().await?;
+    let Some(contact) = find_contact(&mut tx, channel, destination).await? else {
+        return Ok(());
+    };
+    let Some(to) = contact.destination(channel).map(str::to_string) else {
+        return Ok(());
+    };
+    if contact.is_verified(channel) {
+        return Ok(());
+    }
+    issue_code(&mut tx, state, contact.id, channel, &to).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Record a pending code and queue its delivery. The code is minted when it is sent, so
+/// neither it nor a message containing it is ever stored.
+async fn issue_code(
+    conn: &mut PgConnection,
+    state: &AppState,
+    user_id: Uuid,
+    channel: ContactChannel,
+    to: &str,
+) -> ApiResult<DateTime<Utc>> {
+    let expires_at = Utc::now() + Duration::minutes(state.config.auth.verification_code_expiry_minutes);
+
+    // Only the newest code counts
+    sqlx::query(
+        "UPDATE verification_codes SET consumed_at = now() WHERE user_id = $1 AND channel = $2 AND consumed_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(channel.as_str())
+    .execute(&mut *conn)
+    .await?;
+
+    let code_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO verification_codes (user_id, channel, destination, expires_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(channel.as_str())
+    .bind(to)
+    .bind(expires_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let job = BackgroundJob::SendVerificationCode { code_id };
+    job_queue::enqueue_with(&mut *conn, &job, Utc::now(), state.jobs.max_attempts(), None).await?;
+
+    tracing::info!(user_id = %user_id, channel = channel.as_str(), "Verification code requested");
+    Ok(expires_at)
+}
+
+/// Mint a pending code and send it. A retry mints a new code and resets its attempts,
+/// voiding any code an attempt that reported failure may have delivered.
+pub async fn deliver_verification_code(state: &AppState, code_id: Uuid) -> ApiResult<()> {
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+
+    let Some((channel, destination, expires_at)) = sqlx::query_as::<_, (String, String, DateTime<Utc>)>(
+        r#"
+        UPDATE verification_codes SET code_hash = $2, attempts = 0
+        WHERE id = $1 AND consumed_at IS NULL AND expires_at > now()
+        RETURNING channel, destination, expires_at
+        "#,
+    )
+    .bind(code_id)
+    .bind(auth_service::hash_token(&code))
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        tracing::debug!(code_id = %code_id, "Verification code superseded or expired; not sending");
+        return Ok(());
+    };
+
+    let body = format!(
+        "Your FieldForge verification code is {}. It expires in {} minutes.",
+        code,
+        (expires_at - Utc::now()).num_minutes().max(1)
+    );
+    send_message(state, channel_of(&channel), &destination, "Your FieldForge verification code", &body).await
+}
+
+/// Check a code against the newest one sent for `destination` and mark the channel
+/// verified. Each wrong guess counts against the code.
+pub async fn confirm_verification_code(
+    state: &AppState,
+    channel: ContactChannel,
+    destination: &str,
+    code: &str,
+) -> ApiResult<Uuid> {
+    let invalid = || ApiError::BadRequest("Invalid or expired verification code".into());
+
+    let mut tx = state.db.begin().await?;
+
+    let contact = find_contact(&mut tx, channel, destination).await?.ok_or_else(invalid)?;
+    let current = contact.destination(channel).ok_or_else(invalid)?.to_string();
+
+    let (code_id, code_hash, attempts, expires_at): (Uuid, Option<String>, i32, DateTime<Utc>) = sqlx::query_as(
+        r#"
+        SELECT id, code_hash, attempts, expires_at FROM verification_codes
+        WHERE user_id = $1 AND channel = $2 AND destination = $3 AND consumed_at IS NULL
+        ORDER BY created_at DESC
+        LIMIT 1
+        FOR UPDATE
+        "#,
+    )
+    .bind(contact.id)
+    .bind(channel.as_str())
+    .bind(&current)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid)?;
+
+    if expires_at <= Utc::now() || attempts >= MAX_CODE_ATTEMPTS {
+        return Err(invalid());
+    }
+
+    if code_hash.as_deref() != Some(auth_service::hash_token(code.trim()).as_str()) {
+        sqlx::query("UPDATE verification_codes SET attempts = attempts + 1 WHERE id = $1")
+            .bind(code_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Err(invalid());
+    }
+
+    sqlx::query("UPDATE verification_codes SET consumed_at = now() WHERE id = $1")
+        .bind(code_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let column = match channel {
+        ContactChannel::Email => "email_verified",
+        ContactChannel::Phone => "phone_verified",
+    };
+    sqlx::query(&format!("UPDATE users SET {} = true, updated_at = now() WHERE id = $1", column))
+        .bind(contact.id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    tracing::info!(user_id = %contact.id, channel = channel.as_str(), "Contact verified");
+    Ok(contact.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::services::messaging::Channel;
+    use crate::services::payment_provider::FakeProvider;
+    use crate::test_support;
+
+    #[sqlx::test(migrations = "./src/db/migrations")]
+    #[ignore = "needs Postgres (DATABASE_URL), Redis (REDIS_URL) and JWT_SECRET"]
+    async fn a_reset_link_arrives_by_email_and_sets_the_password(db: PgPool) {
+        let (state, outbox) = test_support::state_with_outbox(db.clone(), FakeProvider::default()).await;
+        let app = test_support::app(state.clone());
+        let team_id = test_support::team(&db, "acme").await;
+        // Unique per run, since the request limits live in Redis rather than the test database
+        let email = format!("reset-{}@example.com", Uuid::new_v4().simple());
+        test_support::member(&db, team_id, &email, "owner").await;
+
+        let (status, _) =
+            test_support::call(&app, "POST", "/api/v1/auth/password/forgot", None, Some(json!({ "email": email }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(outbox.messages().is_empty(), "the link goes out from the queue");
+
+        let reset_id: Uuid = sqlx::query_scalar("SELECT id FROM password_reset_tokens").fetch_one(&db).await.unwrap();
+        deliver_password_reset(&state, reset_id).await.unwrap();
+
+        let sent = outbox.last_to(&email).expect("a reset email");
+        assert_eq!(sent.channel, Channel::Email);
+        assert_eq!(sent.subject.as_deref(), Some("Reset your FieldForge password"));
+        assert!(sent.body.starts_with("Hi Sam,"));
+        let prefix = format!("{}/reset-password?token=", state.config.server.app_url.trim_end_matches('/'));
+        let token = sent
+            .body
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+            .expect("the email carries the link");
+
+        let reset = json!({ "token": token, "password": "a new passphrase" });
+        let (status, body) = test_support::call(&app, "POST", "/api/v1/auth/password/reset", None, Some(reset)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let verified: bool = sqlx::query_scalar("SELECT email_verified FROM users WHERE email = $1").bind(&email).fetch_one(&db).await.unwrap();
+        assert!(verified, "receiving the link proves the address");
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::models::webhook::{Webhook, WebhookDelivery};
+use crate::services::events::{self, WEBHOOK_TEST};
+use crate::services::outbound;
+use crate::AppState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "X-FieldForge-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-FieldForge-Timestamp";
+pub const EVENT_HEADER: &str = "X-FieldForge-Event";
+pub const DELIVERY_HEADER: &str = "X-FieldForge-Delivery";
+
+/// Response bodies are kept in the delivery log for debugging, not in full; only this many
+/// bytes are read off the wire
+const MAX_RESPONSE_BODY: usize = 2048;
+
+/// `sha256=<hex>` over `"{timestamp}.{body}"`. Subscribers recompute it with their secret and
+/// reject stale timestamps, so a captured delivery can't be replayed against them later.
+pub fn sign(body: &str, secret: &str, timestamp: i64) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", HEXLOWER.encode(&mac.finalize().into_bytes()))
+}
+
+pub fn generate_secret() -> String {
+    format!("whsec_{}", Uuid::new_v4().to_string().replace('-', ""))
+}
+
+/// What happened to one HTTP attempt
+struct Attempt {
+    response_status: Option<i32>,
+    response_body: Option<String>,
+    error: Option<String>,
+    duration_ms: i32,
+}
+
+impl Attempt {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+async fn post(state: &AppState, webhook: &Webhook, event_id: Uuid, event_type: &str, payload: &Value) -> Attempt {
+    let body = payload.to_string();
+    let timestamp = Utc::now().timestamp();
+    let started = Instant::now();
+
+    let mut attempt = Attempt {
+        response_status: None,
+        response_body: None,
+        error: None,
+        duration_ms: 0,
+    };
+
+    // DNS may have changed since the URL was saved; the connection is pinned to what was checked
+    let target = match outbound::resolve_public(&webhook.url, true).await {
+        Ok(target) => target,
+        Err(e) => {
+            attempt.error = Some(format!("Refused to deliver: webhook URL {}", e));
+            attempt.duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+            return attempt;
+        }
+    };
+
+    let client = target
+        .client_builder()
+        .timeout(Duration::from_secs(state.config.webhooks.timeout_secs))
+        .build();
+
+    let result = match client {
+        Ok(client) => {
+            client
+                .post(target.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(reqwest::header::USER_AGENT, "FieldForge-Webhooks/1.0")
+                .header(EVENT_HEADER, event_type)
+                .header(DELIVERY_HEADER, event_id.to_string())
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, sign(&body, &webhook.secret, timestamp))
+                .body(body)
+                .send()
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            attempt.response_status = Some(status.as_u16() as i32);
+            attempt.response_body = Some(read_excerpt(response).await);
+            if !status.is_success() {
+                attempt.error = Some(format!("Subscriber responded {}", status));
+            }
+        }
+        Err(e) if e.is_timeout() => attempt.error = Some("Timed out waiting for the subscriber".into()),
+        Err(e) => attempt.error = Some(format!("Request failed: {}", e)),
+    }
+
+    attempt.duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    attempt
+}
+
+/// The start of a subscriber's response. Reading stops at [`MAX_RESPONSE_BODY`] so a huge or
+/// endless body can't hold the worker or its memory; a read error keeps what arrived.
+async fn read_excerpt(mut response: reqwest::Response) -> String {
+    let mut bytes = Vec::new();
+    while let Ok(Some(chunk)) = response.chunk().await {
+        let room = MAX_RESPONSE_BODY - bytes.len();
+        bytes.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        if bytes.len() == MAX_RESPONSE_BODY {
+            break;
+        }
+    }
+    excerpt(&bytes)
+}
+
+/// Decode a possibly truncated body, dropping a character the cut split in half
+fn excerpt(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(e) if e.error_len().is_none() => String::from_utf8_lossy(&bytes[..e.valid_up_to()]).into_owned(),
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+async fn record(
+    state: &AppState,
+    webhook: &Webhook,
+    event_id: Uuid,
+    event_type: &str,
+    payload: &Value,
+    replay_of: Option<Uuid>,
+    attempt: &Attempt,
+) -> anyhow::Result<WebhookDelivery> {
+    let delivery = sqlx::query_as::<_, WebhookDelivery>(
+        r#"
+        INSERT INTO webhook_deliveries (webhook_id, team_id, event_id, event_type, payload, attempt, replay_of,
+                                        status, response_status, response_body, error, duration_ms)
+        VALUES ($1, $2, $3, $4, $5,
+                (SELECT COUNT(*) + 1 FROM webhook_deliveries WHERE webhook_id = $1 AND event_id = $3),
+                $6, $7, $8, $9, $10, $11)
+        RETURNING *
+        "#,
+    )
+    .bind(webhook.id)
+    .bind(webhook.team_id)
+    .bind(event_id)
+    .bind(event_type)
+    .bind(payload)
+    .bind(replay_of)
+    .bind(if attempt.succeeded() { "succeeded" } else { "failed" })
+    .bind(attempt.response_status)
+    .bind(&attempt.response_body)
+    .bind(&attempt.error)
+    .bind(attempt.duration_ms)
+    .fetch_one(&state.db)
+    .await?;
+    Ok(delivery)
+}
+
+/// Deliver one event to one subscription. Runs from the queue; an error schedules a retry
+/// with the queue's backoff. Every attempt is logged, and a subscription that keeps failing
+/// is switched off once `failure_count` reaches `WEBHOOK_DISABLE_AFTER_FAILURES`.
+pub async fn deliver(
+    state: &AppState,
+    webhook_id: Uuid,
+    event_id: Uuid,
+    event_type: &str,
+    payload: &Value,
+    replay_of: Option<Uuid>,
+) -> anyhow::Result<()> {
+    let webhook = sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE id = $1")
+        .bind(webhook_id)
+        .fetch_optional(&state.db)
+        .await?;
+    let Some(webhook) = webhook.filter(|w| w.is_active) else {
+        tracing::debug!(webhook_id = %webhook_id, event_id = %event_id, "Webhook gone or disabled; dropping delivery");
+        return Ok(());
+    };
+
+    let attempt = post(state, &webhook, event_id, event_type, payload).await;
+    let delivery = record(state, &webhook, event_id, event_type, payload, replay_of, &attempt).await?;
+
+    if attempt.succeeded() {
+        sqlx::query(
+            r#"
+            UPDATE webhooks SET last_triggered_at = now(), last_status_code = $2, failure_count = 0
+            WHERE id = $1
+            "#,
+        )
+        .bind(webhook.id)
+        .bind(attempt.response_status)
+        .execute(&state.db)
+        .await?;
+        tracing::info!(webhook_id = %webhook.id, event_id = %event_id, event_type = %event_type, "Webhook delivered");
+        return Ok(());
+    }
+
+    let disabled = sqlx::query_scalar::<_, bool>(
+        r#"
+        UPDATE webhooks SET
+            last_triggered_at = now(),
+            last_status_code = $2,
+            failure_count = failure_count + 1,
+            is_active = failure_count + 1 < $3,
+            disabled_at = CASE WHEN failure_count + 1 >= $3 THEN now() ELSE disabled_at END
+        WHERE id = $1
+        RETURNING NOT is_active
+        "#,
+    )
+    .bind(webhook.id)
+    .bind(attempt.response_status)
+    .bind(state.config.webhooks.disable_after_failures)
+    .fetch_one(&state.db)
+    .await?;
+
+    let error = attempt.error.unwrap_or_default();
+    if disabled {
+        // Nothing left to retry against; the delivery stays in the log for replay
+        tracing::warn!(webhook_id = %webhook.id, delivery_id = %delivery.id, error = %error, "Webhook disabled after repeated failures");
+        return Ok(());
+    }
+    Err(anyhow::anyhow!("Delivery {} attempt {} failed: {}", delivery.id, delivery.attempt, error))
+}
+
+/// Send a one-off `webhook.test` event and return the logged attempt. Test sends don't
+/// count towards `failure_count`, so checking a broken endpoint can't switch it off.
+pub async fn send_test(state: &AppState, webhook: &Webhook) -> anyhow::Result<WebhookDelivery> {
+    let event_id = Uuid::new_v4();
+    let now = Utc::now();
+    let payload = events::envelope(event_id, webhook.team_id, WEBHOOK_TEST, now, &json!({ "webhook_id": webhook.id }));
+
+    let attempt = post(state, webhook, event_id, WEBHOOK_TEST, &payload).await;
+    let delivery = record(state, webhook, event_id, WEBHOOK_TEST, &payload, None, &attempt).await?;
+
+    sqlx::query("UPDATE webhooks SET last_triggered_at = now(), last_status_code = $2 WHERE id = $1")
+        .bind(webhook.id)
+        .bind(attempt.response_status)
+        .execute(&state.db)
+        .await?;
+
+    Ok(delivery)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpts_drop_a_character_cut_in_half() {
+        assert_eq!(excerpt("ok ✓".as_bytes()), "ok ✓");
+        let cut = &"ok ✓".as_bytes()[..4];
+        assert_eq!(excerpt(cut), "ok ");
+        assert_eq!(excerpt(b"bad \xff byte"), "bad \u{fffd} byte");
+    }
+}
@@ -0,0 +1,162 @@
+//! Fixtures shared by the database-backed tests. Those tests run against the Postgres
+//! `DATABASE_URL` points at (each gets a fresh database from `#[sqlx::test]`) and the
+//! Redis at `REDIS_URL`, so they're `#[ignore]`d by default.
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::config::{LoginVerificationPolicy, Settings};
+use crate::services::messaging::CaptureSender;
+use crate::services::payment_provider::FakeProvider;
+use crate::services::{job_queue, realtime, routing, storage};
+use crate::AppState;
+
+pub const WEBHOOK_SECRET: &str = "whsec_test";
+
+/// App state with in-memory messaging, storage and payments
+pub async fn state(db: PgPool, payments: FakeProvider) -> Arc<AppState> {
+    state_with_outbox(db, payments).await.0
+}
+
+/// Like [`state`], with a handle on the messages it sends
+pub async fn state_with_outbox(db: PgPool, payments: FakeProvider) -> (Arc<AppState>, CaptureSender) {
+    if std::env::var_os("MFA_ENCRYPTION_KEY").is_none() {
+        std::env::set_var("MFA_ENCRYPTION_KEY", "dGVzdC1vbmx5LW1mYS1lbmNyeXB0aW9uLWtleS0zMmI=");
+    }
+    let mut config = Settings::from_env().expect("DATABASE_URL and JWT_SECRET must be set");
+    config.stripe.webhook_secret = WEBHOOK_SECRET.into();
+    config.storage.backend = "memory".into();
+    config.tracking.routing_provider = "straight_line".into();
+    config.auth.login_verification = LoginVerificationPolicy::Off;
+
+    let outbox = CaptureSender::default();
+    let redis = redis::Client::open(config.redis.url.as_str()).expect("valid REDIS_URL");
+    let state = Arc::new(AppState {
+        jobs: job_queue::JobQueue::new(db.clone(), &config.queue),
+        redis: redis::aio::ConnectionManager::new(redis).await.expect("Redis is reachable"),
+        messenger: Arc::new(outbox.clone()),
+        payments: Arc::new(payments),
+        storage: storage::from_settings(&config).unwrap(),
+        realtime: realtime::RealtimeHub::new(),
+        routing: routing::from_settings(&config).unwrap(),
+        db,
+        config,
+    });
+    (state, outbox)
+}
+
+/// The API as main.rs mounts it
+pub fn app(state: Arc<AppState>) -> Router {
+    Router::new().nest("/api/v1", crate::routes::api_router(state.clone())).with_state(state)
+}
+
+/// An access token for a user, as login would issue it
+pub fn bearer(state: &AppState, user_id: Uuid, team_id: Uuid) -> String {
+    let token = crate::middleware::auth::create_token(user_id, "", "", Some(team_id), &state.config.auth.jwt_secret, 1).unwrap();
+    format!("Bearer {}", token)
+}
+
+/// Send a JSON request and return the status with the decoded body (`Null` when empty)
+pub async fn call(app: &Router, method: &str, uri: &str, bearer: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(bearer) = bearer {
+        request = request.header("authorization", bearer);
+    }
+    let body = match body {
+        Some(body) => {
+            request = request.header("content-type", "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let response = app.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+pub async fn team(db: &PgPool, slug: &str) -> Uuid {
+    sqlx::query_scalar("INSERT INTO teams (name, slug) VALUES ($1, $1) RETURNING id")
+        .bind(slug)
+        .fetch_one(db)
+        .await
+        .unwrap()
+}
+
+/// A user whose default team is `team_id`, with a membership there in `role`
+pub async fn member(db: &PgPool, team_id: Uuid, email: &str, role: &str) -> Uuid {
+    let user_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO users (team_id, email, password_hash, first_name, last_name, role)
+        VALUES ($1, $2, 'unused', 'Sam', 'Ortiz', $3::user_role)
+        RETURNING id
+        "#,
+    )
+    .bind(team_id)
+    .bind(email)
+    .bind(role)
+    .fetch_one(db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO team_memberships (team_id, user_id, role) VALUES ($1, $2, $3)")
+        .bind(team_id)
+        .bind(user_id)
+        .bind(role)
+        .execute(db)
+        .await
+        .unwrap();
+    user_id
+}
+
+pub async fn customer(db: &PgPool, team_id: Uuid) -> Uuid {
+    sqlx::query_scalar(
+        "INSERT INTO customers (team_id, first_name, last_name, email) VALUES ($1, 'Dana', 'Reyes', 'dana@example.com') RETURNING id",
+    )
+    .bind(team_id)
+    .fetch_one(db)
+    .await
+    .unwrap()
+}
+
+/// A job for `customer_id` in `status`, optionally assigned
+pub async fn job(db: &PgPool, team_id: Uuid, customer_id: Uuid, status: &str, assigned_to: Option<Uuid>) -> Uuid {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO jobs (team_id, customer_id, assigned_to, title, status)
+        VALUES ($1, $2, $3, 'Replace water heater', $4::job_status)
+        RETURNING id
+        "#,
+    )
+    .bind(team_id)
+    .bind(customer_id)
+    .bind(assigned_to)
+    .bind(status)
+    .fetch_one(db)
+    .await
+    .unwrap()
+}
+
+pub async fn property(db: &PgPool, team_id: Uuid, customer_id: Uuid, latitude: f64, longitude: f64) -> Uuid {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO properties (team_id, customer_id, address_line1, city, state, zip_code, latitude, longitude)
+        VALUES ($1, $2, '12 Elm St', 'Springfield', 'IL', '62701', $3, $4)
+        RETURNING id
+        "#,
+    )
+    .bind(team_id)
+    .bind(customer_id)
+    .bind(latitude)
+    .bind(longitude)
+    .fetch_one(db)
+    .await
+    .unwrap()
+}